@@ -0,0 +1,81 @@
+//! Generating unified diffs between two versions of a file, as done for the commit and diff
+//! pages, without the syntax highlighting layered on top.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use gix::diff::blob::{intern::InternedInput, sources::lines_with_terminator, Algorithm};
+
+#[path = "../src/unified_diff_builder.rs"]
+#[allow(dead_code)]
+mod unified_diff_builder;
+
+use unified_diff_builder::{Callback, UnifiedDiffBuilder};
+
+const BEFORE: &str = include_str!("../src/git.rs");
+
+/// Writes lines out prefixed as `git diff` would.
+struct Plain;
+
+impl Callback for Plain {
+    fn addition(&mut self, data: &str, dst: &mut String) {
+        dst.push('+');
+        dst.push_str(data);
+    }
+
+    fn remove(&mut self, data: &str, dst: &mut String) {
+        dst.push('-');
+        dst.push_str(data);
+    }
+
+    fn context(&mut self, data: &str, dst: &mut String) {
+        dst.push(' ');
+        dst.push_str(data);
+    }
+}
+
+/// Edits every `step`th line of the file, giving a diff with hunks spread throughout it.
+fn edit(step: usize) -> String {
+    BEFORE
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i % step == 0 {
+                format!("{line} // edited\n")
+            } else {
+                format!("{line}\n")
+            }
+        })
+        .collect()
+}
+
+fn diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    group.throughput(Throughput::Bytes(BEFORE.len() as u64));
+
+    for (name, step) in [("sparse", 200), ("dense", 7)] {
+        let after = edit(step);
+
+        for algorithm in [Algorithm::Histogram, Algorithm::Myers] {
+            group.bench_function(format!("{name}/{algorithm:?}"), |b| {
+                b.iter(|| {
+                    let input = InternedInput::new(
+                        lines_with_terminator(black_box(BEFORE)),
+                        lines_with_terminator(black_box(after.as_str())),
+                    );
+
+                    gix::diff::blob::diff(
+                        algorithm,
+                        &input,
+                        UnifiedDiffBuilder::with_writer(&input, String::new(), Plain),
+                    )
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, diff);
+criterion_main!(benches);
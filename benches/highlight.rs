@@ -0,0 +1,40 @@
+//! Syntax highlighting of whole files, as done for the blob view and highlighted READMEs.
+
+use std::{hint::black_box, path::Path};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[path = "../src/ansi.rs"]
+#[allow(dead_code)]
+mod ansi;
+#[path = "../src/syntax_highlight.rs"]
+#[allow(dead_code)]
+mod syntax_highlight;
+
+use syntax_highlight::{format_file, prime_highlighters, FileIdentifier};
+
+/// Real files from this repository, so the inputs look like what's served.
+const INPUTS: [(&str, &str); 3] = [
+    ("git.rs", include_str!("../src/git.rs")),
+    ("base.html", include_str!("../templates/base.html")),
+    ("README.md", include_str!("../README.md")),
+];
+
+fn highlight(c: &mut Criterion) {
+    // loading the grammars is a one-off cost at startup, not part of highlighting a file
+    prime_highlighters();
+
+    let mut group = c.benchmark_group("highlight");
+
+    for (name, content) in INPUTS {
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| format_file(black_box(content), FileIdentifier::Path(Path::new(name))));
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, highlight);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+//! The per-commit work of rendering a page of the log: drawing its row of the commit graph and
+//! linkifying its summary.
+
+use std::{hint::black_box, str::FromStr};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[path = "../src/commit_graph.rs"]
+#[allow(dead_code)]
+mod commit_graph;
+#[path = "../src/linkify.rs"]
+#[allow(dead_code)]
+mod linkify;
+
+use commit_graph::CommitGraph;
+use linkify::{LinkRule, Linkifier};
+
+/// The number of commits shown on a page of the log.
+const PAGE_SIZE: usize = 100;
+
+fn hash(n: usize) -> [u8; 20] {
+    let mut hash = [0; 20];
+    hash[..8].copy_from_slice(&(n as u64).to_be_bytes());
+    hash
+}
+
+/// A history with a topic branch merged in every few commits, newest first, as the log lists
+/// it.
+fn history() -> Vec<([u8; 20], Vec<[u8; 20]>)> {
+    let mut commits = Vec::with_capacity(PAGE_SIZE);
+    let mut topic = 1_000_000;
+
+    for n in 0..PAGE_SIZE {
+        let mut parents = vec![hash(n + 1)];
+
+        if n % 5 == 0 {
+            topic += 1;
+            parents.push(hash(topic));
+            commits.push((hash(n), parents));
+            commits.push((hash(topic), vec![hash(n + 3)]));
+        } else {
+            commits.push((hash(n), parents));
+        }
+    }
+
+    commits
+}
+
+fn graph(c: &mut Criterion) {
+    let history = history();
+
+    let mut group = c.benchmark_group("log");
+    group.throughput(Throughput::Elements(history.len() as u64));
+
+    group.bench_function("graph", |b| {
+        b.iter(|| {
+            let mut graph = CommitGraph::default();
+
+            for (hash, parents) in &history {
+                black_box(graph.push(hash, parents));
+            }
+        });
+    });
+
+    let site_rules = [
+        LinkRule::from_str(r"#(\d+) https://github.com/w4/rgit/issues/$1").unwrap(),
+        LinkRule::from_str(r"CVE-(\d+-\d+) https://www.cve.org/CVERecord?id=CVE-$1").unwrap(),
+    ];
+    let linkifier = Linkifier::new("rgit.git", &site_rules, []);
+    let summaries: Vec<String> = (0..history.len())
+        .map(|n| format!("Fix crash when opening <empty> trees (#{n}, CVE-2024-{n})"))
+        .collect();
+
+    group.bench_function("summaries", |b| {
+        b.iter(|| {
+            for (summary, (hash, _)) in summaries.iter().zip(&history) {
+                black_box(linkifier.render_summary(summary, hash));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, graph);
+criterion_main!(benches);
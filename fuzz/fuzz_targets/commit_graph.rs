@@ -0,0 +1,20 @@
+//! Parent links are taken from the repository as-is, so the graph has to lay out any shape of
+//! history without panicking, including parents that never appear and cycles.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/commit_graph.rs"]
+mod commit_graph;
+
+fuzz_target!(|commits: Vec<(u8, Vec<u8>)>| {
+    // small ids so that commits frequently share parents
+    let id = |v: u8| [v % 16; 20];
+    let mut graph = commit_graph::CommitGraph::default();
+
+    for (hash, parents) in commits {
+        let parents: Vec<_> = parents.into_iter().take(8).map(id).collect();
+        let _ = graph.push(&id(hash), &parents);
+    }
+});
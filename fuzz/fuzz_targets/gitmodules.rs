@@ -0,0 +1,18 @@
+//! `.gitmodules` is read from the tree being viewed to link submodules to their upstream.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(file) =
+        gix_submodule::File::from_bytes(data, None::<std::path::PathBuf>, &Default::default())
+    else {
+        return;
+    };
+
+    for name in file.names() {
+        let _ = file.url(name);
+        let _ = file.path(name);
+    }
+});
@@ -0,0 +1,20 @@
+//! Searched files come from the repository and the needle from the user. The first line of the
+//! input is taken as the needle.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/grep.rs"]
+mod grep;
+
+fuzz_target!(|input: &str| {
+    let (needle, content) = input.split_once('\n').unwrap_or((input, ""));
+
+    // empty searches are rejected before they get this far
+    if needle.is_empty() {
+        return;
+    }
+
+    let _ = grep::search(String::new(), content, needle);
+});
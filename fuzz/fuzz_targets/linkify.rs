@@ -0,0 +1,18 @@
+//! Link rules can be configured by a repository's owner, so both the rule and the commit message
+//! it's applied to are untrusted. The first line of the input is taken as the rule.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/linkify.rs"]
+#[allow(dead_code)]
+mod linkify;
+
+fuzz_target!(|input: &str| {
+    let (rule, text) = input.split_once('\n').unwrap_or((input, ""));
+
+    let linkifier = linkify::Linkifier::new("fuzz.git", &[], [rule]);
+    let _ = linkifier.render(text);
+    let _ = linkifier.render_summary(text, &[0; 20]);
+});
@@ -0,0 +1,13 @@
+//! `.mailmap` files are read from the repository to resolve commit authors.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for entry in gix_mailmap::parse(data) {
+        let _ = entry;
+    }
+
+    let _ = gix_mailmap::Snapshot::from_bytes(data);
+});
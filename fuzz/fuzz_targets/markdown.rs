@@ -0,0 +1,17 @@
+//! READMEs and trusted descriptions are rendered from Markdown written by whoever owns the
+//! repository. READMEs are rendered without the syntax highlighting of code blocks, which would
+//! pull in every tree-sitter grammar.
+
+#![no_main]
+
+use comrak::ComrakPlugins;
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/markdown.rs"]
+#[allow(dead_code)]
+mod markdown;
+
+fuzz_target!(|input: &str| {
+    let _ = markdown::render(input, &ComrakPlugins::default());
+    let _ = markdown::render_trusted_description(input);
+});
@@ -0,0 +1,18 @@
+//! A `projects.list` picks the repositories served from a scan root, and must never be able to
+//! name one outside of it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/projects_list.rs"]
+#[allow(dead_code)]
+mod projects_list;
+
+fuzz_target!(|input: &str| {
+    let projects = projects_list::ProjectsList::parse(input);
+
+    for path in &projects.paths {
+        assert!(!path.is_absolute() && !path.starts_with(".."));
+    }
+});
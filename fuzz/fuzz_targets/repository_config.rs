@@ -0,0 +1,23 @@
+//! Per-repository settings (owner, link rules, snapshot formats...) are read from the
+//! repository's `config` and `cgitrc` files, both written by whoever owns the repository.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/repository_config.rs"]
+#[allow(dead_code)]
+mod repository_config;
+
+use repository_config::{CgitRc, RepositoryConfig};
+
+fuzz_target!(|input: &str| {
+    let config = RepositoryConfig::parse(input);
+    let _ = config.get("gitweb", "owner");
+    let _ = config.get_bool("rgit", "bundle");
+    for _ in config.get_all("rgit", "linkrule") {}
+
+    let cgitrc = CgitRc::parse(input);
+    let _ = cgitrc.get("desc");
+    let _ = cgitrc.get_bool("hide");
+});
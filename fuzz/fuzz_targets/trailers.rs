@@ -0,0 +1,20 @@
+//! Commit messages come straight from the repository, so splitting the trailers off of them has
+//! to cope with anything.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/trailers.rs"]
+#[allow(dead_code)]
+mod trailers;
+
+fuzz_target!(|body: &str| {
+    let (rest, trailers) = trailers::split_trailers(body);
+    assert!(body.starts_with(rest));
+
+    for trailer in &trailers {
+        let _ = trailer.person();
+        let _ = trailer.fixes_hash();
+    }
+});
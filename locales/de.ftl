@@ -0,0 +1,269 @@
+site-description = Git-Repository-Browser
+homepage = Homepage
+pagination-next = weiter
+breadcrumbs-path = Pfad:
+
+## Relative and absolute times
+
+time-now = gerade eben
+time-seconds-ago = { $count ->
+    [one] vor { $count } Sekunde
+   *[other] vor { $count } Sekunden
+}
+time-minutes-ago = { $count ->
+    [one] vor { $count } Minute
+   *[other] vor { $count } Minuten
+}
+time-hours-ago = { $count ->
+    [one] vor { $count } Stunde
+   *[other] vor { $count } Stunden
+}
+time-days-ago = { $count ->
+    [one] vor { $count } Tag
+   *[other] vor { $count } Tagen
+}
+time-weeks-ago = { $count ->
+    [one] vor { $count } Woche
+   *[other] vor { $count } Wochen
+}
+time-months-ago = { $count ->
+    [one] vor { $count } Monat
+   *[other] vor { $count } Monaten
+}
+time-years-ago = { $count ->
+    [one] vor { $count } Jahr
+   *[other] vor { $count } Jahren
+}
+duration-milliseconds = { $count ->
+    [one] { $count } Millisekunde
+   *[other] { $count } Millisekunden
+}
+duration-seconds = { $count ->
+    [one] { $count } Sekunde
+   *[other] { $count } Sekunden
+}
+duration-minutes = { $count ->
+    [one] { $count } Minute
+   *[other] { $count } Minuten
+}
+duration-hours = { $count ->
+    [one] { $count } Stunde
+   *[other] { $count } Stunden
+}
+duration-days = { $count ->
+    [one] { $count } Tag
+   *[other] { $count } Tage
+}
+datetime-format = [day].[month].[year] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]
+
+## Navigation
+
+nav-index = Übersicht
+nav-atom = Atom
+nav-about = Info
+nav-summary = Zusammenfassung
+nav-refs = Refs
+nav-log = Verlauf
+nav-tree = Dateien
+nav-commit = Commit
+nav-diff = Diff
+nav-grep = Suche
+branch-label = Branch
+branch-switch = wechseln
+branch-switch-placeholder = Ref wechseln
+
+footer-generated-by = erzeugt von
+footer-at = am
+footer-in = in
+
+error-back-to-index = Zurück zur Repository-Übersicht
+
+## Feeds
+
+feed-new-repositories = Neue Repositorys
+feed-commits = Commits in { $repository }
+feed-commits-changing = Commits in { $repository }, die { $path } ändern
+feed-filtered-commits = Gefilterte Commits in { $repository }
+feed-commits-by = Commits von { $name }
+feed-tags = Tags in { $repository }
+feed-rewrites = Umgeschriebene Branches in { $repository }
+feed-rewrite-title = { $reference } wurde umgeschrieben
+
+## Table columns
+
+column-age = Alter
+column-author = Autor
+column-branch = Branch
+column-change = Änderung
+column-commit-message = Commit-Nachricht
+column-commits = Commits
+column-description = Beschreibung
+column-download = Download
+column-idle = Inaktiv
+column-latest-commit = Letzter Commit
+column-mode = Modus
+column-name = Name
+column-object = Objekt
+column-owner = Besitzer
+column-reference = Referenz
+column-repository = Repository
+column-size = Größe
+column-tag = Tag
+column-type = Typ
+
+## Repository index
+
+index-by-path = nach Pfad
+index-by-activity = nach Aktivität
+index-recently-active = kürzlich aktiv
+index-recently-added = kürzlich hinzugefügt
+index-feed = Feed
+index-archived = archiviert
+index-indexing = wird indiziert…
+index-unnamed-repository = Unbenanntes Repository; bearbeite die Datei 'description', um es zu benennen.
+
+## Repository summary
+
+summary-archived = Dieses Repository wurde archiviert und wird nicht mehr gepflegt.
+summary-mirror-of = Spiegel von
+summary-last-fetched = zuletzt abgerufen
+summary-fetch-failed = fehlgeschlagen
+summary-last-succeeded = zuletzt erfolgreich
+summary-behind-upstream = { $count ->
+    [one] { $count } Commit hinter Upstream
+   *[other] { $count } Commits hinter Upstream
+}
+summary-up-to-date = auf dem Stand von Upstream
+summary-on-disk = auf der Festplatte
+summary-objects = { $count ->
+    [one] { $count } Objekt
+   *[other] { $count } Objekte
+}
+summary-clone = klonen
+summary-bundle = Klon-Bundle
+summary-indexing = Dieses Repository wird noch indiziert, seine Commits erscheinen hier in Kürze.
+
+about-no-readme = Keine README im HEAD des Repositorys.
+
+## Refs
+
+refs-branch-commits = { $count ->
+    [one] { $count } Commit auf diesem Branch
+   *[other] { $count } Commits auf diesem Branch
+}
+refs-ahead = { $count ->
+    [one] { $count } Commit voraus
+   *[other] { $count } Commits voraus
+}
+refs-behind = { $count ->
+    [one] { $count } Commit hinter dem Standard-Branch
+   *[other] { $count } Commits hinter dem Standard-Branch
+}
+refs-mbox-title = Die Commits, die dem Standard-Branch voraus sind, als Patch-E-Mails herunterladen
+refs-reflog = [Referenzverlauf]
+refs-backports = [Backports]
+reflog-created = erstellt
+reflog-fast-forward = vorgespult
+reflog-forced = erzwungen aktualisiert
+reflog-deleted = gelöscht
+reflog-empty = Es wurden noch keine Änderungen an Referenzen beobachtet.
+
+## Log
+
+log-commits-changing = Commits, die Folgendes ändern:
+log-order = Reihenfolge:
+log-order-indexed = indiziert
+log-order-date = Datum
+log-order-topo = topologisch
+log-merges = Merges:
+log-merges-shown = angezeigt
+log-merges-hidden = ausgeblendet
+log-filter-author = E-Mail des Autors
+log-filter-committer = E-Mail des Committers
+log-filter-since = seit
+log-filter-until = bis
+log-filter-submit = filtern
+log-filter-clear = zurücksetzen
+log-live = live
+live-help = Neue Commits erscheinen hier, sobald sie indiziert wurden.
+live-full-log = vollständiges Log
+
+## Commits and tags
+
+commit-author = Autor
+commit-committer = Committer
+commit-commit = Commit
+commit-tree = Baum
+commit-parent = Vorgänger
+commit-download = Download
+commit-patch = Patch
+commit-files-changed = { $count ->
+    [one] { $count } Datei geändert
+   *[other] { $count } Dateien geändert
+}
+commit-view-upstream = im Upstream ansehen
+commit-reverts = macht rückgängig
+commit-reverted-by = rückgängig gemacht durch
+commit-fixed-by = behoben durch
+commit-backported-by = zurückportiert durch
+commit-diff-against = Diff
+commit-diff-shown = Diff angezeigt
+diff-heading = Diff
+diff-heading-parent = Diff gegen Vorgänger { $parent }
+commit-ambiguous = { $spec } passt auf mehr als ein Objekt
+commit-ambiguous-help = Mit mehr Stellen des Hashes lässt sich eines davon auswählen.
+
+tag-name = Tag-Name
+tag-date = Tag-Datum
+tag-tagged-by = getaggt von
+tag-signature = Signatur
+tag-signed = signiert mit { $kind } (nicht verifiziert)
+tag-unsigned = nicht signiert
+tag-type = Tag-Typ
+tag-lightweight = leichtgewichtig
+tag-tagged-commit = getaggter Commit
+tag-commit-message = Commit-Nachricht
+tag-commit-author = Commit-Autor
+tag-tagged-object = getaggtes Objekt
+
+## Files
+
+file-rendered = gerendert
+file-source = Quelltext
+file-plain = roh
+file-history = Verlauf
+file-download = herunterladen
+file-download-named = { $name } herunterladen
+file-lfs = { $count ->
+    [one] mit Git LFS gespeichert, { $count } Byte
+   *[other] mit Git LFS gespeichert, { $count } Bytes
+}
+file-binary = { $count ->
+    [one] Binärdatei, { $count } Byte
+   *[other] Binärdatei, { $count } Bytes
+}
+tree-finder-placeholder = Zu Datei springen (t)
+
+snapshot-preparing = Erstelle
+snapshot-written = { $count ->
+    [one] bisher { $count } Byte geschrieben. Der Download beginnt, sobald es fertig ist.
+   *[other] bisher { $count } Bytes geschrieben. Der Download beginnt, sobald es fertig ist.
+}
+
+## Search
+
+grep-placeholder = Dateiinhalte durchsuchen
+grep-submit = suchen
+grep-no-results = Keine Datei enthält
+grep-file-truncated = weitere Treffer in dieser Datei werden nicht angezeigt
+grep-truncated = Nur die ersten { $count } passenden Dateien werden angezeigt.
+grep-budget-exceeded = { $count ->
+    [one] Die Suche wurde vorzeitig beendet, es wurde nur { $count } passende Datei gefunden.
+   *[other] Die Suche wurde vorzeitig beendet, es wurden nur { $count } passende Dateien gefunden.
+}
+
+## Timezone picker
+
+timezone-label = Zeiten in
+timezone-placeholder = wie erfasst
+timezone-submit = setzen
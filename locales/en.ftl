@@ -0,0 +1,273 @@
+# Strings shown throughout the web interface. Every other catalog falls back to this one for
+# anything it's missing, so new strings only have to be added here to start with.
+
+site-description = Git repository browser
+homepage = homepage
+pagination-next = next
+breadcrumbs-path = path:
+
+## Relative and absolute times
+
+time-now = just now
+time-seconds-ago = { $count ->
+    [one] { $count } second ago
+   *[other] { $count } seconds ago
+}
+time-minutes-ago = { $count ->
+    [one] { $count } minute ago
+   *[other] { $count } minutes ago
+}
+time-hours-ago = { $count ->
+    [one] { $count } hour ago
+   *[other] { $count } hours ago
+}
+time-days-ago = { $count ->
+    [one] { $count } day ago
+   *[other] { $count } days ago
+}
+time-weeks-ago = { $count ->
+    [one] { $count } week ago
+   *[other] { $count } weeks ago
+}
+time-months-ago = { $count ->
+    [one] { $count } month ago
+   *[other] { $count } months ago
+}
+time-years-ago = { $count ->
+    [one] { $count } year ago
+   *[other] { $count } years ago
+}
+duration-milliseconds = { $count ->
+    [one] { $count } millisecond
+   *[other] { $count } milliseconds
+}
+duration-seconds = { $count ->
+    [one] { $count } second
+   *[other] { $count } seconds
+}
+duration-minutes = { $count ->
+    [one] { $count } minute
+   *[other] { $count } minutes
+}
+duration-hours = { $count ->
+    [one] { $count } hour
+   *[other] { $count } hours
+}
+duration-days = { $count ->
+    [one] { $count } day
+   *[other] { $count } days
+}
+# A format description as understood by the `time` crate
+datetime-format = [year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]
+
+## Navigation
+
+nav-index = index
+nav-atom = atom
+nav-about = about
+nav-summary = summary
+nav-refs = refs
+nav-log = log
+nav-tree = tree
+nav-commit = commit
+nav-diff = diff
+nav-grep = grep
+branch-label = branch
+branch-switch = switch
+branch-switch-placeholder = switch ref
+
+footer-generated-by = generated by
+footer-at = at
+footer-in = in
+
+error-back-to-index = Back to the repository index
+
+## Feeds
+
+feed-new-repositories = New repositories
+feed-commits = { $repository } commits
+feed-commits-changing = { $repository } commits changing { $path }
+feed-filtered-commits = { $repository } filtered commits
+feed-commits-by = Commits by { $name }
+feed-tags = { $repository } tags
+feed-rewrites = { $repository } rewritten branches
+feed-rewrite-title = { $reference } was rewritten
+
+## Table columns
+
+column-age = Age
+column-author = Author
+column-branch = Branch
+column-change = Change
+column-commit-message = Commit message
+column-commits = Commits
+column-description = Description
+column-download = Download
+column-idle = Idle
+column-latest-commit = Latest commit
+column-mode = Mode
+column-name = Name
+column-object = Object
+column-owner = Owner
+column-reference = Reference
+column-repository = Repository
+column-size = Size
+column-tag = Tag
+column-type = Type
+
+## Repository index
+
+index-by-path = by path
+index-by-activity = by activity
+index-recently-active = recently active
+index-recently-added = recently added
+index-feed = feed
+index-archived = archived
+index-indexing = indexing…
+index-unnamed-repository = Unnamed repository; edit this file 'description' to name the repository.
+
+## Repository summary
+
+summary-archived = This repository has been archived, it's no longer maintained.
+summary-mirror-of = mirror of
+summary-last-fetched = last fetched
+summary-fetch-failed = failed
+summary-last-succeeded = last succeeded
+summary-behind-upstream = { $count ->
+    [one] { $count } commit behind upstream
+   *[other] { $count } commits behind upstream
+}
+summary-up-to-date = up to date with upstream
+summary-on-disk = on disk
+summary-objects = { $count ->
+    [one] { $count } object
+   *[other] { $count } objects
+}
+summary-clone = clone
+summary-bundle = clone bundle
+summary-indexing = This repository is still being indexed, its commits will show up here shortly.
+
+about-no-readme = No README in repository HEAD.
+
+## Refs
+
+refs-branch-commits = { $count ->
+    [one] { $count } commit on this branch
+   *[other] { $count } commits on this branch
+}
+refs-ahead = { $count ->
+    [one] { $count } commit ahead
+   *[other] { $count } commits ahead
+}
+refs-behind = { $count ->
+    [one] { $count } commit behind the default branch
+   *[other] { $count } commits behind the default branch
+}
+refs-mbox-title = Download the commits ahead of the default branch as patch emails
+refs-reflog = [reference history]
+refs-backports = [backports]
+reflog-created = created
+reflog-fast-forward = fast-forwarded
+reflog-forced = force-updated
+reflog-deleted = deleted
+reflog-empty = No changes to references have been seen yet.
+
+## Log
+
+log-commits-changing = commits changing
+log-order = order:
+log-order-indexed = indexed
+log-order-date = date
+log-order-topo = topo
+log-merges = merges:
+log-merges-shown = shown
+log-merges-hidden = hidden
+log-filter-author = author email
+log-filter-committer = committer email
+log-filter-since = since
+log-filter-until = until
+log-filter-submit = filter
+log-filter-clear = clear
+log-live = live
+live-help = New commits appear here as they're indexed.
+live-full-log = full log
+
+## Commits and tags
+
+commit-author = author
+commit-committer = committer
+commit-commit = commit
+commit-tree = tree
+commit-parent = parent
+commit-download = download
+commit-patch = patch
+commit-files-changed = { $count ->
+    [one] { $count } file changed
+   *[other] { $count } files changed
+}
+commit-view-upstream = view on upstream
+commit-reverts = reverts
+commit-reverted-by = reverted by
+commit-fixed-by = fixed by
+commit-backported-by = backported by
+commit-diff-against = diff
+commit-diff-shown = diff shown
+diff-heading = Diff
+diff-heading-parent = Diff against parent { $parent }
+commit-ambiguous = { $spec } matches more than one object
+commit-ambiguous-help = Give more of the hash to pick out one of them.
+
+tag-name = tag name
+tag-date = tag date
+tag-tagged-by = tagged by
+tag-signature = signature
+tag-signed = signed with { $kind } (unverified)
+tag-unsigned = unsigned
+tag-type = tag type
+tag-lightweight = lightweight
+tag-tagged-commit = tagged commit
+tag-commit-message = commit message
+tag-commit-author = commit author
+tag-tagged-object = tagged object
+
+## Files
+
+file-rendered = rendered
+file-source = source
+file-plain = plain
+file-history = history
+file-download = download
+file-download-named = download { $name }
+file-lfs = { $count ->
+    [one] stored with Git LFS, { $count } byte
+   *[other] stored with Git LFS, { $count } bytes
+}
+file-binary = { $count ->
+    [one] binary file, { $count } byte
+   *[other] binary file, { $count } bytes
+}
+tree-finder-placeholder = Go to file (t)
+
+snapshot-preparing = Preparing
+snapshot-written = { $count ->
+    [one] { $count } byte written so far. The download will start once it's ready.
+   *[other] { $count } bytes written so far. The download will start once it's ready.
+}
+
+## Search
+
+grep-placeholder = Search file contents
+grep-submit = search
+grep-no-results = No files contain
+grep-file-truncated = further matches in this file not shown
+grep-truncated = Only the first { $count } matching files are shown.
+grep-budget-exceeded = { $count ->
+    [one] The search was stopped early, only { $count } matching file was found.
+   *[other] The search was stopped early, only { $count } matching files were found.
+}
+
+## Timezone picker
+
+timezone-label = times in
+timezone-placeholder = as recorded
+timezone-submit = set
@@ -0,0 +1,99 @@
+#!/usr/bin/env bash
+#
+# Builds the fixture repositories used by the integration tests into the given scan path. Each
+# fixture is built in a scratch working copy and then cloned bare into the scan path, which is
+# what rgit expects to find.
+
+set -euo pipefail
+
+if [ $# -ne 1 ]; then
+	echo "usage: $0 <scan-path>" >&2
+	exit 1
+fi
+
+scan_path="$(mkdir -p "$1" && cd "$1" && pwd)"
+work="$(mktemp -d)"
+trap 'rm -rf "$work"' EXIT
+
+export GIT_CONFIG_NOSYSTEM=1
+export GIT_CONFIG_GLOBAL=/dev/null
+export GIT_AUTHOR_NAME="Fixture Author"
+export GIT_AUTHOR_EMAIL="author@example.com"
+export GIT_COMMITTER_NAME="Fixture Committer"
+export GIT_COMMITTER_EMAIL="committer@example.com"
+
+# commits are timestamped a day apart from a fixed point, so output is stable between runs
+timestamp=1700000000
+
+commit() {
+	timestamp=$((timestamp + 86400))
+	GIT_AUTHOR_DATE="@$timestamp +0000" GIT_COMMITTER_DATE="@$timestamp +0000" \
+		git commit --quiet --allow-empty "$@"
+}
+
+publish() {
+	local name="$1"
+	git clone --quiet --bare "$work/$name" "$scan_path/$name.git"
+	git -C "$scan_path/$name.git" pack-refs --all
+	echo "$2" >"$scan_path/$name.git/description"
+}
+
+# a small library, used as a submodule of the main fixture
+git init --quiet -b main "$work/library"
+(
+	cd "$work/library"
+	echo "pub fn add(a: i32, b: i32) -> i32 { a + b }" >lib.rs
+	git add lib.rs
+	commit -m "Add library"
+)
+publish library "A library vendored by the kitchen sink"
+
+# exercises branches, tags, renames, merges, binary files and submodules
+git init --quiet -b main "$work/kitchen-sink"
+(
+	cd "$work/kitchen-sink"
+
+	printf '# Kitchen sink\n\nA fixture repository for rgit.\n' >README.md
+	printf 'fn main() {\n    println!("hello");\n}\n' >main.rs
+	git add README.md main.rs
+	commit -m "Initial commit"
+
+	git tag lightweight-tag
+
+	printf 'fn main() {\n    println!("hello, world");\n}\n' >main.rs
+	git add main.rs
+	commit -m "Greet the world" -m "Signed-off-by: Fixture Author <author@example.com>"
+
+	mkdir src
+	git mv main.rs src/main.rs
+	commit -m "Move main.rs into src"
+
+	{ printf '\x89PNG\r\n\x1a\n'; head -c 2048 /dev/zero; } >binary.bin
+	git add binary.bin
+	commit -m "Add a binary file"
+
+	GIT_COMMITTER_DATE="@$timestamp +0000" git tag -a v1.0.0 -m "Version 1.0.0"
+
+	git checkout --quiet -b feature
+	printf 'pub fn greeting() -> &'"'"'static str {\n    "hello"\n}\n' >src/greeting.rs
+	git add src/greeting.rs
+	commit -m "Add a greeting module"
+
+	git checkout --quiet main
+	printf '\nSee src/main.rs.\n' >>README.md
+	git add README.md
+	commit -m "Point the README at the source"
+
+	timestamp=$((timestamp + 86400))
+	GIT_AUTHOR_DATE="@$timestamp +0000" GIT_COMMITTER_DATE="@$timestamp +0000" \
+		git merge --quiet --no-ff feature -m "Merge branch 'feature'"
+
+	git -c protocol.file.allow=always submodule --quiet add "$scan_path/library.git" vendor/library
+	commit -m "Vendor the library as a submodule"
+)
+publish kitchen-sink "Every git feature rgit renders"
+
+# a repository with nothing in it, which should still be listed without errors
+git init --quiet --bare "$scan_path/empty.git"
+touch "$scan_path/empty.git/packed-refs"
+echo "An empty repository" >"$scan_path/empty.git/description"
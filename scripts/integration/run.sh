@@ -0,0 +1,122 @@
+#!/usr/bin/env bash
+#
+# End-to-end tests: builds the fixture repositories, indexes them into a fresh database and
+# checks the pages rendered for them over HTTP.
+#
+# usage: scripts/integration/run.sh [path-to-rgit-binary]
+
+set -euo pipefail
+
+root="$(cd "$(dirname "$0")/../.." && pwd)"
+rgit="${1:-}"
+
+if [ -z "$rgit" ]; then
+	cargo build --quiet --manifest-path "$root/Cargo.toml"
+	rgit="$root/target/debug/rgit"
+fi
+
+tmp="$(mktemp -d)"
+port="${RGIT_TEST_PORT:-3939}"
+base="http://127.0.0.1:$port"
+
+cleanup() {
+	if [ -n "${server:-}" ]; then
+		kill "$server" 2>/dev/null || true
+		wait "$server" 2>/dev/null || true
+	fi
+
+	rm -rf "$tmp"
+}
+trap cleanup EXIT
+
+"$root/scripts/integration/build-fixtures.sh" "$tmp/repos"
+
+RUST_LOG="${RUST_LOG:-warn}" "$rgit" --db-store "$tmp/db" --refresh-interval never \
+	"127.0.0.1:$port" "$tmp/repos" >"$tmp/rgit.log" 2>&1 &
+server=$!
+
+# the server starts accepting requests before the first index run has finished, so wait until
+# the newest commit of the main fixture shows up
+for _ in $(seq 1 300); do
+	if curl -sf "$base/api/v1/log/kitchen-sink.git" 2>/dev/null | grep -q "Vendor the library"; then
+		break
+	fi
+
+	if ! kill -0 "$server" 2>/dev/null; then
+		cat "$tmp/rgit.log" >&2
+		echo "rgit exited before indexing finished" >&2
+		exit 1
+	fi
+
+	sleep 0.1
+done
+
+failures=0
+
+# expect <status> <path> [pattern...] - fetches the path, checking the status code and that the
+# body contains each of the patterns
+expect() {
+	local status="$1" path="$2"
+	shift 2
+
+	local actual
+	actual="$(curl -s -o "$tmp/body" -w '%{http_code}' "$base$path")"
+
+	if [ "$actual" != "$status" ]; then
+		echo "FAIL $path: expected status $status, got $actual" >&2
+		failures=$((failures + 1))
+		return
+	fi
+
+	for pattern in "$@"; do
+		if ! grep -qF -- "$pattern" "$tmp/body"; then
+			echo "FAIL $path: body doesn't contain '$pattern'" >&2
+			failures=$((failures + 1))
+			return
+		fi
+	done
+
+	echo "ok   $path"
+}
+
+repo=/kitchen-sink.git
+merge="$(git -C "$tmp/repos/kitchen-sink.git" rev-parse HEAD~1)"
+rename="$(git -C "$tmp/repos/kitchen-sink.git" rev-parse v1.0.0~1)"
+
+expect 200 / "kitchen-sink.git" "library.git" "Every git feature rgit renders"
+expect 200 "$repo" "Vendor the library as a submodule" "v1.0.0" "feature"
+expect 200 "$repo/about" "A fixture repository for rgit."
+expect 200 "$repo/log" "Merge branch" "Add a greeting module" "Initial commit"
+expect 200 "$repo/log?h=feature" "Add a greeting module"
+expect 200 "$repo/log?author=author%40example.com" "Initial commit"
+expect 400 "$repo/log?since=yesterday"
+expect 200 "$repo/refs" "main" "feature" "v1.0.0" "lightweight-tag"
+expect 200 "$repo/tags" "v1.0.0" "lightweight-tag"
+expect 200 "$repo/tag/v1.0.0" "Version 1.0.0"
+expect 200 "$repo/tag/lightweight-tag"
+expect 404 "$repo/tag/no-such-tag"
+expect 200 "$repo/tree" "src" "binary.bin" "vendor"
+expect 200 "$repo/tree/src/main.rs" "hello, world"
+expect 200 "$repo/tree/binary.bin"
+expect 200 "$repo/grep?q=hello" "src/main.rs" "<mark>hello</mark>"
+expect 200 "$repo/grep?q=hello&h=feature" "src/greeting.rs"
+expect 404 "$repo/tree/no/such/path"
+expect 200 "$repo/tree-index/main" "\"src/main.rs\"" "\".gitmodules\""
+expect 200 "$repo/tree-index/feature" "\"src/greeting.rs\""
+expect 200 "$repo/commit?id=$merge" "Merge branch"
+expect 200 "$repo/commit?id=$rename" "src/main.rs"
+expect 404 "$repo/commit?id=0000000000000000000000000000000000000000"
+expect 200 "$repo/diff?id=$merge"
+expect 200 "$repo/patch?id=$rename" "Move main.rs into src"
+expect 200 "$repo/atom" "<feed"
+expect 200 "$repo/snapshot?h=v1.0.0"
+expect 200 /library.git/tree "lib.rs"
+expect 404 /no-such-repository.git
+expect 200 "/api/v1/commit$repo" "Vendor the library as a submodule"
+expect 200 /api/v1/activity "kitchen-sink.git"
+
+if [ "$failures" -ne 0 ]; then
+	echo "$failures check(s) failed, server log follows" >&2
+	cat "$tmp/rgit.log" >&2
+	exit 1
+fi
@@ -0,0 +1,67 @@
+//! `--activity-hints`, tunes how often each repository is indexed and how long its pages may be
+//! cached for by how often it's been committed to, so instances serving a mix of busy and
+//! long-dormant repositories don't spend as much on the dormant ones as the busy ones.
+
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::database::schema::repository::{ArchivedRepository, RECENT_COMMITS};
+
+/// A repository is hot when its newest few commits were all made within this long.
+const HOT_WITHIN: time::Duration = time::Duration::days(7);
+/// A repository is dormant when it hasn't been committed to for this long.
+const DORMANT_AFTER: time::Duration = time::Duration::days(90);
+
+/// Whether `--activity-hints` was given.
+#[derive(Debug, Copy, Clone)]
+pub struct ActivityHints(pub bool);
+
+/// How busy a repository is, judged from the commits on its default branch as of the last index
+/// run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Activity {
+    /// Committed to several times in the last week
+    Hot,
+    Active,
+    /// Not committed to in months, or never
+    Dormant,
+}
+
+impl Activity {
+    pub fn of(repository: &ArchivedRepository, now: OffsetDateTime) -> Self {
+        let recent = repository.recent_commits.as_slice();
+
+        let Some(newest) = recent.first() else {
+            return Self::Dormant;
+        };
+
+        if now - newest.time() > DORMANT_AFTER {
+            Self::Dormant
+        } else if recent.len() == usize::try_from(RECENT_COMMITS).unwrap_or(usize::MAX)
+            && recent.last().is_some_and(|v| now - v.time() <= HOT_WITHIN)
+        {
+            Self::Hot
+        } else {
+            Self::Active
+        }
+    }
+
+    /// How often the repository should be indexed, `None` to leave it to the global schedule.
+    pub fn refresh_interval(self) -> Option<Duration> {
+        match self {
+            Self::Hot => Some(Duration::from_secs(60)),
+            Self::Active => None,
+            Self::Dormant => Some(Duration::from_secs(6 * 60 * 60)),
+        }
+    }
+
+    /// How long browsers and proxies may cache the repository's pages for before checking back.
+    pub fn max_age(self) -> Duration {
+        match self {
+            Self::Hot => Duration::from_secs(30),
+            Self::Active => Duration::from_secs(5 * 60),
+            Self::Dormant => Duration::from_secs(60 * 60),
+        }
+    }
+}
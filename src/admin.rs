@@ -0,0 +1,317 @@
+//! `rgit admin <command>`, tends to the repositories in the scan path rather than to the index.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
+
+use crate::{
+    database::{
+        indexer::{find_upstream_url, DEFAULT_DESCRIPTION},
+        schema::repository::Repository,
+    },
+    forge::Forge,
+    maintenance,
+    markdown::render_trusted_description,
+    repository_config::{CgitRc, RepositoryConfig},
+    scan_path::{DiscoveryConfig, ScanPaths, ScanRoot},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Writes the gitweb `description` file and `gitweb.owner` of every repository missing them,
+    /// taking them from a mapping file, the repository's `cgitrc` or its upstream forge
+    Backfill {
+        /// The paths in which your Git repositories reside, as given to the server
+        #[clap(value_name = "SCAN_PATH", required = true)]
+        scan_paths: Vec<ScanRoot>,
+        /// A CSV (`path,description,owner`) or TOML (a table per repository path, with
+        /// `description` and `owner` keys) file giving the metadata of each repository, picked
+        /// by the file's extension
+        #[clap(long)]
+        mapping: Option<PathBuf>,
+        /// Fetches metadata the mapping and `cgitrc` don't give from the upstream forge of
+        /// mirrored repositories
+        #[clap(long)]
+        forge: bool,
+        /// Path to the `RocksDB` database, as given to `--db-store` when serving, to update the
+        /// indexed metadata along with the repositories. The server must be stopped first,
+        /// otherwise it'll pick the changes up on its next index run
+        #[clap(short, long)]
+        db_store: Option<PathBuf>,
+        /// Prints what would be written without writing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+/// The metadata given for a repository by the mapping file.
+#[derive(Deserialize, Debug, Default)]
+struct Metadata {
+    description: Option<String>,
+    owner: Option<String>,
+}
+
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    match &args.command {
+        Command::Backfill {
+            scan_paths,
+            mapping,
+            forge,
+            db_store,
+            dry_run,
+        } => backfill(
+            &ScanPaths::new(scan_paths.clone(), DiscoveryConfig::default()),
+            &mapping
+                .as_deref()
+                .map(load_mapping)
+                .transpose()?
+                .unwrap_or_default(),
+            *forge,
+            db_store.as_deref(),
+            *dry_run,
+        ),
+    }
+}
+
+fn backfill(
+    scan_paths: &ScanPaths,
+    mapping: &HashMap<String, Metadata>,
+    forge: bool,
+    db_store: Option<&Path>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let db = db_store
+        .map(|db_store| maintenance::open(db_store, false))
+        .transpose()?;
+    let mut written = 0_usize;
+
+    for (relative, git_dir) in scan_paths.discover() {
+        let Some(path) = relative.to_str() else {
+            continue;
+        };
+
+        let config = RepositoryConfig::load(&git_dir);
+        let cgitrc = CgitRc::load(&git_dir);
+        let given = mapping.get(path);
+
+        let description_missing = !std::fs::read_to_string(git_dir.join("description"))
+            .is_ok_and(|v| !v.trim().is_empty() && !v.starts_with(DEFAULT_DESCRIPTION));
+        let owner_missing = config.get("gitweb", "owner").is_none();
+
+        if !description_missing && !owner_missing {
+            continue;
+        }
+
+        // the forge is only asked once the other sources have come up short
+        let mut upstream = None;
+        let mut from_forge = || {
+            if !forge {
+                return None;
+            }
+
+            upstream
+                .get_or_insert_with(|| {
+                    let url = find_upstream_url(&config)?;
+
+                    match Forge::from_url(url)?.fetch() {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            println!("{path}: failed to fetch metadata from {url}: {e}");
+                            None
+                        }
+                    }
+                })
+                .as_ref()
+                .map(|v| (v.description.clone(), v.owner.clone()))
+        };
+
+        let description = description_missing
+            .then(|| {
+                given
+                    .and_then(|v| v.description.clone())
+                    .or_else(|| cgitrc.get("desc").map(ToString::to_string))
+                    .or_else(|| from_forge()?.0)
+            })
+            .flatten()
+            .filter(|v| !v.trim().is_empty());
+
+        let owner = owner_missing
+            .then(|| {
+                given
+                    .and_then(|v| v.owner.clone())
+                    .or_else(|| cgitrc.get("owner").map(ToString::to_string))
+                    .or_else(|| from_forge()?.1)
+            })
+            .flatten()
+            .filter(|v| !v.trim().is_empty());
+
+        if let Some(description) = &description {
+            println!("{path}: description = {description}");
+
+            if !dry_run {
+                std::fs::write(git_dir.join("description"), description.trim())
+                    .with_context(|| format!("Failed to write description of {path}"))?;
+            }
+        }
+
+        if let Some(owner) = &owner {
+            println!("{path}: gitweb.owner = {owner}");
+
+            if !dry_run {
+                set_owner(&git_dir, owner.trim())
+                    .with_context(|| format!("Failed to write owner of {path}"))?;
+            }
+        }
+
+        if description.is_none() && owner.is_none() {
+            continue;
+        }
+
+        written += 1;
+
+        if let (Some(db), false) = (&db, dry_run) {
+            update_index(db, path, &config, description.as_deref(), owner.as_deref())?;
+        }
+    }
+
+    if dry_run {
+        println!("Would backfill {written} repositories");
+    } else {
+        println!("Backfilled {written} repositories");
+    }
+
+    Ok(())
+}
+
+/// Writes `gitweb.owner` with git itself, so the rest of the config is left as it was.
+fn set_owner(git_dir: &Path, owner: &str) -> anyhow::Result<()> {
+    let output = std::process::Command::new("git")
+        .arg("config")
+        .arg("--file")
+        .arg(git_dir.join("config"))
+        .args(["gitweb.owner", owner])
+        .output()
+        .context("Failed to run git config")?;
+
+    ensure!(
+        output.status.success(),
+        "git config exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(())
+}
+
+/// Updates the indexed metadata of a repository to match what was written, repositories that
+/// haven't been indexed yet will pick it up when they are.
+fn update_index(
+    db: &rocksdb::DB,
+    path: &str,
+    config: &RepositoryConfig,
+    description: Option<&str>,
+    owner: Option<&str>,
+) -> anyhow::Result<()> {
+    let Some(repository) = Repository::open(db, path)? else {
+        return Ok(());
+    };
+
+    let mut repository = repository.get().deserialize()?;
+
+    if let Some(description) = description {
+        let description = description.trim();
+
+        repository.description = Some(description.to_string());
+        repository.description_html = config
+            .get_bool("rgit", "trusteddescription")
+            .then(|| render_trusted_description(description));
+    }
+
+    if let Some(owner) = owner {
+        repository.owner = Some(owner.trim().to_string());
+    }
+
+    repository.insert(db, path)
+}
+
+/// Reads the mapping file, as CSV or TOML depending on its extension.
+fn load_mapping(path: &Path) -> anyhow::Result<HashMap<String, Metadata>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read mapping from {}", path.display()))?;
+
+    match path.extension().and_then(|v| v.to_str()) {
+        Some("toml") => toml::from_str(&content).context("Failed to parse mapping"),
+        Some("csv") => parse_csv(&content),
+        _ => bail!("Mapping must be a .csv or .toml file"),
+    }
+}
+
+/// Parses a CSV mapping of `path,description,owner`, with an optional header line. Fields
+/// containing commas can be quoted, with quotes inside them doubled up.
+fn parse_csv(content: &str) -> anyhow::Result<HashMap<String, Metadata>> {
+    let mut mapping = HashMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || (i == 0 && line.trim_start().starts_with("path,")) {
+            continue;
+        }
+
+        let fields =
+            split_csv_line(line).with_context(|| format!("Invalid mapping on line {}", i + 1))?;
+
+        let [path, description, owner] = <[String; 3]>::try_from(fields).map_err(|fields| {
+            anyhow::anyhow!(
+                "Expected 3 fields on line {} of mapping, got {}",
+                i + 1,
+                fields.len()
+            )
+        })?;
+
+        let non_empty = |v: String| Some(v).filter(|v| !v.trim().is_empty());
+
+        mapping.insert(
+            path.trim().trim_matches('/').to_string(),
+            Metadata {
+                description: non_empty(description),
+                owner: non_empty(owner),
+            },
+        );
+    }
+
+    Ok(mapping)
+}
+
+fn split_csv_line(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    ensure!(!quoted, "unterminated quoted field");
+    fields.push(field);
+
+    Ok(fields)
+}
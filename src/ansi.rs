@@ -0,0 +1,278 @@
+//! Renders text containing ANSI escape sequences, such as build logs and test output checked into
+//! repositories, as HTML. Colours and text attributes set with SGR sequences become styled spans,
+//! and every other escape sequence is dropped rather than shown as garbage.
+
+use std::fmt::Write;
+
+/// Whether `text` has escape sequences in it that would render as garbage if shown as-is.
+pub fn contains_escapes(text: &str) -> bool {
+    text.contains("\x1b[") || text.contains("\x1b]")
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Colour {
+    #[default]
+    Default,
+    /// One of the 16 colours the terminal's palette defines, styled by the stylesheet so they
+    /// suit the light and dark themes
+    Palette(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Colour {
+    /// A colour from the 256 colour palette, the first 16 of which are the terminal's own.
+    fn indexed(index: u8) -> Self {
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+        match index {
+            0..=15 => Self::Palette(index),
+            16..=231 => {
+                let index = index - 16;
+                Self::Rgb(
+                    LEVELS[usize::from(index / 36)],
+                    LEVELS[usize::from(index / 6 % 6)],
+                    LEVELS[usize::from(index % 6)],
+                )
+            }
+            232..=255 => {
+                let level = 8 + (index - 232) * 10;
+                Self::Rgb(level, level, level)
+            }
+        }
+    }
+}
+
+const BOLD: u8 = 1 << 0;
+const DIM: u8 = 1 << 1;
+const ITALIC: u8 = 1 << 2;
+const UNDERLINE: u8 = 1 << 3;
+const STRIKETHROUGH: u8 = 1 << 4;
+
+const ATTRIBUTE_CLASSES: [(u8, &str); 5] = [
+    (BOLD, "ansi-bold"),
+    (DIM, "ansi-dim"),
+    (ITALIC, "ansi-italic"),
+    (UNDERLINE, "ansi-underline"),
+    (STRIKETHROUGH, "ansi-strikethrough"),
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Style {
+    foreground: Colour,
+    background: Colour,
+    attributes: u8,
+}
+
+impl Style {
+    fn apply(&mut self, parameters: &str) {
+        let mut parameters = parameters.split([';', ':']).map(|v| {
+            if v.is_empty() {
+                Some(0)
+            } else {
+                v.parse::<u8>().ok()
+            }
+        });
+
+        while let Some(parameter) = parameters.next() {
+            let Some(parameter) = parameter else {
+                continue;
+            };
+
+            match parameter {
+                0 => *self = Self::default(),
+                1 => self.attributes |= BOLD,
+                2 => self.attributes |= DIM,
+                3 => self.attributes |= ITALIC,
+                4 => self.attributes |= UNDERLINE,
+                9 => self.attributes |= STRIKETHROUGH,
+                22 => self.attributes &= !(BOLD | DIM),
+                23 => self.attributes &= !ITALIC,
+                24 => self.attributes &= !UNDERLINE,
+                29 => self.attributes &= !STRIKETHROUGH,
+                30..=37 => self.foreground = Colour::Palette(parameter - 30),
+                39 => self.foreground = Colour::Default,
+                40..=47 => self.background = Colour::Palette(parameter - 40),
+                49 => self.background = Colour::Default,
+                90..=97 => self.foreground = Colour::Palette(parameter - 90 + 8),
+                100..=107 => self.background = Colour::Palette(parameter - 100 + 8),
+                38 | 48 => {
+                    let colour = match parameters.next().flatten() {
+                        Some(5) => parameters.next().flatten().map(Colour::indexed),
+                        Some(2) => match (
+                            parameters.next().flatten(),
+                            parameters.next().flatten(),
+                            parameters.next().flatten(),
+                        ) {
+                            (Some(r), Some(g), Some(b)) => Some(Colour::Rgb(r, g, b)),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    if let Some(colour) = colour {
+                        if parameter == 38 {
+                            self.foreground = colour;
+                        } else {
+                            self.background = colour;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn open(&self, out: &mut String) {
+        if *self == Self::default() {
+            return;
+        }
+
+        out.push_str(r#"<span class="ansi"#);
+
+        if let Colour::Palette(index) = self.foreground {
+            write!(out, " ansi-fg-{index}").unwrap();
+        }
+
+        if let Colour::Palette(index) = self.background {
+            write!(out, " ansi-bg-{index}").unwrap();
+        }
+
+        for (attribute, class) in ATTRIBUTE_CLASSES {
+            if self.attributes & attribute != 0 {
+                write!(out, " {class}").unwrap();
+            }
+        }
+
+        out.push('"');
+
+        match (self.foreground, self.background) {
+            (Colour::Rgb(r, g, b), Colour::Rgb(br, bg, bb)) => write!(
+                out,
+                r#" style="color: #{r:02x}{g:02x}{b:02x}; background-color: #{br:02x}{bg:02x}{bb:02x}""#
+            )
+            .unwrap(),
+            (Colour::Rgb(r, g, b), _) => {
+                write!(out, r#" style="color: #{r:02x}{g:02x}{b:02x}""#).unwrap();
+            }
+            (_, Colour::Rgb(r, g, b)) => {
+                write!(out, r#" style="background-color: #{r:02x}{g:02x}{b:02x}""#).unwrap();
+            }
+            _ => {}
+        }
+
+        out.push('>');
+    }
+
+    fn close(&self, out: &mut String) {
+        if *self != Self::default() {
+            out.push_str("</span>");
+        }
+    }
+}
+
+/// Renders text a line at a time, carrying the style set by one line over to the next.
+#[derive(Default)]
+pub struct Renderer {
+    style: Style,
+}
+
+impl Renderer {
+    /// Writes `line` to `out` as escaped HTML, any spans it opens being closed again at the end
+    /// of the line so it can be wrapped in an element of its own.
+    pub fn render_line(&mut self, line: &str, out: &mut String) {
+        // a carriage return moves the cursor back to the start of the line, so only what was
+        // written after the last one would still be visible in a terminal
+        let line = line.trim_end_matches('\r');
+        let (overwritten, line) = line.rsplit_once('\r').unwrap_or(("", line));
+
+        // styles set in the overwritten text still apply to what follows
+        for token in tokenize(overwritten) {
+            if let Token::Sgr(parameters) = token {
+                self.style.apply(parameters);
+            }
+        }
+
+        self.style.open(out);
+
+        for token in tokenize(line) {
+            match token {
+                Token::Text(text) => v_htmlescape::b_escape(text.as_bytes(), out),
+                Token::Sgr(parameters) => {
+                    let previous = self.style;
+                    self.style.apply(parameters);
+
+                    if previous != self.style {
+                        previous.close(out);
+                        self.style.open(out);
+                    }
+                }
+            }
+        }
+
+        self.style.close(out);
+    }
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    /// The parameters of an SGR sequence, which sets the style of the text after it
+    Sgr(&'a str),
+}
+
+/// Splits `text` into the runs of text between escape sequences and the SGR sequences amongst
+/// them, dropping every other sequence.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('\x1b') {
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+
+        let sequence = &rest[start + 1..];
+
+        rest = match sequence.as_bytes().first() {
+            // control sequence, ended by a byte in the range `@` to `~`
+            Some(b'[') => {
+                let body = &sequence[1..];
+
+                match body.find(|c: char| ('@'..='~').contains(&c)) {
+                    Some(end) => {
+                        if body.as_bytes()[end] == b'm' {
+                            tokens.push(Token::Sgr(&body[..end]));
+                        }
+
+                        &body[end + 1..]
+                    }
+                    None => "",
+                }
+            }
+            // operating system command (eg. setting the window title or a hyperlink), ended by
+            // BEL or ST
+            Some(b']') => {
+                let body = &sequence[1..];
+
+                match (body.find('\x07'), body.find("\x1b\\")) {
+                    (Some(bel), Some(st)) if st < bel => &body[st + 2..],
+                    (Some(bel), _) => &body[bel + 1..],
+                    (None, Some(st)) => &body[st + 2..],
+                    (None, None) => "",
+                }
+            }
+            // anything else is a two character sequence
+            Some(_) => {
+                let mut chars = sequence.chars();
+                chars.next();
+                chars.as_str()
+            }
+            None => "",
+        };
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    tokens
+}
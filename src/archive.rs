@@ -0,0 +1,433 @@
+//! The formats snapshots of a tree can be downloaded in, the writers that stream them back
+//! to the requester as they're built, and the checksums offered alongside them.
+
+use std::{
+    fmt::{Display, Formatter},
+    io::{ErrorKind, Read, Write},
+    path::PathBuf,
+    process::{ChildStdin, Command, Stdio},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context};
+use bytes::{Bytes, BytesMut};
+use flate2::write::GzEncoder;
+use tar::Builder;
+use time::OffsetDateTime;
+use xz2::write::XzEncoder;
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+/// Size of the chunks the archive is sent back to the requester in.
+const BUFFER_CAP: usize = 512 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SnapshotFormat {
+    TarGz,
+    TarXz,
+    TarZst,
+    Zip,
+}
+
+impl SnapshotFormat {
+    /// The formats offered for repositories that don't configure any.
+    pub const DEFAULT: &'static [Self] = &[Self::TarGz];
+
+    const ALL: &'static [Self] = &[Self::TarGz, Self::TarXz, Self::TarZst, Self::Zip];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::TarXz => "tar.xz",
+            Self::TarZst => "tar.zst",
+            Self::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::TarGz => "application/gzip",
+            Self::TarXz => "application/x-xz",
+            Self::TarZst => "application/zstd",
+            Self::Zip => "application/zip",
+        }
+    }
+
+    /// The compression level used when the repository doesn't configure one, favouring speed
+    /// since snapshots are built on every request.
+    fn default_level(self) -> u32 {
+        match self {
+            Self::TarGz | Self::Zip => 1,
+            // xz is slow at any level, the lower levels still compress far better than gzip
+            Self::TarXz => 2,
+            Self::TarZst => 3,
+        }
+    }
+
+    /// Splits a snapshot file name, such as `docs.tar.gz.sha256`, into its stem and the format
+    /// (including any checksum extension) it was requested in.
+    pub fn split_file_name(name: &str) -> Option<(&str, &str)> {
+        let (without_checksum, _) = SnapshotChecksum::split_format(name);
+
+        Self::ALL.iter().find_map(|format| {
+            let stem = without_checksum
+                .strip_suffix(format.extension())?
+                .strip_suffix('.')?;
+
+            (!stem.is_empty()).then(|| (stem, &name[stem.len() + 1..]))
+        })
+    }
+
+    /// Clamps a configured compression level to the range the format supports.
+    pub fn level(self, level: Option<u32>) -> u32 {
+        let level = level.unwrap_or_else(|| self.default_level());
+
+        match self {
+            Self::TarGz | Self::TarXz | Self::Zip => level.min(9),
+            // levels above 19 need a lot of memory to compress and decompress
+            Self::TarZst => level.clamp(1, 19),
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().trim_start_matches('.') {
+            "tar.gz" | "tgz" => Ok(Self::TarGz),
+            "tar.xz" | "txz" => Ok(Self::TarXz),
+            "tar.zst" | "tzst" => Ok(Self::TarZst),
+            "zip" => Ok(Self::Zip),
+            other => Err(anyhow!("unknown snapshot format `{other}`")),
+        }
+    }
+}
+
+impl Display for SnapshotFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Buffers archive output, sending it back to the requester each time a chunk fills up.
+pub struct ChannelWriter {
+    buffer: BytesMut,
+    res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
+}
+
+impl ChannelWriter {
+    pub fn new(res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>) -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(BUFFER_CAP + 1024),
+            res,
+        }
+    }
+
+    fn send(&mut self) -> std::io::Result<()> {
+        let chunk = self.buffer.split().freeze();
+
+        self.res
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::new(ErrorKind::BrokenPipe, "requester gone"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= BUFFER_CAP {
+            self.send()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Sends whatever is left in the buffer, encoders are only flushed once they're finished.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.send()
+        }
+    }
+}
+
+/// An archive in one of the [`SnapshotFormat`]s being written out.
+///
+/// Archives are reproducible, every entry is given the same modification time and entries are
+/// written in the order they're given, so the same tree always produces the same bytes and
+/// checksums of snapshots can be relied on.
+pub enum ArchiveWriter<W: Write> {
+    Tar(Builder<Compressor<W>>, u64),
+    Zip(ZipWriter<StreamWriter<W>>, i64, zip::DateTime),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Creates an archive writing to `out`, with every entry timestamped with `mtime`.
+    pub fn new(
+        format: SnapshotFormat,
+        level: Option<u32>,
+        mtime: i64,
+        out: W,
+    ) -> anyhow::Result<Self> {
+        let level = format.level(level);
+
+        let compressor = match format {
+            SnapshotFormat::TarGz => {
+                Compressor::Gzip(GzEncoder::new(out, flate2::Compression::new(level)))
+            }
+            SnapshotFormat::TarXz => Compressor::Xz(XzEncoder::new(out, level)),
+            SnapshotFormat::TarZst => Compressor::Zstd(zstd::Encoder::new(
+                out,
+                i32::try_from(level).unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+            )?),
+            SnapshotFormat::Zip => {
+                return Ok(Self::Zip(
+                    ZipWriter::new_stream(out),
+                    i64::from(level),
+                    zip_time(mtime),
+                ));
+            }
+        };
+
+        Ok(Self::Tar(
+            Builder::new(compressor),
+            u64::try_from(mtime).unwrap_or_default(),
+        ))
+    }
+
+    pub fn append(&mut self, path: &str, mode: u32, data: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Tar(builder, mtime) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(mode);
+                header.set_mtime(*mtime);
+
+                builder.append_data(&mut header, path, data)?;
+            }
+            Self::Zip(writer, level, mtime) => {
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .compression_level(Some(*level))
+                    .last_modified_time(*mtime)
+                    .unix_permissions(mode);
+
+                writer.start_file(path, options)?;
+                writer.write_all(data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes out the end of the archive and flushes any remaining output.
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Tar(builder, _) => builder.into_inner()?.finish()?.flush()?,
+            Self::Zip(writer, ..) => writer.finish()?.flush()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The stream compressors tarballs can be written through.
+pub enum Compressor<W: Write> {
+    Gzip(GzEncoder<W>),
+    Xz(XzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressor<W> {
+    /// Writes out the end of the compressed stream, returning the underlying writer.
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Xz(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Xz(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Xz(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Converts a unix timestamp to the MS-DOS time zip uses, which can't represent anything before
+/// 1980.
+fn zip_time(timestamp: i64) -> zip::DateTime {
+    let Ok(time) = OffsetDateTime::from_unix_timestamp(timestamp) else {
+        return zip::DateTime::default();
+    };
+
+    u16::try_from(time.year())
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                time.month().into(),
+                time.day(),
+                time.hour(),
+                time.minute(),
+                time.second(),
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// A companion file offered alongside each snapshot so downloads can be verified.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SnapshotChecksum {
+    /// The SHA-256 of the snapshot, in the format `sha256sum --check` expects.
+    Sha256,
+    /// A detached signature of the snapshot, produced by the configured signing command.
+    Signature,
+}
+
+impl SnapshotChecksum {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Signature => "asc",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Sha256 => "text/plain; charset=utf-8",
+            Self::Signature => "application/pgp-signature",
+        }
+    }
+
+    /// Splits the companion file extension, if any, off of a requested snapshot format (eg.
+    /// `tar.gz.sha256`).
+    pub fn split_format(format: &str) -> (&str, Option<Self>) {
+        for checksum in [Self::Sha256, Self::Signature] {
+            if let Some(format) = format
+                .strip_suffix(checksum.extension())
+                .and_then(|v| v.strip_suffix('.'))
+            {
+                return (format, Some(checksum));
+            }
+        }
+
+        (format, None)
+    }
+}
+
+impl Display for SnapshotChecksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// How snapshots are signed.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SnapshotConfig {
+    /// A command used to sign snapshots, offering `.asc` signatures alongside each of them. The
+    /// command is ran using `sh -c`, given the snapshot on stdin and is expected to write a
+    /// detached signature to stdout (eg. "gpg --batch --armor --detach-sign")
+    #[clap(long)]
+    pub snapshot_signing_command: Option<Arc<str>>,
+    /// Builds snapshots in the background rather than streaming them as they're built, showing
+    /// requesters a page that refreshes until the snapshot is ready. Built snapshots are cached
+    /// in `--snapshot-cache-dir`, or `--snapshot-cache-url` if given
+    #[clap(long)]
+    pub background_snapshots: bool,
+    /// The directory snapshots built in the background are cached in, which is cleared on
+    /// startup. When caching to a bucket, snapshots are built here before being uploaded.
+    /// Defaults to a directory in the system's temporary directory. A directory that wasn't
+    /// created by rgit must be empty
+    #[clap(long)]
+    pub snapshot_cache_dir: Option<PathBuf>,
+    /// An S3-compatible bucket to cache snapshots built in the background in, given as
+    /// `s3://<bucket>[/<prefix>]`, so instances sharing the bucket serve each other's snapshots.
+    /// Credentials, region and endpoint are read from the `AWS_*` environment variables
+    #[clap(long)]
+    pub snapshot_cache_url: Option<String>,
+}
+
+impl SnapshotConfig {
+    /// The companion files offered alongside each snapshot.
+    pub fn checksums(&self) -> &'static [SnapshotChecksum] {
+        if self.snapshot_signing_command.is_some() {
+            &[SnapshotChecksum::Sha256, SnapshotChecksum::Signature]
+        } else {
+            &[SnapshotChecksum::Sha256]
+        }
+    }
+}
+
+/// The snapshot downloads offered for a repository.
+pub struct Snapshots {
+    pub formats: Vec<SnapshotFormat>,
+    pub checksums: &'static [SnapshotChecksum],
+}
+
+/// Pipes a snapshot written by `write` through the signing command, returning the signature it
+/// outputs.
+pub fn sign(
+    command: &str,
+    write: impl FnOnce(&mut ChildStdin) -> anyhow::Result<()>,
+) -> anyhow::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to spawn snapshot signing command")?;
+
+    let mut stdout = child.stdout.take().context("stdout not captured")?;
+    let reader = std::thread::spawn(move || {
+        let mut signature = String::new();
+        stdout.read_to_string(&mut signature).map(|_| signature)
+    });
+
+    // stdin is dropped as soon as the snapshot is written, letting the command know it's done
+    let written = child
+        .stdin
+        .take()
+        .context("stdin not captured")
+        .and_then(|mut stdin| write(&mut stdin));
+
+    if let Err(error) = written {
+        let _res = child.kill();
+        let _res = child.wait();
+        return Err(error.context("Failed to write snapshot to signing command"));
+    }
+
+    let signature = reader
+        .join()
+        .map_err(|_| anyhow!("Signing command reader panicked"))?
+        .context("Failed to read signature from signing command")?;
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Snapshot signing command exited with {status}");
+    }
+
+    Ok(signature)
+}
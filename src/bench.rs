@@ -0,0 +1,326 @@
+//! `rgit bench <repository>`, times the hot paths of indexing and rendering against a real
+//! repository so that changes to them can be compared objectively.
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure, Context};
+use axum::{extract::Query, http::Uri, response::IntoResponse, Extension};
+use gix::ObjectId;
+
+use crate::{
+    branding::{Branding, BrandingConfig},
+    database::{
+        indexer::{self, IndexOptions, IndexState},
+        progress::IndexProgress,
+    },
+    git::Git,
+    install_layout,
+    layers::logger::REQ_TIMESTAMP,
+    linkify::LinkRule,
+    methods::repo::{log, Repository, RepositoryPath},
+    open_db,
+    repository_pool::RepositoryPool,
+    scan_path::ScanPaths,
+    schedule::RunPlan,
+    syntax_highlight::{fetch_highlighter_config, format_file, prime_highlighters, FileIdentifier},
+};
+
+/// The same budget the server uses by default, so diffs are highlighted as they would be when
+/// served.
+const DIFF_HIGHLIGHT_BUDGET: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Path to the bare repository to run the benchmarks against
+    repository: PathBuf,
+    /// Number of times each benchmark is run
+    #[clap(long, default_value_t = 10)]
+    iterations: usize,
+    /// Number of commits, starting from HEAD, to generate diffs for
+    #[clap(long, default_value_t = 50)]
+    diff_commits: usize,
+    /// Number of files from the tree at HEAD to syntax highlight
+    #[clap(long, default_value_t = 200)]
+    highlight_files: usize,
+    #[clap(flatten)]
+    branding: BrandingConfig,
+}
+
+pub async fn run(args: &Args) -> anyhow::Result<()> {
+    let repository = args
+        .repository
+        .canonicalize()
+        .context("Failed to find repository")?;
+    let name = PathBuf::from(
+        repository
+            .file_name()
+            .context("Repository path has no file name")?,
+    );
+
+    install_layout(Branding::load(&args.branding)?)?;
+    prime_highlighters();
+
+    // the indexer works over a whole scan path, so give it one containing just this repository
+    let scratch = std::env::temp_dir().join(format!("rgit-bench-{}", std::process::id()));
+    let scan_path = scratch.join("repositories");
+    std::fs::create_dir_all(&scan_path)?;
+    std::os::unix::fs::symlink(&repository, scan_path.join(&name))?;
+
+    let result = run_all(args, &repository, &name, &scan_path, &scratch).await;
+
+    std::fs::remove_dir_all(&scratch)?;
+
+    result
+}
+
+async fn run_all(
+    args: &Args,
+    repository: &Path,
+    name: &Path,
+    scan_path: &Path,
+    scratch: &Path,
+) -> anyhow::Result<()> {
+    let repo = gix::open::Options::isolated()
+        .open_path_as_is(true)
+        .open(repository)?
+        .to_thread_local();
+    let scan_paths = &ScanPaths::from(scan_path.to_path_buf());
+
+    println!(
+        "{:<24} {:>10} {:>10} {:>10} {:>16}",
+        "benchmark", "mean", "min", "max", "throughput"
+    );
+
+    let commits = count_commits(&repo)?;
+    let mut iteration = 0;
+
+    measure(
+        "commit ingestion",
+        args.iterations,
+        Some((commits, "commits")),
+        || {
+            iteration += 1;
+            let db_store = scratch.join(format!("ingest-{iteration}"));
+
+            async move {
+                let start = Instant::now();
+                let db = open_db(&db_store)?;
+                indexer::run(
+                    scan_paths,
+                    &db,
+                    &RepositoryPool::default(),
+                    &IndexProgress::default(),
+                    &IndexOptions::default(),
+                    &RunPlan::All,
+                    &mut IndexState::default(),
+                );
+                let elapsed = start.elapsed();
+
+                drop(db);
+                std::fs::remove_dir_all(&db_store)?;
+
+                Ok(elapsed)
+            }
+        },
+    )
+    .await?;
+
+    let db = open_db(&scratch.join("db"))?;
+    let repositories = Arc::new(RepositoryPool::default());
+    indexer::run(
+        scan_paths,
+        &db,
+        &repositories,
+        &IndexProgress::default(),
+        &IndexOptions::default(),
+        &RunPlan::All,
+        &mut IndexState::default(),
+    );
+
+    measure("log page", args.iterations, None, || {
+        let db = db.clone();
+        let repositories = repositories.clone();
+
+        timed(async move {
+            let query = Query::try_from_uri(&Uri::from_static("/log"))?;
+
+            let response = log::handle(
+                Extension(Repository(name.to_path_buf())),
+                Extension(RepositoryPath(repository.to_path_buf())),
+                Extension(db),
+                Extension(Arc::new(Git::new(
+                    DIFF_HIGHLIGHT_BUDGET,
+                    repositories,
+                    None,
+                ))),
+                Extension(Arc::<[LinkRule]>::from([])),
+                query,
+                Query(log::FilterQuery::default()),
+            )
+            .await
+            .into_response();
+
+            ensure!(
+                response.status().is_success(),
+                "log page returned {}",
+                response.status()
+            );
+
+            Ok(())
+        })
+    })
+    .await?;
+
+    let diff_commits = repo
+        .head_commit()?
+        .ancestors()
+        .all()?
+        .take(args.diff_commits)
+        .map(|info| Ok(info?.id))
+        .collect::<anyhow::Result<Vec<ObjectId>>>()?;
+
+    measure(
+        "diff generation",
+        args.iterations,
+        Some((diff_commits.len(), "commits")),
+        || {
+            let diff_commits = diff_commits.clone();
+
+            timed(async move {
+                // a fresh instance each time, so nothing is served from its caches
+                let git = Arc::new(Git::new(
+                    DIFF_HIGHLIGHT_BUDGET,
+                    Arc::new(RepositoryPool::default()),
+                    None,
+                ));
+                let open_repo = git.repo(repository.to_path_buf(), None).await?;
+
+                for id in diff_commits {
+                    open_repo
+                        .clone()
+                        .commit(&id.to_string(), 0, true)
+                        .await
+                        .map_err(|e| anyhow!("{e:?}"))?;
+                }
+
+                Ok(())
+            })
+        },
+    )
+    .await?;
+
+    let files = highlightable_files(&repo, args.highlight_files)?;
+    let files = &files;
+    let lines: usize = files
+        .iter()
+        .map(|(_, content)| content.lines().count())
+        .sum();
+
+    measure(
+        "syntax highlighting",
+        args.iterations,
+        Some((lines, "lines")),
+        || {
+            timed(async move {
+                for (path, content) in files {
+                    format_file(content, FileIdentifier::Path(path))?;
+                }
+
+                Ok(())
+            })
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Counts the commits reachable from every local branch, which is the work an index run from
+/// scratch has to do.
+fn count_commits(repo: &gix::Repository) -> anyhow::Result<usize> {
+    let tips = repo
+        .references()?
+        .local_branches()?
+        .filter_map(Result::ok)
+        .filter_map(|mut reference| reference.peel_to_id_in_place().ok())
+        .map(gix::Id::detach)
+        .collect::<Vec<_>>();
+
+    Ok(repo.rev_walk(tips).all()?.count())
+}
+
+/// Reads up to `limit` text files from the tree at HEAD that have a highlighter available.
+fn highlightable_files(
+    repo: &gix::Repository,
+    limit: usize,
+) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let mut recorder = gix::traverse::tree::Recorder::default();
+    repo.head_commit()?
+        .tree()?
+        .traverse()
+        .breadthfirst(&mut recorder)?;
+
+    Ok(recorder
+        .records
+        .into_iter()
+        .filter(|entry| entry.mode.is_blob())
+        .map(|entry| (PathBuf::from(entry.filepath.to_string()), entry.oid))
+        .filter(|(path, _)| fetch_highlighter_config(path).is_some())
+        .filter_map(|(path, oid)| {
+            let blob = repo.find_object(oid).ok()?;
+            Some((path, String::from_utf8(blob.data.clone()).ok()?))
+        })
+        .take(limit)
+        .collect())
+}
+
+/// Times a future, running it inside a request scope so pages can be rendered from it.
+async fn timed(fut: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    REQ_TIMESTAMP.scope(start, fut).await?;
+    Ok(start.elapsed())
+}
+
+/// Runs a benchmark `iterations` times and prints a summary of how long it took, along with its
+/// throughput if the amount of work done by each iteration is known.
+async fn measure<F, Fut>(
+    name: &str,
+    iterations: usize,
+    work: Option<(usize, &str)>,
+    mut f: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<Duration>>,
+{
+    let mut timings = Vec::with_capacity(iterations);
+
+    for _ in 0..iterations.max(1) {
+        timings.push(f().await.with_context(|| format!("{name} failed"))?);
+    }
+
+    let total: Duration = timings.iter().sum();
+    let mean = total / u32::try_from(timings.len())?;
+    let min = timings.iter().min().copied().unwrap_or_default();
+    let max = timings.iter().max().copied().unwrap_or_default();
+
+    let throughput = work.map_or_else(String::new, |(amount, unit)| {
+        #[allow(clippy::cast_precision_loss)]
+        let per_second = amount as f64 / mean.as_secs_f64();
+        format!("{per_second:.0} {unit}/s")
+    });
+
+    println!(
+        "{name:<24} {:>10} {:>10} {:>10} {throughput:>16}",
+        format!("{mean:.2?}"),
+        format!("{min:.2?}"),
+        format!("{max:.2?}"),
+    );
+
+    Ok(())
+}
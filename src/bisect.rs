@@ -0,0 +1,163 @@
+//! Picks the commits to test next when bisecting, the same way `git bisect` does, so bisections
+//! can be driven through the API without a clone of the repository.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use gix::ObjectId;
+use serde::Serialize;
+
+/// The most commits offered as equally good places to test next.
+const MAX_MIDPOINTS: usize = 10;
+
+/// The most commits walked in total counting the ancestors of merges. Each merge walks every
+/// candidate behind it, so ranges with many merges would otherwise take quadratic time.
+const MAX_MERGE_WALK: usize = 10_000_000;
+
+/// How a bisection stands, given the commits marked so far.
+#[derive(Serialize, Debug)]
+pub struct Bisection {
+    /// The number of commits that could still be the first bad one, including skipped commits
+    pub remaining: usize,
+    /// Roughly how many more commits need testing to find the first bad one
+    pub steps: u32,
+    /// The commits to test next, each splitting the remaining commits as evenly as the others.
+    /// Empty once the first bad commit has been found, or if only skipped commits are left to test
+    pub midpoints: Vec<String>,
+    /// The first bad commit, once it's the only commit left
+    pub first_bad: Option<String>,
+}
+
+/// Bisects `candidates`, the bad commit and those of its ancestors that aren't ancestors of a good
+/// commit, each given with its parents. Skipped commits are counted towards how evenly a commit
+/// splits the candidates, but are never picked themselves. Returns `None` if the candidates have
+/// too many merges between them to count through.
+pub fn bisect(
+    candidates: &[(ObjectId, Vec<ObjectId>)],
+    skip: &HashSet<ObjectId>,
+) -> Option<Bisection> {
+    let total = candidates.len();
+
+    if total == 1 {
+        return Some(Bisection {
+            remaining: 1,
+            steps: 0,
+            midpoints: Vec::new(),
+            first_bad: Some(candidates[0].0.to_string()),
+        });
+    }
+
+    let reach = count_reachable(candidates)?;
+
+    // the best commits to test are those with as close to half of the candidates behind them as
+    // possible, since testing them rules out the most commits whichever way it goes
+    let scores: Vec<_> = candidates
+        .iter()
+        .zip(&reach)
+        .map(|((id, _), &reach)| {
+            if skip.contains(id) {
+                0
+            } else {
+                reach.min(total - reach)
+            }
+        })
+        .collect();
+
+    let best = scores.iter().copied().max().unwrap_or_default();
+
+    let midpoints = if best == 0 {
+        Vec::new()
+    } else {
+        candidates
+            .iter()
+            .zip(&scores)
+            .filter(|(_, &score)| score == best)
+            .map(|((id, _), _)| id.to_string())
+            .take(MAX_MIDPOINTS)
+            .collect()
+    };
+
+    Some(Bisection {
+        remaining: total,
+        steps: total.checked_ilog2().unwrap_or_default(),
+        midpoints,
+        first_bad: None,
+    })
+}
+
+/// Counts the candidates each candidate can reach through its parents, including itself, or
+/// `None` if walking the ancestors of merges goes over [`MAX_MERGE_WALK`].
+fn count_reachable(candidates: &[(ObjectId, Vec<ObjectId>)]) -> Option<Vec<usize>> {
+    let index: HashMap<_, _> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _))| (*id, i))
+        .collect();
+
+    // parents outside of the candidates are good, so don't count
+    let parents: Vec<Vec<usize>> = candidates
+        .iter()
+        .map(|(_, parents)| {
+            parents
+                .iter()
+                .filter_map(|v| index.get(v).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut children = vec![Vec::new(); candidates.len()];
+    let mut pending_parents = vec![0; candidates.len()];
+
+    for (child, parents) in parents.iter().enumerate() {
+        pending_parents[child] = parents.len();
+
+        for &parent in parents {
+            children[parent].push(child);
+        }
+    }
+
+    // visit parents before their children, so a commit with a single parent can build on the
+    // count of that parent
+    let mut queue: VecDeque<_> = (0..candidates.len())
+        .filter(|&i| pending_parents[i] == 0)
+        .collect();
+    let mut reach = vec![0; candidates.len()];
+    let mut budget = MAX_MERGE_WALK;
+
+    while let Some(i) = queue.pop_front() {
+        reach[i] = match parents[i].as_slice() {
+            [] => 1,
+            [parent] => reach[*parent] + 1,
+            // the histories of the parents of a merge overlap, so they have to be walked
+            _ => count_ancestors(i, &parents, &mut budget)?,
+        };
+
+        for &child in &children[i] {
+            pending_parents[child] -= 1;
+
+            if pending_parents[child] == 0 {
+                queue.push_back(child);
+            }
+        }
+    }
+
+    Some(reach)
+}
+
+/// Walks the candidates reachable from `start`, counting each of them once, or `None` if that
+/// would walk more than `budget` candidates. Those walked are taken off of the budget.
+fn count_ancestors(start: usize, parents: &[Vec<usize>], budget: &mut usize) -> Option<usize> {
+    let mut seen = HashSet::from([start]);
+    let mut queue = vec![start];
+
+    while let Some(i) = queue.pop() {
+        *budget = budget.checked_sub(1)?;
+
+        for &parent in &parents[i] {
+            if seen.insert(parent) {
+                queue.push(parent);
+            }
+        }
+    }
+
+    Some(seen.len())
+}
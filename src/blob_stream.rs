@@ -0,0 +1,271 @@
+//! Serving raw blobs too large to comfortably hold in memory, by streaming them from their loose
+//! object on disk or from a copy extracted from the pack they're stored in.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    body::Body,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use flate2::read::ZlibDecoder;
+use gix::{object::Kind, ObjectId};
+use moka::sync::Cache;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+use crate::{
+    cache_dir,
+    git::{is_binary, sniff_mime},
+};
+
+/// How much of a blob is read before streaming it, to tell its content type from.
+const SNIFF_LENGTH: u64 = 8000;
+
+/// The size of the chunks loose objects are inflated and sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How large blobs are served.
+#[derive(clap::Args, Debug, Clone)]
+pub struct BlobStreamConfig {
+    /// Streams raw blobs larger than this many bytes from disk rather than loading them into
+    /// memory, so downloads of very large files don't spike memory usage
+    #[clap(long)]
+    pub stream_blob_threshold: Option<u64>,
+    /// The directory packed blobs over `--stream-blob-threshold` are extracted to so they can be
+    /// streamed, which is cleared on startup. Defaults to a directory in the system's temporary
+    /// directory. A directory that wasn't created by rgit must be empty
+    #[clap(long)]
+    pub blob_cache_dir: Option<PathBuf>,
+    /// The most bytes of extracted blobs kept in `--blob-cache-dir`, the least recently
+    /// requested are removed first
+    #[clap(long, default_value_t = 4 * 1024 * 1024 * 1024)]
+    pub blob_cache_size: u64,
+}
+
+/// A packed blob extracted to the cache directory.
+#[derive(Clone)]
+struct Extracted {
+    path: Arc<PathBuf>,
+    size: u64,
+}
+
+pub struct BlobStreamer {
+    threshold: u64,
+    dir: PathBuf,
+    extracted: Cache<ObjectId, Extracted>,
+}
+
+impl BlobStreamer {
+    pub fn new(threshold: u64, dir: PathBuf, cache_size: u64) -> Result<Self> {
+        // extractions from the last run may have been left half written
+        cache_dir::reset(&dir)?;
+
+        // weighed in KiB, since entries can only weigh up to `u32::MAX`
+        let extracted = Cache::builder()
+            .max_capacity(cache_size / 1024)
+            .weigher(|_, v: &Extracted| u32::try_from(v.size / 1024).unwrap_or(u32::MAX))
+            .eviction_listener(|_, v: Extracted, cause| {
+                // blobs still being streamed carry on from their open file
+                if cause.was_evicted() {
+                    if let Err(error) = std::fs::remove_file(&*v.path) {
+                        error!(%error, "Failed to remove evicted blob {}", v.path.display());
+                    }
+                }
+            })
+            .build();
+
+        Ok(Self {
+            threshold,
+            dir,
+            extracted,
+        })
+    }
+
+    /// Opens the blob for streaming if it's over the threshold, returning `None` if it should be
+    /// loaded into memory as usual, or isn't a blob at all.
+    pub fn try_open(&self, repo: &gix::Repository, id: ObjectId) -> Result<Option<LargeBlob>> {
+        let Some(header) = repo.try_find_header(id)? else {
+            return Ok(None);
+        };
+
+        if header.kind() != Kind::Blob || header.size() <= self.threshold {
+            return Ok(None);
+        }
+
+        let hex = id.to_hex().to_string();
+        let loose = repo
+            .common_dir()
+            .join("objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+
+        let (source, prefix) = match File::open(loose) {
+            Ok(file) => {
+                let mut reader = inflate_loose(file)?;
+                let prefix = read_prefix(&mut reader)?;
+
+                // the prefix has already been taken from the decoder, so it's sent ahead of it
+                let reader = Cursor::new(prefix.clone()).chain(reader);
+
+                (Source::Loose(Box::new(reader)), prefix)
+            }
+            Err(_) => {
+                let mut file = self.extract(repo, id, &hex)?;
+                let prefix = read_prefix(&mut file)?;
+                file.seek(SeekFrom::Start(0))?;
+
+                (Source::Extracted(file), prefix)
+            }
+        };
+
+        Ok(Some(LargeBlob {
+            size: header.size(),
+            mime: sniff_mime(&prefix),
+            binary: is_binary(&prefix),
+            source,
+        }))
+    }
+
+    /// Fetches the copy of a packed blob extracted to the cache directory, extracting it first if
+    /// this is the first time it's been asked for. Blobs in packs can only be read whole, so it's
+    /// held in memory once while it's written out and streamed from disk from then on.
+    fn extract(&self, repo: &gix::Repository, id: ObjectId, hex: &str) -> Result<File> {
+        let extracted = self
+            .extracted
+            .try_get_with(id, || -> Result<Extracted> {
+                let blob = repo.find_object(id)?.try_into_blob()?;
+                let path = self.dir.join(hex);
+
+                // written under a temporary name so a failed extraction is never streamed
+                let partial = self.dir.join(format!("{hex}.{}", uuid::Uuid::new_v4()));
+                std::fs::write(&partial, &blob.data).context("Failed to extract blob")?;
+                let size = blob.data.len() as u64;
+                drop(blob);
+                std::fs::rename(&partial, &path)
+                    .context("Failed to move extracted blob into place")?;
+
+                Ok(Extracted {
+                    path: Arc::new(path),
+                    size,
+                })
+            })
+            .map_err(|error| anyhow!("{error:#}"))?;
+
+        File::open(&*extracted.path).map_err(|error| {
+            // evicted between being looked up and opened, it'll be extracted again next time
+            self.extracted.invalidate(&id);
+            anyhow::Error::new(error).context("Failed to open extracted blob")
+        })
+    }
+}
+
+/// Opens a loose object for reading, skipping past its header.
+fn inflate_loose(file: File) -> Result<BufReader<ZlibDecoder<File>>> {
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, ZlibDecoder::new(file));
+
+    let mut header = Vec::new();
+    reader
+        .read_until(0, &mut header)
+        .context("Failed to read loose object header")?;
+
+    if !header.starts_with(b"blob ") || header.last() != Some(&0) {
+        bail!("Loose object isn't a blob");
+    }
+
+    Ok(reader)
+}
+
+fn read_prefix(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut prefix = Vec::new();
+    reader
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut prefix)
+        .context("Failed to read blob")?;
+    Ok(prefix)
+}
+
+/// A blob over `--stream-blob-threshold`, ready to be streamed to the client.
+pub struct LargeBlob {
+    pub size: u64,
+    /// The content type of the blob, if it's a binary format we can preview
+    pub mime: Option<&'static str>,
+    pub binary: bool,
+    source: Source,
+}
+
+enum Source {
+    /// An inflating reader over the loose object
+    Loose(Box<dyn Read + Send>),
+    /// A copy of a packed object, extracted to the cache directory
+    Extracted(File),
+}
+
+impl IntoResponse for LargeBlob {
+    fn into_response(self) -> Response {
+        let content_type = match (self.mime, self.binary) {
+            (Some(mime), _) => mime,
+            (None, true) => "application/octet-stream",
+            (None, false) => "text/plain; charset=UTF-8",
+        };
+
+        let body = match self.source {
+            Source::Loose(reader) => Body::from_stream(ReceiverStream::new(inflate_stream(reader))),
+            Source::Extracted(file) => {
+                Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(file)))
+            }
+        };
+
+        (
+            [
+                (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+                (header::CONTENT_LENGTH, HeaderValue::from(self.size)),
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    HeaderValue::from_static("nosniff"),
+                ),
+            ],
+            body,
+        )
+            .into_response()
+    }
+}
+
+/// Inflates a loose object on a blocking thread, sending it on a chunk at a time as the client
+/// is ready for it.
+fn inflate_stream(
+    mut reader: Box<dyn Read + Send>,
+) -> tokio::sync::mpsc::Receiver<std::io::Result<Bytes>> {
+    let (send, recv) = tokio::sync::mpsc::channel(1);
+
+    tokio::task::spawn_blocking(move || loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+
+        let chunk = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
+                chunk.truncate(n);
+                Ok(Bytes::from(chunk))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+
+        let failed = chunk.is_err();
+
+        // the client going away drops the receiver, there's no one left to send to
+        if send.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    });
+
+    recv
+}
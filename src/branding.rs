@@ -0,0 +1,109 @@
+//! Site-wide appearance options: forcing a colour scheme, and admin-supplied CSS and HTML that
+//! are injected into every page without having to rebuild the binary.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use regex::{Captures, Regex};
+
+static COLOUR_SCHEME_QUERY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"@media\s*\(\s*prefers-color-scheme\s*:\s*(light|dark)\s*\)").unwrap()
+});
+
+/// The colour scheme pages are rendered in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColourScheme {
+    /// Follow the browser's `prefers-color-scheme` preference.
+    #[default]
+    Auto,
+    /// Always use the light theme.
+    Light,
+    /// Always use the dark theme.
+    Dark,
+}
+
+impl ColourScheme {
+    /// Rewrites the `prefers-color-scheme` media queries in a stylesheet so that only the rules
+    /// for the forced scheme apply, leaving the stylesheet untouched when following the browser.
+    pub fn apply(self, css: &str) -> String {
+        let forced = match self {
+            Self::Auto => return css.to_string(),
+            Self::Light => "light",
+            Self::Dark => "dark",
+        };
+
+        COLOUR_SCHEME_QUERY
+            .replace_all(css, |captures: &Captures<'_>| {
+                if &captures[1] == forced {
+                    "@media all"
+                } else {
+                    "@media not all"
+                }
+            })
+            .into_owned()
+    }
+
+    /// The value of the `color-scheme` meta tag, so form controls and scrollbars match the page.
+    pub fn meta_content(self) -> &'static str {
+        match self {
+            Self::Auto => "light dark",
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BrandingConfig {
+    /// Forces the site to use a light or dark theme, rather than following the browser's
+    /// preference
+    #[clap(long = "theme", value_enum, default_value_t = ColourScheme::Auto)]
+    colour_scheme: ColourScheme,
+    /// Path to a stylesheet that's served after the built-in one, for overriding the site's styles
+    #[clap(long)]
+    custom_css: Option<PathBuf>,
+    /// Path to a HTML fragment that replaces the logo in the top-left of every page
+    #[clap(long)]
+    logo_html: Option<PathBuf>,
+    /// Path to a HTML fragment that's appended to the footer of every page
+    #[clap(long)]
+    footer_html: Option<PathBuf>,
+    /// URL of an image shown alongside links to the site when they're shared in chat tools and
+    /// social media
+    #[clap(long)]
+    social_image: Option<String>,
+}
+
+pub struct Branding {
+    pub colour_scheme: ColourScheme,
+    pub custom_css: Option<String>,
+    pub logo_html: Option<String>,
+    pub footer_html: Option<String>,
+    pub social_image: Option<String>,
+}
+
+impl Branding {
+    /// Reads the files referenced by the configuration, these are only read at startup so the
+    /// server needs to be restarted to pick up any changes.
+    pub fn load(config: &BrandingConfig) -> anyhow::Result<Self> {
+        let read = |path: Option<&Path>| {
+            path.map(|path| {
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))
+            })
+            .transpose()
+        };
+
+        Ok(Self {
+            colour_scheme: config.colour_scheme,
+            custom_css: read(config.custom_css.as_deref())?,
+            logo_html: read(config.logo_html.as_deref())?,
+            footer_html: read(config.footer_html.as_deref())?,
+            social_image: config.social_image.clone(),
+        })
+    }
+}
@@ -0,0 +1,205 @@
+//! `--bundle-dir`, periodically builds a bundle of the branches and tags of each repository that
+//! opts in with `rgit.bundle`, served at `/<repo>/bundle`. Mirrors and CI can seed a clone from
+//! the bundle, which is a plain file download, and fetch whatever's changed since from rgit
+//! rather than having it build a packfile of the repository's entire history.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use gix::refs::Category;
+use tracing::{error, info, info_span};
+use xxhash_rust::const_xxh3;
+
+use crate::{
+    database::schema::repository::Repository, reindex::SharedDb, scan_path::ScanPaths, shutdown,
+};
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct BundleConfig {
+    /// Path to a directory to build clone bundles into, for the repositories that ask for one
+    /// with `rgit.bundle`. Bundles aren't built unless this is given
+    #[clap(long, value_parser)]
+    pub bundle_dir: Option<PathBuf>,
+    /// How often bundles are rebuilt, repositories whose branches and tags haven't changed since
+    /// their bundle was built are skipped
+    #[clap(long, default_value_t = Duration::from_secs(24 * 60 * 60).into())]
+    pub bundle_interval: humantime::Duration,
+}
+
+/// The directory bundles are built into.
+pub struct Bundles {
+    dir: PathBuf,
+}
+
+impl Bundles {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create bundle directory {}", dir.display()))?;
+
+        Ok(Self { dir })
+    }
+
+    /// The path the bundle of the repository at `relative` is kept at, whether or not it's been
+    /// built yet.
+    pub fn path(&self, relative: &Path) -> PathBuf {
+        self.dir.join(format!("{}.bundle", stem(relative)))
+    }
+
+    /// The size of the repository's bundle, if one has been built.
+    pub fn size(&self, relative: &Path) -> Option<u64> {
+        std::fs::metadata(self.path(relative)).ok().map(|v| v.len())
+    }
+
+    /// Builds bundles straight away and then every `interval`, until the process shuts down.
+    pub fn spawn(self: Arc<Self>, db: Arc<SharedDb>, scan_paths: ScanPaths, interval: Duration) {
+        let scan_paths = Arc::new(scan_paths);
+
+        tokio::spawn(async move {
+            loop {
+                let bundles = self.clone();
+                let db = db.get();
+                let scan_paths = scan_paths.clone();
+
+                let res =
+                    tokio::task::spawn_blocking(move || bundles.refresh(&db, &scan_paths)).await;
+
+                if let Err(error) = res {
+                    error!(%error, "Bundle builder panicked");
+                }
+
+                tokio::select! {
+                    () = tokio::time::sleep(interval) => {}
+                    () = shutdown::wait() => break,
+                }
+            }
+        });
+    }
+
+    /// Rebuilds the bundle of every repository asking for one whose references have changed, and
+    /// removes those of repositories that no longer ask for one.
+    fn refresh(&self, db: &rocksdb::DB, scan_paths: &ScanPaths) {
+        let repositories = match Repository::fetch_all(db) {
+            Ok(v) => v,
+            Err(error) => {
+                error!(%error, "Failed to list repositories to build bundles for");
+                return;
+            }
+        };
+
+        for (relative, repository) in repositories {
+            if shutdown::requested() {
+                break;
+            }
+
+            let relative = Path::new(&relative);
+
+            if !repository.get().bundle {
+                self.remove(relative);
+                continue;
+            }
+
+            let _span = info_span!("bundle", repository = %relative.display()).entered();
+
+            let Some(git_dir) = scan_paths.resolve(relative) else {
+                self.remove(relative);
+                continue;
+            };
+
+            if let Err(error) = self.build(relative, &git_dir) {
+                error!(%error, "Failed to build bundle");
+            }
+        }
+    }
+
+    /// Builds the bundle of the repository at `git_dir`, unless its references are the same as
+    /// they were when its bundle was last built.
+    fn build(&self, relative: &Path, git_dir: &Path) -> anyhow::Result<()> {
+        let fingerprint = fingerprint(git_dir)?;
+        let fingerprint_path = self.dir.join(format!("{}.refs", stem(relative)));
+
+        if std::fs::read_to_string(&fingerprint_path).is_ok_and(|v| v == fingerprint) {
+            return Ok(());
+        }
+
+        // git refuses to build a bundle without any references in it
+        if fingerprint.is_empty() {
+            self.remove(relative);
+            return Ok(());
+        }
+
+        info!("Building bundle");
+
+        let path = self.path(relative);
+        let staging = path.with_extension("bundle.tmp");
+
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(git_dir)
+            .args(["bundle", "create", "--quiet"])
+            .arg(&staging)
+            .args(["--branches", "--tags"])
+            .output()
+            .context("Failed to run git bundle")?;
+
+        if !output.status.success() {
+            let _res = std::fs::remove_file(&staging);
+        }
+
+        ensure!(
+            output.status.success(),
+            "git bundle exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        // renamed into place so a bundle being downloaded is never the one being written
+        std::fs::rename(&staging, &path).context("Failed to move bundle into place")?;
+        std::fs::write(&fingerprint_path, fingerprint)?;
+
+        Ok(())
+    }
+
+    fn remove(&self, relative: &Path) {
+        let _res = std::fs::remove_file(self.path(relative));
+        let _res = std::fs::remove_file(self.dir.join(format!("{}.refs", stem(relative))));
+    }
+}
+
+/// The name a repository's bundle is stored under, derived from the path of the repository.
+fn stem(relative: &Path) -> String {
+    format!(
+        "{:016x}",
+        const_xxh3::xxh3_64(relative.as_os_str().as_encoded_bytes())
+    )
+}
+
+/// Lists the branches and tags of the repository and what they point to, so a bundle is only
+/// rebuilt once they've changed.
+fn fingerprint(git_dir: &Path) -> anyhow::Result<String> {
+    let repository = gix::open::Options::isolated()
+        .open_path_as_is(true)
+        .open(git_dir)
+        .context("Failed to open repository")?
+        .to_thread_local();
+
+    let mut references: Vec<_> = repository
+        .references()?
+        .all()?
+        .filter_map(Result::ok)
+        .filter(|v| {
+            matches!(
+                v.name().category(),
+                Some(Category::LocalBranch | Category::Tag)
+            )
+        })
+        .filter_map(|v| Some(format!("{} {}\n", v.target().try_id()?, v.name().as_bstr())))
+        .collect();
+    references.sort_unstable();
+
+    Ok(references.concat())
+}
@@ -0,0 +1,38 @@
+//! The directories rgit keeps files it can rebuild in, which are emptied on startup since
+//! whatever was left in them by the last run is no longer tracked.
+
+use std::{io::ErrorKind, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+/// Left in each cache directory so it's known to be rgit's to empty.
+const MARKER: &str = ".rgit-cache";
+
+/// Empties the cache directory `dir`, creating it if it doesn't exist. A directory that rgit
+/// didn't create is only used if it's already empty, so pointing a cache at the wrong directory
+/// can't delete anything.
+pub fn reset(dir: &Path) -> Result<()> {
+    match std::fs::read_dir(dir) {
+        Ok(_) if dir.join(MARKER).exists() => {
+            std::fs::remove_dir_all(dir)
+                .with_context(|| format!("Failed to clear {}", dir.display()))?;
+        }
+        Ok(mut entries) => {
+            if entries.next().is_some() {
+                bail!(
+                    "Refusing to use {} as a cache, it isn't empty and wasn't created by rgit",
+                    dir.display()
+                );
+            }
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to read {}", dir.display()));
+        }
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(dir.join(MARKER), b"").context("Failed to mark cache directory")?;
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Lays out a list of commits into lanes to draw a graph of branches and merges alongside them,
+//! similar to `git log --graph` but with a single line per commit.
+
+use std::fmt::Write;
+
+/// Number of colours lanes cycle through, each has a `graph-lane-<n>` class in the stylesheet.
+const LANE_COLOURS: usize = 6;
+
+bitflags::bitflags! {
+    /// The sides of a cell lines leave it from.
+    #[derive(Default, Clone, Copy)]
+    struct Edges: u8 {
+        const UP = 1;
+        const DOWN = 1 << 1;
+        const LEFT = 1 << 2;
+        const RIGHT = 1 << 3;
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Cell {
+    edges: Edges,
+    commit: bool,
+    /// The lane the horizontal line to the right of this cell belongs to, if any.
+    gap: Option<usize>,
+}
+
+impl Cell {
+    fn glyph(self) -> char {
+        if self.commit {
+            return '●';
+        }
+
+        let [up, down, left, right] = [Edges::UP, Edges::DOWN, Edges::LEFT, Edges::RIGHT]
+            .map(|edge| self.edges.contains(edge));
+
+        match (up, down, left, right) {
+            (true, true, true, true) => '┼',
+            (true, true, true, false) => '┤',
+            (true, true, false, true) => '├',
+            (true, false, true, true) => '┴',
+            (true, false, true, false) => '┘',
+            (true, false, false, true) => '└',
+            (false, true, true, true) => '┬',
+            (false, true, true, false) => '┐',
+            (false, true, false, true) => '┌',
+            (true, ..) | (_, true, ..) => '│',
+            (false, false, true, _) | (false, false, _, true) => '─',
+            (false, false, false, false) => ' ',
+        }
+    }
+}
+
+/// Tracks which commit each lane is waiting on as commits are added, newest first.
+#[derive(Default)]
+pub struct CommitGraph {
+    lanes: Vec<Option<[u8; 20]>>,
+}
+
+impl CommitGraph {
+    /// Adds the next commit to the graph, returning the HTML for its row.
+    pub fn push(&mut self, hash: &[u8; 20], parents: &[[u8; 20]]) -> String {
+        let column = self
+            .find(hash)
+            .unwrap_or_else(|| self.free_lane(usize::MAX));
+
+        let mut cells = vec![Cell::default(); self.lanes.len()];
+
+        for (cell, lane) in cells.iter_mut().zip(&self.lanes) {
+            cell.edges.set(Edges::UP, lane.is_some());
+        }
+
+        cells[column].commit = true;
+
+        // other lanes waiting on this commit are branches that forked from it, so join them in
+        for (lane, waiting) in self.lanes.iter_mut().enumerate() {
+            if lane != column && waiting.as_ref() == Some(hash) {
+                *waiting = None;
+                connect(&mut cells, lane, column);
+            }
+        }
+
+        self.lanes[column] = parents.first().copied();
+
+        // merge commits fan out to the lanes of their other parents
+        for parent in parents.iter().skip(1) {
+            let lane = self.find(parent).unwrap_or_else(|| {
+                let lane = self.free_lane(column);
+                self.lanes[lane] = Some(*parent);
+                lane
+            });
+
+            if cells.len() < self.lanes.len() {
+                cells.resize(self.lanes.len(), Cell::default());
+            }
+
+            connect(&mut cells, lane, column);
+        }
+
+        for (cell, lane) in cells.iter_mut().zip(&self.lanes) {
+            cell.edges.set(Edges::DOWN, lane.is_some());
+        }
+
+        while self.lanes.last().is_some_and(Option::is_none) {
+            self.lanes.pop();
+        }
+
+        render(&cells)
+    }
+
+    fn find(&self, hash: &[u8; 20]) -> Option<usize> {
+        self.lanes.iter().position(|v| v.as_ref() == Some(hash))
+    }
+
+    /// Finds an unused lane other than `except`, adding a new one if they're all in use.
+    fn free_lane(&mut self, except: usize) -> usize {
+        if let Some(lane) = (0..self.lanes.len()).find(|&i| i != except && self.lanes[i].is_none())
+        {
+            return lane;
+        }
+
+        self.lanes.push(None);
+        self.lanes.len() - 1
+    }
+}
+
+/// Draws a horizontal line from `lane` across to the commit at `column`.
+fn connect(cells: &mut [Cell], lane: usize, column: usize) {
+    let (start, end) = (lane.min(column), lane.max(column));
+
+    cells[start].edges.insert(Edges::RIGHT);
+    cells[end].edges.insert(Edges::LEFT);
+
+    for cell in &mut cells[start..end] {
+        cell.gap = Some(lane);
+    }
+
+    for cell in &mut cells[start + 1..end] {
+        cell.edges.insert(Edges::LEFT | Edges::RIGHT);
+    }
+}
+
+fn render(cells: &[Cell]) -> String {
+    let mut out = String::new();
+
+    for (lane, cell) in cells.iter().enumerate() {
+        let glyph = cell.glyph();
+
+        if glyph == ' ' {
+            out.push(' ');
+        } else {
+            write!(
+                out,
+                r#"<span class="graph-lane-{}">{glyph}</span>"#,
+                lane % LANE_COLOURS
+            )
+            .unwrap();
+        }
+
+        match cell.gap {
+            Some(gap) => write!(
+                out,
+                r#"<span class="graph-lane-{}">─</span>"#,
+                gap % LANE_COLOURS
+            )
+            .unwrap(),
+            None => out.push(' '),
+        }
+    }
+
+    out
+}
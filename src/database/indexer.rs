@@ -1,58 +1,297 @@
 use std::{
-    collections::HashSet,
+    cell::Cell,
+    collections::{BTreeMap, HashMap, HashSet},
     ffi::OsStr,
     fmt::Debug,
-    path::{Path, PathBuf},
-    sync::Arc,
+    num::NonZeroUsize,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use gix::{bstr::ByteSlice, refs::Category, Reference};
-use ini::Ini;
-use itertools::Itertools;
-use rocksdb::WriteBatch;
-use time::{OffsetDateTime, UtcOffset};
-use tracing::{error, info, info_span, instrument, warn};
-
-use crate::database::schema::{
-    commit::Commit,
-    repository::{ArchivedRepository, Repository, RepositoryId},
-    tag::{Tag, TagTree},
+use gix::{bstr::ByteSlice, refs::Category, ObjectId, Reference};
+use sha2::{Digest, Sha256};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use tracing::{error, info, info_span, instrument, warn, Event, Level, Subscriber};
+use tracing_subscriber::{layer, Layer};
+use uuid::Uuid;
+
+use crate::{
+    archive::SnapshotFormat,
+    database::{
+        progress::IndexProgress,
+        schema::{
+            activity::{ActivityIndex, ACTIVITY_INDEX_SIZE},
+            author::AuthoredCommit,
+            commit::{Author, Commit, CommitTree},
+            commit_location::CommitLocations,
+            compression::{self, CommitEncoder},
+            index_run::{IndexRun, IndexRunLog, IndexRunPhase},
+            prefixes::ALL_FAMILIES,
+            ref_update::{RefUpdate, RefUpdateKind, RefUpdateLog},
+            relation::{CommitRelations, RelationKind},
+            repository::{
+                ArchivedRepository, Divergence, Head, HeadCommit, Heads, MirrorStatus,
+                RecentCommit, Repository, RepositoryId, RepositoryLink, RepositorySize,
+                UpstreamMetadata, YokedRepository, RECENT_COMMITS,
+            },
+            tag::{Tag, TagTree},
+            tombstone::Tombstone,
+        },
+        storage::{Batch, Storage},
+    },
+    forge::Forge,
+    git::{default_branch_override, split_tag_signature},
+    linkify::LinkRule,
+    markdown::render_trusted_description,
+    methods::repo::{BranchOrder, DEFAULT_BRANCHES, REPOSITORY_VIEWS},
+    repository_config::{CgitRc, RepositoryConfig},
+    repository_pool::RepositoryPool,
+    scan_path::ScanPaths,
+    schedule::RunPlan,
+    shutdown,
+    trailers::{reverted_hash, split_trailers, upstream_hash, Trailer},
 };
 
-pub fn run(scan_path: &Path, db: &Arc<rocksdb::DB>) {
-    let span = info_span!("index_update");
+/// Settings for an index run that stay the same from one run to the next.
+#[derive(Clone, Debug, Default)]
+pub struct IndexOptions {
+    /// How often metadata of mirrored repositories is refreshed from their upstream forge
+    pub forge_sync_interval: Option<Duration>,
+    /// Whether commits are written compressed
+    pub compress_commits: bool,
+    /// How long a repository that's gone from the scan path is kept for before everything
+    /// indexed for it is deleted, zero deletes it straight away
+    pub tombstone_retention: Duration,
+    /// Whether the scan path is on a network filesystem, where failing to open a repository is
+    /// more likely to be a hiccup than the repository being broken or gone
+    pub network_filesystem: bool,
+    /// How many index runs in a row a repository has to be missing for before it's removed, on
+    /// a network filesystem
+    pub missing_runs: u32,
+}
+
+/// Opening a repository on a network filesystem is retried this many times, waiting twice as
+/// long as the last time between each attempt.
+const NETWORK_RETRIES: u32 = 3;
+
+/// How long to wait before the first retry.
+const NETWORK_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// What an index run carries over to the next, kept by whoever runs the indexer.
+#[derive(Default)]
+pub struct IndexState {
+    /// The number of index runs in a row each repository has been found missing from disk.
+    missing_runs: HashMap<String, u32>,
+}
+
+thread_local! {
+    /// The number of errors logged so far by the index run on this thread, `None` outside of a
+    /// run.
+    static RUN_ERRORS: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Counts the errors logged by index runs towards their reports. Only events that make it past
+/// the log filter are seen, so errors aren't counted if they're filtered out of the logs.
+pub struct RunErrorCounter;
+
+impl<S: Subscriber> Layer<S> for RunErrorCounter {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        if *event.metadata().level() == Level::ERROR {
+            RUN_ERRORS.with(|errors| errors.set(errors.get().map(|v| v + 1)));
+        }
+    }
+}
+
+/// Times each phase of an index run for its report.
+struct PhaseTimer<'a> {
+    report: &'a mut IndexRun,
+    started: Instant,
+}
+
+impl PhaseTimer<'_> {
+    /// Ends the current phase under the given name, and starts timing the next.
+    fn finish(&mut self, name: &str) {
+        let now = Instant::now();
+
+        self.report.phases.push(IndexRunPhase {
+            name: name.to_string(),
+            duration_ms: duration_millis(now - self.started),
+        });
+
+        self.started = now;
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Runs a full index update, returning the references that had new commits ingested keyed by
+/// the repository they belong to. A report of the run is written to the [`IndexRunLog`].
+pub fn run(
+    scan_paths: &ScanPaths,
+    db: &Arc<rocksdb::DB>,
+    repositories: &RepositoryPool,
+    progress: &IndexProgress,
+    options: &IndexOptions,
+    plan: &RunPlan,
+    state: &mut IndexState,
+) -> BTreeMap<String, Vec<String>> {
+    let request_id = Uuid::new_v4();
+
+    // shares the field name used by web requests, so log aggregators can group each index run's
+    // logs the same way
+    let span = info_span!("index_update", %request_id);
     let _entered = span.enter();
 
+    let started = Instant::now();
+    let mut report = IndexRun {
+        id: request_id.to_string(),
+        started_at: OffsetDateTime::now_utc().unix_timestamp(),
+        ..IndexRun::default()
+    };
+    RUN_ERRORS.with(|errors| errors.set(Some(0)));
+
     info!("Starting index update");
 
-    update_repository_metadata(scan_path, db);
-    update_repository_reflog(scan_path, db.clone());
-    update_repository_tags(scan_path, db.clone());
+    let mut encoder = CommitEncoder::new(db, options.compress_commits).unwrap_or_else(|error| {
+        error!(%error, "Failed to load commit compression dictionary, writing uncompressed");
+        CommitEncoder::uncompressed()
+    });
+
+    let mut timer = PhaseTimer {
+        report: &mut report,
+        started,
+    };
+
+    remove_missing_repositories(
+        scan_paths,
+        db,
+        options,
+        plan,
+        &mut state.missing_runs,
+        timer.report,
+    );
+    timer.finish("remove_missing");
+
+    update_repository_metadata(scan_paths, db, repositories, options, plan, timer.report);
+    timer.finish("metadata");
+
+    let updated_references = update_repository_reflog(
+        scan_paths,
+        db.clone(),
+        repositories,
+        progress,
+        &mut encoder,
+        options,
+        plan,
+        timer.report,
+    );
+    timer.finish("references");
+
+    update_repository_tags(scan_paths, db.clone(), repositories, options, plan);
+    timer.finish("tags");
+
+    match Tombstone::purge_expired(db, options.tombstone_retention) {
+        Ok(0) => {}
+        Ok(purged) => info!("Purged {purged} repositories that have been gone from disk"),
+        Err(error) => error!(%error, "Failed to purge removed repositories"),
+    }
+
+    if let Err(error) = ActivityIndex::new(db.clone()).truncate(ACTIVITY_INDEX_SIZE) {
+        error!(%error, "Failed to truncate activity index");
+    }
+
+    if options.compress_commits {
+        if let Err(error) = compression::compress_existing(db) {
+            error!(%error, "Failed to compress existing commits");
+        }
+    }
+    timer.finish("maintenance");
 
     info!("Flushing to disk");
 
+    // commits are written without the WAL, so every column family needs flushing for what's
+    // been written to survive a restart
+    for family in ALL_FAMILIES {
+        let Some(cf) = db.cf_handle(family) else {
+            continue;
+        };
+
+        if let Err(error) = db.flush_cf(cf) {
+            error!(%error, "Failed to flush {family} to disk");
+        }
+    }
+
     if let Err(error) = db.flush() {
         error!(%error, "Failed to flush database to disk");
     }
+    timer.finish("flush");
 
-    info!("Finished index update");
+    report.duration_ms = duration_millis(started.elapsed());
+    report.interrupted = shutdown::requested();
+    report.errors = RUN_ERRORS.with(|errors| errors.take()).unwrap_or_default();
+
+    // the report only reaches the database on the next flush, losing it to a crash isn't worth
+    // flushing everything again for
+    if let Err(error) = IndexRunLog::new(db.clone()).insert(&report) {
+        warn!(%error, "Failed to record index run report");
+    }
+
+    info!(
+        repositories_scanned = report.repositories_scanned,
+        repositories_added = report.repositories_added,
+        repositories_removed = report.repositories_removed,
+        commits_ingested = report.commits_ingested,
+        errors = report.errors,
+        "Finished index update"
+    );
+
+    updated_references
 }
 
-#[instrument(skip(db))]
-fn update_repository_metadata(scan_path: &Path, db: &rocksdb::DB) {
-    let mut discovered = Vec::new();
-    discover_repositories(scan_path, &mut discovered);
+#[instrument(skip(db, repositories, options, plan, report))]
+fn update_repository_metadata(
+    scan_paths: &ScanPaths,
+    db: &rocksdb::DB,
+    repositories: &RepositoryPool,
+    options: &IndexOptions,
+    plan: &RunPlan,
+    report: &mut IndexRun,
+) {
+    // repositories found when the database is empty were already there rather than newly added
+    let initial_run = Repository::fetch_all(db).is_ok_and(|v| v.is_empty());
+    let now = OffsetDateTime::now_utc();
+
+    for (relative, repository_path) in scan_paths.discover() {
+        if shutdown::requested() {
+            return;
+        }
 
-    for repository in discovered {
-        let Some(relative) = get_relative_path(scan_path, &repository) else {
-            continue;
-        };
+        report.repositories_scanned += 1;
 
-        let id = match Repository::open(db, relative) {
-            Ok(v) => v.map_or_else(RepositoryId::new, |v| {
-                RepositoryId(v.get().id.0.to_native())
-            }),
+        let relative = relative.as_path();
+
+        match relative.to_str().map(|v| Tombstone::restore(db, v)) {
+            Some(Ok(true)) => info!(
+                "Repository {} is back on disk, restoring it",
+                relative.display()
+            ),
+            Some(Ok(false)) | None => {}
+            Some(Err(error)) => {
+                warn!(%error, "Failed to restore repository {}", relative.display());
+            }
+        }
+
+        let existing = match Repository::open(db, relative) {
+            Ok(v) => v,
             Err(error) => {
                 // maybe we could nuke it ourselves, but we need to instantly trigger
                 // a reindex and we could enter into an infinite loop if there's a bug
@@ -62,18 +301,44 @@ fn update_repository_metadata(scan_path: &Path, db: &rocksdb::DB) {
             }
         };
 
+        // newly discovered repositories are always picked up, so they're known to the scheduler
+        if existing.is_some() && !relative.to_str().is_some_and(|v| plan.includes(v)) {
+            continue;
+        }
+
+        let id = existing.as_ref().map_or_else(RepositoryId::new, |v| {
+            RepositoryId(v.get().id.0.to_native())
+        });
+
         let Some(name) = relative.file_name().and_then(OsStr::to_str) else {
             continue;
         };
-        let description = std::fs::read(repository.join("description")).unwrap_or_default();
-        let description = String::from_utf8(description)
-            .ok()
-            .filter(|v| !v.is_empty());
 
-        let repository_path = scan_path.join(relative);
+        let config = RepositoryConfig::load(&repository_path);
+        let cgitrc = CgitRc::load(&repository_path);
 
-        let mut git_repository = match gix::open(repository_path.clone()) {
-            Ok(v) => v,
+        let upstream_metadata = options
+            .forge_sync_interval
+            .and_then(|interval| sync_upstream_metadata(&config, existing.as_ref(), interval));
+
+        let description = std::fs::read(repository_path.join("description")).unwrap_or_default();
+        let description = String::from_utf8(description)
+            .ok()
+            .filter(|v| !v.is_empty() && !v.starts_with(DEFAULT_DESCRIPTION))
+            .or_else(|| cgitrc.get("desc").map(ToString::to_string))
+            .or_else(|| upstream_metadata.as_ref()?.description.clone());
+
+        let description_html = description
+            .as_deref()
+            .filter(|_| config.get_bool("rgit", "trusteddescription"))
+            .map(render_trusted_description);
+
+        let mut git_repository = match with_retries(
+            options,
+            || repositories.get(&repository_path),
+            is_not_found,
+        ) {
+            Ok(v) => v.to_thread_local(),
             Err(error) => {
                 warn!(%error, "Failed to open repository {} to update metadata, skipping", relative.display());
                 continue;
@@ -86,18 +351,345 @@ fn update_repository_metadata(scan_path: &Path, db: &rocksdb::DB) {
             id,
             name: name.to_string(),
             description,
-            owner: find_gitweb_owner(repository_path.as_path()),
+            description_html,
+            owner: config
+                .get("gitweb", "owner")
+                .or_else(|| cgitrc.get("owner"))
+                .map(ToString::to_string)
+                .or_else(|| upstream_metadata.as_ref()?.owner.clone()),
+            homepage: config
+                .get("rgit", "homepage")
+                .or_else(|| config.get("gitweb", "homepage"))
+                .or_else(|| cgitrc.get("homepage"))
+                .map(str::trim)
+                .filter(|v| v.starts_with("https://") || v.starts_with("http://"))
+                .map(ToString::to_string),
+            links: config
+                .get_all("rgit", "link")
+                .filter_map(|link| match parse_repository_link(link) {
+                    Some(v) => Some(v),
+                    None => {
+                        warn!(link, "Invalid rgit.link in {}, ignoring", relative.display());
+                        None
+                    }
+                })
+                .collect(),
+            hidden: cgitrc.get_bool("hide"),
             last_modified: {
-                let r =
-                    find_last_committed_time(&git_repository).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+                let r = read_agefile(&repository_path)
+                    .or_else(|| find_last_committed_time(&git_repository).ok())
+                    .unwrap_or(OffsetDateTime::UNIX_EPOCH);
                 (r.unix_timestamp(), r.offset().whole_seconds())
             },
-            default_branch: find_default_branch(&git_repository).ok().flatten(),
+            first_indexed: match &existing {
+                Some(existing) => existing
+                    .get()
+                    .first_indexed
+                    .as_ref()
+                    .map(|v| (v.0.to_native(), v.1.to_native())),
+                None => (!initial_run).then(|| (now.unix_timestamp(), 0)),
+            },
+            default_branch: config
+                .get("rgit", "defaultbranch")
+                .and_then(default_branch_override)
+                .or_else(|| find_default_branch(&git_repository).ok().flatten()),
+            upstream_metadata,
+            link_rules: config
+                .get_all("rgit", "linkrule")
+                .filter(|rule| match LinkRule::from_str(rule) {
+                    Ok(_) => true,
+                    Err(error) => {
+                        warn!(%error, rule, "Invalid rgit.linkRule in {}, ignoring", relative.display());
+                        false
+                    }
+                })
+                .map(ToString::to_string)
+                .collect(),
+            snapshot_formats: config
+                .get_all("rgit", "snapshotformats")
+                .flat_map(|v| v.split(|c: char| c == ',' || c.is_whitespace()))
+                .filter(|v| !v.is_empty())
+                .filter(|format| match SnapshotFormat::from_str(format) {
+                    Ok(_) => true,
+                    Err(error) => {
+                        warn!(%error, "Invalid rgit.snapshotFormats in {}, ignoring", relative.display());
+                        false
+                    }
+                })
+                .map(ToString::to_string)
+                .collect(),
+            snapshot_compression_level: config
+                .get("rgit", "snapshotcompressionlevel")
+                .and_then(|v| v.trim().parse().ok()),
+            mirror_status: find_mirror_status(
+                &repository_path,
+                &config,
+                &git_repository,
+                existing.as_ref(),
+            ),
+            commit_count: existing
+                .as_ref()
+                .map_or(0, |v| v.get().commit_count.to_native()),
+            size: match measure_repository(&git_repository, existing.as_ref()) {
+                Ok(v) => Some(v),
+                Err(error) => {
+                    warn!(%error, "Failed to measure repository {}", relative.display());
+                    None
+                }
+            },
+            recent_commits: existing
+                .as_ref()
+                .and_then(|v| {
+                    rkyv::deserialize::<Vec<RecentCommit>, rkyv::rancor::Error>(
+                        &v.get().recent_commits,
+                    )
+                    .ok()
+                })
+                .unwrap_or_default(),
+            refresh_interval: config.get("rgit", "refreshinterval").and_then(|v| {
+                match humantime::parse_duration(v.trim()) {
+                    Ok(v) if !v.is_zero() => Some(v.as_secs().max(1)),
+                    _ => {
+                        warn!(value = v, "Invalid rgit.refreshInterval in {}, ignoring", relative.display());
+                        None
+                    }
+                }
+            }),
+            branch_order: config.get("rgit", "branchorder").and_then(|v| {
+                if BranchOrder::parse(v).is_some() {
+                    Some(v.trim().to_string())
+                } else {
+                    warn!(value = v, "Invalid rgit.branchOrder in {}, ignoring", relative.display());
+                    None
+                }
+            }),
+            bundle_uris: config
+                .get_all("rgit", "bundleuri")
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            bundle: config.get_bool("rgit", "bundle"),
+            mainline_branch: config
+                .get("rgit", "mainlinebranch")
+                .and_then(default_branch_override),
+            stable_branches: config
+                .get_all("rgit", "stablebranch")
+                .filter_map(default_branch_override)
+                .collect(),
+            crawler_excluded_views: config
+                .get_all("rgit", "crawlerexclude")
+                .flat_map(|v| v.split(|c: char| c == ',' || c.is_whitespace()))
+                .filter(|v| !v.is_empty())
+                .filter(|view| {
+                    if REPOSITORY_VIEWS.contains(view) {
+                        true
+                    } else {
+                        warn!(view, "Invalid rgit.crawlerExclude in {}, ignoring", relative.display());
+                        false
+                    }
+                })
+                .map(ToString::to_string)
+                .collect(),
         }
         .insert(db, relative);
 
-        if let Err(error) = res {
-            warn!(%error, "Failed to insert repository");
+        match res {
+            Ok(()) if existing.is_none() => report.repositories_added += 1,
+            Ok(()) => {}
+            Err(error) => warn!(%error, "Failed to insert repository"),
+        }
+    }
+}
+
+/// Parses an `rgit.link` value in the form `<label> <url>`, where the label may contain spaces
+/// and the URL must be `http` or `https`.
+fn parse_repository_link(value: &str) -> Option<RepositoryLink> {
+    let (label, url) = value.trim().rsplit_once(char::is_whitespace)?;
+    let label = label.trim();
+
+    (!label.is_empty() && (url.starts_with("https://") || url.starts_with("http://"))).then(|| {
+        RepositoryLink {
+            label: label.to_string(),
+            url: url.to_string(),
+        }
+    })
+}
+
+/// The description `git init` writes out to new repositories.
+pub const DEFAULT_DESCRIPTION: &str = "Unnamed repository;";
+
+/// Returns the upstream clone URL of a mirrored repository, either explicitly set via
+/// `rgit.upstream` or taken from `origin` if it was cloned with `--mirror`.
+pub fn find_upstream_url(config: &RepositoryConfig) -> Option<&str> {
+    config.get("rgit", "upstream").or_else(|| {
+        config
+            .get_bool(r#"remote "origin""#, "mirror")
+            .then(|| config.get(r#"remote "origin""#, "url"))
+            .flatten()
+    })
+}
+
+/// Works out how up to date a mirror is from the `FETCH_HEAD` left behind by `git fetch`. Git
+/// truncates `FETCH_HEAD` as soon as a fetch starts and only writes the fetched refs to it once
+/// they've been fetched, so an empty `FETCH_HEAD` means the last fetch failed.
+#[instrument(skip(config, git_repository, existing))]
+fn find_mirror_status(
+    repository_path: &Path,
+    config: &RepositoryConfig,
+    git_repository: &gix::Repository,
+    existing: Option<&YokedRepository>,
+) -> Option<MirrorStatus> {
+    let upstream_url = find_upstream_url(config)?;
+
+    let (last_fetch, succeeded) = match std::fs::metadata(repository_path.join("FETCH_HEAD")) {
+        Ok(metadata) => (
+            metadata
+                .modified()
+                .ok()
+                .map(|v| OffsetDateTime::from(v).unix_timestamp()),
+            metadata.len() > 0,
+        ),
+        Err(_) => (None, false),
+    };
+
+    let last_successful_fetch = if succeeded {
+        last_fetch
+    } else {
+        if last_fetch.is_some() {
+            warn!(upstream_url, "Last fetch of mirror from upstream failed");
+        }
+
+        existing
+            .and_then(|v| v.get().mirror_status.as_ref())
+            .and_then(|v| v.last_successful_fetch.as_ref())
+            .map(|v| v.to_native())
+    };
+
+    let commit_url = config
+        .get("rgit", "upstreamcommiturl")
+        .map(str::trim)
+        .filter(|v| {
+            let valid =
+                (v.starts_with("https://") || v.starts_with("http://")) && v.contains("{commit}");
+
+            if !valid {
+                warn!(url = *v, "Invalid rgit.upstreamCommitUrl, ignoring");
+            }
+
+            valid
+        })
+        .map(ToString::to_string)
+        .or_else(|| Some(Forge::from_url(upstream_url)?.commit_url_template()));
+
+    Some(MirrorStatus {
+        upstream_url: upstream_url.to_string(),
+        last_fetch,
+        last_successful_fetch,
+        behind: find_upstream_lag(git_repository),
+        commit_url,
+    })
+}
+
+/// Counts the commits on the remote-tracking branch of `origin` that haven't made it to the
+/// default branch. Mirrors cloned with `--mirror` fetch straight into their branches and don't
+/// have remote-tracking branches, so this is only determinable for regular clones.
+///
+/// Both branches are only walked as far back as needed, lags of more than [`MAX_UPSTREAM_LAG`]
+/// commits are reported as that many.
+fn find_upstream_lag(repo: &gix::Repository) -> Option<u64> {
+    const MAX_UPSTREAM_LAG: usize = 1_000;
+    /// How many of the default branch's commits are looked at to find the ones already merged.
+    const MAX_LOCAL_WALK: usize = 10_000;
+
+    let head = repo.head().ok()?;
+    let branch = head.referent_name()?.shorten().to_string();
+
+    let local = head.into_peeled_id().ok()?.detach();
+    let remote = repo
+        .find_reference(format!("refs/remotes/origin/{branch}").as_str())
+        .ok()?
+        .peel_to_id_in_place()
+        .ok()?
+        .detach();
+
+    if local == remote {
+        return Some(0);
+    }
+
+    let candidates: Vec<_> = repo
+        .rev_walk([remote])
+        .sorting(gix::revision::walk::Sorting::ByCommitTimeNewestFirst)
+        .selected(move |id| id != local.as_ref())
+        .ok()?
+        .take(MAX_UPSTREAM_LAG)
+        .filter_map(Result::ok)
+        .collect();
+
+    // anything the default branch has merged from upstream is at least as new as the oldest
+    // candidate, so there's no need to walk its history any further back than that
+    let oldest = candidates.iter().filter_map(|info| info.commit_time).min();
+    let merged: HashSet<ObjectId> = repo
+        .rev_walk([local])
+        .sorting(gix::revision::walk::Sorting::ByCommitTimeNewestFirst)
+        .all()
+        .ok()?
+        .map_while(Result::ok)
+        .take_while(|info| info.commit_time >= oldest)
+        .take(MAX_LOCAL_WALK)
+        .map(|info| info.id)
+        .collect();
+
+    Some(
+        candidates
+            .iter()
+            .filter(|info| !merged.contains(&info.id))
+            .count() as u64,
+    )
+}
+
+/// Refreshes metadata from the upstream forge of a mirror if the previously fetched copy is
+/// older than `interval`, falling back to the previously fetched copy if the forge can't be
+/// reached.
+fn sync_upstream_metadata(
+    config: &RepositoryConfig,
+    existing: Option<&YokedRepository>,
+    interval: Duration,
+) -> Option<UpstreamMetadata> {
+    // repositories can opt out of syncing with `rgit.forgeSync = false`
+    if config.get("rgit", "forgesync").is_some() && !config.get_bool("rgit", "forgesync") {
+        return None;
+    }
+
+    let forge = Forge::from_url(find_upstream_url(config)?)?;
+
+    let previous = existing
+        .and_then(|v| v.get().upstream_metadata.as_ref())
+        .and_then(|v| rkyv::deserialize::<UpstreamMetadata, rkyv::rancor::Error>(v).ok());
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    if let Some(previous) = previous.as_ref() {
+        let age = Duration::from_secs(now.saturating_sub(previous.fetched_at).unsigned_abs());
+        if age < interval {
+            return Some(previous.clone());
+        }
+    }
+
+    match forge.fetch() {
+        Ok(metadata) => {
+            info!(?forge, "Fetched upstream metadata");
+
+            Some(UpstreamMetadata {
+                description: metadata.description.filter(|v| !v.is_empty()),
+                owner: metadata.owner,
+                topics: metadata.topics,
+                fetched_at: now,
+            })
+        }
+        Err(error) => {
+            warn!(%error, ?forge, "Failed to fetch upstream metadata");
+            previous
         }
     }
 }
@@ -130,18 +722,190 @@ fn find_last_committed_time(repo: &gix::Repository) -> Result<OffsetDateTime, an
     Ok(timestamp)
 }
 
-#[instrument(skip(db))]
-fn update_repository_reflog(scan_path: &Path, db: Arc<rocksdb::DB>) {
+/// Adds up the size of every file in the repository's git directory and counts the objects in
+/// it, so operators can spot repositories that have grown out of hand. Walking the directory is
+/// slow for large repositories, so the previous measurement is kept if nothing's been pushed
+/// since.
+fn measure_repository(
+    repo: &gix::Repository,
+    existing: Option<&YokedRepository>,
+) -> anyhow::Result<RepositorySize> {
+    fn walk(dir: &Path, bytes: &mut u64) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+
+            // symlinks, such as alternates pointing elsewhere, aren't followed
+            if file_type.is_dir() {
+                walk(&entry.path(), bytes)?;
+            } else if file_type.is_file() {
+                *bytes += entry.metadata()?.len();
+            }
+        }
+
+        Ok(())
+    }
+
+    let refs = refs_fingerprint(repo)?;
+
+    if let Some(previous) = existing
+        .and_then(|v| v.get().size.as_ref())
+        .filter(|v| v.refs == refs)
+    {
+        return Ok(RepositorySize {
+            bytes: previous.bytes.to_native(),
+            objects: previous.objects.to_native(),
+            refs,
+        });
+    }
+
+    let mut bytes = 0;
+    walk(repo.git_dir(), &mut bytes).context("Failed to walk git directory")?;
+
+    // loose objects live in directories named after the first byte of their ID
+    let mut loose = 0;
+    for entry in std::fs::read_dir(repo.common_dir().join("objects"))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        if name.len() == 2 && name.bytes().all(|c| c.is_ascii_hexdigit()) {
+            loose += std::fs::read_dir(entry.path())?.count() as u64;
+        }
+    }
+
+    let packed = repo
+        .objects
+        .packed_object_count()
+        .context("Failed to count packed objects")?;
+
+    Ok(RepositorySize {
+        bytes,
+        objects: loose + packed,
+        refs,
+    })
+}
+
+/// A digest of every reference in the repository and what it points to, which changes whenever
+/// anything is pushed.
+fn refs_fingerprint(repo: &gix::Repository) -> anyhow::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|error| anyhow::anyhow!("{error}"))?;
+
+        hasher.update(reference.name().as_bstr());
+        hasher.update([0]);
+        match reference.target() {
+            gix::refs::TargetRef::Object(id) => hasher.update(id.as_bytes()),
+            gix::refs::TargetRef::Symbolic(name) => hasher.update(name.as_bstr()),
+        }
+        hasher.update([0]);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Reads the time the repository was last pushed to from the cgit-style agefile that
+/// post-receive hooks commonly leave at `info/web/last-modified`, which saves walking every
+/// reference to find it.
+fn read_agefile(repository_path: &Path) -> Option<OffsetDateTime> {
+    let content = std::fs::read_to_string(repository_path.join("info/web/last-modified")).ok()?;
+    let content = content.trim();
+
+    let parsed = parse_agefile_time(content);
+
+    if parsed.is_none() && !content.is_empty() {
+        warn!(
+            content,
+            "Unrecognised agefile in {}, ignoring",
+            repository_path.display()
+        );
+    }
+
+    parsed
+}
+
+/// Parses an agefile timestamp, either as seconds since the epoch or in the
+/// `YYYY-MM-DD HH:MM:SS [+-]HHMM` format given by `git for-each-ref --format='%(committerdate:iso)'`.
+fn parse_agefile_time(s: &str) -> Option<OffsetDateTime> {
+    if let Ok(seconds) = s.trim_start_matches('@').parse::<i64>() {
+        return OffsetDateTime::from_unix_timestamp(seconds).ok();
+    }
+
+    let mut parts = s.split_whitespace();
+    let first = parts.next()?;
+    let (date, clock) = match first.split_once('T') {
+        Some(v) => v,
+        None => (first, parts.next()?),
+    };
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i32>);
+    let year = date.next()?.ok()?;
+    let month = u8::try_from(date.next()?.ok()?).ok()?;
+    let day = u8::try_from(date.next()?.ok()?).ok()?;
+
+    let mut clock = clock.splitn(3, ':').map(str::parse::<u8>);
+    let hour = clock.next()?.ok()?;
+    let minute = clock.next()?.ok()?;
+    let second = clock.next().transpose().ok()?.unwrap_or(0);
+
+    let offset = match parts.next() {
+        Some(offset) => {
+            let (sign, offset) = match offset.split_at_checked(1)? {
+                ("+", offset) => (1, offset),
+                ("-", offset) => (-1, offset),
+                _ => return None,
+            };
+            let offset = offset.replace(':', "");
+            let hours = offset.get(..2)?.parse::<i8>().ok()?;
+            let minutes = offset.get(2..)?.parse::<i8>().ok()?;
+
+            UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()?
+        }
+        None => UtcOffset::UTC,
+    };
+
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    let clock = Time::from_hms(hour, minute, second).ok()?;
+
+    Some(PrimitiveDateTime::new(date, clock).assume_offset(offset))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(db, repositories, progress, encoder, options, plan, report))]
+fn update_repository_reflog(
+    scan_paths: &ScanPaths,
+    db: Arc<rocksdb::DB>,
+    repositories: &RepositoryPool,
+    progress: &IndexProgress,
+    encoder: &mut CommitEncoder,
+    options: &IndexOptions,
+    plan: &RunPlan,
+    report: &mut IndexRun,
+) -> BTreeMap<String, Vec<String>> {
+    let mut updated_references = BTreeMap::new();
+
     let repos = match Repository::fetch_all(&db) {
         Ok(v) => v,
         Err(error) => {
             error!(%error, "Failed to read repository index to update reflog, consider deleting database directory");
-            return;
+            return updated_references;
         }
     };
 
     for (relative_path, db_repository) in repos {
-        let Some(git_repository) = open_repo(scan_path, &relative_path, db_repository.get(), &db)
+        if shutdown::requested() {
+            break;
+        }
+
+        if !plan.includes(&relative_path) {
+            continue;
+        }
+
+        let Some(git_repository) = open_repo(scan_paths, &relative_path, repositories, options)
         else {
             continue;
         };
@@ -162,9 +926,30 @@ fn update_repository_reflog(scan_path: &Path, db: Arc<rocksdb::DB>) {
             }
         };
 
+        let previously_indexed = db_repository.get().is_indexed(&db).unwrap_or_else(|error| {
+            error!(%error, "Failed to check whether {relative_path} has been indexed");
+            true
+        });
+
         let mut valid_references = Vec::new();
+        let mut ref_updates = Vec::new();
+
+        // the references as of the last run, to tell created references from ones that were
+        // deleted and then came back, and to find the ones that have since been deleted
+        let previous_references: HashSet<String> = match db_repository.get().heads(&db) {
+            Ok(Some(heads)) => heads.get().0.iter().map(|v| v.name.to_string()).collect(),
+            Ok(None) => HashSet::new(),
+            Err(error) => {
+                warn!(%error, "Failed to read previous heads for {relative_path}");
+                HashSet::new()
+            }
+        };
 
         for reference in references {
+            if shutdown::requested() {
+                break;
+            }
+
             let mut reference = match reference {
                 Ok(v) => v,
                 Err(error) => {
@@ -183,33 +968,271 @@ fn update_repository_reflog(scan_path: &Path, db: Arc<rocksdb::DB>) {
 
             valid_references.push(reference_name.as_bstr().to_string());
 
-            if let Err(error) = branch_index_update(
+            match branch_index_update(
                 &mut reference,
                 &relative_path,
                 db_repository.get(),
                 db.clone(),
                 &git_repository,
+                encoder,
                 false,
+                &mut report.commits_ingested,
             ) {
-                error!(%error, "Failed to update reflog for {relative_path}@{:?}", valid_references.last());
+                Ok(Some(mut update)) => {
+                    updated_references
+                        .entry(relative_path.clone())
+                        .or_insert_with(Vec::new)
+                        .push(update.reference.clone());
+
+                    if !previous_references.contains(&update.reference) {
+                        update.kind = RefUpdateKind::Created;
+                        update.old = None;
+                    }
+
+                    ref_updates.push(update);
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    error!(%error, "Failed to update reflog for {relative_path}@{:?}", valid_references.last());
+                }
             }
         }
 
-        if let Err(error) = db_repository.get().replace_heads(&db, &valid_references) {
+        // the references we didn't get to would be missing from the heads
+        if shutdown::requested() {
+            break;
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for name in &previous_references {
+            if valid_references.contains(name) {
+                continue;
+            }
+
+            let old = match db_repository
+                .get()
+                .commit_tree(db.clone(), name)
+                .fetch_latest_one()
+            {
+                Ok(v) => v.map(|v| v.get().hash),
+                Err(error) => {
+                    warn!(%error, "Failed to find last indexed commit of {relative_path}@{name}");
+                    None
+                }
+            };
+
+            ref_updates.push(RefUpdate {
+                reference: name.clone(),
+                kind: RefUpdateKind::Deleted,
+                old,
+                new: None,
+                observed_at: now,
+            });
+        }
+
+        // the first run over a repository would otherwise log every reference as created
+        if previously_indexed {
+            if let Err(error) = RefUpdateLog::new(
+                db.clone(),
+                RepositoryId(db_repository.get().id.0.to_native()),
+            )
+            .insert(&ref_updates)
+            {
+                error!(%error, "Failed to record reference updates for {relative_path}");
+            }
+        }
+
+        let heads = build_heads(&git_repository, db_repository.get(), &db, valid_references);
+
+        if let Err(error) = db_repository.get().replace_heads(&db, &heads) {
             error!(%error, "Failed to update heads");
+        } else {
+            if previously_indexed {
+                for update in &ref_updates {
+                    progress.reference_changed(&relative_path, update);
+                }
+            } else {
+                progress.repository_ready(&relative_path);
+            }
+
+            if !ref_updates.is_empty() {
+                progress.repository_updated(&relative_path);
+            }
+        }
+
+        if let Err(error) = update_default_branch_summary(&db, &relative_path, db_repository.get())
+        {
+            error!(%error, "Failed to update default branch summary for {relative_path}");
         }
     }
+
+    updated_references
 }
 
-#[instrument(skip(reference, db_repository, db, git_repository))]
+/// Records the length and newest few commits of the default branch on the repository, so the
+/// index page can show them without going through each repository's commits.
+fn update_default_branch_summary(
+    db: &Arc<rocksdb::DB>,
+    relative_path: &str,
+    db_repository: &ArchivedRepository,
+) -> anyhow::Result<()> {
+    let summary = db_repository
+        .default_branch
+        .as_deref()
+        .into_iter()
+        .chain(DEFAULT_BRANCHES)
+        .map(|branch| db_repository.commit_tree(db.clone(), branch))
+        .find_map(|tree| {
+            let commit_count = tree.len().ok().filter(|v| *v > 0)?;
+            let recent = tree.fetch_latest(RECENT_COMMITS, 0).ok()?;
+            Some((commit_count, recent))
+        });
+
+    let Some((commit_count, recent)) = summary else {
+        return Ok(());
+    };
+
+    if db_repository.commit_count.to_native() == commit_count
+        && db_repository.recent_commits.len() == recent.len()
+        && db_repository
+            .recent_commits
+            .iter()
+            .zip(&recent)
+            .all(|(existing, commit)| existing.hash == commit.get().hash)
+    {
+        return Ok(());
+    }
+
+    let mut repository = db_repository.deserialize()?;
+    repository.commit_count = commit_count;
+    repository.recent_commits = recent
+        .iter()
+        .map(|commit| {
+            let commit = commit.get();
+
+            RecentCommit {
+                hash: commit.hash,
+                summary: commit.summary.to_string(),
+                author: commit.author.name.to_string(),
+                time: (
+                    commit.author.time.0.to_native(),
+                    commit.author.time.1.to_native(),
+                ),
+            }
+        })
+        .collect();
+    repository.insert(db, relative_path)
+}
+
+/// Works out how far each branch has diverged from the default branch so the refs page doesn't
+/// have to, reusing the previous result for any branch where neither tip has moved.
+fn build_heads(
+    git_repository: &gix::Repository,
+    db_repository: &ArchivedRepository,
+    db: &Arc<rocksdb::DB>,
+    references: Vec<String>,
+) -> Heads {
+    let previous = match db_repository.heads(db) {
+        Ok(v) => v,
+        Err(error) => {
+            warn!(%error, "Failed to read previous heads, recomputing divergence");
+            None
+        }
+    };
+
+    // the tip of a reference and the number of commits reachable from it, as indexed
+    let indexed_tip = |name: &str| -> Option<HeadCommit> {
+        let commit_tree = db_repository.commit_tree(db.clone(), name);
+        let latest = commit_tree.fetch_latest_one().ok()??;
+        let latest = latest.get();
+
+        Some(HeadCommit {
+            hash: latest.hash,
+            summary: latest.summary.to_string(),
+            author: rkyv::deserialize::<Author, rkyv::rancor::Error>(&latest.author).ok()?,
+            committed_at: latest.committer.time.0.to_native(),
+            commit_count: commit_tree.len().ok()?,
+        })
+    };
+
+    let default_branch = db_repository.default_branch.as_deref();
+    let default_tip = default_branch
+        .and_then(indexed_tip)
+        .map(|v| (v.hash, v.commit_count));
+    let mut default_ancestors: Option<Arc<HashSet<ObjectId>>> = None;
+
+    let mut heads = Vec::with_capacity(references.len());
+
+    for name in references {
+        let latest = indexed_tip(&name);
+
+        let divergence = default_tip
+            .filter(|_| name.starts_with("refs/heads/") && Some(name.as_str()) != default_branch)
+            .and_then(|(base, base_len)| {
+                let (tip, len) = latest.as_ref().map(|v| (v.hash, v.commit_count))?;
+
+                let previous = previous
+                    .as_ref()
+                    .and_then(|heads| heads.get().0.iter().find(|head| head.name.as_str() == name))
+                    .and_then(|head| head.divergence.as_ref())
+                    .filter(|v| v.tip == tip && v.base == base);
+
+                if let Some(previous) = previous {
+                    return rkyv::deserialize::<Divergence, rkyv::rancor::Error>(previous).ok();
+                }
+
+                let ancestors = default_ancestors
+                    .get_or_insert_with(|| {
+                        Arc::new(
+                            git_repository
+                                .rev_walk([ObjectId::Sha1(base)])
+                                .all()
+                                .into_iter()
+                                .flatten()
+                                .filter_map(Result::ok)
+                                .map(|info| info.id)
+                                .collect(),
+                        )
+                    })
+                    .clone();
+
+                let ahead = git_repository
+                    .rev_walk([ObjectId::Sha1(tip)])
+                    .selected(move |id| !ancestors.contains(id))
+                    .ok()?
+                    .filter_map(Result::ok)
+                    .count() as u64;
+
+                Some(Divergence {
+                    ahead,
+                    behind: base_len.saturating_sub(len.saturating_sub(ahead)),
+                    tip,
+                    base,
+                })
+            });
+
+        heads.push(Head {
+            name,
+            divergence,
+            latest,
+        });
+    }
+
+    Heads(heads)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(reference, db_repository, db, git_repository, encoder, ingested))]
 fn branch_index_update(
     reference: &mut Reference<'_>,
     relative_path: &str,
     db_repository: &ArchivedRepository,
     db: Arc<rocksdb::DB>,
     git_repository: &gix::Repository,
+    encoder: &mut CommitEncoder,
     force_reindex: bool,
-) -> Result<(), anyhow::Error> {
+    ingested: &mut u64,
+) -> Result<Option<RefUpdate>, anyhow::Error> {
     info!("Refreshing indexes");
 
     let commit_tree = db_repository.commit_tree(db.clone(), reference.name().as_bstr().to_str()?);
@@ -219,11 +1242,14 @@ fn branch_index_update(
     }
 
     let commit = reference.peel_to_commit()?;
+    let new = match commit.id().detach() {
+        ObjectId::Sha1(d) => d,
+    };
 
     let latest_indexed = if let Some(latest_indexed) = commit_tree.fetch_latest_one()? {
         if commit.id().as_bytes() == latest_indexed.get().hash.as_slice() {
             info!("No commits since last index");
-            return Ok(());
+            return Ok(None);
         }
 
         Some(latest_indexed)
@@ -231,71 +1257,317 @@ fn branch_index_update(
         None
     };
 
-    // TODO: stop collecting into a vec
     let revwalk = git_repository
         .rev_walk([commit.id().detach()])
         .all()?
-        .collect::<Vec<_>>()
+        .map(|rev| rev.map(|rev| rev.id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the walk is newest first, but commits are numbered in the order they're ingested
+    let mut pending = revwalk.into_iter().rev().collect::<Vec<_>>();
+
+    if let Some(latest_indexed) = &latest_indexed {
+        let Some(position) = pending
+            .iter()
+            .position(|id| id.as_bytes() == latest_indexed.get().hash.as_slice())
+        else {
+            warn!("Detected converged history, forcing reindex");
+
+            let old = latest_indexed.get().hash;
+
+            return Ok(branch_index_update(
+                reference,
+                relative_path,
+                db_repository,
+                db,
+                git_repository,
+                encoder,
+                true,
+                ingested,
+            )?
+            .map(|update| RefUpdate {
+                kind: RefUpdateKind::Forced,
+                old: Some(old),
+                ..update
+            }));
+        };
+
+        pending.drain(..=position);
+    }
+
+    ingest_commits(
+        git_repository,
+        relative_path,
+        &commit_tree,
+        &CommitRelations::new(db.clone(), RepositoryId(db_repository.id.0.to_native())),
+        RepositoryId(db_repository.id.0.to_native()),
+        &db,
+        encoder,
+        &pending,
+    )?;
+
+    *ingested += pending.len() as u64;
+
+    Ok(Some(RefUpdate {
+        reference: reference.name().as_bstr().to_string(),
+        kind: if latest_indexed.is_some() {
+            RefUpdateKind::FastForward
+        } else {
+            RefUpdateKind::Created
+        },
+        old: latest_indexed.map(|v| v.get().hash),
+        new: Some(new),
+        observed_at: OffsetDateTime::now_utc().unix_timestamp(),
+    }))
+}
+
+/// Number of commits written to the database in each batch.
+const INGEST_CHUNK_SIZE: usize = 250;
+
+/// Number of loaded chunks allowed to queue up waiting on the writer, bounding memory use when
+/// the database is slower to write to than commits are to load.
+const INGEST_CHANNEL_CAPACITY: usize = 8;
+
+/// Upper bound on the threads loading commits for a single branch.
+const MAX_INGEST_LOADERS: usize = 8;
+
+/// Number of chunks loaders can get ahead of the one the writer is waiting on. Chunks that arrive
+/// early are held on to until it's their turn, so without a limit one slow chunk would leave the
+/// rest of the branch piling up in memory behind it.
+const INGEST_MAX_CHUNKS_AHEAD: usize = 16;
+
+/// How many chunks have been written, for loaders to wait on before running too far ahead.
+#[derive(Default)]
+struct IngestProgress {
+    written: Mutex<usize>,
+    advanced: Condvar,
+}
+
+impl IngestProgress {
+    /// Blocks until chunk `index` is within [`INGEST_MAX_CHUNKS_AHEAD`] of the writer.
+    fn wait_for_turn(&self, index: usize) {
+        let written = self.written.lock().unwrap();
+        let _written = self
+            .advanced
+            .wait_while(written, |written| {
+                index >= written.saturating_add(INGEST_MAX_CHUNKS_AHEAD)
+            })
+            .unwrap();
+    }
+
+    fn advance(&self, written: usize) {
+        *self.written.lock().unwrap() = written;
+        self.advanced.notify_all();
+    }
+}
+
+/// Loads commits on a pool of threads while the calling thread writes them to the database,
+/// chunks are written in the order they were walked so commit numbering and the branch's counter
+/// stay consistent regardless of which loader finishes first.
+#[allow(clippy::too_many_arguments)]
+fn ingest_commits(
+    git_repository: &gix::Repository,
+    relative_path: &str,
+    commit_tree: &CommitTree,
+    relations: &CommitRelations,
+    repository_id: RepositoryId,
+    db: &Arc<rocksdb::DB>,
+    encoder: &mut CommitEncoder,
+    ids: &[ObjectId],
+) -> Result<(), anyhow::Error> {
+    let repository = git_repository.clone().into_sync();
+    let mailmap = git_repository.open_mailmap();
+    let chunks = ids.chunks(INGEST_CHUNK_SIZE).collect::<Vec<_>>();
+    let next_chunk = AtomicUsize::new(0);
+    let progress = IngestProgress::default();
+
+    let loaders = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(MAX_INGEST_LOADERS)
+        .min(chunks.len());
+
+    let (tx, rx) = mpsc::sync_channel(INGEST_CHANNEL_CAPACITY);
+
+    std::thread::scope(|scope| {
+        for _ in 0..loaders {
+            let tx = tx.clone();
+            let (repository, mailmap, chunks, next_chunk, progress) =
+                (&repository, &mailmap, &chunks, &next_chunk, &progress);
+
+            scope.spawn(move || {
+                let repository = repository.to_thread_local();
+
+                loop {
+                    let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                    let Some(chunk) = chunks.get(index) else {
+                        break;
+                    };
+
+                    progress.wait_for_turn(index);
+
+                    let commits = chunk
+                        .iter()
+                        .map(|id| load_commit(&repository, mailmap, *id))
+                        .collect::<Result<Vec<_>, _>>();
+
+                    // the writer has bailed out, so there's nobody left to load commits for
+                    if tx.send((index, commits)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(tx);
+
+        // returning drops the receiver, which stops the loaders if the writer failed
+        let result = write_commits(
+            rx,
+            chunks.len(),
+            relative_path,
+            commit_tree,
+            relations,
+            repository_id,
+            db,
+            encoder,
+            &progress,
+        );
+
+        // let any loaders still waiting for their turn see the writer's gone
+        progress.advance(usize::MAX);
+
+        result
+    })
+}
+
+/// A commit ready to be written, along with the commits its message says it reverts, fixes or
+/// backports.
+type LoadedCommit = (Commit, Vec<(RelationKind, [u8; 20])>);
+
+fn load_commit(
+    repository: &gix::Repository,
+    mailmap: &gix::mailmap::Snapshot,
+    id: ObjectId,
+) -> Result<LoadedCommit, anyhow::Error> {
+    let commit = repository.find_commit(id)?;
+    let author = mailmap.resolve(commit.author()?);
+    let committer = mailmap.resolve(commit.committer()?);
+
+    let commit = Commit::new(&commit, author.to_ref(), committer.to_ref())?;
+    let relations = find_relations(repository, &commit.message);
+
+    Ok((commit, relations))
+}
+
+/// Resolves the commits a commit message says it reverts, fixes or backports, dropping any that
+/// aren't in the repository (ie. because they were cherry-picked from elsewhere).
+fn find_relations(repository: &gix::Repository, message: &str) -> Vec<(RelationKind, [u8; 20])> {
+    // trailers can only follow the summary, which is never one itself
+    let body = message.split_once("\n\n").map_or("", |(_, body)| body);
+    let (_, trailers) = split_trailers(body);
+
+    let mut hashes: Vec<_> = trailers
+        .iter()
+        .filter_map(Trailer::fixes_hash)
+        .map(|hash| (RelationKind::Fixes, hash))
+        .collect();
+    hashes.extend(reverted_hash(message).map(|hash| (RelationKind::Reverts, hash)));
+    hashes.extend(upstream_hash(message).map(|hash| (RelationKind::Backports, hash)));
+
+    hashes
         .into_iter()
-        .rev();
+        .filter_map(|(kind, hash)| {
+            let id = repository
+                .rev_parse_single(format!("{hash}^{{commit}}").as_str())
+                .ok()?;
+
+            match id.detach() {
+                ObjectId::Sha1(d) => Some((kind, d)),
+            }
+        })
+        .collect()
+}
 
+/// Writes each chunk of loaded commits in a batch of its own, holding on to any that arrive ahead
+/// of their turn until the chunks before them have been written. `progress` is advanced as each
+/// chunk is written, which keeps the number held on to bounded.
+#[allow(clippy::too_many_arguments)]
+fn write_commits(
+    rx: Receiver<(usize, Result<Vec<LoadedCommit>, anyhow::Error>)>,
+    chunk_count: usize,
+    relative_path: &str,
+    commit_tree: &CommitTree,
+    relations: &CommitRelations,
+    repository_id: RepositoryId,
+    db: &Arc<rocksdb::DB>,
+    encoder: &mut CommitEncoder,
+    progress: &IngestProgress,
+) -> Result<(), anyhow::Error> {
     let tree_len = commit_tree.len()?;
-    let mut seen = false;
+    let mut ahead = BTreeMap::new();
     let mut i = 0;
-    for revs in &revwalk.chunks(250) {
-        let mut batch = WriteBatch::default();
-
-        for rev in revs {
-            let rev = rev?;
 
-            if let (false, Some(latest_indexed)) = (seen, &latest_indexed) {
-                if rev.id.as_bytes() == latest_indexed.get().hash.as_slice() {
-                    seen = true;
-                }
+    for index in 0..chunk_count {
+        // each batch updates the counter along with the commits it writes, so stopping between
+        // them leaves the tree consistent for the next run to pick up from
+        if shutdown::requested() {
+            anyhow::bail!("Shutting down, stopped after {i} commits");
+        }
 
-                continue;
+        let commits = loop {
+            if let Some(commits) = ahead.remove(&index) {
+                break commits;
             }
 
-            seen = true;
+            let (received, commits) = rx.recv().context("commit loader exited early")?;
+            ahead.insert(received, commits?);
+        };
+
+        let mut batch = Batch::default();
+        let mut latest = [0; 20];
 
+        for (commit, commit_relations) in commits {
             if ((i + 1) % 25_000) == 0 {
                 info!("{} commits ingested", i + 1);
             }
 
-            let commit = rev.object()?;
-            let author = commit.author()?;
-            let committer = commit.committer()?;
+            commit.insert(commit_tree, tree_len + i, encoder, &mut batch)?;
+            latest = commit.hash;
+            CommitLocations::insert(commit.hash, repository_id, relative_path, &mut batch);
+
+            for (kind, target) in commit_relations {
+                relations.insert(target, kind, commit.hash, &mut batch);
+
+                if kind == RelationKind::Backports {
+                    commit_tree.insert_backport(target, commit.hash, &mut batch);
+                }
+            }
 
-            Commit::new(&commit, author, committer)?.insert(
-                &commit_tree,
-                tree_len + i,
-                &mut batch,
-            )?;
+            let authored = AuthoredCommit {
+                repository: relative_path.to_string(),
+                commit,
+            };
+            authored.insert(&mut batch)?;
+            ActivityIndex::insert(&authored, &mut batch)?;
             i += 1;
         }
 
-        commit_tree.update_counter(tree_len + i, &mut batch)?;
+        commit_tree.update_counter(tree_len + i, latest, &mut batch);
         db.write_without_wal(batch)?;
-    }
-
-    if !seen && !force_reindex {
-        warn!("Detected converged history, forcing reindex");
-
-        return branch_index_update(
-            reference,
-            relative_path,
-            db_repository,
-            db,
-            git_repository,
-            true,
-        );
+        progress.advance(index + 1);
     }
 
     Ok(())
 }
 
-#[instrument(skip(db))]
-fn update_repository_tags(scan_path: &Path, db: Arc<rocksdb::DB>) {
+#[instrument(skip(db, repositories, options, plan))]
+fn update_repository_tags(
+    scan_paths: &ScanPaths,
+    db: Arc<rocksdb::DB>,
+    repositories: &RepositoryPool,
+    options: &IndexOptions,
+    plan: &RunPlan,
+) {
     let repos = match Repository::fetch_all(&db) {
         Ok(v) => v,
         Err(error) => {
@@ -305,7 +1577,15 @@ fn update_repository_tags(scan_path: &Path, db: Arc<rocksdb::DB>) {
     };
 
     for (relative_path, db_repository) in repos {
-        let Some(git_repository) = open_repo(scan_path, &relative_path, db_repository.get(), &db)
+        if shutdown::requested() {
+            break;
+        }
+
+        if !plan.includes(&relative_path) {
+            continue;
+        }
+
+        let Some(git_repository) = open_repo(scan_paths, &relative_path, repositories, options)
         else {
             continue;
         };
@@ -367,7 +1647,16 @@ fn tag_index_update(
     if let Ok(tag) = reference.peel_to_tag() {
         info!("Inserting newly discovered tag to index");
 
-        Tag::new(tag.tagger()?)?.insert(tag_tree, tag_name)?;
+        let decoded = tag.decode()?;
+        let message = decoded.message.to_string();
+        let (message, _signature) = split_tag_signature(&message);
+
+        Tag::new(decoded.tagger, Some(message.to_string()))?.insert(tag_tree, tag_name)?;
+    } else if let Ok(commit) = git_repository.find_reference(tag_name)?.peel_to_commit() {
+        // lightweight tags have no tagger of their own, so are dated by the commit they point to
+        info!("Inserting newly discovered lightweight tag to index");
+
+        Tag::new(Some(commit.committer()?), None)?.insert(tag_tree, tag_name)?;
     }
 
     Ok(())
@@ -381,25 +1670,26 @@ fn tag_index_delete(tag_name: &str, tag_tree: &TagTree) -> Result<(), anyhow::Er
     Ok(())
 }
 
-#[instrument(skip(scan_path, db_repository, db))]
+#[instrument(skip(scan_paths, repositories, options))]
 fn open_repo<P: AsRef<Path> + Debug>(
-    scan_path: &Path,
+    scan_paths: &ScanPaths,
     relative_path: P,
-    db_repository: &ArchivedRepository,
-    db: &rocksdb::DB,
+    repositories: &RepositoryPool,
+    options: &IndexOptions,
 ) -> Option<gix::Repository> {
-    match gix::open(scan_path.join(relative_path.as_ref())) {
-        Ok(mut v) => {
+    let Some(path) = scan_paths.resolve(relative_path) else {
+        warn!("Repository no longer listed by any scan path, skipping");
+        return None;
+    };
+
+    match with_retries(options, || repositories.get(&path), is_not_found) {
+        Ok(v) => {
+            let mut v = v.to_thread_local();
             v.object_cache_size(10 * 1024 * 1024);
             Some(v)
         }
-        Err(gix::open::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
-            warn!("Repository gone from disk, removing from db");
-
-            if let Err(error) = db_repository.delete(db, relative_path) {
-                warn!(%error, "Failed to delete dangling index");
-            }
-
+        Err(error) if is_not_found(&error) => {
+            warn!("Repository gone from disk, skipping");
             None
         }
         Err(error) => {
@@ -409,40 +1699,127 @@ fn open_repo<P: AsRef<Path> + Debug>(
     }
 }
 
-fn get_relative_path<'a>(relative_to: &Path, full_path: &'a Path) -> Option<&'a Path> {
-    full_path.strip_prefix(relative_to).ok()
+fn is_not_found(error: &gix::open::Error) -> bool {
+    matches!(error, gix::open::Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Calls `f` until it succeeds, retrying with an exponential backoff if the scan path is on a
+/// network filesystem, where errors are usually down to the filesystem rather than the
+/// repository. Errors saying the repository doesn't exist aren't retried, these are handled by
+/// [`remove_missing_repositories`] instead.
+fn with_retries<T, E: std::fmt::Display>(
+    options: &IndexOptions,
+    mut f: impl FnMut() -> Result<T, E>,
+    is_not_found: impl Fn(&E) -> bool,
+) -> Result<T, E> {
+    let mut backoff = NETWORK_RETRY_BACKOFF;
+
+    for _ in 0..NETWORK_RETRIES {
+        match f() {
+            Err(error)
+                if options.network_filesystem
+                    && !is_not_found(&error)
+                    && !shutdown::requested() =>
+            {
+                warn!(%error, "Failed to access repository, retrying in {backoff:?}");
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            res => return res,
+        }
+    }
+
+    f()
 }
 
-fn discover_repositories(current: &Path, discovered_repos: &mut Vec<PathBuf>) {
-    let current = match std::fs::read_dir(current) {
+/// Removes repositories that are no longer on disk from the index, or moves them to the recycle
+/// bin if tombstones are being kept. On a network filesystem a repository has to be missing on
+/// several index runs in a row before it's removed, as a flaky mount can briefly report it as
+/// gone, and no repositories are removed at all if the scan path itself can't be read.
+#[instrument(skip(db, options, plan, missing_runs, report))]
+fn remove_missing_repositories(
+    scan_paths: &ScanPaths,
+    db: &rocksdb::DB,
+    options: &IndexOptions,
+    plan: &RunPlan,
+    missing_runs: &mut HashMap<String, u32>,
+    report: &mut IndexRun,
+) {
+    if let Some((scan_path, error)) = scan_paths.unreadable() {
+        error!(%error, "Failed to read scan path {}, not checking for removed repositories", scan_path.display());
+        return;
+    }
+
+    let repos = match Repository::fetch_all(db) {
         Ok(v) => v,
         Err(error) => {
-            error!(%error, "Failed to enter repository directory {}", current.display());
+            error!(%error, "Failed to read repository index to check for removed repositories");
             return;
         }
     };
 
-    let dirs = current
-        .filter_map(Result::ok)
-        .map(|v| v.path())
-        .filter(|path| path.is_dir());
+    let required_runs = if options.network_filesystem {
+        options.missing_runs.max(1)
+    } else {
+        1
+    };
 
-    for dir in dirs {
-        if dir.join("packed-refs").is_file() {
-            // we've hit what looks like a bare git repo, lets take it
-            discovered_repos.push(dir);
+    for (relative_path, db_repository) in repos {
+        if shutdown::requested() {
+            break;
+        }
+
+        if !plan.includes(&relative_path) {
+            continue;
+        }
+
+        // repositories taken off their projects list go the same way as ones gone from disk
+        if let Some(path) = scan_paths.resolve(&relative_path) {
+            match with_retries(
+                options,
+                || std::fs::metadata(&path),
+                |e| e.kind() == std::io::ErrorKind::NotFound,
+            ) {
+                Ok(_) => {
+                    missing_runs.remove(&relative_path);
+                    continue;
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => {
+                    warn!(%error, "Failed to check {relative_path} is still on disk, keeping it");
+                    continue;
+                }
+            }
+        }
+
+        let runs = missing_runs.entry(relative_path.clone()).or_default();
+        *runs += 1;
+
+        if *runs < required_runs {
+            warn!(
+                "Repository {relative_path} gone from disk, keeping it until it's been missing \
+                 for {required_runs} index runs ({runs} so far)"
+            );
+            continue;
+        }
+
+        missing_runs.remove(&relative_path);
+
+        if options.tombstone_retention.is_zero() {
+            warn!("Repository {relative_path} gone from disk, removing from db");
+
+            match db_repository.get().delete(db, &relative_path) {
+                Ok(()) => report.repositories_removed += 1,
+                Err(error) => warn!(%error, "Failed to delete dangling index"),
+            }
         } else {
-            // probably not a bare git repo, lets recurse deeper
-            discover_repositories(&dir, discovered_repos);
+            warn!("Repository {relative_path} gone from disk, moving it to the recycle bin");
+
+            match Tombstone::bury(db, &relative_path) {
+                Ok(true) => report.repositories_removed += 1,
+                Ok(false) => {}
+                Err(error) => warn!(%error, "Failed to move dangling index to the recycle bin"),
+            }
         }
     }
 }
-
-fn find_gitweb_owner(repository_path: &Path) -> Option<String> {
-    // Load the Git config file and attempt to extract the owner from the "gitweb" section.
-    // If the owner is not found, an empty string is returned.
-    Ini::load_from_file(repository_path.join("config"))
-        .ok()?
-        .section_mut(Some("gitweb"))
-        .and_then(|section| section.remove("owner"))
-}
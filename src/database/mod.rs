@@ -1,2 +1,4 @@
 pub mod indexer;
+pub mod progress;
 pub mod schema;
+pub mod storage;
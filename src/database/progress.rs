@@ -0,0 +1,94 @@
+//! Announces repositories as the indexer ingests them for the first time, so the server can start
+//! serving straight away on a fresh database and pages can pick up repositories as they become
+//! ready rather than waiting on the whole scan path. Changes to references found on later runs
+//! are announced as they're indexed, for pages following a branch live and the `/events` stream.
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::database::schema::ref_update::{RefUpdate, RefUpdateKind};
+
+/// Number of announcements buffered for each subscriber, subscribers that fall further behind
+/// than this are told they've missed some.
+const CHANNEL_CAPACITY: usize = 256;
+
+pub struct IndexProgress {
+    ready: broadcast::Sender<Arc<str>>,
+    events: broadcast::Sender<IndexEvent>,
+}
+
+#[derive(Clone, Debug)]
+pub enum IndexEvent {
+    /// A reference was created, moved or deleted, sent once the commits it was moved to have
+    /// been indexed
+    ReferenceChanged(ReferenceChange),
+    /// An index run over a repository changed any of its references, sent after each of the
+    /// changes themselves
+    RepositoryUpdated { repository: Arc<str> },
+}
+
+impl IndexEvent {
+    /// The path of the repository the event is about, relative to the scan path.
+    pub fn repository(&self) -> &Arc<str> {
+        match self {
+            Self::ReferenceChanged(change) => &change.repository,
+            Self::RepositoryUpdated { repository } => repository,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReferenceChange {
+    pub repository: Arc<str>,
+    /// The full name of the reference (ie. `refs/heads/main`)
+    pub reference: Arc<str>,
+    pub kind: RefUpdateKind,
+    pub old: Option<[u8; 20]>,
+    pub new: Option<[u8; 20]>,
+}
+
+impl Default for IndexProgress {
+    fn default() -> Self {
+        Self {
+            ready: broadcast::Sender::new(CHANNEL_CAPACITY),
+            events: broadcast::Sender::new(CHANNEL_CAPACITY),
+        }
+    }
+}
+
+impl IndexProgress {
+    /// Announces that a repository's references have been indexed for the first time.
+    pub fn repository_ready(&self, relative_path: &str) {
+        // there's nobody to tell if no pages are waiting on the indexer
+        let _res = self.ready.send(Arc::from(relative_path));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.ready.subscribe()
+    }
+
+    /// Announces a change to one of a repository's references.
+    pub fn reference_changed(&self, relative_path: &str, update: &RefUpdate) {
+        let _res = self
+            .events
+            .send(IndexEvent::ReferenceChanged(ReferenceChange {
+                repository: Arc::from(relative_path),
+                reference: Arc::from(update.reference.as_str()),
+                kind: update.kind,
+                old: update.old,
+                new: update.new,
+            }));
+    }
+
+    /// Announces that an index run over a repository has finished changing its references.
+    pub fn repository_updated(&self, relative_path: &str) {
+        let _res = self.events.send(IndexEvent::RepositoryUpdated {
+            repository: Arc::from(relative_path),
+        });
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<IndexEvent> {
+        self.events.subscribe()
+    }
+}
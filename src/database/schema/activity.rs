@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rocksdb::{IteratorMode, WriteBatch};
+use yoke::Yoke;
+
+use crate::database::{
+    schema::{
+        author::{AuthoredCommit, YokedAuthoredCommit},
+        descending_timestamp,
+        prefixes::ACTIVITY_FAMILY,
+    },
+    storage::Batch,
+};
+
+/// The number of commits retained in the activity index, older commits are dropped at the end of
+/// each index run.
+pub const ACTIVITY_INDEX_SIZE: usize = 10_000;
+
+/// Index of the most recent commits across every repository, sorted newest first by commit time.
+/// Commits that appear in multiple repositories, such as forks, are only indexed once.
+pub struct ActivityIndex {
+    db: Arc<rocksdb::DB>,
+}
+
+impl ActivityIndex {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    fn cf(&self) -> anyhow::Result<&rocksdb::ColumnFamily> {
+        self.db
+            .cf_handle(ACTIVITY_FAMILY)
+            .context("missing activity column family")
+    }
+
+    pub fn insert(value: &AuthoredCommit, tx: &mut Batch) -> anyhow::Result<()> {
+        let mut key = Vec::with_capacity(8 + value.commit.hash.len());
+        key.extend_from_slice(&descending_timestamp(value.commit.committer.time.0));
+        key.extend_from_slice(&value.commit.hash);
+
+        tx.put(
+            ACTIVITY_FAMILY,
+            key,
+            rkyv::to_bytes::<rkyv::rancor::Error>(value)?,
+        );
+
+        Ok(())
+    }
+
+    /// Fetches up to `amount` commits, starting after the commit with the given cursor if one is
+    /// given. Each commit is returned alongside the cursor to continue from it.
+    pub fn fetch_latest(
+        &self,
+        after: Option<&[u8]>,
+        amount: usize,
+    ) -> anyhow::Result<Vec<(Box<[u8]>, YokedAuthoredCommit)>> {
+        let mode = match after {
+            Some(cursor) => IteratorMode::From(cursor, rocksdb::Direction::Forward),
+            None => IteratorMode::Start,
+        };
+
+        self.db
+            .iterator_cf(self.cf()?, mode)
+            .skip_while(|v| {
+                v.as_ref()
+                    .is_ok_and(|(key, _)| after.is_some_and(|after| **key == *after))
+            })
+            .take(amount)
+            .map(|v| {
+                let (key, value) = v.context("failed to read commit")?;
+
+                let commit = Yoke::try_attach_to_cart(value, |data| {
+                    rkyv::access::<_, rkyv::rancor::Error>(data).context("failed to deserialize")
+                })?;
+
+                Ok((key, commit))
+            })
+            .collect()
+    }
+
+    /// Drops everything but the newest `retain` commits from the index.
+    pub fn truncate(&self, retain: usize) -> anyhow::Result<()> {
+        let cf = self.cf()?;
+
+        let Some(first_expired) = self
+            .db
+            .iterator_cf(cf, IteratorMode::Start)
+            .nth(retain)
+            .transpose()?
+        else {
+            return Ok(());
+        };
+
+        let mut last = self.db.raw_iterator_cf(cf);
+        last.seek_to_last();
+        last.status()?;
+
+        let Some(last) = last.key() else {
+            return Ok(());
+        };
+
+        // delete_range_cf excludes the end key, so tack a byte on to include the last commit
+        let mut end = last.to_vec();
+        end.push(0);
+
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(cf, first_expired.0, end);
+        self.db.write_without_wal(batch)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::database::{schema::prefixes::ANNOTATIONS_FAMILY, storage::Storage};
+
+/// Flags and metadata set on a repository through the admin API, taking precedence over what the
+/// indexer read from disk. These are kept apart from [`super::repository::Repository`] so the
+/// indexer rewriting a repository's metadata leaves them alone.
+#[derive(
+    Serialize, Archive, Deserialize, Debug, Clone, Default, serde::Serialize, serde::Deserialize,
+)]
+#[serde(default)]
+pub struct Annotations {
+    /// Left out of the index page, the repository is still served to anyone with its URL
+    pub hidden: bool,
+    /// Marked as no longer maintained
+    pub archived: bool,
+    /// Listed ahead of every other repository on the index page
+    pub pinned: bool,
+    /// Shown in place of the repository's own description
+    pub description: Option<String>,
+    /// Shown in place of the topics fetched from the repository's upstream forge
+    pub topics: Option<Vec<String>>,
+}
+
+impl Annotations {
+    pub fn open(database: &dyn Storage, path: &str) -> Result<Option<Self>> {
+        let Some(value) = database.get(ANNOTATIONS_FAMILY, path.as_bytes())? else {
+            return Ok(None);
+        };
+
+        rkyv::from_bytes::<Self, rkyv::rancor::Error>(&value)
+            .map(Some)
+            .context("Failed to deserialize annotations")
+    }
+
+    pub fn fetch_all(database: &dyn Storage) -> Result<HashMap<String, Self>> {
+        database
+            .iter_prefix(ANNOTATIONS_FAMILY, &[])?
+            .map(|entry| {
+                let (key, value) = entry?;
+                let key = String::from_utf8(key.into_vec()).context("invalid repo name")?;
+                let value = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&value)
+                    .context("Failed to deserialize annotations")?;
+
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    pub fn insert(&self, database: &dyn Storage, path: &str) -> Result<()> {
+        database.put(
+            ANNOTATIONS_FAMILY,
+            path.as_bytes(),
+            &rkyv::to_bytes::<rkyv::rancor::Error>(self)?,
+        )
+    }
+
+    pub fn delete(database: &dyn Storage, path: &str) -> Result<()> {
+        database.delete(ANNOTATIONS_FAMILY, path.as_bytes())
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::database::storage::{Storage, DEFAULT_FAMILY};
+
+/// The key the announcement is stored under in the default column family.
+const ANNOUNCEMENT_KEY: &str = "announcement";
+
+/// A notice shown at the top of every page, set through the admin API for things like planned
+/// maintenance or a move to a new address.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone)]
+pub struct Announcement {
+    pub message: String,
+    pub severity: Severity,
+    /// Unix timestamp after which the announcement is no longer shown
+    pub expires_at: Option<i64>,
+}
+
+#[derive(
+    Serialize,
+    Archive,
+    Deserialize,
+    Debug,
+    Copy,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    /// The class the banner is given, so it can be styled to match.
+    pub fn class(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl Announcement {
+    /// Returns the announcement, if one is set and hasn't expired.
+    pub fn open(database: &dyn Storage) -> Result<Option<Self>> {
+        let Some(value) = database.get(DEFAULT_FAMILY, ANNOUNCEMENT_KEY.as_bytes())? else {
+            return Ok(None);
+        };
+
+        let announcement = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&value)
+            .context("Failed to deserialize announcement")?;
+
+        Ok(Some(announcement).filter(|v| !v.expired()))
+    }
+
+    pub fn insert(&self, database: &dyn Storage) -> Result<()> {
+        database.put(
+            DEFAULT_FAMILY,
+            ANNOUNCEMENT_KEY.as_bytes(),
+            &rkyv::to_bytes::<rkyv::rancor::Error>(self)?,
+        )
+    }
+
+    pub fn delete(database: &dyn Storage) -> Result<()> {
+        database.delete(DEFAULT_FAMILY, ANNOUNCEMENT_KEY.as_bytes())
+    }
+
+    pub fn expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|v| v <= OffsetDateTime::now_utc().unix_timestamp())
+    }
+}
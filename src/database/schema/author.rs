@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rkyv::{Archive, Serialize};
+use rocksdb::{IteratorMode, ReadOptions};
+use yoke::{Yoke, Yokeable};
+
+use crate::database::{
+    schema::{commit::Commit, descending_timestamp, prefixes::AUTHOR_FAMILY, Yoked},
+    storage::Batch,
+};
+
+/// A commit made by an author, along with the repository it was found in.
+#[derive(Serialize, Archive, Debug, Yokeable)]
+pub struct AuthoredCommit {
+    pub repository: String,
+    pub commit: Commit,
+}
+
+impl AuthoredCommit {
+    pub fn insert(&self, tx: &mut Batch) -> anyhow::Result<()> {
+        AuthorIndex::insert(self, tx)
+    }
+}
+
+pub type YokedAuthoredCommit = Yoked<&'static <AuthoredCommit as Archive>::Archived>;
+
+/// Index of commits across every repository, keyed by the (mailmapped) email address of their
+/// author and sorted newest first. Commits that appear in multiple repositories, such as forks,
+/// are only indexed once.
+pub struct AuthorIndex {
+    db: Arc<rocksdb::DB>,
+}
+
+impl AuthorIndex {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    /// Normalises an email address into the identity commits are indexed under.
+    pub fn identity(email: &str) -> String {
+        email.trim().to_lowercase()
+    }
+
+    fn prefix(identity: &str) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(identity.len() + 1);
+        prefix.extend_from_slice(identity.as_bytes());
+        prefix.push(b'\0');
+        prefix
+    }
+
+    fn insert(value: &AuthoredCommit, tx: &mut Batch) -> anyhow::Result<()> {
+        let mut key = Self::prefix(&Self::identity(&value.commit.author.email));
+        key.extend_from_slice(&descending_timestamp(value.commit.author.time.0));
+        key.extend_from_slice(&value.commit.hash);
+
+        tx.put(
+            AUTHOR_FAMILY,
+            key,
+            rkyv::to_bytes::<rkyv::rancor::Error>(value)?,
+        );
+
+        Ok(())
+    }
+
+    pub fn fetch_latest(
+        &self,
+        identity: &str,
+        amount: usize,
+        offset: usize,
+    ) -> anyhow::Result<Vec<YokedAuthoredCommit>> {
+        let cf = self
+            .db
+            .cf_handle(AUTHOR_FAMILY)
+            .context("missing author column family")?;
+
+        let prefix = Self::prefix(&Self::identity(identity));
+
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+
+        self.db
+            .iterator_cf_opt(
+                cf,
+                opts,
+                IteratorMode::From(&prefix, rocksdb::Direction::Forward),
+            )
+            .skip(offset)
+            .take(amount)
+            .map(|v| {
+                Yoke::try_attach_to_cart(v.context("failed to read commit")?.1, |data| {
+                    rkyv::access::<_, rkyv::rancor::Error>(data).context("failed to deserialize")
+                })
+            })
+            .collect()
+    }
+}
@@ -2,16 +2,21 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use gix::{actor::SignatureRef, ObjectId};
-use rkyv::{Archive, Serialize};
-use rocksdb::{IteratorMode, ReadOptions, WriteBatch};
+use rkyv::{Archive, Deserialize, Serialize};
 use time::{OffsetDateTime, UtcOffset};
-use tracing::debug;
+use tracing::{debug, warn};
 use yoke::{Yoke, Yokeable};
 
-use crate::database::schema::{
-    prefixes::{COMMIT_COUNT_FAMILY, COMMIT_FAMILY},
-    repository::RepositoryId,
-    Yoked,
+use crate::database::{
+    schema::{
+        author::AuthorIndex,
+        compression::{self, CommitEncoder},
+        descending_timestamp,
+        prefixes::{BACKPORT_FAMILY, COMMIT_COUNT_FAMILY, COMMIT_FAMILY, COMMIT_FILTER_FAMILY},
+        repository::RepositoryId,
+        Yoked,
+    },
+    storage::{Batch, Storage},
 };
 
 #[derive(Serialize, Archive, Debug, Yokeable)]
@@ -21,6 +26,7 @@ pub struct Commit {
     pub author: Author,
     pub committer: Author,
     pub hash: [u8; 20],
+    pub parents: Vec<[u8; 20]>,
 }
 
 impl Commit {
@@ -39,15 +45,44 @@ impl Commit {
             hash: match commit.id().detach() {
                 ObjectId::Sha1(d) => d,
             },
+            parents: commit
+                .parent_ids()
+                .map(|id| match id.detach() {
+                    ObjectId::Sha1(d) => d,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn insert(
+        &self,
+        tree: &CommitTree,
+        id: u64,
+        encoder: &mut CommitEncoder,
+        tx: &mut Batch,
+    ) -> anyhow::Result<()> {
+        tree.insert(id, self, encoder, tx)
+    }
+
+    /// Archives a commit that didn't come from the index, so it can be displayed alongside those
+    /// that did.
+    pub fn into_yoked(self) -> anyhow::Result<YokedCommit> {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&self)?;
+
+        Yoke::try_attach_to_cart(Box::from(bytes.as_slice()), |data| {
+            rkyv::access::<_, rkyv::rancor::Error>(data)
         })
+        .context("Failed to deserialize commit")
     }
+}
 
-    pub fn insert(&self, tree: &CommitTree, id: u64, tx: &mut WriteBatch) -> anyhow::Result<()> {
-        tree.insert(id, self, tx)
+impl ArchivedCommit {
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
     }
 }
 
-#[derive(Serialize, Archive, Debug)]
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Author {
     pub name: String,
     pub email: String,
@@ -74,15 +109,126 @@ impl TryFrom<SignatureRef<'_>> for Author {
     }
 }
 
+/// Restricts the commits listed from a [`CommitTree`] to those made by a particular author or
+/// committer, or committed within a time range.
+#[derive(Debug, Default, Clone)]
+pub struct CommitFilter {
+    pub author: Option<String>,
+    pub committer: Option<String>,
+    /// Unix timestamp of the earliest commit time to include.
+    pub since: Option<i64>,
+    /// Unix timestamp of the latest commit time to include.
+    pub until: Option<i64>,
+}
+
+impl CommitFilter {
+    pub fn is_empty(&self) -> bool {
+        self.author.is_none()
+            && self.committer.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+    }
+
+    /// Checks a commit against the filter, given the (mailmapped) emails of its author and
+    /// committer and its commit time.
+    pub fn matches(&self, author_email: &str, committer_email: &str, time: i64) -> bool {
+        self.author
+            .as_deref()
+            .map_or(true, |v| AuthorIndex::identity(author_email) == v)
+            && self
+                .committer
+                .as_deref()
+                .map_or(true, |v| AuthorIndex::identity(committer_email) == v)
+            && self.since.map_or(true, |since| time >= since)
+            && self.until.map_or(true, |until| time <= until)
+    }
+
+    fn matches_commit(&self, commit: &ArchivedCommit) -> bool {
+        self.matches(
+            &commit.author.email,
+            &commit.committer.email,
+            commit.committer.time.0.to_native(),
+        )
+    }
+}
+
+/// Secondary keys written alongside each commit in a [`CommitTree`] so filtered listings can be
+/// answered without scanning the whole tree.
+#[derive(Copy, Clone)]
+#[repr(u8)]
+enum FilterKey {
+    Author = b'a',
+    Committer = b'c',
+    Time = b't',
+}
+
 pub struct CommitTree {
-    db: Arc<rocksdb::DB>,
+    db: Arc<dyn Storage>,
     pub prefix: Box<[u8]>,
 }
 
 pub type YokedCommit = Yoked<&'static <Commit as Archive>::Archived>;
 
+/// The state of a [`CommitTree`] as of the last batch of commits written to it.
+struct Checkpoint {
+    count: u64,
+    latest: [u8; 20],
+}
+
+impl Checkpoint {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(29);
+        out.push(compression::CHECKPOINT);
+        out.extend_from_slice(&self.count.to_be_bytes());
+        out.extend_from_slice(&self.latest);
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some((&compression::CHECKPOINT, rest)) = bytes.split_first() else {
+            anyhow::bail!("invalid commit checkpoint");
+        };
+        let (count, latest) = rest
+            .split_first_chunk::<8>()
+            .context("commit checkpoint is truncated")?;
+
+        Ok(Self {
+            count: u64::from_be_bytes(*count),
+            latest: latest
+                .try_into()
+                .context("commit checkpoint is truncated")?,
+        })
+    }
+}
+
+/// Checks every branch's commit counter against the checkpoint written with its commits,
+/// repairing any left inconsistent by a crash partway through indexing. Branches that can't be
+/// repaired have their commits dropped, to be indexed again from scratch.
+pub fn repair(db: Arc<dyn Storage>) -> anyhow::Result<()> {
+    let mut repaired = 0_usize;
+
+    for entry in db.iter_prefix(COMMIT_COUNT_FAMILY, &[])? {
+        let (prefix, _) = entry.context("failed to read commit counter")?;
+
+        let tree = CommitTree {
+            db: db.clone(),
+            prefix,
+        };
+
+        if tree.repair()? {
+            repaired += 1;
+        }
+    }
+
+    if repaired > 0 {
+        warn!("Repaired {repaired} branches left partially indexed");
+    }
+
+    Ok(())
+}
+
 impl CommitTree {
-    pub(super) fn new(db: Arc<rocksdb::DB>, repository: RepositoryId, reference: &str) -> Self {
+    pub(super) fn new(db: Arc<dyn Storage>, repository: RepositoryId, reference: &str) -> Self {
         let mut prefix = Vec::with_capacity(std::mem::size_of::<u64>() + reference.len() + 1);
         prefix.extend_from_slice(&repository.to_be_bytes());
         prefix.extend_from_slice(reference.as_bytes());
@@ -98,39 +244,110 @@ impl CommitTree {
         let mut to = self.prefix.clone();
         *to.last_mut().unwrap() += 1;
 
-        let commit_cf = self
-            .db
-            .cf_handle(COMMIT_FAMILY)
-            .context("commit column family missing")?;
-        self.db.delete_range_cf(commit_cf, &self.prefix, &to)?;
-
-        let commit_count_cf = self
-            .db
-            .cf_handle(COMMIT_COUNT_FAMILY)
-            .context("missing column family")?;
-        self.db.delete_cf(commit_count_cf, &self.prefix)?;
+        self.db.delete_range(COMMIT_FAMILY, &self.prefix, &to)?;
+        self.db
+            .delete_range(COMMIT_FILTER_FAMILY, &self.prefix, &to)?;
+        self.db.delete(COMMIT_COUNT_FAMILY, &self.prefix)?;
+        self.db.delete_range(BACKPORT_FAMILY, &self.prefix, &to)?;
 
         Ok(())
     }
 
-    pub fn update_counter(&self, count: u64, tx: &mut WriteBatch) -> anyhow::Result<()> {
-        let cf = self
-            .db
-            .cf_handle(COMMIT_COUNT_FAMILY)
-            .context("missing column family")?;
+    /// Records that `backport`, one of the commits in the tree, is a backport of `upstream`.
+    pub fn insert_backport(&self, upstream: [u8; 20], backport: [u8; 20], tx: &mut Batch) {
+        let mut key = self.prefix.to_vec();
+        key.extend_from_slice(&upstream);
 
-        tx.put_cf(cf, &self.prefix, count.to_be_bytes());
+        tx.put(BACKPORT_FAMILY, key, backport);
+    }
 
-        Ok(())
+    /// Looks up the backports of each of `upstream` in the tree, `None` for those that haven't
+    /// been backported to it.
+    pub fn fetch_backports(&self, upstream: &[[u8; 20]]) -> anyhow::Result<Vec<Option<[u8; 20]>>> {
+        upstream
+            .iter()
+            .map(|hash| {
+                let mut key = self.prefix.to_vec();
+                key.extend_from_slice(hash);
+
+                Ok(self
+                    .db
+                    .get(BACKPORT_FAMILY, &key)?
+                    .and_then(|v| v.try_into().ok()))
+            })
+            .collect()
     }
 
-    pub fn len(&self) -> anyhow::Result<u64> {
-        let cf = self
-            .db
-            .cf_handle(COMMIT_COUNT_FAMILY)
-            .context("missing column family")?;
+    /// Sets the number of commits in the tree, `latest` being the hash of the newest of them.
+    ///
+    /// Commits are written without the WAL and each column family is flushed independently, so
+    /// the counter alone can end up ahead of the commits that made it to disk after a crash. A
+    /// checkpoint is written alongside the commits in their own column family, which always
+    /// survives or is lost along with them, for [`repair`] to restore the counter from.
+    pub fn update_counter(&self, count: u64, latest: [u8; 20], tx: &mut Batch) {
+        tx.put(
+            COMMIT_COUNT_FAMILY,
+            self.prefix.to_vec(),
+            count.to_be_bytes(),
+        );
+        tx.put(
+            COMMIT_FAMILY,
+            self.prefix.to_vec(),
+            Checkpoint { count, latest }.to_bytes(),
+        );
+    }
+
+    fn checkpoint(&self) -> anyhow::Result<Option<Checkpoint>> {
+        self.db
+            .get(COMMIT_FAMILY, &self.prefix)?
+            .map(|v| Checkpoint::from_bytes(&v))
+            .transpose()
+    }
+
+    /// Brings the counter back in line with the commits actually stored, returning whether
+    /// anything had to be changed.
+    fn repair(&self) -> anyhow::Result<bool> {
+        let len = self.len()?;
+
+        let Some(checkpoint) = self.checkpoint()? else {
+            // written before checkpoints existed, all we can do is check the newest commit made it
+            if len == 0 || self.fetch(len - 1)?.is_some() {
+                return Ok(false);
+            }
+
+            self.drop_commits()?;
+            return Ok(true);
+        };
+
+        let latest = checkpoint
+            .count
+            .checked_sub(1)
+            .map(|id| self.fetch(id))
+            .transpose()?
+            .flatten();
+
+        if !latest.is_some_and(|latest| latest.get().hash == checkpoint.latest) {
+            // the checkpoint is written in the same batch as the commits so this shouldn't
+            // happen, but there's no trusting the tree if it does
+            self.drop_commits()?;
+            return Ok(true);
+        }
+
+        if len == checkpoint.count {
+            return Ok(false);
+        }
 
-        let Some(res) = self.db.get_pinned_cf(cf, &self.prefix)? else {
+        self.db.put(
+            COMMIT_COUNT_FAMILY,
+            &self.prefix,
+            &checkpoint.count.to_be_bytes(),
+        )?;
+
+        Ok(true)
+    }
+
+    pub fn len(&self) -> anyhow::Result<u64> {
+        let Some(res) = self.db.get(COMMIT_COUNT_FAMILY, &self.prefix)? else {
             return Ok(0);
         };
 
@@ -138,50 +355,154 @@ impl CommitTree {
         Ok(u64::from_be_bytes(out))
     }
 
-    fn insert(&self, id: u64, commit: &Commit, tx: &mut WriteBatch) -> anyhow::Result<()> {
-        let cf = self
-            .db
-            .cf_handle(COMMIT_FAMILY)
-            .context("missing column family")?;
-
+    fn insert(
+        &self,
+        id: u64,
+        commit: &Commit,
+        encoder: &mut CommitEncoder,
+        tx: &mut Batch,
+    ) -> anyhow::Result<()> {
         let mut key = self.prefix.to_vec();
         key.extend_from_slice(&id.to_be_bytes());
 
-        tx.put_cf(cf, key, rkyv::to_bytes::<rkyv::rancor::Error>(commit)?);
+        tx.put(COMMIT_FAMILY, key, encoder.encode(commit)?);
+
+        let author = AuthorIndex::identity(&commit.author.email);
+        let committer = AuthorIndex::identity(&commit.committer.email);
+
+        for (kind, value) in [
+            (FilterKey::Author, author.as_bytes()),
+            (FilterKey::Committer, committer.as_bytes()),
+            (
+                FilterKey::Time,
+                &descending_timestamp(commit.committer.time.0)[..],
+            ),
+        ] {
+            let mut key = self.filter_prefix(kind, value);
+            key.extend_from_slice(&id.to_be_bytes());
+            tx.put(COMMIT_FILTER_FAMILY, key, []);
+        }
 
         Ok(())
     }
 
-    pub fn fetch_latest_one(&self) -> Result<Option<YokedCommit>, anyhow::Error> {
-        let mut key = self.prefix.to_vec();
-        key.extend_from_slice(&(self.len()?.saturating_sub(1)).to_be_bytes());
+    fn filter_prefix(&self, kind: FilterKey, value: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.prefix.len() + value.len() + 2);
+        key.extend_from_slice(&self.prefix);
+        key.push(kind as u8);
+        key.extend_from_slice(value);
+
+        // emails are variable length, so terminate them to stop one matching the start of another
+        if !matches!(kind, FilterKey::Time) {
+            key.push(b'\0');
+        }
+
+        key
+    }
 
-        let cf = self
-            .db
-            .cf_handle(COMMIT_FAMILY)
-            .context("missing column family")?;
+    fn fetch(&self, id: u64) -> anyhow::Result<Option<YokedCommit>> {
+        let mut key = self.prefix.to_vec();
+        key.extend_from_slice(&id.to_be_bytes());
 
-        let Some(value) = self.db.get_cf(cf, key)? else {
+        let Some(value) = self.db.get(COMMIT_FAMILY, &key)? else {
             return Ok(None);
         };
 
-        Yoke::try_attach_to_cart(Box::from(value), |value| {
+        Yoke::try_attach_to_cart(compression::decode(&*self.db, &value)?, |value| {
             rkyv::access::<_, rkyv::rancor::Error>(value)
         })
         .context("Failed to deserialize commit")
         .map(Some)
     }
 
+    /// Lists the commits matching `filter`, newest first. Commits by an author or committer are
+    /// found through their email, otherwise through the time the commits were made.
+    pub fn fetch_filtered(
+        &self,
+        filter: &CommitFilter,
+        amount: u64,
+        offset: u64,
+    ) -> anyhow::Result<Vec<YokedCommit>> {
+        if filter.is_empty() {
+            return self.fetch_latest(amount, offset);
+        }
+
+        let person = filter
+            .author
+            .as_deref()
+            .map(|v| (FilterKey::Author, v))
+            .or_else(|| {
+                filter
+                    .committer
+                    .as_deref()
+                    .map(|v| (FilterKey::Committer, v))
+            });
+
+        // ids sort oldest first, whereas descending timestamps sort newest first
+        let (start, end, reverse) = if let Some((kind, email)) = person {
+            let start = self.filter_prefix(kind, email.as_bytes());
+            let mut end = start.clone();
+            *end.last_mut().unwrap() += 1;
+            (start, end, true)
+        } else {
+            let mut start = self.filter_prefix(FilterKey::Time, &[]);
+            let mut end = start.clone();
+
+            if let Some(until) = filter.until {
+                start.extend_from_slice(&descending_timestamp(until));
+            }
+
+            match filter.since.and_then(|since| since.checked_sub(1)) {
+                Some(since) => end.extend_from_slice(&descending_timestamp(since)),
+                None => *end.last_mut().unwrap() += 1,
+            }
+
+            (start, end, false)
+        };
+
+        let mut commits = Vec::new();
+        let mut skipped = 0;
+
+        for entry in self.db.iter_range(
+            COMMIT_FILTER_FAMILY,
+            start.as_slice()..end.as_slice(),
+            reverse,
+        )? {
+            let (key, _) = entry.context("failed to read commit filter")?;
+
+            let id: [u8; std::mem::size_of::<u64>()] = key[key.len() - 8..].try_into()?;
+            let Some(commit) = self.fetch(u64::from_be_bytes(id))? else {
+                continue;
+            };
+
+            if !filter.matches_commit(commit.get()) {
+                continue;
+            }
+
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+
+            commits.push(commit);
+
+            if commits.len() as u64 == amount {
+                break;
+            }
+        }
+
+        Ok(commits)
+    }
+
+    pub fn fetch_latest_one(&self) -> Result<Option<YokedCommit>, anyhow::Error> {
+        self.fetch(self.len()?.saturating_sub(1))
+    }
+
     pub fn fetch_latest(
         &self,
         amount: u64,
         offset: u64,
     ) -> Result<Vec<YokedCommit>, anyhow::Error> {
-        let cf = self
-            .db
-            .cf_handle(COMMIT_FAMILY)
-            .context("missing column family")?;
-
         let latest_commit_id = self.len()?;
         debug!("Searching from latest commit {latest_commit_id}");
 
@@ -196,18 +517,126 @@ impl CommitTree {
         let mut end_key = self.prefix.to_vec();
         end_key.extend_from_slice(&(latest_commit_id.saturating_sub(offset)).to_be_bytes());
 
-        let mut opts = ReadOptions::default();
-        opts.set_iterate_range(start_key.as_slice()..end_key.as_slice());
-
-        opts.set_prefix_same_as_start(true);
-
         self.db
-            .iterator_cf_opt(cf, opts, IteratorMode::End)
+            .iter_range(
+                COMMIT_FAMILY,
+                start_key.as_slice()..end_key.as_slice(),
+                true,
+            )?
             .map(|v| {
-                Yoke::try_attach_to_cart(v.context("failed to read commit")?.1, |data| {
+                let (_, value) = v.context("failed to read commit")?;
+
+                Yoke::try_attach_to_cart(compression::decode(&*self.db, &value)?, |data| {
                     rkyv::access::<_, rkyv::rancor::Error>(data).context("failed to deserialize")
                 })
             })
             .collect::<Result<Vec<_>, anyhow::Error>>()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Author, Commit, CommitFilter, CommitTree};
+    use crate::database::{
+        schema::{compression::CommitEncoder, repository::RepositoryId},
+        storage::{Batch, MemoryStorage, Storage},
+    };
+
+    fn commit(n: u8, email: &str) -> Commit {
+        let author = Author {
+            name: "Test".to_string(),
+            email: email.to_string(),
+            time: (1_700_000_000 + i64::from(n), 0),
+        };
+
+        Commit {
+            summary: format!("commit {n}"),
+            message: String::new(),
+            author: author.clone(),
+            committer: author,
+            hash: [n; 20],
+            parents: Vec::new(),
+        }
+    }
+
+    fn tree_with(commits: &[Commit]) -> CommitTree {
+        let tree = CommitTree::new(
+            Arc::new(MemoryStorage::default()),
+            RepositoryId(1),
+            "refs/heads/main",
+        );
+
+        let mut batch = Batch::default();
+        let mut encoder = CommitEncoder::uncompressed();
+        for (id, commit) in (0..).zip(commits) {
+            commit.insert(&tree, id, &mut encoder, &mut batch).unwrap();
+        }
+        tree.update_counter(
+            commits.len() as u64,
+            commits.last().unwrap().hash,
+            &mut batch,
+        );
+        tree.db.write(batch).unwrap();
+
+        tree
+    }
+
+    fn hashes(commits: &[super::YokedCommit]) -> Vec<u8> {
+        commits.iter().map(|v| v.get().hash[0]).collect()
+    }
+
+    #[test]
+    fn fetch_latest_is_newest_first() {
+        let tree = tree_with(&[
+            commit(1, "a@example.com"),
+            commit(2, "b@example.com"),
+            commit(3, "a@example.com"),
+        ]);
+
+        assert_eq!(tree.len().unwrap(), 3);
+        assert_eq!(hashes(&tree.fetch_latest(2, 0).unwrap()), [3, 2]);
+        assert_eq!(hashes(&tree.fetch_latest(2, 2).unwrap()), [1]);
+    }
+
+    #[test]
+    fn fetch_filtered_by_author_and_time() {
+        let tree = tree_with(&[
+            commit(1, "a@example.com"),
+            commit(2, "b@example.com"),
+            commit(3, "a@example.com"),
+        ]);
+
+        let by_author = CommitFilter {
+            author: Some("a@example.com".to_string()),
+            ..CommitFilter::default()
+        };
+        assert_eq!(
+            hashes(&tree.fetch_filtered(&by_author, 10, 0).unwrap()),
+            [3, 1]
+        );
+
+        let since = CommitFilter {
+            since: Some(1_700_000_002),
+            ..CommitFilter::default()
+        };
+        assert_eq!(hashes(&tree.fetch_filtered(&since, 10, 0).unwrap()), [3, 2]);
+    }
+
+    #[test]
+    fn repair_restores_counter_from_checkpoint() {
+        let tree = tree_with(&[commit(1, "a@example.com"), commit(2, "a@example.com")]);
+        tree.db
+            .put(
+                super::COMMIT_COUNT_FAMILY,
+                &tree.prefix,
+                &5_u64.to_be_bytes(),
+            )
+            .unwrap();
+
+        assert!(tree.repair().unwrap());
+        assert_eq!(tree.len().unwrap(), 2);
+        assert!(!tree.repair().unwrap());
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rocksdb::IteratorMode;
+
+use crate::database::{
+    schema::{
+        prefixes::{COMMIT_LOCATION_BY_REPOSITORY_FAMILY, COMMIT_LOCATION_FAMILY},
+        repository::RepositoryId,
+    },
+    storage::{Batch, Storage},
+};
+
+/// Number of entries deleted in each batch when purging a repository's entries.
+const PURGE_BATCH_SIZE: usize = 10_000;
+
+/// Index of the repositories each commit was found in, across every repository on the instance,
+/// keyed by the commit's hash so commits can be found from an abbreviation of it.
+///
+/// Each entry is mirrored under the repository's ID in a second family, so they can be found
+/// again to be removed along with their repository.
+pub struct CommitLocations {
+    db: Arc<rocksdb::DB>,
+}
+
+/// A repository a commit was found in.
+pub struct CommitLocation {
+    pub hash: [u8; 20],
+    pub repository_id: u64,
+    pub repository: String,
+}
+
+impl CommitLocations {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self { db }
+    }
+
+    fn cf(&self) -> anyhow::Result<&rocksdb::ColumnFamily> {
+        self.db
+            .cf_handle(COMMIT_LOCATION_FAMILY)
+            .context("missing commit location column family")
+    }
+
+    /// Records that `hash` can be found in the repository at `path`.
+    pub fn insert(hash: [u8; 20], repository: RepositoryId, path: &str, batch: &mut Batch) {
+        let mut key = Vec::with_capacity(20 + 8);
+        key.extend_from_slice(&hash);
+        key.extend_from_slice(&repository.0.to_be_bytes());
+
+        let mut by_repository = Vec::with_capacity(8 + 20);
+        by_repository.extend_from_slice(&repository.0.to_be_bytes());
+        by_repository.extend_from_slice(&hash);
+
+        batch.put(COMMIT_LOCATION_FAMILY, key, path);
+        batch.put(COMMIT_LOCATION_BY_REPOSITORY_FAMILY, by_repository, []);
+    }
+
+    /// Removes the entry of every commit found in the repository with the ID `repository`, for
+    /// when the repository is dropped from the index.
+    pub fn purge(database: &dyn Storage, repository: u64) -> anyhow::Result<()> {
+        let start = repository.to_be_bytes();
+        let mut end = start;
+        *end.last_mut().unwrap() += 1;
+
+        let mut batch = Batch::default();
+
+        for entry in database.iter_prefix(COMMIT_LOCATION_BY_REPOSITORY_FAMILY, &start)? {
+            let (key, _) = entry?;
+
+            let mut location = Vec::with_capacity(20 + 8);
+            location.extend_from_slice(&key[start.len()..]);
+            location.extend_from_slice(&start);
+            batch.delete(COMMIT_LOCATION_FAMILY, location);
+
+            if batch.len() == PURGE_BATCH_SIZE {
+                database.write(std::mem::take(&mut batch))?;
+            }
+        }
+
+        batch.delete_range(COMMIT_LOCATION_BY_REPOSITORY_FAMILY, start, end);
+        database.write(batch)
+    }
+
+    /// Fetches the locations of commits whose hash starts with the given hex `prefix`, looking
+    /// at no more than `limit` entries.
+    pub fn find_by_prefix(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<CommitLocation>> {
+        // an odd number of digits leaves half a byte, which is matched against the hex instead
+        let whole_bytes = const_hex::decode(&prefix[..prefix.len() & !1])?;
+
+        let mut locations = Vec::new();
+
+        for entry in self
+            .db
+            .iterator_cf(
+                self.cf()?,
+                IteratorMode::From(&whole_bytes, rocksdb::Direction::Forward),
+            )
+            .take(limit)
+        {
+            let (key, value) = entry?;
+
+            if !key.starts_with(&whole_bytes) {
+                break;
+            }
+
+            let (Ok(hash), Ok(repository_id)) = (
+                <[u8; 20]>::try_from(&key[..20.min(key.len())]),
+                key.get(20..).unwrap_or_default().try_into(),
+            ) else {
+                continue;
+            };
+
+            if !const_hex::encode(hash).starts_with(prefix) {
+                continue;
+            }
+
+            locations.push(CommitLocation {
+                hash,
+                repository_id: u64::from_be_bytes(repository_id),
+                repository: String::from_utf8_lossy(&value).into_owned(),
+            });
+        }
+
+        Ok(locations)
+    }
+}
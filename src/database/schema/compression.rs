@@ -0,0 +1,280 @@
+//! Optional zstd compression of the commits stored in [`COMMIT_FAMILY`], using a dictionary
+//! trained on the commits already in the index. Commit messages and author details are small and
+//! highly repetitive, so compressing them individually only pays off with a shared dictionary.
+//!
+//! Every stored commit is prefixed by a byte saying how it was encoded, so compressed and
+//! uncompressed commits can live side by side and are decoded transparently on read. Enabling
+//! compression on an existing index compresses the commits already in it at the end of the next
+//! index run, once enough commits exist to train a dictionary from.
+
+use std::{
+    io::Read,
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::{bail, Context};
+use rocksdb::{IteratorMode, WriteBatch};
+use tracing::info;
+use zstd::dict::DecoderDictionary;
+
+use crate::{
+    database::{
+        schema::{commit::Commit, prefixes::COMMIT_FAMILY},
+        storage::{Storage, DEFAULT_FAMILY},
+    },
+    shutdown,
+};
+
+/// Key the trained dictionary is stored under, prefixed by its id.
+const DICTIONARY_KEY: &str = "commit_dictionary";
+
+/// Key set once every commit in the index has been compressed, and cleared whenever commits are
+/// written uncompressed.
+const COMPRESSED_KEY: &str = "commit_compression_complete";
+
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+/// Not a commit, but a branch's checkpoint stored alongside its commits. These are never
+/// compressed.
+pub(super) const CHECKPOINT: u8 = 2;
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// zstd's own default dictionary size.
+const DICTIONARY_SIZE: usize = 112 * 1024;
+
+/// Number of commits sampled to train the dictionary.
+const TRAINING_SAMPLES: usize = 10_000;
+
+/// Dictionaries trained on fewer commits than this aren't worth having, so compression is held
+/// off until the index has grown.
+const MIN_TRAINING_SAMPLES: usize = 1_000;
+
+/// Number of commits rewritten in each batch while compressing an existing index.
+const MIGRATION_BATCH_SIZE: usize = 1_000;
+
+/// Dictionaries are immutable once trained, so they're kept around by id rather than being read
+/// back out of the database for every commit.
+static DECODER_DICTIONARIES: LazyLock<moka::sync::Cache<u32, Arc<DecoderDictionary<'static>>>> =
+    LazyLock::new(|| moka::sync::Cache::new(4));
+
+struct Dictionary {
+    id: u32,
+    content: Vec<u8>,
+}
+
+impl Dictionary {
+    fn load(db: &dyn Storage) -> anyhow::Result<Option<Self>> {
+        let Some(value) = db.get(DEFAULT_FAMILY, DICTIONARY_KEY.as_bytes())? else {
+            return Ok(None);
+        };
+
+        let Some((id, content)) = value.split_first_chunk() else {
+            bail!("commit dictionary is truncated");
+        };
+
+        Ok(Some(Self {
+            id: u32::from_be_bytes(*id),
+            content: content.to_vec(),
+        }))
+    }
+
+    fn store(&self, db: &rocksdb::DB) -> anyhow::Result<()> {
+        let mut value = Vec::with_capacity(self.content.len() + 4);
+        value.extend_from_slice(&self.id.to_be_bytes());
+        value.extend_from_slice(&self.content);
+
+        db.put(DICTIONARY_KEY, value)?;
+        Ok(())
+    }
+
+    /// Trains a dictionary on a sample of the uncompressed commits in the index, returning `None`
+    /// if there aren't enough of them yet.
+    fn train(db: &rocksdb::DB) -> anyhow::Result<Option<Self>> {
+        let cf = db
+            .cf_handle(COMMIT_FAMILY)
+            .context("missing column family")?;
+
+        let mut samples = Vec::with_capacity(TRAINING_SAMPLES);
+
+        for entry in db.iterator_cf(cf, IteratorMode::Start) {
+            let (_, value) = entry.context("failed to read commit")?;
+
+            if let Some((&RAW, commit)) = value.split_first() {
+                samples.push(commit.to_vec());
+            }
+
+            if samples.len() == TRAINING_SAMPLES {
+                break;
+            }
+        }
+
+        if samples.len() < MIN_TRAINING_SAMPLES {
+            return Ok(None);
+        }
+
+        let content = zstd::dict::from_samples(&samples, DICTIONARY_SIZE)
+            .context("failed to train commit dictionary")?;
+
+        // trained dictionaries start with a magic number followed by their little-endian id
+        let id = content
+            .get(4..8)
+            .and_then(|id| id.try_into().ok())
+            .map(u32::from_le_bytes)
+            .context("trained dictionary has no id")?;
+
+        Ok(Some(Self { id, content }))
+    }
+}
+
+/// Encodes commits being written to the index, compressing them if enabled and a dictionary has
+/// been trained.
+pub struct CommitEncoder {
+    compressor: Option<(u32, zstd::bulk::Compressor<'static>)>,
+}
+
+impl CommitEncoder {
+    pub fn new(db: &rocksdb::DB, compress: bool) -> anyhow::Result<Self> {
+        if !compress {
+            // anything written from now on won't be compressed, so the next run with compression
+            // enabled needs to go back over the index
+            db.delete(COMPRESSED_KEY)?;
+            return Ok(Self::uncompressed());
+        }
+
+        let compressor = Dictionary::load(db)?
+            .map(|dictionary| {
+                let compressor = zstd::bulk::Compressor::with_dictionary(
+                    COMPRESSION_LEVEL,
+                    &dictionary.content,
+                )?;
+                Ok::<_, anyhow::Error>((dictionary.id, compressor))
+            })
+            .transpose()?;
+
+        Ok(Self { compressor })
+    }
+
+    pub fn uncompressed() -> Self {
+        Self { compressor: None }
+    }
+
+    pub fn encode(&mut self, commit: &Commit) -> anyhow::Result<Vec<u8>> {
+        let archived = rkyv::to_bytes::<rkyv::rancor::Error>(commit)?;
+
+        match &mut self.compressor {
+            Some((id, compressor)) => compress(compressor, *id, &archived),
+            None => {
+                let mut out = Vec::with_capacity(archived.len() + 1);
+                out.push(RAW);
+                out.extend_from_slice(&archived);
+                Ok(out)
+            }
+        }
+    }
+}
+
+fn compress(
+    compressor: &mut zstd::bulk::Compressor<'_>,
+    dictionary_id: u32,
+    archived: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![ZSTD];
+    out.extend_from_slice(&dictionary_id.to_be_bytes());
+    out.extend_from_slice(&compressor.compress(archived)?);
+    Ok(out)
+}
+
+/// Decodes a commit read from the index into a buffer that can be accessed with rkyv.
+pub fn decode(db: &dyn Storage, value: &[u8]) -> anyhow::Result<Box<[u8]>> {
+    match value.split_first() {
+        Some((&RAW, archived)) => Ok(Box::from(archived)),
+        Some((&ZSTD, compressed)) => {
+            let Some((id, frame)) = compressed.split_first_chunk() else {
+                bail!("compressed commit is truncated");
+            };
+            let id = u32::from_be_bytes(*id);
+
+            let dictionary =
+                DECODER_DICTIONARIES.try_get_with(id, || match Dictionary::load(db)? {
+                    Some(dictionary) if dictionary.id == id => {
+                        Ok(Arc::new(DecoderDictionary::copy(&dictionary.content)))
+                    }
+                    _ => Err(anyhow::anyhow!("missing commit dictionary {id}")),
+                });
+            let dictionary = dictionary.map_err(|e| anyhow::anyhow!("{e:?}"))?;
+
+            let mut out = Vec::new();
+            zstd::stream::Decoder::with_prepared_dictionary(frame, &dictionary)?
+                .read_to_end(&mut out)?;
+
+            Ok(out.into_boxed_slice())
+        }
+        Some((encoding, _)) => bail!("unknown commit encoding {encoding}"),
+        None => bail!("empty commit"),
+    }
+}
+
+/// Compresses any commits in the index that were written uncompressed, training a dictionary
+/// first if there isn't one. Does nothing if the index is already fully compressed.
+pub fn compress_existing(db: &rocksdb::DB) -> anyhow::Result<()> {
+    if db.get(COMPRESSED_KEY)?.is_some() {
+        return Ok(());
+    }
+
+    let dictionary = match Dictionary::load(db)? {
+        Some(dictionary) => dictionary,
+        None => {
+            let Some(dictionary) = Dictionary::train(db)? else {
+                info!("Not enough commits to train a compression dictionary yet");
+                return Ok(());
+            };
+
+            dictionary.store(db)?;
+            dictionary
+        }
+    };
+
+    info!("Compressing existing commits");
+
+    let cf = db
+        .cf_handle(COMMIT_FAMILY)
+        .context("missing column family")?;
+    let mut compressor =
+        zstd::bulk::Compressor::with_dictionary(COMPRESSION_LEVEL, &dictionary.content)?;
+
+    let mut batch = WriteBatch::default();
+    let mut compressed = 0_usize;
+
+    for entry in db.iterator_cf(cf, IteratorMode::Start) {
+        // whatever's been compressed so far is written out, the rest is picked up next run
+        if shutdown::requested() {
+            break;
+        }
+
+        let (key, value) = entry.context("failed to read commit")?;
+
+        let Some((&RAW, archived)) = value.split_first() else {
+            continue;
+        };
+
+        batch.put_cf(cf, key, compress(&mut compressor, dictionary.id, archived)?);
+        compressed += 1;
+
+        if batch.len() == MIGRATION_BATCH_SIZE {
+            db.write_without_wal(std::mem::take(&mut batch))?;
+        }
+    }
+
+    db.write_without_wal(batch)?;
+
+    if shutdown::requested() {
+        return Ok(());
+    }
+
+    db.put(COMPRESSED_KEY, [])?;
+
+    info!("Compressed {compressed} commits");
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rkyv::{Archive, Deserialize, Serialize};
+use time::OffsetDateTime;
+use yoke::Yoke;
+
+use crate::database::{
+    schema::{descending_timestamp, prefixes::INDEX_RUN_FAMILY, Yoked},
+    storage::{Batch, Storage},
+};
+
+/// The number of index runs reported on, older reports are dropped as new ones are recorded.
+pub const INDEX_RUN_LOG_SIZE: usize = 100;
+
+/// How long one step of an index run took.
+#[derive(Serialize, Archive, Deserialize, Debug)]
+pub struct IndexRunPhase {
+    pub name: String,
+    pub duration_ms: u64,
+}
+
+/// A report of what a single index run did.
+#[derive(Serialize, Archive, Deserialize, Debug, Default)]
+pub struct IndexRun {
+    /// The `request_id` the run's logs were tagged with
+    pub id: String,
+    /// Unix timestamp of when the run started
+    pub started_at: i64,
+    pub duration_ms: u64,
+    /// The repositories found on disk
+    pub repositories_scanned: u64,
+    /// The repositories found on disk that weren't in the index yet
+    pub repositories_added: u64,
+    /// The repositories removed from the index, or moved to the recycle bin, for being gone
+    /// from disk
+    pub repositories_removed: u64,
+    pub commits_ingested: u64,
+    /// The number of errors logged during the run
+    pub errors: u64,
+    /// Whether the run was cut short by the server shutting down
+    pub interrupted: bool,
+    pub phases: Vec<IndexRunPhase>,
+}
+
+pub type YokedIndexRun = Yoked<&'static <IndexRun as Archive>::Archived>;
+
+impl ArchivedIndexRun {
+    pub fn started_at(&self) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(self.started_at.to_native())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+}
+
+/// The reports of the most recent index runs, newest first.
+pub struct IndexRunLog {
+    db: Arc<dyn Storage>,
+}
+
+impl IndexRunLog {
+    pub fn new(db: Arc<dyn Storage>) -> Self {
+        Self { db }
+    }
+
+    /// Records the report of a finished run, dropping the oldest ones if the log has grown past
+    /// [`INDEX_RUN_LOG_SIZE`].
+    pub fn insert(&self, run: &IndexRun) -> anyhow::Result<()> {
+        let mut batch = Batch::default();
+
+        // runs can start within the same second of each other, so the ID keeps them apart
+        let mut key = Vec::with_capacity(8 + run.id.len());
+        key.extend_from_slice(&descending_timestamp(run.started_at));
+        key.extend_from_slice(run.id.as_bytes());
+
+        batch.put(
+            INDEX_RUN_FAMILY,
+            key,
+            rkyv::to_bytes::<rkyv::rancor::Error>(run)?,
+        );
+
+        // the new report sorts first, so it's never the one dropped
+        for entry in self
+            .db
+            .iter_prefix(INDEX_RUN_FAMILY, &[])?
+            .skip(INDEX_RUN_LOG_SIZE.saturating_sub(1))
+        {
+            let (key, _) = entry?;
+            batch.delete(INDEX_RUN_FAMILY, key);
+        }
+
+        self.db.write(batch)
+    }
+
+    /// Fetches up to `amount` reports, skipping the newest `offset`.
+    pub fn fetch_latest(&self, amount: usize, offset: usize) -> anyhow::Result<Vec<YokedIndexRun>> {
+        self.db
+            .iter_prefix(INDEX_RUN_FAMILY, &[])?
+            .skip(offset)
+            .take(amount)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Yoke::try_attach_to_cart(value, |data| rkyv::access::<_, rkyv::rancor::Error>(data))
+                    .context("Failed to deserialize index run")
+            })
+            .collect()
+    }
+}
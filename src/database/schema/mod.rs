@@ -2,11 +2,31 @@
 
 use yoke::Yoke;
 
+pub mod activity;
+pub mod annotations;
+pub mod announcement;
+pub mod author;
 pub mod commit;
+pub mod commit_location;
+pub mod compression;
+pub mod index_run;
 pub mod prefixes;
+pub mod ref_update;
+pub mod relation;
 pub mod repository;
 pub mod tag;
+pub mod tombstone;
 
 pub type Yoked<T> = Yoke<T, Box<[u8]>>;
 
-pub const SCHEMA_VERSION: &str = "2";
+pub const SCHEMA_VERSION: &str = "34";
+
+/// Encodes a unix timestamp so that newer timestamps sort before older ones when compared as
+/// bytes, for use in keys.
+pub fn descending_timestamp(seconds: i64) -> [u8; 8] {
+    // flip the sign bit so negative timestamps sort before positive ones, then invert the whole
+    // thing to reverse the order
+    #[allow(clippy::cast_sign_loss)]
+    let sortable = (seconds as u64) ^ (1 << 63);
+    (!sortable).to_be_bytes()
+}
@@ -1,5 +1,40 @@
 pub const COMMIT_FAMILY: &str = "commit";
 pub const COMMIT_COUNT_FAMILY: &str = "commit_count";
+pub const COMMIT_FILTER_FAMILY: &str = "commit_filter";
 pub const REPOSITORY_FAMILY: &str = "repository";
+pub const REPOSITORY_CASEFOLD_FAMILY: &str = "repository_casefold";
 pub const TAG_FAMILY: &str = "tag";
+pub const TAG_BY_DATE_FAMILY: &str = "tag_by_date";
 pub const REFERENCE_FAMILY: &str = "repository_refs";
+pub const AUTHOR_FAMILY: &str = "author";
+pub const ACTIVITY_FAMILY: &str = "activity";
+pub const ANNOTATIONS_FAMILY: &str = "annotations";
+pub const TOMBSTONE_FAMILY: &str = "tombstone";
+pub const REF_UPDATE_FAMILY: &str = "ref_update";
+pub const COMMIT_RELATION_FAMILY: &str = "commit_relation";
+pub const BACKPORT_FAMILY: &str = "backport";
+pub const INDEX_RUN_FAMILY: &str = "index_run";
+pub const COMMIT_LOCATION_FAMILY: &str = "commit_location";
+pub const COMMIT_LOCATION_BY_REPOSITORY_FAMILY: &str = "commit_location_by_repository";
+
+/// Every column family in the database, besides the default.
+pub const ALL_FAMILIES: [&str; 18] = [
+    COMMIT_FAMILY,
+    COMMIT_COUNT_FAMILY,
+    COMMIT_FILTER_FAMILY,
+    REPOSITORY_FAMILY,
+    REPOSITORY_CASEFOLD_FAMILY,
+    TAG_FAMILY,
+    TAG_BY_DATE_FAMILY,
+    REFERENCE_FAMILY,
+    AUTHOR_FAMILY,
+    ACTIVITY_FAMILY,
+    ANNOTATIONS_FAMILY,
+    TOMBSTONE_FAMILY,
+    REF_UPDATE_FAMILY,
+    COMMIT_RELATION_FAMILY,
+    BACKPORT_FAMILY,
+    INDEX_RUN_FAMILY,
+    COMMIT_LOCATION_FAMILY,
+    COMMIT_LOCATION_BY_REPOSITORY_FAMILY,
+];
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rkyv::{Archive, Deserialize, Serialize};
+use time::OffsetDateTime;
+use yoke::Yoke;
+
+use crate::database::{
+    schema::{descending_timestamp, prefixes::REF_UPDATE_FAMILY, repository::RepositoryId, Yoked},
+    storage::{Batch, Storage},
+};
+
+/// The number of updates kept for each repository, older ones are dropped as new ones are
+/// recorded.
+pub const REF_UPDATE_LOG_SIZE: usize = 1000;
+
+#[derive(Serialize, Archive, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RefUpdateKind {
+    Created,
+    /// Moved to a commit descending from the one it was at
+    FastForward,
+    /// Moved to a commit that doesn't descend from the one it was at
+    Forced,
+    Deleted,
+}
+
+impl RefUpdateKind {
+    /// The name of this kind of update in the `/events` stream.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::FastForward => "fast-forward",
+            Self::Forced => "forced",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+impl ArchivedRefUpdateKind {
+    /// The key of the label describing this kind of update in the locale catalogs, also used as
+    /// a class on the page.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Created => "reflog-created",
+            Self::FastForward => "reflog-fast-forward",
+            Self::Forced => "reflog-forced",
+            Self::Deleted => "reflog-deleted",
+        }
+    }
+}
+
+/// A change to a branch or tag, as seen by the indexer between two runs. Changes made and undone
+/// between runs aren't seen at all.
+#[derive(Serialize, Archive, Deserialize, Debug)]
+pub struct RefUpdate {
+    /// The full name of the reference (ie. `refs/heads/main`)
+    pub reference: String,
+    pub kind: RefUpdateKind,
+    /// The commit the reference was at, not set for references that were created
+    pub old: Option<[u8; 20]>,
+    /// The commit the reference was found at, not set for references that were deleted
+    pub new: Option<[u8; 20]>,
+    /// Unix timestamp of when the indexer saw the change
+    pub observed_at: i64,
+}
+
+pub type YokedRefUpdate = Yoked<&'static <RefUpdate as Archive>::Archived>;
+
+impl ArchivedRefUpdate {
+    pub fn observed_at(&self) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(self.observed_at.to_native())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+
+    /// The commits the reference was moved between, if this update rewrote its history.
+    pub fn forced_range(&self) -> Option<(&[u8; 20], &[u8; 20])> {
+        if !matches!(self.kind, ArchivedRefUpdateKind::Forced) {
+            return None;
+        }
+
+        Some((self.old.as_ref()?, self.new.as_ref()?))
+    }
+}
+
+/// A server-side reflog of the changes to a single repository's references, newest first.
+pub struct RefUpdateLog {
+    db: Arc<dyn Storage>,
+    prefix: [u8; 8],
+}
+
+impl RefUpdateLog {
+    pub fn new(db: Arc<dyn Storage>, repository: RepositoryId) -> Self {
+        Self {
+            db,
+            prefix: repository.0.to_be_bytes(),
+        }
+    }
+
+    /// Records the updates seen in an index run, dropping the oldest ones if the log has grown
+    /// past [`REF_UPDATE_LOG_SIZE`].
+    pub fn insert(&self, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = Batch::default();
+
+        for update in updates {
+            let mut key = Vec::with_capacity(8 + 8 + update.reference.len());
+            key.extend_from_slice(&self.prefix);
+            key.extend_from_slice(&descending_timestamp(update.observed_at));
+            key.extend_from_slice(update.reference.as_bytes());
+
+            batch.put(
+                REF_UPDATE_FAMILY,
+                key,
+                rkyv::to_bytes::<rkyv::rancor::Error>(update)?,
+            );
+        }
+
+        // the new updates sort first, so they're never the ones dropped
+        for entry in self
+            .db
+            .iter_prefix(REF_UPDATE_FAMILY, &self.prefix)?
+            .skip(REF_UPDATE_LOG_SIZE.saturating_sub(updates.len()))
+        {
+            let (key, _) = entry?;
+            batch.delete(REF_UPDATE_FAMILY, key);
+        }
+
+        self.db.write(batch)
+    }
+
+    /// Fetches up to `amount` updates, skipping the newest `offset`.
+    pub fn fetch_latest(
+        &self,
+        amount: usize,
+        offset: usize,
+    ) -> anyhow::Result<Vec<YokedRefUpdate>> {
+        self.db
+            .iter_prefix(REF_UPDATE_FAMILY, &self.prefix)?
+            .skip(offset)
+            .take(amount)
+            .map(|entry| {
+                let (_, value) = entry?;
+                Yoke::try_attach_to_cart(value, |data| rkyv::access::<_, rkyv::rancor::Error>(data))
+                    .context("Failed to deserialize ref update")
+            })
+            .collect()
+    }
+
+    /// Fetches up to `amount` of the newest updates that rewrote a reference's history, ie. the
+    /// references that were force-pushed.
+    pub fn fetch_forced(&self, amount: usize) -> anyhow::Result<Vec<YokedRefUpdate>> {
+        let mut forced = Vec::new();
+
+        for entry in self.db.iter_prefix(REF_UPDATE_FAMILY, &self.prefix)? {
+            if forced.len() >= amount {
+                break;
+            }
+
+            let (_, value) = entry?;
+
+            let update: YokedRefUpdate = Yoke::try_attach_to_cart(value, |data| {
+                rkyv::access::<_, rkyv::rancor::Error>(data)
+            })
+            .context("Failed to deserialize ref update")?;
+
+            if update.get().forced_range().is_some() {
+                forced.push(update);
+            }
+        }
+
+        Ok(forced)
+    }
+
+    /// Copies every update into `other`, used to carry the log over to a rebuilt index where the
+    /// repository has a different ID.
+    pub fn copy_to(&self, other: &Self) -> anyhow::Result<()> {
+        let mut batch = Batch::default();
+
+        for entry in self.db.iter_prefix(REF_UPDATE_FAMILY, &self.prefix)? {
+            let (key, value) = entry?;
+            let mut new_key = Vec::with_capacity(key.len());
+            new_key.extend_from_slice(&other.prefix);
+            new_key.extend_from_slice(&key[self.prefix.len()..]);
+            batch.put(REF_UPDATE_FAMILY, new_key, value);
+        }
+
+        other.db.write(batch)
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rocksdb::IteratorMode;
+
+use crate::database::{
+    schema::{prefixes::COMMIT_RELATION_FAMILY, repository::RepositoryId},
+    storage::Batch,
+};
+
+/// How one commit refers back to another in its message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RelationKind {
+    /// `This reverts commit <hash>.`, as written by `git revert`
+    Reverts,
+    /// A `Fixes: <hash> ("summary")` trailer
+    Fixes,
+    /// `commit <hash> upstream.` and the like, as written in backports to stable branches
+    Backports,
+}
+
+impl RelationKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Reverts => 0,
+            Self::Fixes => 1,
+            Self::Backports => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Reverts),
+            1 => Some(Self::Fixes),
+            2 => Some(Self::Backports),
+            _ => None,
+        }
+    }
+
+    /// The key of the label shown on the referenced commit's page, in the locale catalogs.
+    pub fn reverse_label(self) -> &'static str {
+        match self {
+            Self::Reverts => "commit-reverted-by",
+            Self::Fixes => "commit-fixed-by",
+            Self::Backports => "commit-backported-by",
+        }
+    }
+}
+
+/// The commits of a repository that revert, fix or backport other commits, looked up by the
+/// commit they refer to so its page can link to them.
+pub struct CommitRelations {
+    db: Arc<rocksdb::DB>,
+    prefix: [u8; 8],
+}
+
+impl CommitRelations {
+    pub fn new(db: Arc<rocksdb::DB>, repository: RepositoryId) -> Self {
+        Self {
+            db,
+            prefix: repository.0.to_be_bytes(),
+        }
+    }
+
+    fn cf(&self) -> anyhow::Result<&rocksdb::ColumnFamily> {
+        self.db
+            .cf_handle(COMMIT_RELATION_FAMILY)
+            .context("missing commit relation column family")
+    }
+
+    /// Records that `source` reverts, fixes or backports `target`.
+    pub fn insert(
+        &self,
+        target: [u8; 20],
+        kind: RelationKind,
+        source: [u8; 20],
+        batch: &mut Batch,
+    ) {
+        let mut key = Vec::with_capacity(8 + 20 + 1 + 20);
+        key.extend_from_slice(&self.prefix);
+        key.extend_from_slice(&target);
+        key.push(kind.to_byte());
+        key.extend_from_slice(&source);
+
+        batch.put(COMMIT_RELATION_FAMILY, key, []);
+    }
+
+    /// Fetches the commits that revert, fix or backport `target`.
+    pub fn referring_to(&self, target: [u8; 20]) -> anyhow::Result<Vec<(RelationKind, [u8; 20])>> {
+        let mut prefix = [0; 28];
+        prefix[..8].copy_from_slice(&self.prefix);
+        prefix[8..].copy_from_slice(&target);
+
+        let mut relations = Vec::new();
+
+        for entry in self.db.iterator_cf(
+            self.cf()?,
+            IteratorMode::From(&prefix, rocksdb::Direction::Forward),
+        ) {
+            let (key, _) = entry?;
+
+            let Some(rest) = key.strip_prefix(&prefix) else {
+                break;
+            };
+
+            let Some((&kind, source)) = rest.split_first() else {
+                continue;
+            };
+
+            if let (Some(kind), Ok(source)) = (RelationKind::from_byte(kind), source.try_into()) {
+                relations.push((kind, source));
+            }
+        }
+
+        Ok(relations)
+    }
+}
@@ -2,18 +2,29 @@ use std::{collections::BTreeMap, ops::Deref, path::Path, sync::Arc};
 
 use anyhow::{Context, Result};
 use rand::random;
-use rkyv::{Archive, Serialize};
-use rocksdb::IteratorMode;
+use rkyv::{Archive, Deserialize, Serialize};
+use time::{OffsetDateTime, UtcOffset};
 use yoke::{Yoke, Yokeable};
 
-use crate::database::schema::{
-    commit::CommitTree,
-    prefixes::{COMMIT_FAMILY, REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY},
-    tag::TagTree,
-    Yoked,
+use crate::{
+    archive::SnapshotFormat,
+    database::{
+        schema::{
+            commit::{Author, CommitTree},
+            commit_location::CommitLocations,
+            prefixes::{
+                BACKPORT_FAMILY, COMMIT_COUNT_FAMILY, COMMIT_FAMILY, COMMIT_FILTER_FAMILY,
+                COMMIT_RELATION_FAMILY, REFERENCE_FAMILY, REF_UPDATE_FAMILY,
+                REPOSITORY_CASEFOLD_FAMILY, REPOSITORY_FAMILY, TAG_BY_DATE_FAMILY, TAG_FAMILY,
+            },
+            tag::TagTree,
+            Yoked,
+        },
+        storage::{Batch, Storage},
+    },
 };
 
-#[derive(Serialize, Archive, Debug, PartialEq, Eq, Hash, Yokeable)]
+#[derive(Serialize, Archive, Deserialize, Debug, PartialEq, Eq, Hash, Yokeable)]
 pub struct Repository {
     /// The ID of the repository, as stored in `RocksDB`
     pub id: RepositoryId,
@@ -22,35 +33,157 @@ pub struct Repository {
     /// The description of the repository, as it is stored in the `description` file in the
     /// bare repo root
     pub description: Option<String>,
+    /// The description of the repository rendered as sanitised HTML, only populated if the
+    /// repository has opted into trusted descriptions via `rgit.trustedDescription`
+    pub description_html: Option<String>,
     /// The owner of the repository (`gitweb.owner` in the repository configuration)
     pub owner: Option<String>,
+    /// The homepage of the project the repository belongs to (`rgit.homepage` or
+    /// `gitweb.homepage` in the repository configuration, or `homepage` in its `cgitrc`)
+    pub homepage: Option<String>,
+    /// Links to the project's other resources, such as its bug tracker, CI or mailing list
+    /// archive (`rgit.link` in the repository configuration)
+    pub links: Vec<RepositoryLink>,
+    /// Whether the repository is left off the index page (`hide` in its `cgitrc`), it's still
+    /// served at its URL
+    pub hidden: bool,
     /// The last time this repository was updated, currently read from the directory mtime
     pub last_modified: (i64, i32),
+    /// When the indexer first picked the repository up. Not set for repositories found by the
+    /// first index run of an empty database, since they weren't so much added as already there
+    pub first_indexed: Option<(i64, i32)>,
     /// The default branch for Git operations
     pub default_branch: Option<String>,
+    /// Metadata fetched from the upstream forge of a mirrored repository, if syncing is enabled
+    pub upstream_metadata: Option<UpstreamMetadata>,
+    /// Rules for linking references in commit messages (`rgit.linkRule`), in the form
+    /// `<pattern> <url>`
+    pub link_rules: Vec<String>,
+    /// The snapshot formats offered for download (`rgit.snapshotFormats`), the first being the
+    /// default. Empty if the repository doesn't configure any
+    pub snapshot_formats: Vec<String>,
+    /// The compression level to build snapshots with (`rgit.snapshotCompressionLevel`)
+    pub snapshot_compression_level: Option<u32>,
+    /// How up to date a mirrored repository is with its upstream, `None` if the repository isn't
+    /// a mirror
+    pub mirror_status: Option<MirrorStatus>,
+    /// How often, in seconds, the repository is indexed (`rgit.refreshInterval`), overriding
+    /// the schedule the rest of the scan path is indexed on
+    pub refresh_interval: Option<u64>,
+    /// How the repository's branches are ordered after the default branch (`rgit.branchOrder`),
+    /// overriding `--branch-order`
+    pub branch_order: Option<String>,
+    /// Pre-generated bundles advertised to git clients through protocol v2's `bundle-uri` command
+    /// (`rgit.bundleUri`), so they can fetch most of a large repository from elsewhere before
+    /// cloning the rest
+    pub bundle_uris: Vec<String>,
+    /// Whether a bundle of the repository is built with `--bundle-dir` and served at
+    /// `/<repo>/bundle` (`rgit.bundle`)
+    pub bundle: bool,
+    /// The branch stable branches take backports from (`rgit.mainlineBranch`), the default
+    /// branch if not set
+    pub mainline_branch: Option<String>,
+    /// The branches tracked on the backports page (`rgit.stableBranch`), as full reference names
+    pub stable_branches: Vec<String>,
+    /// The pages crawlers are asked to stay away from (`rgit.crawlerExclude`), named by the first
+    /// segment of their path after the repository (ie. `tree`, `log` or `snapshot`)
+    pub crawler_excluded_views: Vec<String>,
+    /// The number of commits on the default branch, as of the last index run
+    pub commit_count: u64,
+    /// How much the repository takes up on disk, as of the last index run
+    pub size: Option<RepositorySize>,
+    /// The newest commits on the default branch as of the last index run, newest first
+    pub recent_commits: Vec<RecentCommit>,
+}
+
+/// The number of commits on the default branch kept on each repository for the index page.
+pub const RECENT_COMMITS: u64 = 3;
+
+/// Enough of a commit to describe it in a list of repositories.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecentCommit {
+    pub hash: [u8; 20],
+    pub summary: String,
+    pub author: String,
+    pub time: (i64, i32),
+}
+
+impl ArchivedRecentCommit {
+    pub fn time(&self) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(self.time.0.to_native())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+            .to_offset(
+                UtcOffset::from_whole_seconds(self.time.1.to_native()).unwrap_or(UtcOffset::UTC),
+            )
+    }
+}
+
+/// A labelled link to one of the project's resources.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepositoryLink {
+    pub label: String,
+    pub url: String,
+}
+
+/// The space taken up by a repository's git directory and the number of objects in it.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepositorySize {
+    pub bytes: u64,
+    /// Loose and packed objects, objects in more than one pack are counted once for each
+    pub objects: u64,
+    /// A digest of the repository's references as of the measurement, it's only measured again
+    /// once they've changed
+    pub refs: [u8; 32],
+}
+
+/// Description, owner and topics as reported by the forge a mirrored repository is fetched from.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpstreamMetadata {
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub topics: Vec<String>,
+    /// Unix timestamp of when this metadata was last successfully fetched
+    pub fetched_at: i64,
+}
+
+/// The state of the fetches keeping a mirrored repository up to date with its upstream.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MirrorStatus {
+    pub upstream_url: String,
+    /// Unix timestamp of the last time a fetch from upstream was attempted
+    pub last_fetch: Option<i64>,
+    /// Unix timestamp of the last time a fetch from upstream succeeded
+    pub last_successful_fetch: Option<i64>,
+    /// The number of commits the default branch is behind upstream's, if the repository has a
+    /// remote-tracking branch to compare against
+    pub behind: Option<u64>,
+    /// The URL of a commit's page on the upstream forge, with `{commit}` standing in for its ID
+    /// (`rgit.upstreamCommitUrl`, or worked out from the upstream URL for forges we know)
+    pub commit_url: Option<String>,
+}
+
+impl ArchivedMirrorStatus {
+    /// Whether the last attempt to fetch from upstream failed.
+    pub fn failing(&self) -> bool {
+        self.last_fetch.as_ref().map(|v| v.to_native())
+            != self.last_successful_fetch.as_ref().map(|v| v.to_native())
+    }
 }
 
 pub type YokedRepository = Yoked<&'static <Repository as Archive>::Archived>;
 
 impl Repository {
-    pub fn exists<P: AsRef<Path>>(database: &rocksdb::DB, path: P) -> Result<bool> {
-        let cf = database
-            .cf_handle(REPOSITORY_FAMILY)
-            .context("repository column family missing")?;
+    pub fn exists<P: AsRef<Path>>(database: &dyn Storage, path: P) -> Result<bool> {
         let path = path.as_ref().to_str().context("invalid path")?;
 
-        Ok(database.get_pinned_cf(cf, path)?.is_some())
+        Ok(database.get(REPOSITORY_FAMILY, path.as_bytes())?.is_some())
     }
 
-    pub fn fetch_all(database: &rocksdb::DB) -> Result<BTreeMap<String, YokedRepository>> {
-        let cf = database
-            .cf_handle(REPOSITORY_FAMILY)
-            .context("repository column family missing")?;
-
+    pub fn fetch_all(database: &dyn Storage) -> Result<BTreeMap<String, YokedRepository>> {
         database
-            .iterator_cf(cf, IteratorMode::Start)
-            .filter_map(Result::ok)
-            .map(|(key, value)| {
+            .iter_prefix(REPOSITORY_FAMILY, &[])?
+            .map(|entry| {
+                let (key, value) = entry?;
                 let key = String::from_utf8(key.into_vec()).context("invalid repo name")?;
                 let value = Yoke::try_attach_to_cart(value, |data| {
                     rkyv::access::<_, rkyv::rancor::Error>(data)
@@ -61,27 +194,41 @@ impl Repository {
             .collect()
     }
 
-    pub fn insert<P: AsRef<Path>>(&self, database: &rocksdb::DB, path: P) -> Result<()> {
-        let cf = database
-            .cf_handle(REPOSITORY_FAMILY)
-            .context("repository column family missing")?;
+    pub fn insert<P: AsRef<Path>>(&self, database: &dyn Storage, path: P) -> Result<()> {
         let path = path.as_ref().to_str().context("invalid path")?;
 
-        database.put_cf(cf, path, rkyv::to_bytes::<rkyv::rancor::Error>(self)?)?;
+        let mut batch = Batch::default();
+        batch.put(
+            REPOSITORY_FAMILY,
+            path,
+            rkyv::to_bytes::<rkyv::rancor::Error>(self)?,
+        );
+        batch.put(REPOSITORY_CASEFOLD_FAMILY, casefold(path), path);
+        database.write(batch)
+    }
 
-        Ok(())
+    /// Finds the exact path of the repository at `path`, ignoring differences in casing. If
+    /// several repositories differ only in casing, the last one indexed wins.
+    pub fn find_case_insensitive<P: AsRef<Path>>(
+        database: &dyn Storage,
+        path: P,
+    ) -> Result<Option<String>> {
+        let path = path.as_ref().to_str().context("invalid path")?;
+
+        let Some(exact) = database.get(REPOSITORY_CASEFOLD_FAMILY, casefold(path).as_bytes())?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(String::from_utf8(exact).context("invalid repo name")?))
     }
 
     pub fn open<P: AsRef<Path>>(
-        database: &rocksdb::DB,
+        database: &dyn Storage,
         path: P,
     ) -> Result<Option<YokedRepository>> {
-        let cf = database
-            .cf_handle(REPOSITORY_FAMILY)
-            .context("repository column family missing")?;
-
         let path = path.as_ref().to_str().context("invalid path")?;
-        let Some(value) = database.get_cf(cf, path)? else {
+        let Some(value) = database.get(REPOSITORY_FAMILY, path.as_bytes())? else {
             return Ok(None);
         };
 
@@ -94,34 +241,85 @@ impl Repository {
 }
 
 impl ArchivedRepository {
-    pub fn delete<P: AsRef<Path>>(&self, database: &rocksdb::DB, path: P) -> Result<()> {
-        let start_id = self.id.0.to_native().to_be_bytes();
-        let mut end_id = start_id;
-        *end_id.last_mut().unwrap() += 1;
+    /// An owned copy of the repository, to write back with some of its fields changed.
+    pub fn deserialize(&self) -> Result<Repository> {
+        rkyv::deserialize::<Repository, rkyv::rancor::Error>(self)
+            .context("Failed to deserialize repository")
+    }
 
-        // delete commits
-        let commit_cf = database
-            .cf_handle(COMMIT_FAMILY)
-            .context("commit column family missing")?;
-        database.delete_range_cf(commit_cf, start_id, end_id)?;
+    /// The newest commit on the default branch, as of the last index run.
+    pub fn latest_commit(&self) -> Option<&ArchivedRecentCommit> {
+        self.recent_commits.first()
+    }
+
+    /// The snapshot formats offered for this repository, the first being the default.
+    pub fn snapshot_formats(&self) -> Vec<SnapshotFormat> {
+        let formats: Vec<_> = self
+            .snapshot_formats
+            .iter()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+
+        if formats.is_empty() {
+            SnapshotFormat::DEFAULT.to_vec()
+        } else {
+            formats
+        }
+    }
 
-        // delete tags
-        let tag_cf = database
-            .cf_handle(TAG_FAMILY)
-            .context("tag column family missing")?;
-        database.delete_range_cf(tag_cf, start_id, end_id)?;
+    pub fn delete<P: AsRef<Path>>(&self, database: &dyn Storage, path: P) -> Result<()> {
+        self.delete_indexed(database)?;
 
         // delete self
-        let repo_cf = database
-            .cf_handle(REPOSITORY_FAMILY)
-            .context("repository column family missing")?;
         let path = path.as_ref().to_str().context("invalid path")?;
-        database.delete_cf(repo_cf, path)?;
+        database.delete(REPOSITORY_FAMILY, path.as_bytes())?;
+
+        // the lookup may belong to another repository differing only in casing by now
+        let key = casefold(path);
+        if database
+            .get(REPOSITORY_CASEFOLD_FAMILY, key.as_bytes())?
+            .as_deref()
+            == Some(path.as_bytes())
+        {
+            database.delete(REPOSITORY_CASEFOLD_FAMILY, key.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes everything indexed for the repository (commits, references, tags...), which is
+    /// keyed by its ID, leaving its entry at its path alone.
+    pub fn delete_indexed(&self, database: &dyn Storage) -> Result<()> {
+        let start_id = self.id.0.to_native().to_be_bytes();
+        let mut end_id = start_id;
+        *end_id.last_mut().unwrap() += 1;
+
+        for family in [
+            // commits
+            COMMIT_FAMILY,
+            COMMIT_FILTER_FAMILY,
+            COMMIT_COUNT_FAMILY,
+            // heads
+            REFERENCE_FAMILY,
+            // tags
+            TAG_FAMILY,
+            TAG_BY_DATE_FAMILY,
+            // ref updates
+            REF_UPDATE_FAMILY,
+            // reverts, fixes and backports
+            COMMIT_RELATION_FAMILY,
+            BACKPORT_FAMILY,
+        ] {
+            database.delete_range(family, &start_id, &end_id)?;
+        }
+
+        // delete the short link lookups of its commits
+        CommitLocations::purge(database, self.id.0.to_native())?;
 
         Ok(())
     }
 
-    pub fn commit_tree(&self, database: Arc<rocksdb::DB>, reference: &str) -> CommitTree {
+    pub fn commit_tree(&self, database: Arc<dyn Storage>, reference: &str) -> CommitTree {
         CommitTree::new(database, RepositoryId(self.id.0.to_native()), reference)
     }
 
@@ -129,30 +327,29 @@ impl ArchivedRepository {
         TagTree::new(database, RepositoryId(self.id.0.to_native()))
     }
 
-    pub fn replace_heads(&self, database: &rocksdb::DB, new_heads: &Vec<String>) -> Result<()> {
-        let cf = database
-            .cf_handle(REFERENCE_FAMILY)
-            .context("missing reference column family")?;
-
-        database.put_cf(
-            cf,
-            self.id.0.to_native().to_be_bytes(),
-            rkyv::to_bytes::<rkyv::rancor::Error>(new_heads)?,
-        )?;
+    /// Whether the indexer has been through this repository's references at least once, until
+    /// then its pages would be missing commits.
+    pub fn is_indexed(&self, database: &dyn Storage) -> Result<bool> {
+        Ok(database
+            .get(REFERENCE_FAMILY, &self.id.0.to_native().to_be_bytes())?
+            .is_some())
+    }
 
-        Ok(())
+    pub fn replace_heads(&self, database: &dyn Storage, new_heads: &Heads) -> Result<()> {
+        database.put(
+            REFERENCE_FAMILY,
+            &self.id.0.to_native().to_be_bytes(),
+            &rkyv::to_bytes::<rkyv::rancor::Error>(new_heads)?,
+        )
     }
 
     #[allow(clippy::type_complexity)]
     pub fn heads(
         &self,
-        database: &rocksdb::DB,
+        database: &dyn Storage,
     ) -> Result<Option<Yoke<&'static ArchivedHeads, Box<[u8]>>>> {
-        let cf = database
-            .cf_handle(REFERENCE_FAMILY)
-            .context("missing reference column family")?;
-
-        let Some(bytes) = database.get_cf(cf, self.id.0.to_native().to_be_bytes())? else {
+        let Some(bytes) = database.get(REFERENCE_FAMILY, &self.id.0.to_native().to_be_bytes())?
+        else {
             return Ok(None);
         };
 
@@ -165,9 +362,45 @@ impl ArchivedRepository {
 }
 
 #[derive(Serialize, Archive, Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Heads(pub Vec<String>);
+pub struct Heads(pub Vec<Head>);
+
+#[derive(Serialize, Archive, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Head {
+    /// The full name of the reference (ie. `refs/heads/main`)
+    pub name: String,
+    /// How far this branch has diverged from the default branch, not set for the default branch
+    /// itself or for tags
+    pub divergence: Option<Divergence>,
+    /// The newest indexed commit on the reference, not set if it hasn't been indexed yet
+    pub latest: Option<HeadCommit>,
+}
+
+/// Enough of the newest commit on a reference to list it alongside the repository's other
+/// references without going through each of their commits.
+#[derive(Serialize, Archive, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeadCommit {
+    pub hash: [u8; 20],
+    pub summary: String,
+    pub author: Author,
+    /// Unix timestamp of when the commit was committed
+    pub committed_at: i64,
+    /// The number of commits reachable from the reference
+    pub commit_count: u64,
+}
 
-#[derive(Serialize, Archive, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Archive, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Divergence {
+    /// Number of commits on the branch that aren't on the default branch
+    pub ahead: u64,
+    /// Number of commits on the default branch that aren't on the branch
+    pub behind: u64,
+    /// The tip of the branch this was computed against
+    pub tip: [u8; 20],
+    /// The tip of the default branch this was computed against
+    pub base: [u8; 20],
+}
+
+#[derive(Serialize, Archive, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct RepositoryId(pub u64);
 
 impl RepositoryId {
@@ -183,3 +416,8 @@ impl Deref for RepositoryId {
         &self.0
     }
 }
+
+/// The key a repository is looked up by when matching paths case-insensitively.
+pub(super) fn casefold(path: &str) -> String {
+    path.to_lowercase()
+}
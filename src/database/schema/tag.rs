@@ -3,11 +3,13 @@ use std::{collections::HashSet, sync::Arc};
 use anyhow::Context;
 use gix::actor::SignatureRef;
 use rkyv::{Archive, Serialize};
+use rocksdb::WriteBatch;
 use yoke::{Yoke, Yokeable};
 
 use crate::database::schema::{
-    commit::{ArchivedAuthor, Author},
-    prefixes::TAG_FAMILY,
+    commit::Author,
+    descending_timestamp,
+    prefixes::{TAG_BY_DATE_FAMILY, TAG_FAMILY},
     repository::RepositoryId,
     Yoked,
 };
@@ -15,12 +17,18 @@ use crate::database::schema::{
 #[derive(Serialize, Archive, Debug, Yokeable)]
 pub struct Tag {
     pub tagger: Option<Author>,
+    /// The message of an annotated tag, without any signature
+    pub message: Option<String>,
 }
 
 impl Tag {
-    pub fn new(tagger: Option<SignatureRef<'_>>) -> Result<Self, anyhow::Error> {
+    pub fn new(
+        tagger: Option<SignatureRef<'_>>,
+        message: Option<String>,
+    ) -> Result<Self, anyhow::Error> {
         Ok(Self {
             tagger: tagger.map(TryFrom::try_from).transpose()?,
+            message,
         })
     }
 
@@ -42,17 +50,36 @@ impl TagTree {
         Self { db, prefix }
     }
 
+    /// The key of a tag in the date ordered index, which sorts newest first.
+    fn date_key(&self, name: &str, tag: &ArchivedTag) -> Vec<u8> {
+        let time = tag.tagger.as_ref().map_or(0, |v| v.time.0.to_native());
+
+        let mut key = self.prefix.to_be_bytes().to_vec();
+        key.extend_from_slice(&descending_timestamp(time));
+        key.extend_from_slice(name.as_bytes());
+        key
+    }
+
     pub fn insert(&self, name: &str, value: &Tag) -> anyhow::Result<()> {
         let cf = self
             .db
             .cf_handle(TAG_FAMILY)
             .context("missing tag column family")?;
+        let date_cf = self
+            .db
+            .cf_handle(TAG_BY_DATE_FAMILY)
+            .context("missing tag by date column family")?;
 
         let mut db_name = self.prefix.to_be_bytes().to_vec();
         db_name.extend_from_slice(name.as_ref());
 
-        self.db
-            .put_cf(cf, db_name, rkyv::to_bytes::<rkyv::rancor::Error>(value)?)?;
+        let value = rkyv::to_bytes::<rkyv::rancor::Error>(value)?;
+        let archived = rkyv::access::<ArchivedTag, rkyv::rancor::Error>(&value)?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(date_cf, self.date_key(name, archived), &value);
+        batch.put_cf(cf, db_name, &value);
+        self.db.write(batch)?;
 
         Ok(())
     }
@@ -62,10 +89,23 @@ impl TagTree {
             .db
             .cf_handle(TAG_FAMILY)
             .context("missing tag column family")?;
+        let date_cf = self
+            .db
+            .cf_handle(TAG_BY_DATE_FAMILY)
+            .context("missing tag by date column family")?;
 
         let mut db_name = self.prefix.to_be_bytes().to_vec();
         db_name.extend_from_slice(name.as_ref());
-        self.db.delete_cf(cf, db_name)?;
+
+        let mut batch = WriteBatch::default();
+
+        if let Some(value) = self.db.get_cf(cf, &db_name)? {
+            let archived = rkyv::access::<ArchivedTag, rkyv::rancor::Error>(&value)?;
+            batch.delete_cf(date_cf, self.date_key(name, archived));
+        }
+
+        batch.delete_cf(cf, db_name);
+        self.db.write(batch)?;
 
         Ok(())
     }
@@ -89,14 +129,18 @@ impl TagTree {
             .collect())
     }
 
-    pub fn fetch_all(&self) -> anyhow::Result<Vec<(YokedString, YokedTag)>> {
+    /// Fetches tags newest first, as ordered by the date they were tagged.
+    pub fn fetch_latest(
+        &self,
+        amount: usize,
+        offset: usize,
+    ) -> anyhow::Result<Vec<(YokedString, YokedTag)>> {
         let cf = self
             .db
-            .cf_handle(TAG_FAMILY)
-            .context("missing tag column family")?;
+            .cf_handle(TAG_BY_DATE_FAMILY)
+            .context("missing tag by date column family")?;
 
-        let mut res = self
-            .db
+        self.db
             .prefix_iterator_cf(cf, self.prefix.to_be_bytes())
             .filter_map(Result::ok)
             .filter_map(|(name, value)| {
@@ -104,6 +148,8 @@ impl TagTree {
                     let data = data
                         .strip_prefix(&self.prefix.to_be_bytes())
                         .ok_or(())?
+                        .get(std::mem::size_of::<u64>()..)
+                        .ok_or(())?
                         .strip_prefix(b"refs/tags/")
                         .ok_or(())?;
                     simdutf8::basic::from_utf8(data).map_err(|_| ())
@@ -112,20 +158,14 @@ impl TagTree {
 
                 Some((name, value))
             })
+            .skip(offset)
+            .take(amount)
             .map(|(name, value)| {
                 let value = Yoke::try_attach_to_cart(value, |data| {
                     rkyv::access::<_, rkyv::rancor::Error>(data)
                 })?;
                 Ok((name, value))
             })
-            .collect::<anyhow::Result<Vec<(YokedString, YokedTag)>>>()?;
-
-        res.sort_unstable_by(|a, b| {
-            let a_tagger = a.1.get().tagger.as_ref().map(ArchivedAuthor::time);
-            let b_tagger = b.1.get().tagger.as_ref().map(ArchivedAuthor::time);
-            b_tagger.cmp(&a_tagger)
-        });
-
-        Ok(res)
+            .collect()
     }
 }
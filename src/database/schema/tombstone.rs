@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{Context, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+use rocksdb::{IteratorMode, WriteBatch};
+use time::OffsetDateTime;
+
+use crate::database::schema::{
+    prefixes::{REPOSITORY_CASEFOLD_FAMILY, REPOSITORY_FAMILY, TOMBSTONE_FAMILY},
+    repository::{casefold, Repository},
+};
+
+/// A repository that has disappeared from the scan path. Rather than deleting its index straight
+/// away, the repository's entry is moved here while the commits, references and tags it owns are
+/// left in place, so a repository that was only briefly missing (eg. while a network filesystem
+/// was remounted) can be restored without reindexing it from scratch.
+#[derive(Serialize, Archive, Deserialize, Debug)]
+pub struct Tombstone {
+    /// Unix timestamp of when the repository was found to be missing
+    pub removed_at: i64,
+    /// The repository's serialised entry in the repository column family
+    pub repository: Vec<u8>,
+}
+
+impl Tombstone {
+    /// Moves the repository at `path` out of the index, returning `false` if it wasn't indexed.
+    pub fn bury(database: &rocksdb::DB, path: &str) -> Result<bool> {
+        let repository_cf = database
+            .cf_handle(REPOSITORY_FAMILY)
+            .context("repository column family missing")?;
+        let casefold_cf = database
+            .cf_handle(REPOSITORY_CASEFOLD_FAMILY)
+            .context("repository casefold column family missing")?;
+        let tombstone_cf = database
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+
+        let Some(repository) = database.get_cf(repository_cf, path)? else {
+            return Ok(false);
+        };
+
+        let tombstone = Self {
+            removed_at: OffsetDateTime::now_utc().unix_timestamp(),
+            repository,
+        };
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(
+            tombstone_cf,
+            path,
+            rkyv::to_bytes::<rkyv::rancor::Error>(&tombstone)?,
+        );
+        batch.delete_cf(repository_cf, path);
+
+        // the lookup may belong to another repository differing only in casing by now
+        let key = casefold(path);
+        if database.get_pinned_cf(casefold_cf, &key)?.as_deref() == Some(path.as_bytes()) {
+            batch.delete_cf(casefold_cf, key);
+        }
+
+        database.write(batch)?;
+
+        Ok(true)
+    }
+
+    /// Puts a buried repository back into the index, returning `false` if there was no
+    /// tombstone for it.
+    pub fn restore(database: &rocksdb::DB, path: &str) -> Result<bool> {
+        let repository_cf = database
+            .cf_handle(REPOSITORY_FAMILY)
+            .context("repository column family missing")?;
+        let casefold_cf = database
+            .cf_handle(REPOSITORY_CASEFOLD_FAMILY)
+            .context("repository casefold column family missing")?;
+        let tombstone_cf = database
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+
+        let Some(value) = database.get_pinned_cf(tombstone_cf, path)? else {
+            return Ok(false);
+        };
+
+        let tombstone = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&value)
+            .context("Failed to deserialize tombstone")?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(repository_cf, path, tombstone.repository);
+        batch.put_cf(casefold_cf, casefold(path), path);
+        batch.delete_cf(tombstone_cf, path);
+        database.write(batch)?;
+
+        Ok(true)
+    }
+
+    /// Copies every tombstone into another database, such as an index being rebuilt, except for
+    /// repositories it already has. Nothing indexed for the buried repositories comes along, so
+    /// they're indexed from scratch if they're restored.
+    pub fn copy_all(from: &rocksdb::DB, to: &rocksdb::DB) -> Result<()> {
+        let from_cf = from
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+        let to_cf = to
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+
+        let mut batch = WriteBatch::default();
+        for entry in from.iterator_cf(from_cf, IteratorMode::Start) {
+            let (key, value) = entry.context("Failed to read tombstone")?;
+
+            let path = std::str::from_utf8(&key).context("invalid repo name")?;
+            if !Repository::exists(to, path)? {
+                batch.put_cf(to_cf, &key, value);
+            }
+        }
+        to.write(batch)?;
+
+        Ok(())
+    }
+
+    pub fn fetch_all(database: &rocksdb::DB) -> Result<BTreeMap<String, Self>> {
+        let cf = database
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+
+        database
+            .iterator_cf(cf, IteratorMode::Start)
+            .filter_map(Result::ok)
+            .map(|(key, value)| {
+                let key = String::from_utf8(key.into_vec()).context("invalid repo name")?;
+                let value = rkyv::from_bytes::<Self, rkyv::rancor::Error>(&value)
+                    .context("Failed to deserialize tombstone")?;
+
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// Deletes everything indexed for repositories that have been buried for longer than
+    /// `retention`, returning the number of repositories purged.
+    pub fn purge_expired(database: &rocksdb::DB, retention: Duration) -> Result<usize> {
+        let cf = database
+            .cf_handle(TOMBSTONE_FAMILY)
+            .context("tombstone column family missing")?;
+
+        let cutoff = (OffsetDateTime::now_utc() - retention).unix_timestamp();
+        let mut purged = 0;
+
+        for (path, tombstone) in Self::fetch_all(database)? {
+            if tombstone.removed_at > cutoff {
+                continue;
+            }
+
+            let buried = rkyv::access::<<Repository as Archive>::Archived, rkyv::rancor::Error>(
+                &tombstone.repository,
+            )
+            .context("Failed to deserialize buried repository")?;
+
+            // the path may have been taken by a new repository since, whose entry mustn't be
+            // deleted along with the old one. Everything else is keyed by the old repository's
+            // ID, so goes either way
+            if Repository::exists(database, &path)? {
+                buried.delete_indexed(database)?;
+            } else {
+                buried.delete(database, &path)?;
+            }
+
+            database.delete_cf(cf, &path)?;
+            purged += 1;
+        }
+
+        Ok(purged)
+    }
+}
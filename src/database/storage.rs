@@ -0,0 +1,464 @@
+//! The key-value store the index is kept in. `RocksDB` is the only backend rgit ships with, the
+//! schema types written against [`Storage`] rather than `RocksDB` itself can be pointed at any
+//! store able to keep its keys in order, such as an embedded database or an in-memory map.
+
+use std::{ops::Range, sync::Arc};
+
+use anyhow::{Context, Result};
+use rocksdb::{
+    DBIteratorWithThreadMode, Direction, IteratorMode, ReadOptions, WriteBatch,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
+
+/// The family holding values that don't belong to any other, such as the schema version.
+pub const DEFAULT_FAMILY: &str = DEFAULT_COLUMN_FAMILY_NAME;
+
+/// A key and its value, as read back from the store.
+pub type Entry = (Box<[u8]>, Box<[u8]>);
+
+/// Entries read back from the store in key order, stopping at the first that couldn't be read.
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<Entry>> + 'a>;
+
+/// An ordered key-value store split into named families, each one keeping its keys sorted
+/// bytewise so they can be iterated by prefix.
+pub trait Storage: Send + Sync {
+    fn get(&self, family: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, family: &str, key: &[u8], value: &[u8]) -> Result<()>;
+
+    fn delete(&self, family: &str, key: &[u8]) -> Result<()>;
+
+    /// Deletes every key from `from` (inclusive) up to `to` (exclusive).
+    fn delete_range(&self, family: &str, from: &[u8], to: &[u8]) -> Result<()>;
+
+    /// Applies every write in the batch at once.
+    fn write(&self, batch: Batch) -> Result<()>;
+
+    /// Applies every write in the batch at once, allowing the store to lose them if the process
+    /// crashes before they're flushed. Used for writes that can be redone, like indexed commits.
+    fn write_without_wal(&self, batch: Batch) -> Result<()> {
+        self.write(batch)
+    }
+
+    /// Iterates over the keys starting with `prefix` in order, every key in the family if the
+    /// prefix is empty.
+    fn iter_prefix<'a>(&'a self, family: &str, prefix: &[u8]) -> Result<Entries<'a>>;
+
+    /// Iterates over the keys in `range`, from the last one backwards if `reverse` is set.
+    fn iter_range<'a>(
+        &'a self,
+        family: &str,
+        range: Range<&[u8]>,
+        reverse: bool,
+    ) -> Result<Entries<'a>>;
+}
+
+/// Writes to be applied together by [`Storage::write`].
+#[derive(Default)]
+pub struct Batch(Vec<Operation>);
+
+enum Operation {
+    Put {
+        family: &'static str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        family: &'static str,
+        key: Vec<u8>,
+    },
+    DeleteRange {
+        family: &'static str,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+}
+
+impl Batch {
+    pub fn put(&mut self, family: &'static str, key: impl Into<Vec<u8>>, value: impl AsRef<[u8]>) {
+        self.0.push(Operation::Put {
+            family,
+            key: key.into(),
+            value: value.as_ref().to_vec(),
+        });
+    }
+
+    pub fn delete(&mut self, family: &'static str, key: impl Into<Vec<u8>>) {
+        self.0.push(Operation::Delete {
+            family,
+            key: key.into(),
+        });
+    }
+
+    /// Deletes every key from `from` (inclusive) up to `to` (exclusive).
+    pub fn delete_range(
+        &mut self,
+        family: &'static str,
+        from: impl Into<Vec<u8>>,
+        to: impl Into<Vec<u8>>,
+    ) {
+        self.0.push(Operation::DeleteRange {
+            family,
+            from: from.into(),
+            to: to.into(),
+        });
+    }
+
+    /// The number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<T: Storage + ?Sized> Storage for Arc<T> {
+    fn get(&self, family: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(family, key)
+    }
+
+    fn put(&self, family: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        (**self).put(family, key, value)
+    }
+
+    fn delete(&self, family: &str, key: &[u8]) -> Result<()> {
+        (**self).delete(family, key)
+    }
+
+    fn delete_range(&self, family: &str, from: &[u8], to: &[u8]) -> Result<()> {
+        (**self).delete_range(family, from, to)
+    }
+
+    fn write(&self, batch: Batch) -> Result<()> {
+        (**self).write(batch)
+    }
+
+    fn write_without_wal(&self, batch: Batch) -> Result<()> {
+        (**self).write_without_wal(batch)
+    }
+
+    fn iter_prefix<'a>(&'a self, family: &str, prefix: &[u8]) -> Result<Entries<'a>> {
+        (**self).iter_prefix(family, prefix)
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        family: &str,
+        range: Range<&[u8]>,
+        reverse: bool,
+    ) -> Result<Entries<'a>> {
+        (**self).iter_range(family, range, reverse)
+    }
+}
+
+/// The handle of `family`, `None` for the default family which doesn't have one when the
+/// database is opened without listing it, so it's accessed through the plain methods instead.
+fn column_family<'a>(
+    db: &'a rocksdb::DB,
+    family: &str,
+) -> Result<Option<&'a rocksdb::ColumnFamily>> {
+    if family == DEFAULT_FAMILY {
+        return Ok(None);
+    }
+
+    db.cf_handle(family)
+        .map(Some)
+        .with_context(|| format!("missing {family} column family"))
+}
+
+fn iterator<'a>(
+    db: &'a rocksdb::DB,
+    family: &str,
+    opts: ReadOptions,
+    mode: IteratorMode,
+) -> Result<DBIteratorWithThreadMode<'a, rocksdb::DB>> {
+    Ok(match column_family(db, family)? {
+        Some(cf) => db.iterator_cf_opt(cf, opts, mode),
+        None => db.iterator_opt(mode, opts),
+    })
+}
+
+fn to_rocksdb_batch(db: &rocksdb::DB, batch: Batch) -> Result<WriteBatch> {
+    let mut rocksdb_batch = WriteBatch::default();
+
+    for operation in batch.0 {
+        match operation {
+            Operation::Put { family, key, value } => match column_family(db, family)? {
+                Some(cf) => rocksdb_batch.put_cf(cf, key, value),
+                None => rocksdb_batch.put(key, value),
+            },
+            Operation::Delete { family, key } => match column_family(db, family)? {
+                Some(cf) => rocksdb_batch.delete_cf(cf, key),
+                None => rocksdb_batch.delete(key),
+            },
+            Operation::DeleteRange { family, from, to } => match column_family(db, family)? {
+                Some(cf) => rocksdb_batch.delete_range_cf(cf, from, to),
+                None => rocksdb_batch.delete_range(from, to),
+            },
+        }
+    }
+
+    Ok(rocksdb_batch)
+}
+
+impl Storage for rocksdb::DB {
+    fn get(&self, family: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(match column_family(self, family)? {
+            Some(cf) => self.get_cf(cf, key)?,
+            None => rocksdb::DB::get(self, key)?,
+        })
+    }
+
+    fn put(&self, family: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        match column_family(self, family)? {
+            Some(cf) => self.put_cf(cf, key, value)?,
+            None => rocksdb::DB::put(self, key, value)?,
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, family: &str, key: &[u8]) -> Result<()> {
+        match column_family(self, family)? {
+            Some(cf) => self.delete_cf(cf, key)?,
+            None => rocksdb::DB::delete(self, key)?,
+        }
+
+        Ok(())
+    }
+
+    fn delete_range(&self, family: &str, from: &[u8], to: &[u8]) -> Result<()> {
+        match column_family(self, family)? {
+            Some(cf) => self.delete_range_cf(cf, from, to)?,
+            None => {
+                let mut batch = WriteBatch::default();
+                batch.delete_range(from, to);
+                rocksdb::DB::write(self, batch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, batch: Batch) -> Result<()> {
+        Ok(rocksdb::DB::write(self, to_rocksdb_batch(self, batch)?)?)
+    }
+
+    fn write_without_wal(&self, batch: Batch) -> Result<()> {
+        Ok(rocksdb::DB::write_without_wal(
+            self,
+            to_rocksdb_batch(self, batch)?,
+        )?)
+    }
+
+    fn iter_prefix<'a>(&'a self, family: &str, prefix: &[u8]) -> Result<Entries<'a>> {
+        let prefix = prefix.to_vec();
+        let iter = iterator(
+            self,
+            family,
+            ReadOptions::default(),
+            IteratorMode::From(&prefix, Direction::Forward),
+        )?;
+
+        Ok(Box::new(
+            iter.map(|entry| entry.context("failed to read from the database"))
+                .take_while(move |entry| {
+                    entry
+                        .as_ref()
+                        .map_or(true, |(key, _)| key.starts_with(&prefix))
+                }),
+        ))
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        family: &str,
+        range: Range<&[u8]>,
+        reverse: bool,
+    ) -> Result<Entries<'a>> {
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_range(range.start..range.end);
+
+        let mode = if reverse {
+            IteratorMode::End
+        } else {
+            IteratorMode::Start
+        };
+
+        Ok(Box::new(iterator(self, family, opts, mode)?.map(|entry| {
+            entry.context("failed to read from the database")
+        })))
+    }
+}
+
+/// A store kept entirely in memory, for running the schema types without a database on disk.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStorage(std::sync::RwLock<std::collections::BTreeMap<(String, Vec<u8>), Vec<u8>>>);
+
+#[cfg(test)]
+impl MemoryStorage {
+    /// Copies out the entries of `family` matching `filter`, as the lock can't be held on to by
+    /// an iterator.
+    fn collect(&self, family: &str, filter: impl Fn(&[u8]) -> bool) -> Vec<Result<Entry>> {
+        self.0
+            .read()
+            .unwrap()
+            .range((family.to_string(), Vec::new())..)
+            .take_while(|((entry_family, _), _)| entry_family == family)
+            .filter(|((_, key), _)| filter(key))
+            .map(|((_, key), value)| Ok((Box::from(key.as_slice()), Box::from(value.as_slice()))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Storage for MemoryStorage {
+    fn get(&self, family: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .0
+            .read()
+            .unwrap()
+            .get(&(family.to_string(), key.to_vec()))
+            .cloned())
+    }
+
+    fn put(&self, family: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.0
+            .write()
+            .unwrap()
+            .insert((family.to_string(), key.to_vec()), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, family: &str, key: &[u8]) -> Result<()> {
+        self.0
+            .write()
+            .unwrap()
+            .remove(&(family.to_string(), key.to_vec()));
+        Ok(())
+    }
+
+    fn delete_range(&self, family: &str, from: &[u8], to: &[u8]) -> Result<()> {
+        self.0.write().unwrap().retain(|(entry_family, key), _| {
+            entry_family != family || key.as_slice() < from || key.as_slice() >= to
+        });
+        Ok(())
+    }
+
+    fn write(&self, batch: Batch) -> Result<()> {
+        for operation in batch.0 {
+            match operation {
+                Operation::Put { family, key, value } => self.put(family, &key, &value)?,
+                Operation::Delete { family, key } => self.delete(family, &key)?,
+                Operation::DeleteRange { family, from, to } => {
+                    self.delete_range(family, &from, &to)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn iter_prefix<'a>(&'a self, family: &str, prefix: &[u8]) -> Result<Entries<'a>> {
+        Ok(Box::new(
+            self.collect(family, |key| key.starts_with(prefix))
+                .into_iter(),
+        ))
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        family: &str,
+        range: Range<&[u8]>,
+        reverse: bool,
+    ) -> Result<Entries<'a>> {
+        let mut entries = self.collect(family, |key| range.contains(&key));
+
+        if reverse {
+            entries.reverse();
+        }
+
+        Ok(Box::new(entries.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Batch, MemoryStorage, Storage, DEFAULT_FAMILY};
+
+    const FAMILY: &str = "test";
+    const OTHER_FAMILY: &str = "test_other";
+
+    fn keys(storage: &dyn Storage, family: &str, prefix: &[u8]) -> Vec<Vec<u8>> {
+        storage
+            .iter_prefix(family, prefix)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.into_vec())
+            .collect()
+    }
+
+    #[test]
+    fn iter_prefix_stays_within_prefix_and_family() {
+        let storage = MemoryStorage::default();
+        storage.put(FAMILY, b"aa", b"1").unwrap();
+        storage.put(FAMILY, b"ab", b"2").unwrap();
+        storage.put(FAMILY, b"b", b"3").unwrap();
+        storage.put(OTHER_FAMILY, b"ac", b"4").unwrap();
+
+        assert_eq!(
+            keys(&storage, FAMILY, b"a"),
+            [b"aa".to_vec(), b"ab".to_vec()]
+        );
+        assert_eq!(
+            keys(&storage, FAMILY, b""),
+            [b"aa".to_vec(), b"ab".to_vec(), b"b".to_vec()]
+        );
+        assert_eq!(keys(&storage, OTHER_FAMILY, b""), [b"ac".to_vec()]);
+    }
+
+    #[test]
+    fn iter_range_excludes_end_and_reverses() {
+        let storage = MemoryStorage::default();
+        for key in [b"a", b"b", b"c", b"d"] {
+            storage.put(FAMILY, key, b"").unwrap();
+        }
+
+        let range = |reverse| -> Vec<Vec<u8>> {
+            storage
+                .iter_range(FAMILY, b"b".as_slice()..b"d".as_slice(), reverse)
+                .unwrap()
+                .map(|entry| entry.unwrap().0.into_vec())
+                .collect()
+        };
+
+        assert_eq!(range(false), [b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(range(true), [b"c".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn batch_applies_every_operation() {
+        let storage = MemoryStorage::default();
+        storage.put(FAMILY, b"a", b"").unwrap();
+        storage.put(FAMILY, b"b1", b"").unwrap();
+        storage.put(FAMILY, b"b2", b"").unwrap();
+        storage.put(OTHER_FAMILY, b"b1", b"").unwrap();
+
+        let mut batch = Batch::default();
+        batch.put(FAMILY, b"c".to_vec(), b"");
+        batch.delete(FAMILY, b"a".to_vec());
+        batch.delete_range(FAMILY, b"b".to_vec(), b"c".to_vec());
+        batch.put(DEFAULT_FAMILY, b"version".to_vec(), b"1");
+        assert_eq!(batch.len(), 4);
+        storage.write(batch).unwrap();
+
+        assert_eq!(keys(&storage, FAMILY, b""), [b"c".to_vec()]);
+        assert_eq!(keys(&storage, OTHER_FAMILY, b""), [b"b1".to_vec()]);
+        assert_eq!(
+            storage.get(DEFAULT_FAMILY, b"version").unwrap().as_deref(),
+            Some(b"1".as_slice())
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! `--email-display`, how much of the email addresses of authors, committers and taggers is shown
+//! in pages, feeds and the API, so public instances don't hand them to spam harvesters. Patches
+//! and mboxes are left alone since they're meant to be applied, and their addresses are in the
+//! repository for anyone who clones it anyway.
+
+use std::{borrow::Cow, sync::OnceLock};
+
+use clap::ValueEnum;
+
+static EMAIL_DISPLAY: OnceLock<EmailDisplay> = OnceLock::new();
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum EmailDisplay {
+    /// Show addresses in full.
+    #[default]
+    Full,
+    /// Show the part of the address before the `@`, as `user@…`.
+    User,
+    /// Show the domain of the address, as `…@example.com`.
+    Domain,
+    /// Don't show addresses at all.
+    Hidden,
+}
+
+impl EmailDisplay {
+    /// Sets how addresses are shown, must be called before serving any requests.
+    pub fn install(self) {
+        let _res = EMAIL_DISPLAY.set(self);
+    }
+
+    pub fn current() -> Self {
+        EMAIL_DISPLAY.get().copied().unwrap_or_default()
+    }
+
+    /// The part of `email` that may be shown, or `None` if none of it may.
+    pub fn apply(self, email: &str) -> Option<Cow<'_, str>> {
+        match (self, email.rsplit_once('@')) {
+            (Self::Full, _) => Some(Cow::Borrowed(email)),
+            (Self::User, Some((user, _))) => Some(Cow::Owned(format!("{user}@…"))),
+            (Self::User, None) => Some(Cow::Borrowed(email)),
+            (Self::Domain, Some((_, domain))) => Some(Cow::Owned(format!("…@{domain}"))),
+            (Self::Domain | Self::Hidden, _) => None,
+        }
+    }
+}
+
+/// Whether addresses are shown in full, and so can be linked to, put in `mailto:` links and
+/// given in feeds, which require a complete address.
+pub fn in_full() -> bool {
+    EmailDisplay::current() == EmailDisplay::Full
+}
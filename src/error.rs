@@ -0,0 +1,161 @@
+//! The error type returned by request handlers, mapping failures onto the status code that best
+//! describes them and rendering them as a page in the site's theme.
+
+use std::{borrow::Cow, fmt, sync::Arc, time::Duration};
+
+use askama::Template;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::into_response;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub enum Error {
+    /// The repository, reference, object or path the user asked for doesn't exist.
+    NotFound(Cow<'static, str>),
+    /// The request's parameters couldn't be understood.
+    BadRequest(Cow<'static, str>),
+    /// The repository or object the user asked for was taken down, with the reason why.
+    LegalReasons(Cow<'static, str>),
+    /// The request took too long to serve, or the database couldn't be read.
+    Unavailable(anyhow::Error),
+    /// Anything else, these are logged but the details aren't shown to the user.
+    Internal(anyhow::Error),
+}
+
+impl Error {
+    pub fn repository_not_found() -> Self {
+        Self::NotFound(Cow::Borrowed("Repository not found"))
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::LegalReasons(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Works out the status code an error should be reported with by looking for the errors we know
+/// how to classify in its chain of causes.
+fn classify(e: &anyhow::Error) -> StatusCode {
+    use gix::object::find::existing;
+
+    for cause in e.chain() {
+        let not_found = cause.is::<NotFound>()
+            || cause.is::<gix::revision::spec::parse::single::Error>()
+            || matches!(
+                cause.downcast_ref::<gix::reference::find::existing::Error>(),
+                Some(gix::reference::find::existing::Error::NotFound { .. })
+            )
+            || matches!(
+                cause.downcast_ref::<existing::Error>(),
+                Some(existing::Error::NotFound { .. })
+            )
+            || matches!(
+                cause.downcast_ref::<existing::with_conversion::Error>(),
+                Some(existing::with_conversion::Error::Find(
+                    existing::Error::NotFound { .. }
+                ))
+            );
+
+        if not_found {
+            return StatusCode::NOT_FOUND;
+        } else if cause.is::<gix::hash::decode::Error>() {
+            return StatusCode::BAD_REQUEST;
+        } else if cause.is::<rocksdb::Error>() || cause.is::<tokio::time::error::Elapsed>() {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+    }
+
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        match classify(&e) {
+            StatusCode::NOT_FOUND => Self::NotFound(Cow::Owned(e.to_string())),
+            StatusCode::BAD_REQUEST => Self::BadRequest(Cow::Owned(e.to_string())),
+            StatusCode::SERVICE_UNAVAILABLE => Self::Unavailable(e),
+            _ => Self::Internal(e),
+        }
+    }
+}
+
+impl From<NotFound> for Error {
+    fn from(e: NotFound) -> Self {
+        Self::NotFound(Cow::Borrowed(e.0))
+    }
+}
+
+/// Errors shared between requests through a cache can't be moved out of, so the chain of causes
+/// is flattened into a message once they've been classified.
+impl From<Arc<anyhow::Error>> for Error {
+    fn from(e: Arc<anyhow::Error>) -> Self {
+        match classify(&e) {
+            StatusCode::NOT_FOUND => Self::NotFound(Cow::Owned(e.to_string())),
+            StatusCode::BAD_REQUEST => Self::BadRequest(Cow::Owned(e.to_string())),
+            StatusCode::SERVICE_UNAVAILABLE => Self::Unavailable(anyhow::anyhow!("{e:?}")),
+            _ => Self::Internal(anyhow::anyhow!("{e:?}")),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        let message = match self {
+            Self::NotFound(message) | Self::BadRequest(message) | Self::LegalReasons(message) => {
+                message
+            }
+            Self::Unavailable(e) => {
+                error!("Service unavailable: {e:?}");
+                Cow::Borrowed("The server is too busy to handle this request, try again shortly.")
+            }
+            Self::Internal(e) => {
+                error!("Failed to handle request: {e:?}");
+                Cow::Borrowed("Something went wrong while handling this request.")
+            }
+        };
+
+        (status, into_response(View { status, message })).into_response()
+    }
+}
+
+/// Marks an error as being caused by something the user asked for not existing, for failures
+/// that don't come with a typed error of their own, such as a missing path in a tree.
+#[derive(Debug)]
+pub struct NotFound(pub &'static str);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Fails requests that take longer than `timeout` to serve with a 503.
+pub async fn timeout(State(timeout): State<Duration>, request: Request, next: Next) -> Response {
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(e) => Error::Unavailable(e.into()).into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct View {
+    status: StatusCode,
+    message: Cow<'static, str>,
+}
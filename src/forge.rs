@@ -0,0 +1,159 @@
+//! Fetches repository metadata (description, owner and topics) from the public APIs of the
+//! forges that mirrored repositories are pulled from.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use gix::bstr::ByteSlice;
+use serde::Deserialize;
+
+const USER_AGENT: &str = concat!("rgit/", clap::crate_version!());
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct ForgeMetadata {
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub topics: Vec<String>,
+}
+
+/// A repository hosted on a forge we know how to query.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Forge {
+    GitHub { owner: String, name: String },
+    GitLab { host: String, path: String },
+    SourceHut { owner: String, name: String },
+}
+
+impl Forge {
+    /// Determines the forge from an upstream clone URL, returning `None` if the URL isn't for a
+    /// forge we support.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let url = gix::url::parse(url.as_bytes().as_bstr()).ok()?;
+        let host = url.host()?.to_ascii_lowercase();
+
+        let path = url.path.to_str().ok()?;
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+
+        if host == "github.com" {
+            let (owner, name) = path.split_once('/')?;
+            Some(Self::GitHub {
+                owner: owner.to_string(),
+                name: name.to_string(),
+            })
+        } else if host == "git.sr.ht" {
+            let (owner, name) = path.split_once('/')?;
+            Some(Self::SourceHut {
+                owner: owner.trim_start_matches('~').to_string(),
+                name: name.to_string(),
+            })
+        } else if host.starts_with("gitlab.") || host == "salsa.debian.org" {
+            Some(Self::GitLab {
+                host,
+                path: path.to_string(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The URL of the page showing a commit on the forge, with `{commit}` standing in for the ID
+    /// of the commit.
+    pub fn commit_url_template(&self) -> String {
+        match self {
+            Self::GitHub { owner, name } => {
+                format!("https://github.com/{owner}/{name}/commit/{{commit}}")
+            }
+            Self::GitLab { host, path } => format!("https://{host}/{path}/-/commit/{{commit}}"),
+            Self::SourceHut { owner, name } => {
+                format!("https://git.sr.ht/~{owner}/{name}/commit/{{commit}}")
+            }
+        }
+    }
+
+    pub fn fetch(&self) -> anyhow::Result<ForgeMetadata> {
+        match self {
+            Self::GitHub { owner, name } => {
+                #[derive(Deserialize)]
+                struct Owner {
+                    login: String,
+                }
+
+                #[derive(Deserialize)]
+                struct Response {
+                    description: Option<String>,
+                    owner: Owner,
+                    #[serde(default)]
+                    topics: Vec<String>,
+                }
+
+                let res: Response = get(&format!("https://api.github.com/repos/{owner}/{name}"))?;
+
+                Ok(ForgeMetadata {
+                    description: res.description,
+                    owner: Some(res.owner.login),
+                    topics: res.topics,
+                })
+            }
+            Self::GitLab { host, path } => {
+                #[derive(Deserialize)]
+                struct Namespace {
+                    name: String,
+                }
+
+                #[derive(Deserialize)]
+                struct Response {
+                    description: Option<String>,
+                    namespace: Namespace,
+                    #[serde(default)]
+                    topics: Vec<String>,
+                }
+
+                let res: Response = get(&format!(
+                    "https://{host}/api/v4/projects/{}",
+                    path.replace('/', "%2F")
+                ))?;
+
+                Ok(ForgeMetadata {
+                    description: res.description,
+                    owner: Some(res.namespace.name),
+                    topics: res.topics,
+                })
+            }
+            Self::SourceHut { owner, name } => {
+                #[derive(Deserialize)]
+                struct Owner {
+                    canonical_name: String,
+                }
+
+                #[derive(Deserialize)]
+                struct Response {
+                    description: Option<String>,
+                    owner: Owner,
+                }
+
+                let res: Response = get(&format!("https://git.sr.ht/api/~{owner}/repos/{name}"))?;
+
+                Ok(ForgeMetadata {
+                    description: res.description,
+                    owner: Some(res.owner.canonical_name),
+                    topics: Vec::new(),
+                })
+            }
+        }
+    }
+}
+
+fn get<T: for<'de> Deserialize<'de>>(url: &str) -> anyhow::Result<T> {
+    let response = ureq::get(url)
+        .set("User-Agent", USER_AGENT)
+        .set("Accept", "application/json")
+        .timeout(TIMEOUT)
+        .call()
+        .map_err(|e| anyhow!("request to {url} failed: {e}"))?;
+
+    response
+        .into_json()
+        .with_context(|| format!("invalid response from {url}"))
+}
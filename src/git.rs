@@ -1,8 +1,7 @@
 use anyhow::{anyhow, Context, Result};
 use axum::response::IntoResponse;
-use bytes::{buf::Writer, BufMut, Bytes, BytesMut};
-use comrak::{ComrakPlugins, Options};
-use flate2::write::GzEncoder;
+use bytes::Bytes;
+use comrak::ComrakPlugins;
 use gix::{
     actor::SignatureRef,
     bstr::{BStr, BString, ByteSlice, ByteVec},
@@ -16,50 +15,150 @@ use gix::{
 };
 use itertools::Itertools;
 use moka::future::Cache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::{
-    collections::{BTreeMap, VecDeque},
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
     ffi::OsStr,
     fmt::{self, Arguments, Write},
-    io::ErrorKind,
     path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use tar::Builder;
 use time::{OffsetDateTime, UtcOffset};
 use tracing::{error, instrument, warn};
 
 use crate::{
-    syntax_highlight::{format_file, format_file_inner, ComrakHighlightAdapter, FileIdentifier},
+    archive::{ArchiveWriter, ChannelWriter, SnapshotChecksum, SnapshotFormat},
+    blob_stream::{BlobStreamer, LargeBlob},
+    database::schema::commit::CommitFilter,
+    error::NotFound,
+    grep::{self, GrepFile},
+    lfs::LfsPointer,
+    markup::MarkupFormat,
+    patch::{self, Conflict, FilePatch, FilePreview},
+    repository_pool::RepositoryPool,
+    snapshot_jobs::{PreparedSnapshot, SnapshotJobs, SnapshotKey},
+    syntax_highlight::{
+        format_ansi_with_line_anchors, format_file_inner, format_file_with_line_anchors,
+        ComrakHighlightAdapter, FileIdentifier, LineStyle, Syntax,
+    },
+    trailers::{reverted_hash, split_trailers, Trailer},
     unified_diff_builder::{Callback, UnifiedDiffBuilder},
 };
 
-type ReadmeCacheKey = (PathBuf, Option<Arc<str>>);
+/// Files larger than this are skipped when searching a tree.
+const GREP_MAX_FILE_SIZE: usize = 1024 * 1024;
+
+/// The number of matching files a search returns before stopping early.
+const GREP_MAX_FILES: usize = 100;
+
+/// The number of bytes of blobs a search reads before stopping early.
+const GREP_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// How long a search runs for before stopping early.
+const GREP_TIME_BUDGET: Duration = Duration::from_secs(5);
+
+/// The number of commits a filtered walk of a branch looks at before giving up on finding more.
+const MAX_FILTERED_WALK: usize = 100_000;
+
+type SnapshotChecksumKey = (
+    PathBuf,
+    ObjectId,
+    Option<PathBuf>,
+    SnapshotFormat,
+    u32,
+    SnapshotChecksum,
+);
 
 pub struct Git {
-    commits: Cache<(ObjectId, bool), Arc<Commit>>,
-    readme_cache: Cache<ReadmeCacheKey, Option<(ReadmeFormat, Arc<str>)>>,
-    open_repositories: Cache<PathBuf, ThreadSafeRepository>,
+    commits: Cache<(ObjectId, usize, bool), Arc<Commit>>,
+    repositories: Arc<RepositoryPool>,
+    rendered_blobs: moka::sync::Cache<(ObjectId, BlobRenderer), Arc<str>>,
+    highlighted_diffs: moka::sync::Cache<HighlightedDiffKey, HighlightedFileDiff>,
+    topo_orders: Cache<(PathBuf, ObjectId), Arc<[ObjectId]>>,
+    snapshot_checksums: Cache<SnapshotChecksumKey, Arc<str>>,
+    tree_indexes: moka::sync::Cache<ObjectId, Arc<[String]>>,
+    diff_highlight_budget: Duration,
+    blob_streamer: Option<BlobStreamer>,
 }
 
 impl Git {
-    #[instrument]
-    pub fn new() -> Self {
+    #[instrument(skip(repositories, blob_streamer))]
+    pub fn new(
+        diff_highlight_budget: Duration,
+        repositories: Arc<RepositoryPool>,
+        blob_streamer: Option<BlobStreamer>,
+    ) -> Self {
         Self {
             commits: Cache::builder()
                 .time_to_live(Duration::from_secs(30))
                 .max_capacity(100)
                 .build(),
-            readme_cache: Cache::builder()
-                .time_to_live(Duration::from_secs(30))
-                .max_capacity(100)
+            repositories,
+            rendered_blobs: moka::sync::Cache::builder()
+                .time_to_idle(Duration::from_secs(600))
+                .weigher(|_, v: &Arc<str>| u32::try_from(v.len()).unwrap_or(u32::MAX))
+                .max_capacity(64 * 1024 * 1024)
                 .build(),
-            open_repositories: Cache::builder()
-                .time_to_idle(Duration::from_secs(120))
-                .max_capacity(100)
+            highlighted_diffs: moka::sync::Cache::builder()
+                .time_to_idle(Duration::from_secs(600))
+                .weigher(|_, v: &HighlightedFileDiff| {
+                    u32::try_from(v.output.len()).unwrap_or(u32::MAX)
+                })
+                .max_capacity(64 * 1024 * 1024)
+                .build(),
+            topo_orders: Cache::builder()
+                .time_to_idle(Duration::from_secs(600))
+                .weigher(|_, v: &Arc<[ObjectId]>| u32::try_from(v.len()).unwrap_or(u32::MAX))
+                .max_capacity(4 * 1024 * 1024)
+                .build(),
+            snapshot_checksums: Cache::builder()
+                .time_to_idle(Duration::from_secs(3600))
+                .max_capacity(1000)
+                .build(),
+            tree_indexes: moka::sync::Cache::builder()
+                .time_to_idle(Duration::from_secs(600))
+                .weigher(|_, v: &Arc<[String]>| {
+                    u32::try_from(v.iter().map(String::len).sum::<usize>()).unwrap_or(u32::MAX)
+                })
+                .max_capacity(64 * 1024 * 1024)
                 .build(),
+            diff_highlight_budget,
+            blob_streamer,
+        }
+    }
+
+    /// Renders a blob, or fetches the output from the last time the same content was rendered
+    /// the same way. The blob's ID stands in for its content, so identical files across forks
+    /// and vendored copies are only rendered once.
+    fn render_blob(
+        &self,
+        id: ObjectId,
+        renderer: BlobRenderer,
+        render: impl FnOnce() -> Result<String>,
+    ) -> Result<Arc<str>> {
+        let key = (id, renderer);
+
+        if let Some(output) = self.rendered_blobs.get(&key) {
+            return Ok(output);
+        }
+
+        let output = Arc::<str>::from(render()?);
+        self.rendered_blobs.insert(key, output.clone());
+
+        Ok(output)
+    }
+
+    fn diff_highlighting(&self) -> DiffHighlighting<'_> {
+        DiffHighlighting {
+            cache: &self.highlighted_diffs,
+            deadline: Instant::now() + self.diff_highlight_budget,
         }
     }
 }
@@ -71,24 +170,29 @@ impl Git {
         repo_path: PathBuf,
         branch: Option<Arc<str>>,
     ) -> Result<Arc<OpenRepository>> {
+        let repositories = self.repositories.clone();
         let repo = repo_path.clone();
-        let repo = self
-            .open_repositories
-            .try_get_with_by_ref(&repo_path, async move {
-                tokio::task::spawn_blocking(move || {
-                    gix::open::Options::isolated()
-                        .open_path_as_is(true)
-                        .open(&repo)
-                })
-                .await
-                .context("Failed to join Tokio task")
-                .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?
-                .map_err(|err| {
-                    error!("{}", err);
-                    std::io::Error::new(ErrorKind::Other, "Failed to open repository")
-                })
-            })
-            .await?;
+        let repo = tokio::task::spawn_blocking(move || repositories.get(&repo))
+            .await
+            .context("Failed to join Tokio task")?
+            .map_err(|err| {
+                error!("{}", err);
+                anyhow!("Failed to open repository")
+            })?;
+
+        // pages viewed without a branch show the overridden default branch rather than HEAD
+        let branch = branch.or_else(|| {
+            let value = repo
+                .to_thread_local()
+                .config_snapshot()
+                .string("rgit.defaultBranch")?
+                .to_string();
+            let reference = default_branch_override(&value)?;
+
+            Some(Arc::from(
+                reference.strip_prefix("refs/heads/").unwrap_or(&reference),
+            ))
+        });
 
         Ok(Arc::new(OpenRepository {
             git: self,
@@ -99,6 +203,21 @@ impl Git {
     }
 }
 
+/// Parses `rgit.defaultBranch`, which overrides the default branch of repositories whose `HEAD`
+/// points somewhere unhelpful, into a full reference name. Either a branch name or a full
+/// reference name may be given, as with the other settings naming a branch.
+pub fn default_branch_override(value: &str) -> Option<String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else if value.starts_with("refs/") {
+        Some(value.to_string())
+    } else {
+        Some(format!("refs/heads/{value}"))
+    }
+}
+
 pub struct OpenRepository {
     git: Arc<Git>,
     cache_key: PathBuf,
@@ -113,6 +232,7 @@ impl OpenRepository {
         path: Option<PathBuf>,
         tree_id: Option<&str>,
         formatted: bool,
+        render_markup: bool,
     ) -> Result<PathDestination> {
         let tree_id = tree_id
             .map(ObjectId::from_str)
@@ -139,7 +259,16 @@ impl OpenRepository {
             if let Some(path) = path.as_ref() {
                 let item = tree
                     .peel_to_entry_by_path(path)?
-                    .context("Path doesn't exist in tree")?;
+                    .ok_or(NotFound("Path doesn't exist in tree"))?;
+
+                if !formatted {
+                    if let Some(streamer) = &self.git.blob_streamer {
+                        if let Some(blob) = streamer.try_open(&repo, item.object_id())? {
+                            return Ok(PathDestination::LargeFile(blob));
+                        }
+                    }
+                }
+
                 let object = item.object().context("Path in tree isn't an object")?;
 
                 match object.kind {
@@ -147,15 +276,53 @@ impl OpenRepository {
                         let mut blob = object.into_blob();
 
                         let size = blob.data.len();
+                        let lfs = LfsPointer::parse(&blob.data);
+                        let markup = MarkupFormat::from_path(path).filter(|_| lfs.is_none());
+                        let mime = sniff_mime(&blob.data);
+                        let lfs_server = lfs.as_ref().and_then(|_| {
+                            Some(repo.config_snapshot().string("lfs.url")?.to_string())
+                        });
+
+                        let text = if is_binary(&blob.data) {
+                            None
+                        } else {
+                            simdutf8::basic::from_utf8(&blob.data).ok()
+                        };
 
-                        let content = match (formatted, simdutf8::basic::from_utf8(&blob.data)) {
-                            (true, Err(_)) => Content::Binary(vec![]),
-                            (true, Ok(data)) => Content::Text(Cow::Owned(format_file(
-                                data,
-                                FileIdentifier::Path(path.as_path()),
-                            )?)),
-                            (false, Err(_)) => Content::Binary(blob.take_data()),
-                            (false, Ok(_data)) => Content::Text(Cow::Owned(unsafe {
+                        let content = match (formatted, text, markup.filter(|_| render_markup)) {
+                            (true, None, _) => Content::Binary(vec![]),
+                            (true, Some(data), Some(markup)) => Content::Rendered(
+                                self.git
+                                    .render_blob(blob.id, BlobRenderer::Markup(markup), || {
+                                        markup.render(data)
+                                    })?
+                                    .to_string(),
+                            ),
+                            (true, Some(data), None) if crate::ansi::contains_escapes(data) => {
+                                Content::Text(Cow::Owned(
+                                    self.git
+                                        .render_blob(blob.id, BlobRenderer::Ansi, || {
+                                            Ok(format_ansi_with_line_anchors(data))
+                                        })?
+                                        .to_string(),
+                                ))
+                            }
+                            (true, Some(data), None) => Content::Text(Cow::Owned(
+                                self.git
+                                    .render_blob(
+                                        blob.id,
+                                        BlobRenderer::Highlighted(Syntax::for_path(path)),
+                                        || {
+                                            format_file_with_line_anchors(
+                                                data,
+                                                FileIdentifier::Path(path.as_path()),
+                                            )
+                                        },
+                                    )?
+                                    .to_string(),
+                            )),
+                            (false, None, _) => Content::Binary(blob.take_data()),
+                            (false, Some(_data), _) => Content::Text(Cow::Owned(unsafe {
                                 String::from_utf8_unchecked(blob.take_data())
                             })),
                         };
@@ -168,6 +335,10 @@ impl OpenRepository {
                                 name: item.filename().to_string(),
                             },
                             content,
+                            markup,
+                            mime,
+                            lfs,
+                            lfs_server,
                         }));
                     }
                     Kind::Tree => {
@@ -205,7 +376,7 @@ impl OpenRepository {
                         tree_items.push(match object.kind {
                             Kind::Blob => TreeItem::File(File {
                                 mode: item.mode().0,
-                                size: object.into_blob().data.len(),
+                                size: lfs_aware_size(&object.into_blob().data),
                                 path,
                                 name: item.filename().to_string(),
                             }),
@@ -266,34 +437,212 @@ impl OpenRepository {
         .context("Failed to join Tokio task")?
     }
 
+    /// The root tree of the requested branch, or of HEAD if no branch was requested.
+    fn root_tree<'a>(&self, repo: &'a gix::Repository) -> Result<gix::Tree<'a>> {
+        if let Some(branch) = &self.branch {
+            repo.find_reference(branch.as_ref())?
+                .peel_to_tree()
+                .context("Couldn't find tree for reference")
+        } else {
+            repo.find_reference("HEAD")
+                .context("Failed to find HEAD")?
+                .peel_to_tree()
+                .context("Couldn't find HEAD for reference")
+        }
+    }
+
+    /// Lists the path of every file in the tree, sorted, alongside the ID of the tree. Listings
+    /// are cached by tree ID, so they're shared by every reference pointing at the same tree.
+    #[instrument(skip(self))]
+    pub async fn tree_index(self: Arc<Self>) -> Result<(ObjectId, Arc<[String]>)> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+            let tree = self.root_tree(&repo)?;
+
+            if let Some(files) = self.git.tree_indexes.get(&tree.id) {
+                return Ok((tree.id, files));
+            }
+
+            let mut recorder = gix::traverse::tree::Recorder::default();
+            tree.traverse().breadthfirst(&mut recorder)?;
+
+            let mut files = recorder
+                .records
+                .into_iter()
+                .filter(|entry| entry.mode.is_blob() || entry.mode.is_link())
+                .map(|entry| entry.filepath.to_string())
+                .collect::<Vec<_>>();
+            files.sort_unstable();
+
+            let files = Arc::<[String]>::from(files);
+            self.git.tree_indexes.insert(tree.id, files.clone());
+
+            Ok((tree.id, files))
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Searches the contents of every text file in the tree for `needle`, giving up once
+    /// `GREP_MAX_FILES` files have matched, `GREP_MAX_BYTES` have been read or
+    /// `GREP_TIME_BUDGET` has passed. The search is abandoned if the returned future is dropped,
+    /// ie. because the client went away.
+    #[instrument(skip(self))]
+    pub async fn grep(self: Arc<Self>, needle: String) -> Result<GrepResults> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel_on_drop = CancelOnDrop(cancelled.clone());
+
+        tokio::task::spawn_blocking(move || {
+            let deadline = Instant::now() + GREP_TIME_BUDGET;
+            let repo = self.repo.to_thread_local();
+            let tree = self.root_tree(&repo)?;
+
+            let mut recorder = gix::traverse::tree::Recorder::default();
+            tree.traverse().breadthfirst(&mut recorder)?;
+
+            let mut results = GrepResults::default();
+            let mut bytes_read = 0;
+
+            for entry in recorder.records {
+                if !entry.mode.is_blob() {
+                    continue;
+                }
+
+                if cancelled.load(Ordering::Relaxed) {
+                    anyhow::bail!("Search was cancelled");
+                }
+
+                if bytes_read > GREP_MAX_BYTES || Instant::now() > deadline {
+                    results.exceeded_budget = true;
+                    break;
+                }
+
+                let blob = repo.find_object(entry.oid)?;
+                bytes_read += blob.data.len();
+
+                // skip anything that'd be expensive to search or wouldn't be shown as text
+                if blob.data.len() > GREP_MAX_FILE_SIZE
+                    || blob.data[..blob.data.len().min(8000)].contains(&0)
+                    || blob.data.find(needle.as_bytes()).is_none()
+                {
+                    continue;
+                }
+
+                let content = String::from_utf8_lossy(&blob.data);
+                let Some(file) = grep::search(entry.filepath.to_string(), &content, &needle) else {
+                    continue;
+                };
+
+                if results.files.len() == GREP_MAX_FILES {
+                    results.truncated = true;
+                    break;
+                }
+
+                results.files.push(file);
+            }
+
+            Ok(results)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Opens a blob for streaming if it's over `--stream-blob-threshold`, returning `None` if it
+    /// should be fetched with [`Self::blob`] instead.
+    #[instrument(skip(self))]
+    pub async fn large_blob(self: Arc<Self>, oid: ObjectId) -> Result<Option<LargeBlob>> {
+        if self.git.blob_streamer.is_none() {
+            return Ok(None);
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            match &self.git.blob_streamer {
+                Some(streamer) => streamer.try_open(&repo, oid),
+                None => Ok(None),
+            }
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Fetches a blob directly by its object ID, returning `None` if the object doesn't exist or
+    /// isn't a blob.
+    #[instrument(skip(self))]
+    pub async fn blob(self: Arc<Self>, oid: ObjectId) -> Result<Option<Vec<u8>>> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            let Some(object) = repo.try_find_object(oid)? else {
+                return Ok(None);
+            };
+
+            Ok(object.try_into_blob().ok().map(|mut blob| blob.take_data()))
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
     #[instrument(skip(self))]
     pub async fn tag_info(self: Arc<Self>) -> Result<DetailedTag> {
         tokio::task::spawn_blocking(move || {
             let tag_name = self.branch.clone().context("no tag given")?;
             let repo = self.repo.to_thread_local();
 
-            let tag = repo
+            let mut reference = repo
                 .find_reference(&format!("refs/tags/{tag_name}"))
-                .context("Given tag does not exist in repository")?
-                .peel_to_tag()
-                .context("Couldn't get to a tag from the given reference")?;
-            let tag_target = tag
-                .target_id()
-                .context("Couldn't find tagged object")?
-                .object()?;
+                .context("Given tag does not exist in repository")?;
+
+            // lightweight tags point directly at their target rather than at a tag object
+            let (tag, tag_target) = if let Ok(tag) = reference.peel_to_tag() {
+                let target = tag
+                    .target_id()
+                    .context("Couldn't find tagged object")?
+                    .object()?;
+                (Some(tag), target)
+            } else {
+                let target = repo
+                    .find_reference(&format!("refs/tags/{tag_name}"))?
+                    .try_id()
+                    .context("Couldn't find tagged object")?
+                    .object()?;
+                (None, target)
+            };
 
             let tagged_object = match tag_target.kind {
-                Kind::Commit => Some(TaggedObject::Commit(tag_target.id.to_string())),
+                Kind::Commit => Some(TaggedObject::Commit(Box::new(Commit::try_from(
+                    tag_target.into_commit(),
+                )?))),
                 Kind::Tree => Some(TaggedObject::Tree(tag_target.id.to_string())),
                 _ => None,
             };
 
+            let Some(tag) = tag else {
+                return Ok(DetailedTag {
+                    name: tag_name,
+                    tagger: None,
+                    message: String::new(),
+                    signature: None,
+                    tagged_object,
+                });
+            };
+
             let tag_info = tag.decode()?;
+            let message = tag_info.message.to_string();
+
+            let (message, signature) = if let Some(signature) = tag_info.pgp_signature {
+                (message, Some(signature.to_string()))
+            } else {
+                let (message, signature) = split_tag_signature(&message);
+                (message.to_string(), signature.map(ToString::to_string))
+            };
 
             Ok(DetailedTag {
                 name: tag_name,
                 tagger: tag_info.tagger.map(TryInto::try_into).transpose()?,
-                message: tag_info.message.to_string(),
+                message,
+                signature,
                 tagged_object,
             })
         })
@@ -302,58 +651,508 @@ impl OpenRepository {
     }
 
     #[instrument(skip(self))]
-    pub async fn readme(
-        self: Arc<Self>,
-    ) -> Result<Option<(ReadmeFormat, Arc<str>)>, Arc<anyhow::Error>> {
+    pub async fn readme(self: Arc<Self>) -> Result<Option<(ReadmeFormat, Arc<str>)>> {
         const README_FILES: &[&str] = &["README.md", "README", "README.txt"];
 
-        let git = self.git.clone();
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            let mut head = if let Some(reference) = &self.branch {
+                repo.find_reference(reference.as_ref())?
+            } else {
+                repo.find_reference("HEAD")
+                    .context("Couldn't find HEAD of repository")?
+            };
+
+            let commit = head
+                .peel_to_commit()
+                .context("Couldn't find the commit that the HEAD of the repository refers to")?;
+            let mut tree = commit
+                .tree()
+                .context("Couldn't get the tree that the HEAD refers to")?;
+
+            for name in README_FILES {
+                let Some(tree_entry) = tree.peel_to_entry_by_path(name)? else {
+                    continue;
+                };
+
+                let Some(blob) = tree_entry
+                    .object()
+                    .ok()
+                    .and_then(|v| v.try_into_blob().ok())
+                else {
+                    continue;
+                };
+
+                let Ok(content) = simdutf8::basic::from_utf8(&blob.data) else {
+                    continue;
+                };
+
+                if Path::new(name).extension().and_then(OsStr::to_str) == Some("md") {
+                    // shares its cache entry with the README viewed as a file
+                    let format = MarkupFormat::Markdown;
+                    let value =
+                        self.git
+                            .render_blob(blob.id, BlobRenderer::Markup(format), || {
+                                format.render(content)
+                            })?;
+                    return Ok(Some((ReadmeFormat::Markdown, value)));
+                }
+
+                return Ok(Some((ReadmeFormat::Plaintext, Arc::from(content))));
+            }
+
+            Ok(None)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    pub async fn default_branch(self: Arc<Self>) -> Result<Option<String>> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+            let head = repo.head().context("Couldn't find HEAD of repository")?;
+            Ok(head.referent_name().map(|v| v.shorten().to_string()))
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Walks the commits on the branch live from the repository in the given order, rather than
+    /// the order they were indexed in. If a path is given, only commits that changed it are
+    /// returned.
+    ///
+    /// When filtering, at most [`MAX_FILTERED_WALK`] commits are looked at so that a filter
+    /// matching little or nothing doesn't diff its way through the entire history.
+    #[instrument(skip(self))]
+    pub async fn walk_commits(
+        self: Arc<Self>,
+        order: CommitOrder,
+        path: Option<PathBuf>,
+        no_merges: bool,
+        filter: CommitFilter,
+        amount: usize,
+        offset: usize,
+    ) -> Result<Vec<crate::database::schema::commit::Commit>> {
+        let tip = {
+            let this = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let repo = this.repo.to_thread_local();
+
+                let mut head = if let Some(reference) = &this.branch {
+                    repo.find_reference(reference.as_ref())?
+                } else {
+                    repo.find_reference("HEAD")
+                        .context("Couldn't find HEAD of repository")?
+                };
+
+                Ok::<_, anyhow::Error>(head.peel_to_commit()?.id)
+            })
+            .await
+            .context("Failed to join Tokio task")??
+        };
+
+        let topo_order = if order == CommitOrder::Topo {
+            let this = self.clone();
+            let order = self
+                .git
+                .topo_orders
+                .try_get_with((self.cache_key.clone(), tip), async move {
+                    tokio::task::spawn_blocking(move || {
+                        topological_order(&this.repo.to_thread_local(), tip)
+                    })
+                    .await
+                    .context("Failed to join Tokio task")?
+                })
+                .await
+                .map_err(|e| anyhow!("{e:?}"))?;
+            Some(order)
+        } else {
+            None
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+            let mailmap = repo.open_mailmap();
+
+            let walk: Box<dyn Iterator<Item = Result<ObjectId>> + '_> =
+                if let Some(topo_order) = &topo_order {
+                    Box::new(topo_order.iter().copied().map(Ok))
+                } else {
+                    Box::new(
+                        repo.rev_walk([tip])
+                            .sorting(gix::revision::walk::Sorting::ByCommitTimeNewestFirst)
+                            .all()?
+                            .map(|info| Ok(info?.id)),
+                    )
+                };
+
+            let limit = if no_merges || path.is_some() || !filter.is_empty() {
+                MAX_FILTERED_WALK
+            } else {
+                usize::MAX
+            };
+
+            let ids: Vec<ObjectId> = walk
+                .take(limit)
+                .filter_map(|id| match id {
+                    Ok(id) if no_merges || !filter.is_empty() => {
+                        walked_commit_matches(&repo, &mailmap, id, no_merges, &filter)
+                            .map(|matches| matches.then_some(id))
+                            .transpose()
+                    }
+                    id => Some(id),
+                })
+                .filter_map(|id| match (id, &path) {
+                    (Ok(id), Some(path)) => commit_changed_path(&repo, id, path)
+                        .map(|changed| changed.then_some(id))
+                        .transpose(),
+                    (id, _) => Some(id),
+                })
+                .skip(offset)
+                .take(amount)
+                .collect::<Result<_>>()?;
+
+            ids.into_iter()
+                .map(|id| {
+                    let commit = repo.find_commit(id)?;
+                    let author = mailmap.resolve(commit.author()?);
+                    let committer = mailmap.resolve(commit.committer()?);
+
+                    crate::database::schema::commit::Commit::new(
+                        &commit,
+                        author.to_ref(),
+                        committer.to_ref(),
+                    )
+                })
+                .collect()
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Fetches the commit at the tip of the branch being viewed, with its diff against the parent
+    /// at the given index.
+    #[instrument(skip(self))]
+    pub async fn latest_commit(
+        self: Arc<Self>,
+        parent: usize,
+        highlighted: bool,
+    ) -> Result<Commit> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            let mut head = if let Some(reference) = &self.branch {
+                repo.find_reference(reference.as_ref())?
+            } else {
+                repo.find_reference("HEAD")
+                    .context("Couldn't find HEAD of repository")?
+            };
+
+            let commit = head
+                .peel_to_commit()
+                .context("Couldn't find commit HEAD of repository refers to")?;
+            let (diff_output, diff_stats, files) = fetch_diff_and_stats(
+                &repo,
+                &commit,
+                parent,
+                highlighted.then(|| self.git.diff_highlighting()),
+            )?;
 
-        git.readme_cache
-            .try_get_with((self.cache_key.clone(), self.branch.clone()), async move {
+            let mut commit = Commit::try_from(commit)?;
+            commit.diff_stats = diff_stats;
+            commit.diff = diff_output;
+            commit.files = files;
+            Ok(commit)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Streams a snapshot of the commit back to the requester, limited to the tree at `subtree`
+    /// if one is given.
+    #[instrument(skip_all)]
+    pub async fn archive(
+        self: Arc<Self>,
+        res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
+        cont: tokio::sync::oneshot::Sender<()>,
+        commit: Option<&str>,
+        subtree: Option<PathBuf>,
+        format: SnapshotFormat,
+        compression_level: Option<u32>,
+    ) -> Result<(), anyhow::Error> {
+        let commit = commit
+            .map(ObjectId::from_str)
+            .transpose()
+            .context("failed to build oid")?;
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+            let commit = self.snapshot_commit(&repo, commit)?;
+            let tree = snapshot_tree(&commit, subtree.as_deref())?;
+
+            // tell the web server it can send response headers to the requester
+            if cont.send(()).is_err() {
+                return Err(anyhow!("requester gone"));
+            }
+
+            write_snapshot(
+                &repo,
+                &commit,
+                &tree,
+                format,
+                compression_level,
+                ChannelWriter::new(res),
+            )
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    /// Returns the contents of one of the companion files offered alongside a snapshot. Snapshots
+    /// are reproducible, so these are cached against the commit they were built from.
+    #[instrument(skip(self))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn snapshot_checksum(
+        self: Arc<Self>,
+        commit: Option<&str>,
+        subtree: Option<PathBuf>,
+        format: SnapshotFormat,
+        compression_level: Option<u32>,
+        checksum: SnapshotChecksum,
+        signing_command: Option<Arc<str>>,
+    ) -> Result<Arc<str>> {
+        let commit = commit
+            .map(ObjectId::from_str)
+            .transpose()
+            .context("failed to build oid")?;
+
+        let commit = {
+            let this = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let repo = this.repo.to_thread_local();
+                Ok::<_, anyhow::Error>(this.snapshot_commit(&repo, commit)?.id)
+            })
+            .await
+            .context("Failed to join Tokio task")??
+        };
+
+        let key = (
+            self.cache_key.clone(),
+            commit,
+            subtree.clone(),
+            format,
+            format.level(compression_level),
+            checksum,
+        );
+
+        let this = self.clone();
+        self.git
+            .snapshot_checksums
+            .try_get_with(key, async move {
                 tokio::task::spawn_blocking(move || {
-                    let repo = self.repo.to_thread_local();
+                    let repo = this.repo.to_thread_local();
+                    let commit = repo.find_commit(commit)?;
+                    let tree = snapshot_tree(&commit, subtree.as_deref())?;
+
+                    match checksum {
+                        SnapshotChecksum::Sha256 => {
+                            let mut hasher = Sha256::new();
+                            write_snapshot(
+                                &repo,
+                                &commit,
+                                &tree,
+                                format,
+                                compression_level,
+                                &mut hasher,
+                            )?;
+                            Ok(Arc::from(const_hex::encode(hasher.finalize())))
+                        }
+                        SnapshotChecksum::Signature => {
+                            let command = signing_command
+                                .context("Snapshot signing command isn't configured")?;
+
+                            let signature = crate::archive::sign(&command, |stdin| {
+                                write_snapshot(
+                                    &repo,
+                                    &commit,
+                                    &tree,
+                                    format,
+                                    compression_level,
+                                    stdin,
+                                )
+                            })?;
+
+                            Ok(Arc::from(signature))
+                        }
+                    }
+                })
+                .await
+                .context("Failed to join Tokio task")?
+            })
+            .await
+            .map_err(|e| anyhow!("{e:?}"))
+    }
+
+    /// Starts building a snapshot in the background, or returns the one already built or being
+    /// built for the same commit.
+    #[instrument(skip(self, jobs))]
+    pub async fn prepare_snapshot(
+        self: Arc<Self>,
+        jobs: Arc<SnapshotJobs>,
+        commit: Option<&str>,
+        subtree: Option<PathBuf>,
+        format: SnapshotFormat,
+        compression_level: Option<u32>,
+    ) -> Result<(SnapshotKey, Arc<PreparedSnapshot>)> {
+        let commit = commit
+            .map(ObjectId::from_str)
+            .transpose()
+            .context("failed to build oid")?;
+
+        // resolved up front so requests for things that don't exist fail straight away rather
+        // than in the background
+        let commit = {
+            let this = self.clone();
+            let subtree = subtree.clone();
+            tokio::task::spawn_blocking(move || {
+                let repo = this.repo.to_thread_local();
+                let commit = this.snapshot_commit(&repo, commit)?;
+                snapshot_tree(&commit, subtree.as_deref())?;
+                Ok::<_, anyhow::Error>(commit.id)
+            })
+            .await
+            .context("Failed to join Tokio task")??
+        };
+
+        let key = (
+            self.cache_key.clone(),
+            commit,
+            subtree.clone(),
+            format,
+            format.level(compression_level),
+        );
+
+        let snapshot = jobs.prepare(key.clone(), move |out| {
+            let repo = self.repo.to_thread_local();
+            let commit = repo.find_commit(commit)?;
+            let tree = snapshot_tree(&commit, subtree.as_deref())?;
+            write_snapshot(&repo, &commit, &tree, format, compression_level, out)
+        });
+
+        Ok((key, snapshot))
+    }
+
+    /// Resolves the commit a snapshot should be built from, either the given commit or the tip of
+    /// the branch.
+    fn snapshot_commit<'a>(
+        &self,
+        repo: &'a gix::Repository,
+        commit: Option<ObjectId>,
+    ) -> Result<gix::Commit<'a>> {
+        if let Some(commit) = commit {
+            Ok(repo.find_commit(commit)?)
+        } else if let Some(reference) = &self.branch {
+            Ok(repo.find_reference(reference.as_ref())?.peel_to_commit()?)
+        } else {
+            Ok(repo
+                .find_reference("HEAD")
+                .context("Couldn't find HEAD of repository")?
+                .peel_to_commit()?)
+        }
+    }
+
+    /// Resolves an abbreviated hash, branch or tag name, optionally followed by `~N` and `^N`
+    /// to walk back through its ancestors (eg. `HEAD~3`), to the commit it names.
+    #[instrument(skip(self))]
+    pub async fn resolve_commit(self: Arc<Self>, spec: Arc<str>) -> Result<ResolvedCommit> {
+        // the rest of git's revspec syntax can search every commit message (`:/regex`) or read
+        // the reflog (`@{1}`), neither of which should be reachable from a URL
+        if !is_simple_revspec(&spec) {
+            return Err(NotFound("Couldn't find a commit matching that").into());
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            // only commits are looked for, so an abbreviation shared with trees and blobs still
+            // resolves as long as just the one commit starts with it
+            if let Ok(id) = repo.rev_parse_single(format!("{spec}^{{commit}}").as_str()) {
+                return Ok(ResolvedCommit::Commit(id.detach()));
+            }
+
+            let Ok(prefix) = gix::hash::Prefix::from_hex(&spec) else {
+                return Err(NotFound("Couldn't find a commit matching that").into());
+            };
+
+            let mut candidates = HashSet::new();
+            repo.objects.lookup_prefix(prefix, Some(&mut candidates))?;
+
+            if candidates.len() < 2 {
+                return Err(NotFound("Couldn't find a commit matching that").into());
+            }
 
-                    let mut head = if let Some(reference) = &self.branch {
-                        repo.find_reference(reference.as_ref())?
-                    } else {
-                        repo.find_reference("HEAD")
-                            .context("Couldn't find HEAD of repository")?
+            let mut candidates = candidates
+                .into_iter()
+                .map(|id| {
+                    let object = repo.find_object(id)?;
+                    let kind = object.kind;
+                    let summary = match kind {
+                        Kind::Commit => Some(object.into_commit().message()?.summary().to_string()),
+                        Kind::Tag => Some(object.into_tag().decode()?.name.to_string()),
+                        Kind::Tree | Kind::Blob => None,
                     };
 
-                    let commit = head.peel_to_commit().context(
-                        "Couldn't find the commit that the HEAD of the repository refers to",
-                    )?;
-                    let mut tree = commit
-                        .tree()
-                        .context("Couldn't get the tree that the HEAD refers to")?;
+                    Ok(AmbiguousObject {
+                        id,
+                        kind: object_kind_name(kind),
+                        summary,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            candidates.sort_by_key(|v| v.id);
 
-                    for name in README_FILES {
-                        let Some(tree_entry) = tree.peel_to_entry_by_path(name)? else {
-                            continue;
-                        };
+            Ok(ResolvedCommit::Ambiguous(candidates))
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
 
-                        let Some(blob) = tree_entry
-                            .object()
-                            .ok()
-                            .and_then(|v| v.try_into_blob().ok())
-                        else {
-                            continue;
-                        };
+    /// Fetches a commit, with its diff against the parent at the given index.
+    #[instrument(skip(self))]
+    pub async fn commit(
+        self: Arc<Self>,
+        commit: &str,
+        parent: usize,
+        highlighted: bool,
+    ) -> Result<Arc<Commit>, Arc<anyhow::Error>> {
+        let commit = ObjectId::from_str(commit)
+            .map_err(anyhow::Error::from)
+            .map_err(Arc::new)?;
 
-                        let Ok(content) = simdutf8::basic::from_utf8(&blob.data) else {
-                            continue;
-                        };
+        let git = self.git.clone();
 
-                        if Path::new(name).extension().and_then(OsStr::to_str) == Some("md") {
-                            let value = parse_and_transform_markdown(content);
-                            return Ok(Some((ReadmeFormat::Markdown, Arc::from(value))));
-                        }
+        git.commits
+            .try_get_with((commit, parent, highlighted), async move {
+                tokio::task::spawn_blocking(move || {
+                    let repo = self.repo.to_thread_local();
 
-                        return Ok(Some((ReadmeFormat::Plaintext, Arc::from(content))));
-                    }
+                    let commit = repo.find_commit(commit)?;
+
+                    let (diff_output, diff_stats, files) = fetch_diff_and_stats(
+                        &repo,
+                        &commit,
+                        parent,
+                        highlighted.then(|| self.git.diff_highlighting()),
+                    )?;
+
+                    let mut commit = Commit::try_from(commit)?;
+                    commit.diff_stats = diff_stats;
+                    commit.diff = diff_output;
+                    commit.files = files;
 
-                    Ok(None)
+                    Ok(Arc::new(commit))
                 })
                 .await
                 .context("Failed to join Tokio task")?
@@ -361,143 +1160,376 @@ impl OpenRepository {
             .await
     }
 
-    pub async fn default_branch(self: Arc<Self>) -> Result<Option<String>> {
+    /// Fetches the bad commit and those of its ancestors that aren't ancestors of any of the good
+    /// commits, each with its parents, or `None` if there are more than `limit` of them.
+    #[instrument(skip(self))]
+    pub async fn bisect_candidates(
+        self: Arc<Self>,
+        bad: String,
+        good: Vec<String>,
+        limit: usize,
+    ) -> Result<Option<Vec<(ObjectId, Vec<ObjectId>)>>> {
         tokio::task::spawn_blocking(move || {
             let repo = self.repo.to_thread_local();
-            let head = repo.head().context("Couldn't find HEAD of repository")?;
-            Ok(head.referent_name().map(|v| v.shorten().to_string()))
+
+            let resolve = |rev: &str| -> Result<ObjectId> {
+                Ok(repo
+                    .rev_parse_single(format!("{rev}^{{commit}}").as_str())?
+                    .detach())
+            };
+
+            let bad = resolve(&bad)?;
+            let good = good
+                .iter()
+                .map(|rev| resolve(rev))
+                .collect::<Result<Vec<_>>>()?;
+
+            commits_between(&repo, bad, &good, limit)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Fetches the commits reachable from `head` but not from `base`, oldest first, along with a
+    /// cover letter taken from `head` if it's an annotated tag.
+    #[instrument(skip(self))]
+    pub async fn patch_series(
+        self: Arc<Self>,
+        base: String,
+        head: Option<String>,
+    ) -> Result<PatchSeries> {
+        const MAX_SERIES_LENGTH: usize = 250;
+        /// How many of the base's ancestors are looked at to work out which of the commits
+        /// reachable from the head are already part of it.
+        const MAX_BASE_WALK: usize = 10_000;
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            let head = head
+                .or_else(|| self.branch.as_deref().map(ToString::to_string))
+                .unwrap_or_else(|| "HEAD".to_string());
+
+            let base = repo
+                .rev_parse_single(format!("{base}^{{commit}}").as_str())
+                .context("Couldn't find the base of the series")?
+                .detach();
+            let head_id = repo
+                .rev_parse_single(format!("{head}^{{commit}}").as_str())
+                .context("Couldn't find the head of the series")?
+                .detach();
+
+            let cover_letter = repo
+                .rev_parse_single(head.as_str())?
+                .object()?
+                .try_into_tag()
+                .ok()
+                .map(|tag| -> Result<_> {
+                    let tag = tag.decode()?;
+                    let message = tag.message.to_str_lossy();
+                    let (subject, body) =
+                        message.split_once('\n').unwrap_or((message.as_ref(), ""));
+
+                    Ok(CoverLetter {
+                        author: tag
+                            .tagger
+                            .map(TryInto::try_into)
+                            .transpose()?
+                            .context("Tag has no tagger")?,
+                        subject: subject.trim().to_string(),
+                        body: body.trim().to_string(),
+                    })
+                })
+                .transpose()?;
+
+            let candidates = repo
+                .rev_walk([head_id])
+                .sorting(gix::revision::walk::Sorting::ByCommitTimeNewestFirst)
+                .selected(move |id| id != base.as_ref())?
+                .take(MAX_SERIES_LENGTH + 1)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if candidates.len() > MAX_SERIES_LENGTH {
+                anyhow::bail!("Series is longer than {MAX_SERIES_LENGTH} patches");
+            }
+
+            // merges in the series can reach back into the base's history, which only has to be
+            // walked back as far as the oldest commit we found rather than to the root
+            let oldest = candidates.iter().filter_map(|info| info.commit_time).min();
+            let excluded: HashSet<ObjectId> = repo
+                .rev_walk([base])
+                .sorting(gix::revision::walk::Sorting::ByCommitTimeNewestFirst)
+                .all()?
+                .map_while(Result::ok)
+                .take_while(|info| info.commit_time >= oldest)
+                .take(MAX_BASE_WALK)
+                .map(|info| info.id)
+                .collect();
+
+            let ids: Vec<ObjectId> = candidates
+                .into_iter()
+                .map(|info| info.id)
+                .filter(|id| !excluded.contains(id))
+                .collect();
+
+            if ids.is_empty() {
+                anyhow::bail!("There are no commits in the series");
+            }
+
+            let patches = ids
+                .into_iter()
+                .rev()
+                .map(|id| {
+                    let commit = repo.find_commit(id)?;
+                    let (diff_output, diff_stats, files) =
+                        fetch_diff_and_stats(&repo, &commit, 0, None)?;
+
+                    let mut commit = Commit::try_from(commit)?;
+                    commit.diff_stats = diff_stats;
+                    commit.diff = diff_output;
+                    commit.files = files;
+                    Ok(commit)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(PatchSeries {
+                id: head_id.to_string(),
+                cover_letter,
+                patches,
+            })
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Applies `patch` in memory to the tree of the selected branch (or `HEAD`), returning the
+    /// commit it was applied to alongside how each file fared.
+    #[instrument(skip(self, patch))]
+    pub async fn preview_patch(
+        self: Arc<Self>,
+        patch: Vec<FilePatch>,
+    ) -> Result<(String, Vec<FilePreview>)> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.repo.to_thread_local();
+
+            let reference = self.branch.as_deref().unwrap_or("HEAD");
+            let commit = repo
+                .rev_parse_single(format!("{reference}^{{commit}}").as_str())
+                .map_err(|_| NotFound("Reference doesn't exist"))?
+                .object()?
+                .try_into_commit()?;
+
+            let files = patch
+                .into_iter()
+                .map(|file| -> Result<_> {
+                    let original = match &file.old_path {
+                        Some(path) => commit
+                            .tree()?
+                            .peel_to_entry_by_path(path)?
+                            .and_then(|entry| entry.object().ok())
+                            .and_then(|object| object.try_into_blob().ok())
+                            .map(|mut blob| blob.take_data()),
+                        None => None,
+                    };
+
+                    let Ok(original) = original.as_deref().map(std::str::from_utf8).transpose()
+                    else {
+                        return Ok(FilePreview {
+                            old_path: file.old_path,
+                            new_path: file.new_path,
+                            content: None,
+                            conflicts: vec![Conflict {
+                                hunk: 0,
+                                line: 0,
+                                reason: "binary files can't be previewed",
+                            }],
+                        });
+                    };
+
+                    let (content, conflicts) = match patch::apply(original, &file) {
+                        Ok(content) => (content, Vec::new()),
+                        Err(conflicts) => (None, conflicts),
+                    };
+
+                    Ok(FilePreview {
+                        old_path: file.old_path,
+                        new_path: file.new_path,
+                        content,
+                        conflicts,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok((commit.id.to_string(), files))
         })
         .await
         .context("Failed to join Tokio task")?
     }
+}
 
-    #[instrument(skip(self))]
-    pub async fn latest_commit(self: Arc<Self>, highlighted: bool) -> Result<Commit> {
-        tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
+/// The order to list commits in when walking them live from the repository.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitOrder {
+    /// Newest commit first, by commit date.
+    Date,
+    /// Children always before their parents, keeping lines of history together.
+    Topo,
+}
 
-            let mut head = if let Some(reference) = &self.branch {
-                repo.find_reference(reference.as_ref())?
-            } else {
-                repo.find_reference("HEAD")
-                    .context("Couldn't find HEAD of repository")?
-            };
+impl fmt::Display for CommitOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Date => "date",
+            Self::Topo => "topo",
+        })
+    }
+}
 
-            let commit = head
-                .peel_to_commit()
-                .context("Couldn't find commit HEAD of repository refers to")?;
-            let (diff_output, diff_stats) = fetch_diff_and_stats(&repo, &commit, highlighted)?;
+/// Sorts every commit reachable from `tip` so that no commit comes before all of its children,
+/// following first parents where possible so that lines of history aren't interleaved (as
+/// `git log --topo-order` would).
+fn topological_order(repo: &gix::Repository, tip: ObjectId) -> Result<Arc<[ObjectId]>> {
+    let mut parents: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    let mut children = HashMap::<ObjectId, usize>::new();
 
-            let mut commit = Commit::try_from(commit)?;
-            commit.diff_stats = diff_stats;
-            commit.diff = diff_output;
-            Ok(commit)
-        })
-        .await
-        .context("Failed to join Tokio task")?
+    for info in repo.rev_walk([tip]).all()? {
+        let info = info?;
+
+        for parent in info.parent_ids() {
+            *children.entry(parent.detach()).or_default() += 1;
+        }
+
+        parents.insert(info.id, info.parent_ids().map(gix::Id::detach).collect());
     }
 
-    #[instrument(skip_all)]
-    pub async fn archive(
-        self: Arc<Self>,
-        res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
-        cont: tokio::sync::oneshot::Sender<()>,
-        commit: Option<&str>,
-    ) -> Result<(), anyhow::Error> {
-        let commit = commit
-            .map(ObjectId::from_str)
-            .transpose()
-            .context("failed to build oid")?;
+    let mut out = Vec::with_capacity(parents.len());
+    let mut stack = vec![tip];
 
-        tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
+    while let Some(id) = stack.pop() {
+        out.push(id);
 
-            let tree = if let Some(commit) = commit {
-                repo.find_commit(commit)?.tree()?
-            } else if let Some(reference) = &self.branch {
-                repo.find_reference(reference.as_ref())?.peel_to_tree()?
-            } else {
-                repo.find_reference("HEAD")
-                    .context("Couldn't find HEAD of repository")?
-                    .peel_to_tree()?
-            };
+        // pushed in reverse so the first parent is visited next
+        for parent in parents.get(&id).into_iter().flatten().rev() {
+            let remaining = children.entry(*parent).or_default();
+            *remaining = remaining.saturating_sub(1);
 
-            // tell the web server it can send response headers to the requester
-            if cont.send(()).is_err() {
-                return Err(anyhow!("requester gone"));
+            if *remaining == 0 {
+                stack.push(*parent);
             }
+        }
+    }
 
-            let buffer = BytesMut::with_capacity(BUFFER_CAP + 1024);
-            let mut visitor = ArchivalVisitor {
-                repository: &repo,
-                res,
-                archive: Builder::new(GzEncoder::new(buffer.writer(), flate2::Compression::fast())),
-                path_deque: VecDeque::new(),
-                path: BString::default(),
-            };
+    Ok(Arc::from(out))
+}
 
-            tree.traverse().breadthfirst(&mut visitor)?;
+/// Checks whether a commit found while walking the repository should be listed.
+fn walked_commit_matches(
+    repo: &gix::Repository,
+    mailmap: &gix::mailmap::Snapshot,
+    id: ObjectId,
+    no_merges: bool,
+    filter: &CommitFilter,
+) -> Result<bool> {
+    let commit = repo.find_commit(id)?;
+
+    if no_merges && commit.parent_ids().count() > 1 {
+        return Ok(false);
+    }
 
-            visitor.res.blocking_send(Ok(visitor
-                .archive
-                .into_inner()?
-                .finish()?
-                .into_inner()
-                .freeze()))?;
+    if filter.is_empty() {
+        return Ok(true);
+    }
 
-            Ok::<_, anyhow::Error>(())
-        })
-        .await??;
+    let author = mailmap.resolve(commit.author()?);
+    let committer = mailmap.resolve(commit.committer()?);
 
-        Ok(())
-    }
+    Ok(filter.matches(
+        &author.email.to_str_lossy(),
+        &committer.email.to_str_lossy(),
+        committer.time.seconds,
+    ))
+}
 
-    #[instrument(skip(self))]
-    pub async fn commit(
-        self: Arc<Self>,
-        commit: &str,
-        highlighted: bool,
-    ) -> Result<Arc<Commit>, Arc<anyhow::Error>> {
-        let commit = ObjectId::from_str(commit)
-            .map_err(anyhow::Error::from)
-            .map_err(Arc::new)?;
+/// Whether the commit changed the file or directory at `path`. Like `git log`, commits that
+/// left the path the same as any of their parents (such as merges that took one side's
+/// version) aren't considered to have changed it.
+fn commit_changed_path(repo: &gix::Repository, id: ObjectId, path: &Path) -> Result<bool> {
+    let entry_at_path = |commit: &gix::Commit<'_>| -> Result<Option<ObjectId>> {
+        Ok(commit
+            .tree()?
+            .peel_to_entry_by_path(path)?
+            .map(|entry| entry.object_id()))
+    };
+
+    let commit = repo.find_commit(id)?;
+    let entry = entry_at_path(&commit)?;
+
+    let mut parents = commit.parent_ids().peekable();
+    if parents.peek().is_none() {
+        return Ok(entry.is_some());
+    }
 
-        let git = self.git.clone();
+    for parent in parents {
+        if entry_at_path(&repo.find_commit(parent.detach())?)? == entry {
+            return Ok(false);
+        }
+    }
 
-        git.commits
-            .try_get_with((commit, highlighted), async move {
-                tokio::task::spawn_blocking(move || {
-                    let repo = self.repo.to_thread_local();
+    Ok(true)
+}
 
-                    let commit = repo.find_commit(commit)?;
+/// Finds the tree a snapshot of `commit` should be built from, either the commit's root tree or
+/// the tree at `subtree`.
+fn snapshot_tree<'a>(commit: &gix::Commit<'a>, subtree: Option<&Path>) -> Result<gix::Tree<'a>> {
+    let mut tree = commit.tree()?;
 
-                    let (diff_output, diff_stats) =
-                        fetch_diff_and_stats(&repo, &commit, highlighted)?;
+    let Some(subtree) = subtree else {
+        return Ok(tree);
+    };
 
-                    let mut commit = Commit::try_from(commit)?;
-                    commit.diff_stats = diff_stats;
-                    commit.diff = diff_output;
+    let entry = tree
+        .peel_to_entry_by_path(subtree)?
+        .ok_or(NotFound("Path doesn't exist in tree"))?;
 
-                    Ok(Arc::new(commit))
-                })
-                .await
-                .context("Failed to join Tokio task")?
-            })
-            .await
-    }
+    Ok(entry
+        .object()?
+        .try_into_tree()
+        .context("Path in tree isn't a directory")?)
 }
 
-const BUFFER_CAP: usize = 512 * 1024;
+/// Writes a snapshot of `tree` to `out`, with every entry given the time `commit` was made so the
+/// same commit always produces the same snapshot.
+fn write_snapshot<W: std::io::Write>(
+    repo: &gix::Repository,
+    commit: &gix::Commit<'_>,
+    tree: &gix::Tree<'_>,
+    format: SnapshotFormat,
+    compression_level: Option<u32>,
+    out: W,
+) -> Result<()> {
+    let mtime = commit.committer()?.time.seconds;
+
+    let mut visitor = ArchivalVisitor {
+        repository: repo,
+        archive: ArchiveWriter::new(format, compression_level, mtime, out)?,
+        path_deque: VecDeque::new(),
+        path: BString::default(),
+    };
+
+    tree.traverse().breadthfirst(&mut visitor)?;
+
+    visitor.archive.finish()
+}
 
-pub struct ArchivalVisitor<'a> {
+pub struct ArchivalVisitor<'a, W: std::io::Write> {
     repository: &'a gix::Repository,
-    res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
-    archive: Builder<GzEncoder<Writer<BytesMut>>>,
+    archive: ArchiveWriter<W>,
     path_deque: VecDeque<BString>,
     path: BString,
 }
 
-impl<'a> ArchivalVisitor<'a> {
+impl<'a, W: std::io::Write> ArchivalVisitor<'a, W> {
     fn pop_element(&mut self) {
         if let Some(pos) = self.path.rfind_byte(b'/') {
             self.path.resize(pos, 0);
@@ -514,7 +1546,7 @@ impl<'a> ArchivalVisitor<'a> {
     }
 }
 
-impl<'a> gix::traverse::tree::Visit for ArchivalVisitor<'a> {
+impl<'a, W: std::io::Write> gix::traverse::tree::Visit for ArchivalVisitor<'a, W> {
     fn pop_front_tracked_path_and_set_current(&mut self) {
         self.path = self
             .path_deque
@@ -552,49 +1584,142 @@ impl<'a> gix::traverse::tree::Visit for ArchivalVisitor<'a> {
 
         let blob = object.into_blob();
 
-        let mut header = tar::Header::new_gnu();
-        if let Err(error) = header.set_path(self.path.to_path_lossy()) {
-            warn!(%error, "Attempted to write invalid path to archive");
-            return Action::Continue;
-        }
-        header.set_size(blob.data.len() as u64);
-        #[allow(clippy::cast_sign_loss)]
-        header.set_mode(entry.mode().0.into());
-        header.set_cksum();
+        let mode = entry.mode().0.into();
 
-        if let Err(error) = self.archive.append(&header, blob.data.as_slice()) {
+        if let Err(error) =
+            self.archive
+                .append(&self.path.to_str_lossy(), mode, blob.data.as_slice())
+        {
             warn!(%error, "Failed to append to archive");
             return Action::Cancel;
         }
 
-        if self.archive.get_ref().get_ref().get_ref().len() >= BUFFER_CAP {
-            let b = self.archive.get_mut().get_mut().get_mut().split().freeze();
+        Action::Continue
+    }
+}
+
+/// Whether `spec` is a hash or reference name followed by nothing but `~N` and `^N` suffixes.
+fn is_simple_revspec(spec: &str) -> bool {
+    let (name, suffixes) = spec.split_at(spec.find(['~', '^']).unwrap_or(spec.len()));
+
+    let valid_name = !name.is_empty()
+        && !name.starts_with(['-', '/', '.'])
+        && !name.contains("..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.' | '+'));
+
+    valid_name
+        && suffixes
+            .split(['~', '^'])
+            .skip(1)
+            .all(|count| count.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// Finds the commits reachable from `bad` but from none of `good`, each with its parents. Rather
+/// than walking the whole history behind the good commits, both sides are walked at once newest
+/// first, as `git merge-base` does, until only commits reachable from a good commit are left.
+/// Gives up, returning `None`, once more than `limit` commits are found, or once four times as
+/// many have been walked on either side.
+fn commits_between(
+    repo: &gix::Repository,
+    bad: ObjectId,
+    good: &[ObjectId],
+    limit: usize,
+) -> Result<Option<Vec<(ObjectId, Vec<ObjectId>)>>> {
+    const BAD: u8 = 1;
+    const GOOD: u8 = 1 << 1;
+
+    let time =
+        |id: ObjectId| -> Result<i64> { Ok(repo.find_commit(id)?.committer()?.time.seconds) };
+
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    for (id, flag) in std::iter::once((bad, BAD)).chain(good.iter().map(|id| (*id, GOOD))) {
+        *flags.entry(id).or_default() |= flag;
+        queue.push((time(id)?, id));
+    }
+
+    let mut found = Vec::new();
+    let mut parents: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    let mut walked = 0_usize;
+
+    while queue.iter().any(|(_, id)| flags[id] & GOOD == 0) {
+        let Some((_, id)) = queue.pop() else {
+            break;
+        };
+
+        walked += 1;
+        if walked > limit.saturating_mul(4) {
+            return Ok(None);
+        }
 
-            if self.res.blocking_send(Ok(b)).is_err() {
-                return Action::Cancel;
+        let flag = flags[&id];
+        let commit_parents: Vec<ObjectId> = repo
+            .find_commit(id)?
+            .parent_ids()
+            .map(gix::Id::detach)
+            .collect();
+
+        for &parent in &commit_parents {
+            let existing = flags.entry(parent).or_default();
+
+            // visited again whenever it's reached from the other side, to pass that on to its
+            // ancestors
+            if *existing | flag != *existing {
+                *existing |= flag;
+                queue.push((time(parent)?, parent));
             }
         }
 
-        Action::Continue
+        if flag == BAD && !parents.contains_key(&id) {
+            found.push(id);
+            parents.insert(id, commit_parents);
+
+            if found.len() > limit {
+                return Ok(None);
+            }
+        }
     }
+
+    // commits found early on may have turned out to be reachable from a good commit after all
+    Ok(Some(
+        found
+            .into_iter()
+            .filter(|id| flags[id] == BAD)
+            .map(|id| {
+                let parents = parents.remove(&id).unwrap_or_default();
+                (id, parents)
+            })
+            .collect(),
+    ))
 }
 
-fn parse_and_transform_markdown(s: &str) -> String {
+pub fn parse_and_transform_markdown(s: &str) -> String {
     let mut plugins = ComrakPlugins::default();
 
     plugins.render.codefence_syntax_highlighter = Some(&ComrakHighlightAdapter);
 
-    // enable gfm extensions
-    // https://github.github.com/gfm/
-    let mut options = Options::default();
-    options.extension.autolink = true;
-    options.extension.footnotes = true;
-    options.extension.strikethrough = true;
-    options.extension.table = true;
-    options.extension.tagfilter = true;
-    options.extension.tasklist = true;
+    crate::markdown::render(s, &plugins)
+}
+
+#[derive(Default)]
+pub struct GrepResults {
+    pub files: Vec<GrepFile>,
+    /// Whether the search stopped before looking at every file in the tree.
+    pub truncated: bool,
+    /// Whether the search ran out of time or read too much to look at every file in the tree.
+    pub exceeded_budget: bool,
+}
+
+/// Flags work running on another thread as cancelled once whoever is waiting on it goes away.
+struct CancelOnDrop(Arc<AtomicBool>);
 
-    comrak::markdown_to_html_with_plugins(s, &options, &plugins)
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -606,6 +1731,8 @@ pub enum ReadmeFormat {
 pub enum PathDestination {
     Tree(Vec<TreeItem>),
     File(FileWithContent),
+    /// A file requested raw that's over `--stream-blob-threshold`
+    LargeFile(LargeBlob),
 }
 
 pub enum TreeItem {
@@ -643,12 +1770,93 @@ pub struct File {
 pub struct FileWithContent {
     pub metadata: File,
     pub content: Content,
+    /// Set if the file can also be viewed rendered, rather than as source
+    pub markup: Option<MarkupFormat>,
+    /// The content type of the file, if it's a binary format we can preview
+    pub mime: Option<&'static str>,
+    /// Set if the blob is a Git LFS pointer rather than the file itself
+    pub lfs: Option<LfsPointer>,
+    /// The LFS server to fetch the object referenced by `lfs` from, taken from `lfs.url`
+    pub lfs_server: Option<String>,
+}
+
+impl FileWithContent {
+    pub fn is_image(&self) -> bool {
+        self.mime.is_some_and(|v| v.starts_with("image/"))
+    }
+
+    pub fn is_pdf(&self) -> bool {
+        self.mime == Some("application/pdf")
+    }
+}
+
+impl IntoResponse for FileWithContent {
+    fn into_response(self) -> axum::response::Response {
+        use axum::http;
+
+        match (self.mime, self.content) {
+            (Some(mime), Content::Binary(data)) => {
+                let headers = [
+                    (
+                        http::header::CONTENT_TYPE,
+                        http::HeaderValue::from_static(mime),
+                    ),
+                    (
+                        http::header::X_CONTENT_TYPE_OPTIONS,
+                        http::HeaderValue::from_static("nosniff"),
+                    ),
+                ];
+
+                (headers, data).into_response()
+            }
+            (_, content) => content.into_response(),
+        }
+    }
+}
+
+/// Determines the content type of formats we're able to preview in the browser from their
+/// magic bytes.
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+    ];
+
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map(|(_, mime)| *mime)
+}
+
+/// The size of the file a blob represents, which for LFS pointers is the size of the object
+/// they point to.
+fn lfs_aware_size(data: &[u8]) -> usize {
+    LfsPointer::parse(data)
+        .and_then(|v| usize::try_from(v.size).ok())
+        .unwrap_or(data.len())
+}
+
+/// Uses the same heuristic as Git to determine whether a blob is binary, a NUL byte within the
+/// first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
 }
 
 #[derive(Debug)]
 pub enum Content {
     Text(Cow<'static, str>),
     Binary(Vec<u8>),
+    /// Sanitised HTML rendered from a document format, see [`MarkupFormat`]
+    Rendered(String),
 }
 
 impl IntoResponse for Content {
@@ -672,24 +1880,90 @@ impl IntoResponse for Content {
 
                 (headers, b).into_response()
             }
+            Self::Rendered(html) => {
+                let headers = [(
+                    http::header::CONTENT_TYPE,
+                    http::HeaderValue::from_static("text/html; charset=UTF-8"),
+                )];
+
+                (headers, html).into_response()
+            }
         }
     }
 }
 
 #[derive(Debug)]
 pub enum TaggedObject {
-    Commit(String),
+    Commit(Box<Commit>),
     Tree(String),
 }
 
 #[derive(Debug)]
 pub struct DetailedTag {
     pub name: Arc<str>,
+    /// The tagger of an annotated tag, lightweight tags have no tagger or message
     pub tagger: Option<CommitUser>,
     pub message: String,
+    /// The armored signature appended to the tag message, if the tag was signed
+    pub signature: Option<String>,
     pub tagged_object: Option<TaggedObject>,
 }
 
+impl DetailedTag {
+    /// The type of key the tag was signed with. We don't hold any keys so the signature itself
+    /// can't be verified.
+    pub fn signature_kind(&self) -> Option<&'static str> {
+        let signature = self.signature.as_deref()?;
+
+        Some(if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+            "SSH"
+        } else if signature.starts_with("-----BEGIN SIGNED MESSAGE-----") {
+            "X.509"
+        } else {
+            "PGP"
+        })
+    }
+}
+
+/// Splits an armored signature off the end of a tag message. gix only recognises PGP signatures,
+/// so SSH and X.509 signatures are otherwise left in the message.
+pub fn split_tag_signature(message: &str) -> (&str, Option<&str>) {
+    const SIGNATURE_HEADERS: &[&str] = &[
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+        "-----BEGIN SIGNED MESSAGE-----",
+    ];
+
+    SIGNATURE_HEADERS
+        .iter()
+        .find_map(|header| {
+            let start = if message.starts_with(header) {
+                0
+            } else {
+                message.find(&format!("\n{header}"))? + 1
+            };
+
+            Some((&message[..start], Some(&message[start..])))
+        })
+        .unwrap_or((message, None))
+}
+
+/// A range of commits to be exported as a series of patch emails.
+#[derive(Debug)]
+pub struct PatchSeries {
+    /// The ID of the head commit, used to give the emails stable message IDs
+    pub id: String,
+    pub cover_letter: Option<CoverLetter>,
+    pub patches: Vec<Commit>,
+}
+
+#[derive(Debug)]
+pub struct CoverLetter {
+    pub author: CommitUser,
+    pub subject: String,
+    pub body: String,
+}
+
 #[derive(Debug)]
 pub struct CommitUser {
     name: String,
@@ -736,8 +2010,13 @@ pub struct Commit {
     parents: Vec<String>,
     summary: String,
     body: String,
+    trailers: Vec<Trailer>,
+    /// The full ID of the commit this one says it reverts, if it could be resolved
+    reverts: Option<String>,
     pub diff_stats: String,
     pub diff: String,
+    /// The files changed by the commit, in the order they appear in `diff`
+    pub files: Vec<FileDiff>,
 }
 
 impl TryFrom<gix::Commit<'_>> for Commit {
@@ -745,6 +2024,19 @@ impl TryFrom<gix::Commit<'_>> for Commit {
 
     fn try_from(commit: gix::Commit<'_>) -> Result<Self> {
         let message = commit.message()?;
+        let body = message.body.map_or_else(String::new, ToString::to_string);
+        let (body, mut trailers) = split_trailers(&body);
+
+        for trailer in &mut trailers {
+            trailer.commit = trailer
+                .fixes_hash()
+                .and_then(|hash| commit.repo.rev_parse_single(hash).ok())
+                .map(|id| id.to_string());
+        }
+
+        let reverts = reverted_hash(&body)
+            .and_then(|hash| commit.repo.rev_parse_single(hash).ok())
+            .map(|id| id.to_string());
 
         Ok(Commit {
             author: CommitUser::try_from(commit.author()?)?,
@@ -753,9 +2045,12 @@ impl TryFrom<gix::Commit<'_>> for Commit {
             tree: commit.tree_id()?.to_string(),
             parents: commit.parent_ids().map(|v| v.to_string()).collect(),
             summary: message.summary().to_string(),
-            body: message.body.map_or_else(String::new, ToString::to_string),
+            body: body.to_string(),
+            trailers,
+            reverts,
             diff_stats: String::with_capacity(0),
             diff: String::with_capacity(0),
+            files: Vec::new(),
         })
     }
 }
@@ -785,31 +2080,98 @@ impl Commit {
         &self.summary
     }
 
+    /// The body of the commit message, without the trailers
     pub fn body(&self) -> &str {
         &self.body
     }
+
+    pub fn trailers(&self) -> &[Trailer] {
+        &self.trailers
+    }
+
+    pub fn reverts(&self) -> Option<&str> {
+        self.reverts.as_deref()
+    }
+}
+
+/// What a hash, reference name or revspec given for a commit turned out to name.
+pub enum ResolvedCommit {
+    Commit(ObjectId),
+    /// An abbreviated hash matching more than one object, none of them clearly the one meant
+    Ambiguous(Vec<AmbiguousObject>),
+}
+
+/// One of the objects an ambiguous abbreviated hash matched.
+pub struct AmbiguousObject {
+    pub id: ObjectId,
+    pub kind: &'static str,
+    /// The summary of a commit, or the name of a tag
+    pub summary: Option<String>,
+}
+
+fn object_kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Commit => "commit",
+        Kind::Tag => "tag",
+        Kind::Tree => "tree",
+        Kind::Blob => "blob",
+    }
 }
 
-#[instrument(skip(repo, commit))]
+/// How a blob was rendered, which along with the blob's ID identifies the output in the render
+/// cache. Where the blob was found doesn't come into it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum BlobRenderer {
+    Highlighted(Option<Syntax>),
+    Ansi,
+    Markup(MarkupFormat),
+}
+
+/// The blobs on either side of a diff, and the language they were highlighted as. The same blobs
+/// can be highlighted differently depending on the name of the file they're at.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct HighlightedDiffKey {
+    old: ObjectId,
+    new: ObjectId,
+    language: Option<Syntax>,
+}
+
+/// A syntax highlighted diff of a single pair of blobs.
+#[derive(Clone)]
+struct HighlightedFileDiff {
+    output: Arc<str>,
+    insertions: usize,
+    deletions: usize,
+}
+
+/// Syntax highlighting settings for rendering a single diff. Once `deadline` passes, any
+/// remaining hunks are rendered without highlighting.
+#[derive(Copy, Clone)]
+struct DiffHighlighting<'a> {
+    cache: &'a moka::sync::Cache<HighlightedDiffKey, HighlightedFileDiff>,
+    deadline: Instant,
+}
+
+/// Diffs the commit against the parent at the index given, or against the empty tree if it's a
+/// root commit.
+#[instrument(skip(repo, commit, highlight))]
 fn fetch_diff_and_stats(
     repo: &gix::Repository,
     commit: &gix::Commit<'_>,
-    highlight: bool,
-) -> Result<(String, String)> {
+    parent: usize,
+    highlight: Option<DiffHighlighting<'_>>,
+) -> Result<(String, String, Vec<FileDiff>)> {
     const WIDTH: usize = 80;
 
     let current_tree = commit.tree().context("Couldn't get tree for the commit")?;
-    let parent_tree = commit
-        .ancestors()
-        .first_parent_only()
-        .all()?
-        .nth(1)
-        .transpose()?
-        .map(|v| v.object())
-        .transpose()?
-        .map(|v| v.tree())
-        .transpose()?
-        .unwrap_or_else(|| repo.empty_tree());
+    let parent_tree = match commit.parent_ids().nth(parent) {
+        Some(id) => repo
+            .find_commit(id)?
+            .tree()
+            .context("Couldn't get tree for the parent")?,
+        None if parent == 0 => repo.empty_tree(),
+        None => return Err(NotFound("Commit doesn't have that many parents").into()),
+    };
 
     let mut diffs = Vec::new();
     let mut diff_output = String::new();
@@ -822,13 +2184,15 @@ fn fetch_diff_and_stats(
         &current_tree,
         &mut repo.diff_resource_cache_for_tree_diff()?,
         |change| {
-            if highlight {
+            if let Some(highlight) = highlight {
                 DiffBuilder {
                     output: &mut diff_output,
                     resource_cache: &mut resource_cache,
                     diffs: &mut diffs,
+                    cache: Some(highlight.cache),
                     formatter: SyntaxHighlightedDiffFormatter::new(
                         change.location.to_path().unwrap(),
+                        highlight.deadline,
                     ),
                 }
                 .handle(change)
@@ -837,6 +2201,7 @@ fn fetch_diff_and_stats(
                     output: &mut diff_output,
                     resource_cache: &mut resource_cache,
                     diffs: &mut diffs,
+                    cache: None,
                     formatter: PlainDiffFormatter,
                 }
                 .handle(change)
@@ -913,17 +2278,20 @@ fn fetch_diff_and_stats(
 
     writeln!(diff_stats)?;
 
-    Ok((diff_output, diff_stats))
+    Ok((diff_output, diff_stats, diffs))
 }
 
 #[derive(Default, Debug)]
-struct FileDiff {
-    path: String,
-    insertions: usize,
-    deletions: usize,
+pub struct FileDiff {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
 }
 
 trait DiffFormatter {
+    /// Marks the start of the `index`th file in the diff, so it can be linked to.
+    fn file_start(&self, _output: &mut String, _index: usize) {}
+
     fn file_header(&self, output: &mut String, data: fmt::Arguments<'_>);
 
     fn binary(
@@ -934,12 +2302,24 @@ trait DiffFormatter {
         left_content: &[u8],
         right_content: &[u8],
     );
+
+    /// The grammar hunks are highlighted with, which their cached output is keyed by.
+    fn language(&self) -> Option<Syntax> {
+        None
+    }
+
+    /// Whether the formatter managed to fully format its output, formatters that give up on
+    /// expensive formatting part way through shouldn't have their output cached.
+    fn within_budget(&self) -> bool {
+        true
+    }
 }
 
 struct DiffBuilder<'a, F> {
     output: &'a mut String,
     resource_cache: &'a mut gix::diff::blob::Platform,
     diffs: &'a mut Vec<FileDiff>,
+    cache: Option<&'a moka::sync::Cache<HighlightedDiffKey, HighlightedFileDiff>>,
     formatter: F,
 }
 
@@ -962,6 +2342,8 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
 
         let prep = change.resource_cache.prepare_diff()?;
 
+        self.formatter.file_start(self.output, self.diffs.len());
+
         self.formatter.file_header(
             self.output,
             format_args!(
@@ -1033,6 +2415,43 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
                 self.formatter
                     .file_header(self.output, format_args!("+++ {new_path}"));
 
+                let old_lfs = LfsPointer::parse(prep.old.data.as_slice().unwrap_or_default());
+                let new_lfs = LfsPointer::parse(prep.new.data.as_slice().unwrap_or_default());
+
+                // diffing the pointer text isn't useful, show which objects changed instead
+                if old_lfs.is_some() || new_lfs.is_some() {
+                    self.formatter.file_header(
+                        self.output,
+                        format_args!(
+                            "Git LFS object {} -> {}",
+                            old_lfs.map_or_else(|| "(none)".to_string(), |v| v.to_string()),
+                            new_lfs.map_or_else(|| "(none)".to_string(), |v| v.to_string()),
+                        ),
+                    );
+
+                    self.diffs.push(diff);
+                    self.resource_cache.clear_resource_cache_keep_allocation();
+                    return Ok(gix::object::tree::diff::Action::Continue);
+                }
+
+                let cache_key = HighlightedDiffKey {
+                    old: prep.old.id.to_owned(),
+                    new: prep.new.id.to_owned(),
+                    language: self.formatter.language(),
+                };
+
+                if let Some(cached) = self.cache.and_then(|cache| cache.get(&cache_key)) {
+                    self.output.push_str(&cached.output);
+                    diff.insertions += cached.insertions;
+                    diff.deletions += cached.deletions;
+
+                    self.diffs.push(diff);
+                    self.resource_cache.clear_resource_cache_keep_allocation();
+                    return Ok(gix::object::tree::diff::Action::Continue);
+                }
+
+                let output_start = self.output.len();
+
                 let old_source = gix::diff::blob::sources::lines_with_terminator(
                     simdutf8::basic::from_utf8(prep.old.data.as_slice().unwrap_or_default())?,
                 );
@@ -1050,6 +2469,17 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
 
                 diff.deletions += output.removals as usize;
                 diff.insertions += output.insertions as usize;
+
+                if let Some(cache) = self.cache.filter(|_| self.formatter.within_budget()) {
+                    cache.insert(
+                        cache_key,
+                        HighlightedFileDiff {
+                            output: Arc::from(&self.output[output_start..]),
+                            insertions: diff.insertions,
+                            deletions: diff.deletions,
+                        },
+                    );
+                }
             }
             Operation::ExternalCommand { .. } => {}
             Operation::SourceOrDestinationIsBinary => {
@@ -1112,23 +2542,67 @@ impl Callback for PlainDiffFormatter {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DiffLineKind {
+    Addition,
+    Removal,
+    Context,
+}
+
+impl DiffLineKind {
+    fn class(self) -> &'static str {
+        match self {
+            Self::Addition => "add-line",
+            Self::Removal => "remove-line",
+            Self::Context => "context",
+        }
+    }
+}
+
+/// Buffers up the lines of each hunk, highlighting the before and after sides of the hunk as
+/// contiguous blocks so constructs spanning multiple lines (ie. block comments) are highlighted
+/// correctly, without having to highlight the entirety of both files.
 struct SyntaxHighlightedDiffFormatter<'a> {
     path: &'a Path,
+    language: Option<Syntax>,
+    deadline: Instant,
+    exceeded_budget: bool,
+    hunk: Vec<(DiffLineKind, String)>,
 }
 
 impl<'a> SyntaxHighlightedDiffFormatter<'a> {
-    fn new(path: &'a Path) -> Self {
-        Self { path }
+    fn new(path: &'a Path, deadline: Instant) -> Self {
+        Self {
+            path,
+            language: Syntax::for_path(path),
+            deadline,
+            exceeded_budget: false,
+            hunk: Vec::new(),
+        }
     }
 
-    fn write(&self, output: &mut String, class: &str, data: &str) {
-        write!(output, r#"<span class="diff-{class}">"#).unwrap();
-        format_file_inner(output, data, FileIdentifier::Path(self.path), false).unwrap();
-        write!(output, r#"</span>"#).unwrap();
+    fn highlight(&self, content: &str) -> Vec<String> {
+        let mut out = String::new();
+        format_file_inner(
+            &mut out,
+            content,
+            FileIdentifier::Syntax(self.language),
+            LineStyle::Bare,
+        )
+        .unwrap();
+        split_highlighted_lines(&out)
     }
 }
 
 impl<'a> DiffFormatter for SyntaxHighlightedDiffFormatter<'a> {
+    fn file_start(&self, output: &mut String, index: usize) {
+        write!(
+            output,
+            r#"<span class="diff-file" id="diff-{index}"></span>"#
+        )
+        .unwrap();
+    }
+
     fn file_header(&self, output: &mut String, data: Arguments<'_>) {
         write!(output, r#"<span class="diff-file-header">"#).unwrap();
         write!(output, "{data}").unwrap();
@@ -1145,18 +2619,122 @@ impl<'a> DiffFormatter for SyntaxHighlightedDiffFormatter<'a> {
     ) {
         write!(output, "Binary files {left} and {right} differ").unwrap();
     }
+
+    fn language(&self) -> Option<Syntax> {
+        self.language
+    }
+
+    fn within_budget(&self) -> bool {
+        !self.exceeded_budget
+    }
 }
 
 impl<'a> Callback for SyntaxHighlightedDiffFormatter<'a> {
-    fn addition(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "add-line", data);
+    fn addition(&mut self, data: &str, _dst: &mut String) {
+        self.hunk.push((DiffLineKind::Addition, data.to_string()));
     }
 
-    fn remove(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "remove-line", data);
+    fn remove(&mut self, data: &str, _dst: &mut String) {
+        self.hunk.push((DiffLineKind::Removal, data.to_string()));
     }
 
-    fn context(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "context", data);
+    fn context(&mut self, data: &str, _dst: &mut String) {
+        self.hunk.push((DiffLineKind::Context, data.to_string()));
+    }
+
+    fn end_hunk(&mut self, dst: &mut String) {
+        let hunk = std::mem::take(&mut self.hunk);
+
+        if !self.exceeded_budget && Instant::now() > self.deadline {
+            warn!(path = %self.path.display(), "Diff highlighting budget exceeded");
+            self.exceeded_budget = true;
+        }
+
+        let (mut before, mut after) = if self.exceeded_budget {
+            (Vec::new().into_iter(), Vec::new().into_iter())
+        } else {
+            let mut before = String::new();
+            let mut after = String::new();
+
+            for (kind, line) in &hunk {
+                if *kind != DiffLineKind::Addition {
+                    before.push_str(line);
+                }
+
+                if *kind != DiffLineKind::Removal {
+                    after.push_str(line);
+                }
+            }
+
+            (
+                self.highlight(&before).into_iter(),
+                self.highlight(&after).into_iter(),
+            )
+        };
+
+        for (kind, line) in hunk {
+            let highlighted = match kind {
+                DiffLineKind::Addition => after.next(),
+                DiffLineKind::Removal => before.next(),
+                DiffLineKind::Context => {
+                    after.next();
+                    before.next()
+                }
+            };
+
+            write!(dst, r#"<span class="diff-{}">"#, kind.class()).unwrap();
+
+            if let Some(highlighted) = highlighted {
+                dst.push_str(&highlighted);
+            } else {
+                v_htmlescape::b_escape(line.trim_end_matches(['\r', '\n']).as_bytes(), dst);
+            }
+
+            dst.push_str("\n</span>");
+        }
+    }
+}
+
+/// Splits highlighted HTML into lines, closing any spans left open at the end of a line and
+/// reopening them at the start of the next so each line can be wrapped independently.
+fn split_highlighted_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open_tags: Vec<&str> = Vec::new();
+    let mut current = String::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let next = rest.find(['<', '\n']).unwrap_or(rest.len());
+        current.push_str(&rest[..next]);
+        rest = &rest[next..];
+
+        if let Some(remaining) = rest.strip_prefix('\n') {
+            for _ in &open_tags {
+                current.push_str("</span>");
+            }
+
+            lines.push(std::mem::take(&mut current));
+
+            for tag in &open_tags {
+                current.push_str(tag);
+            }
+
+            rest = remaining;
+        } else if let Some(remaining) = rest.strip_prefix("</span>") {
+            open_tags.pop();
+            current.push_str("</span>");
+            rest = remaining;
+        } else if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |v| v + 1);
+            open_tags.push(&rest[..end]);
+            current.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+
+    if open_tags.is_empty() && !current.is_empty() {
+        lines.push(current);
     }
+
+    lines
 }
@@ -0,0 +1,95 @@
+//! Searches file contents for a literal string, grouping the matches into hunks of surrounding
+//! context the way `git grep -C` does.
+
+use std::ops::Range;
+
+/// Matches shown per file before the rest of the file is skipped.
+pub const MAX_MATCHES_PER_FILE: usize = 10;
+
+/// Lines of context shown either side of a match.
+const CONTEXT_LINES: usize = 2;
+
+pub struct GrepFile {
+    pub path: String,
+    pub hunks: Vec<Vec<GrepLine>>,
+    /// Whether the file had more matches than are shown.
+    pub truncated: bool,
+}
+
+pub struct GrepLine {
+    /// The line number, starting at 1 as in the file view's anchors.
+    pub number: usize,
+    /// The escaped line with each occurrence of the needle wrapped in `<mark>`.
+    pub html: String,
+    pub is_match: bool,
+}
+
+/// Finds the lines of `content` containing `needle`, returning `None` if there are none.
+pub fn search(path: String, content: &str, needle: &str) -> Option<GrepFile> {
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut matches = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.contains(needle))
+        .map(|(i, _)| i);
+
+    let shown: Vec<usize> = matches.by_ref().take(MAX_MATCHES_PER_FILE).collect();
+    let truncated = matches.next().is_some();
+
+    if shown.is_empty() {
+        return None;
+    }
+
+    let hunks = hunk_ranges(&shown, lines.len())
+        .into_iter()
+        .map(|range| {
+            range
+                .map(|i| GrepLine {
+                    number: i + 1,
+                    html: highlight(lines[i], needle),
+                    is_match: shown.binary_search(&i).is_ok(),
+                })
+                .collect()
+        })
+        .collect();
+
+    Some(GrepFile {
+        path,
+        hunks,
+        truncated,
+    })
+}
+
+/// Expands each matching line into a range covering its context, merging ranges that touch.
+fn hunk_ranges(matches: &[usize], line_count: usize) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for &line in matches {
+        let start = line.saturating_sub(CONTEXT_LINES);
+        let end = (line + CONTEXT_LINES + 1).min(line_count);
+
+        match ranges.last_mut() {
+            Some(last) if last.end >= start => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+
+    ranges
+}
+
+fn highlight(line: &str, needle: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(position) = rest.find(needle) {
+        v_htmlescape::b_escape(rest[..position].as_bytes(), &mut out);
+        out.push_str("<mark>");
+        v_htmlescape::b_escape(needle.as_bytes(), &mut out);
+        out.push_str("</mark>");
+        rest = &rest[position + needle.len()..];
+    }
+
+    v_htmlescape::b_escape(rest.as_bytes(), &mut out);
+    out
+}
@@ -0,0 +1,258 @@
+//! Translations of the web interface, kept as Fluent catalogs in `locales/`. Each request is
+//! served in the best match for its `Accept-Language` header out of the catalogs we have, falling
+//! back to `--default-locale`, and strings missing from a catalog fall back to English.
+
+use std::{
+    sync::{LazyLock, OnceLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::Request,
+    http::{
+        header::{ACCEPT_LANGUAGE, VARY},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource, FluentValue};
+use time::{format_description, OffsetDateTime};
+use unic_langid::LanguageIdentifier;
+
+/// The catalogs compiled in, English first as it's what every other falls back to.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+const FALLBACK: usize = 0;
+
+static LOCALES: LazyLock<Vec<Locale>> = LazyLock::new(|| {
+    CATALOGS
+        .iter()
+        .map(|(id, source)| Locale::new(id, source))
+        .collect()
+});
+
+static DEFAULT_LOCALE: OnceLock<usize> = OnceLock::new();
+
+tokio::task_local! {
+    static LOCALE: usize;
+}
+
+struct Locale {
+    id: &'static str,
+    bundle: FluentBundle<FluentResource>,
+}
+
+impl Locale {
+    fn new(id: &'static str, source: &str) -> Self {
+        let langid: LanguageIdentifier = id.parse().expect("invalid locale identifier");
+
+        let resource = FluentResource::try_new(source.to_string())
+            .unwrap_or_else(|(_, errors)| panic!("failed to parse {id} catalog: {errors:?}"));
+
+        let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+        // the isolation marks fluent wraps arguments in show up as stray characters in titles
+        bundle.set_use_isolating(false);
+        bundle
+            .add_resource(resource)
+            .unwrap_or_else(|errors| panic!("failed to load {id} catalog: {errors:?}"));
+
+        Self { id, bundle }
+    }
+
+    fn format(&self, key: &str, args: Option<&FluentArgs<'_>>) -> Option<String> {
+        let pattern = self.bundle.get_message(key)?.value()?;
+        let mut errors = Vec::new();
+
+        Some(
+            self.bundle
+                .format_pattern(pattern, args, &mut errors)
+                .into_owned(),
+        )
+    }
+}
+
+/// The identifiers of every locale there's a catalog for.
+pub fn available() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(id, _)| *id)
+}
+
+/// Sets the locale requests that don't ask for one we have are served in, must be called before
+/// serving any requests.
+pub fn install(default: &str) -> anyhow::Result<()> {
+    let index = find(default).with_context(|| {
+        format!(
+            "No catalog for default locale `{default}`, expected one of: {}",
+            available().collect::<Vec<_>>().join(", ")
+        )
+    })?;
+
+    let _res = DEFAULT_LOCALE.set(index);
+
+    Ok(())
+}
+
+fn find(tag: &str) -> Option<usize> {
+    let tag = tag.trim();
+    let language = tag.split(['-', '_']).next().unwrap_or(tag);
+
+    CATALOGS
+        .iter()
+        .position(|(id, _)| id.eq_ignore_ascii_case(tag))
+        .or_else(|| {
+            CATALOGS
+                .iter()
+                .position(|(id, _)| id.eq_ignore_ascii_case(language))
+        })
+}
+
+fn default_index() -> usize {
+    DEFAULT_LOCALE.get().copied().unwrap_or(FALLBACK)
+}
+
+fn current_index() -> usize {
+    LOCALE.try_with(|v| *v).unwrap_or_else(|_| default_index())
+}
+
+/// The identifier of the locale the current request is being served in, for `<html lang>`.
+pub fn current() -> &'static str {
+    LOCALES[current_index()].id
+}
+
+/// Whether the current request is being served in the default locale.
+pub fn is_default() -> bool {
+    current_index() == default_index()
+}
+
+/// Picks the locale with the highest quality out of an `Accept-Language` header that we have a
+/// catalog for.
+fn negotiate(accept_language: &str) -> Option<usize> {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|v| v.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |v| v.trim().parse().ok())?;
+
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // stable, so equally preferred ranges stay in the order they were given
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges.into_iter().find_map(|(tag, _)| match tag {
+        "*" => Some(default_index()),
+        tag => find(tag),
+    })
+}
+
+/// Serves the request in the locale negotiated from its `Accept-Language` header.
+pub async fn scope(request: Request, next: Next) -> Response {
+    let locale = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(negotiate)
+        .unwrap_or_else(default_index);
+
+    let mut response = LOCALE.scope(locale, next.run(request)).await;
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept-language"));
+
+    response
+}
+
+fn translate(key: &str, args: Option<&FluentArgs<'_>>) -> String {
+    [current_index(), FALLBACK]
+        .into_iter()
+        .find_map(|index| LOCALES[index].format(key, args))
+        .unwrap_or_else(|| key.to_string())
+}
+
+/// The string `key` in the current request's locale.
+pub fn t(key: &str) -> String {
+    translate(key, None)
+}
+
+/// The string `key` in the current request's locale, with `$name` set to `value`.
+pub fn t_with(key: &str, name: &str, value: impl Into<FluentValue<'static>>) -> String {
+    let mut args = FluentArgs::new();
+    args.set(name.to_string(), value);
+    translate(key, Some(&args))
+}
+
+/// The string `key` in the current request's locale, with each of `args` set.
+pub fn t_args<const N: usize>(key: &str, args: [(&str, String); N]) -> String {
+    let args = args
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), FluentValue::from(value)))
+        .collect::<FluentArgs<'_>>();
+
+    translate(key, Some(&args))
+}
+
+/// How long ago something happened, in the largest unit it's at least one of.
+pub fn time_ago(elapsed: Duration) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = MINUTE * 60;
+    const DAY: u64 = HOUR * 24;
+    const WEEK: u64 = DAY * 7;
+    const MONTH: u64 = DAY * 30;
+    const YEAR: u64 = DAY * 365;
+
+    let seconds = elapsed.as_secs();
+
+    let (key, count) = match seconds {
+        0 => return t("time-now"),
+        ..MINUTE => ("time-seconds-ago", seconds),
+        ..HOUR => ("time-minutes-ago", seconds / MINUTE),
+        ..DAY => ("time-hours-ago", seconds / HOUR),
+        ..WEEK => ("time-days-ago", seconds / DAY),
+        ..MONTH => ("time-weeks-ago", seconds / WEEK),
+        ..YEAR => ("time-months-ago", seconds / MONTH),
+        _ => ("time-years-ago", seconds / YEAR),
+    };
+
+    t_with(key, "count", count)
+}
+
+/// How long something took, in the largest unit it's at least one of. Anything under a second is
+/// given in milliseconds to a tenth of one, as page render times usually are.
+pub fn duration(duration: Duration) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = MINUTE * 60;
+    const DAY: u64 = HOUR * 24;
+
+    let seconds = duration.as_secs();
+
+    let (key, count) = match seconds {
+        0 => {
+            let milliseconds = (duration.as_secs_f64() * 10_000.0).round() / 10.0;
+            return t_with("duration-milliseconds", "count", milliseconds);
+        }
+        ..MINUTE => ("duration-seconds", seconds),
+        ..HOUR => ("duration-minutes", seconds / MINUTE),
+        ..DAY => ("duration-hours", seconds / HOUR),
+        _ => ("duration-days", seconds / DAY),
+    };
+
+    t_with(key, "count", count)
+}
+
+/// Formats an absolute time as is customary in the current request's locale.
+pub fn format_datetime(time: OffsetDateTime) -> String {
+    let format = t("datetime-format");
+
+    format_description::parse(&format)
+        .ok()
+        .and_then(|format| time.format(&format).ok())
+        .unwrap_or_else(|| time.to_string())
+}
@@ -0,0 +1,37 @@
+//! Makes the announcement set through the admin API available to `base.html`, so it's shown on
+//! every page without each view having to look it up.
+
+use std::sync::Arc;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use tracing::warn;
+
+use crate::database::schema::announcement::Announcement;
+
+tokio::task_local! {
+    static ANNOUNCEMENT: Option<Arc<Announcement>>;
+}
+
+/// Reads the announcement from the database the request is being served from, which is cheap
+/// enough to do for every request and means secondaries pick it up as they catch up with the
+/// primary.
+pub async fn scope(request: Request, next: Next) -> Response {
+    let announcement = request
+        .extensions()
+        .get::<Arc<rocksdb::DB>>()
+        .and_then(|db| match Announcement::open(db) {
+            Ok(v) => v,
+            Err(error) => {
+                warn!(%error, "Failed to read announcement");
+                None
+            }
+        })
+        .map(Arc::new);
+
+    ANNOUNCEMENT.scope(announcement, next.run(request)).await
+}
+
+/// The announcement to show on the page being rendered, if there is one.
+pub fn current() -> Option<Arc<Announcement>> {
+    ANNOUNCEMENT.try_with(Clone::clone).ok().flatten()
+}
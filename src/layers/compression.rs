@@ -0,0 +1,48 @@
+//! Compresses pages, API responses and feeds for clients that accept it. Anything else, such as
+//! snapshots and packfiles served to git clients, is either already compressed or not worth the
+//! CPU time, so is passed through untouched.
+
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+/// Responses smaller than this are sent as-is, since compressing them would save less than the
+/// overhead of doing so.
+const MINIMUM_SIZE: u16 = 1024;
+
+/// The content types worth compressing, matched against the start of the `Content-Type` header
+/// so parameters such as the charset are ignored.
+const COMPRESSIBLE_CONTENT_TYPES: [&str; 5] = [
+    "text/html",
+    "text/css",
+    "application/json",
+    "application/atom+xml",
+    "application/rss+xml",
+];
+
+/// Negotiates gzip, zstd or brotli with the client for responses of a compressible content type.
+pub fn layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .gzip(true)
+        .zstd(true)
+        .br(true)
+        .compress_when(SizeAbove::new(MINIMUM_SIZE).and(is_compressible))
+}
+
+fn is_compressible(
+    _status: StatusCode,
+    _version: Version,
+    headers: &HeaderMap,
+    _extensions: &Extensions,
+) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|content_type| {
+            COMPRESSIBLE_CONTENT_TYPES
+                .iter()
+                .any(|v| content_type.starts_with(v))
+        })
+}
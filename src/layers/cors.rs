@@ -0,0 +1,62 @@
+//! Cross-origin access to the API and to raw files and snapshots, so tools running in a browser on
+//! another origin can read them without going through a proxy. The HTML pages are never shared
+//! with other origins.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::{
+    header::{CONTENT_DISPOSITION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE},
+    HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct CorsConfig {
+    /// Allows browsers on the given origin (ie. `https://dashboard.example.com`) to make requests
+    /// to the API and fetch raw files and snapshots, may be given multiple times or as `*` to allow
+    /// any origin. Cross-origin requests are refused if not given
+    #[clap(long = "cors-allowed-origin", value_name = "ORIGIN")]
+    pub cors_allowed_origins: Vec<String>,
+    /// The methods cross-origin requests may use, separated by commas
+    #[clap(long, value_delimiter = ',', default_value = "GET,HEAD")]
+    pub cors_allowed_methods: Vec<Method>,
+    /// How long browsers may cache the answer to a preflight request for
+    #[clap(long, default_value = "1h")]
+    pub cors_max_age: humantime::Duration,
+}
+
+/// The layer applied to the routes that can be read from other origins.
+#[derive(Clone)]
+pub struct Cors(pub CorsLayer);
+
+impl CorsConfig {
+    pub fn build(&self) -> anyhow::Result<Cors> {
+        if self.cors_allowed_origins.is_empty() {
+            return Ok(Cors(CorsLayer::new()));
+        }
+
+        let origins = if self.cors_allowed_origins.iter().any(|v| v == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(
+                self.cors_allowed_origins
+                    .iter()
+                    .map(|origin| {
+                        HeaderValue::try_from(origin.trim_end_matches('/'))
+                            .with_context(|| format!("Invalid CORS origin {origin}"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            )
+        };
+
+        Ok(Cors(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(self.cors_allowed_methods.clone())
+                .allow_headers([RANGE, IF_NONE_MATCH, IF_MODIFIED_SINCE])
+                .expose_headers([ETAG, LAST_MODIFIED, CONTENT_DISPOSITION])
+                .max_age(Duration::from(self.cors_max_age)),
+        ))
+    }
+}
@@ -1,6 +1,12 @@
 use std::convert::Infallible;
 
+pub mod announcement;
+pub mod compression;
+pub mod cors;
 pub mod logger;
+pub mod pinned;
+pub mod rate_limit;
+pub mod timezone;
 
 pub trait UnwrapInfallible<T> {
     fn unwrap_infallible(self) -> T;
@@ -0,0 +1,160 @@
+//! Keeps the most visited pages of a site's flagship repositories rendered in memory, so they're
+//! served without touching the database or the repository at all. Pages are rendered on first
+//! request, and rendered again after every index run so they don't fall behind the repository.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use tokio::sync::Notify;
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use super::UnwrapInfallible;
+use crate::url_scheme::repository_url;
+
+/// The pages of each pinned repository kept in memory, relative to the repository's root.
+const PINNED_PAGES: [&str; 3] = ["", "/about", "/log"];
+
+pub struct PinnedPages {
+    /// Request paths of every page that's pinned.
+    paths: HashSet<String>,
+    pages: RwLock<HashMap<String, PinnedPage>>,
+    refresh: Notify,
+}
+
+#[derive(Clone)]
+struct PinnedPage {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Marks a request as coming from the refresher, so it's rendered again rather than being served
+/// from memory.
+#[derive(Clone, Copy)]
+struct Refresh;
+
+impl PinnedPages {
+    pub fn new(repositories: &[String]) -> Self {
+        let paths = repositories
+            .iter()
+            .map(|repository| repository_url(repository.trim_matches('/')))
+            .flat_map(|repository| PINNED_PAGES.map(|page| format!("/{repository}{page}")))
+            .collect();
+
+        Self {
+            paths,
+            pages: RwLock::default(),
+            refresh: Notify::new(),
+        }
+    }
+
+    /// Called after each index run, so the pinned pages are rendered again with whatever changed.
+    pub fn index_updated(&self) {
+        self.refresh.notify_one();
+    }
+
+    /// Renders every pinned page again, through `app`, each time the index is updated.
+    pub fn spawn_refresher(self: Arc<Self>, app: Router) {
+        if self.paths.is_empty() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            loop {
+                self.refresh.notified().await;
+
+                for path in &self.paths {
+                    let mut request = Request::new(Body::empty());
+                    *request.uri_mut() = match path.parse() {
+                        Ok(uri) => uri,
+                        Err(error) => {
+                            warn!(%error, "Pinned page {path} isn't a valid path");
+                            continue;
+                        }
+                    };
+                    request.extensions_mut().insert(Refresh);
+
+                    let response = app.clone().oneshot(request).await.unwrap_infallible();
+
+                    if !response.status().is_success() {
+                        warn!("Failed to render pinned page {path}: {}", response.status());
+                    }
+                }
+
+                info!("Refreshed {} pinned pages", self.paths.len());
+            }
+        });
+    }
+
+    fn get(&self, path: &str) -> Option<PinnedPage> {
+        self.pages.read().unwrap().get(path).cloned()
+    }
+
+    fn insert(&self, path: String, page: PinnedPage) {
+        self.pages.write().unwrap().insert(path, page);
+    }
+}
+
+/// Serves pinned pages from memory, rendering and keeping hold of them if they're not there yet.
+pub async fn serve(
+    State(pinned): State<Arc<PinnedPages>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().trim_end_matches('/');
+
+    // query strings select other pages of the log, or filter it, so only the bare pages are kept,
+    // and only in the default locale and timezone
+    if request.method() != Method::GET
+        || request.uri().query().is_some()
+        || !pinned.paths.contains(path)
+        || !crate::i18n::is_default()
+        || crate::layers::timezone::current().is_some()
+    {
+        return next.run(request).await;
+    }
+
+    let path = path.to_string();
+
+    if request.extensions().get::<Refresh>().is_none() {
+        if let Some(page) = pinned.get(&path) {
+            return (page.headers, page.body).into_response();
+        }
+    }
+
+    let response = next.run(request).await;
+
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(error) => {
+            warn!(%error, "Failed to buffer pinned page {path}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    pinned.insert(
+        path,
+        PinnedPage {
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        },
+    );
+
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,320 @@
+//! Limits how often each client may make requests, so a single crawler can't take the instance
+//! down. Pages that are expensive to render (commits, diffs, snapshots, grep and deep log
+//! pagination) have a stricter budget of their own on top of the overall one.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use moka::sync::Cache;
+use tracing::error;
+
+use crate::{
+    database::schema::repository::Repository, methods::api::is_public, takedown::Takedowns,
+    url_scheme::repository_url,
+};
+
+/// Clients that haven't made a request for this long are forgotten, by which point their
+/// budgets would have been refilled anyway.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The number of clients tracked at once, the least recently seen are forgotten first.
+const MAX_TRACKED_CLIENTS: u64 = 100_000;
+
+/// The last segment of paths to pages that are expensive to render, along with snapshots and
+/// commits which can appear anywhere in the path.
+const EXPENSIVE_PAGES: [&str; 6] = ["commit", "diff", "patch", "mbox", "grep", "snapshot"];
+
+/// Query parameters making the log walk the repository live, or page deep into its history.
+const EXPENSIVE_LOG_PARAMETERS: [&str; 3] = ["ofs", "path", "order"];
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct RateLimitConfig {
+    /// Limits the number of requests each client IP may make per minute, unlimited if not given
+    #[clap(long)]
+    pub rate_limit: Option<NonZeroU32>,
+    /// Limits the number of requests each client IP may make per minute to pages that are
+    /// expensive to render (commits, diffs, patches, snapshots, grep and deep log pagination),
+    /// unlimited if not given
+    #[clap(long)]
+    pub expensive_rate_limit: Option<NonZeroU32>,
+    /// An address, or range of addresses in CIDR notation, of a reverse proxy whose
+    /// `X-Forwarded-For` header is trusted to give the client's IP. May be given multiple times
+    #[clap(long = "trusted-proxy")]
+    pub trusted_proxies: Vec<TrustedProxy>,
+    /// Serves a `/robots.txt` asking crawlers to stay away from pages that are expensive to render
+    #[clap(long)]
+    pub robots_txt: bool,
+}
+
+/// A reverse proxy, or range of them, that requests are accepted as forwarded from.
+#[derive(Copy, Clone, Debug)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl TrustedProxy {
+    fn contains(self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = anyhow::Error;
+
+    /// Parses an address (`10.0.0.1`) or a range of them (`10.0.0.0/8`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+
+        let network = address
+            .parse::<IpAddr>()
+            .context("invalid proxy address")?
+            .to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().context("invalid proxy address range")?,
+            None => max_prefix,
+        };
+
+        anyhow::ensure!(prefix <= max_prefix, "proxy address range is too long");
+
+        Ok(Self { network, prefix })
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Cache<IpAddr, Arc<Mutex<Budgets>>>,
+}
+
+struct Budgets {
+    all: Bucket,
+    expensive: Bucket,
+}
+
+/// A token bucket holding up to a minute's worth of requests, refilling continuously.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: Option<NonZeroU32>) -> Self {
+        Self {
+            tokens: limit.map_or(0.0, |v| f64::from(v.get())),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token from the bucket, returning how long until one is available if it's empty.
+    fn take(&mut self, limit: Option<NonZeroU32>, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+
+        let capacity = f64::from(limit.get());
+        let per_second = capacity / 60.0;
+
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * per_second)
+            .min(capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Cache::builder()
+                .time_to_idle(IDLE_TIMEOUT)
+                .max_capacity(MAX_TRACKED_CLIENTS)
+                .build(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.rate_limit.is_some() || self.config.expensive_rate_limit.is_some()
+    }
+
+    fn is_trusted_proxy(&self, address: IpAddr) -> bool {
+        self.config
+            .trusted_proxies
+            .iter()
+            .any(|proxy| proxy.contains(address))
+    }
+
+    /// The IP of the client that made the request. Requests from trusted proxies are attributed
+    /// to the address they were forwarded for, found by walking `X-Forwarded-For` back from the
+    /// most recent hop past any other trusted proxies, since anything further along the header
+    /// could've been made up by the client.
+    fn client_address(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+
+        if !self.is_trusted_proxy(client) {
+            return client;
+        }
+
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect();
+
+        for hop in forwarded.into_iter().rev() {
+            let Ok(hop) = hop.parse::<IpAddr>() else {
+                break;
+            };
+
+            client = hop.to_canonical();
+
+            if !self.is_trusted_proxy(client) {
+                break;
+            }
+        }
+
+        client
+    }
+
+    /// Takes a request from the client's budgets, returning how long the client has to wait if
+    /// they've run out.
+    fn check(&self, client: IpAddr, expensive: bool) -> Result<(), Duration> {
+        let budgets = self.clients.get_with(client, || {
+            Arc::new(Mutex::new(Budgets {
+                all: Bucket::full(self.config.rate_limit),
+                expensive: Bucket::full(self.config.expensive_rate_limit),
+            }))
+        });
+
+        let mut budgets = budgets.lock().unwrap();
+        let now = Instant::now();
+
+        if expensive {
+            budgets
+                .expensive
+                .take(self.config.expensive_rate_limit, now)?;
+        }
+
+        budgets.all.take(self.config.rate_limit, now)
+    }
+}
+
+/// Whether the request is for a page that's expensive to render.
+fn is_expensive(request: &Request) -> bool {
+    let path = request.uri().path().trim_end_matches('/');
+    let mut segments = path.split('/');
+
+    match segments.next_back() {
+        Some("log") => request.uri().query().is_some_and(|query| {
+            query.split('&').any(|pair| {
+                let key = pair.split_once('=').map_or(pair, |(key, _)| key);
+                EXPENSIVE_LOG_PARAMETERS.contains(&key)
+            })
+        }),
+        Some(last) if EXPENSIVE_PAGES.contains(&last) => true,
+        _ => segments.any(|segment| matches!(segment, "snapshot" | "commit")),
+    }
+}
+
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // requests made internally, such as those refreshing pinned pages, have no client
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|v| v.0.ip());
+
+    let Some(peer) = client.filter(|_| limiter.is_enabled()) else {
+        return next.run(request).await;
+    };
+
+    let client = limiter.client_address(peer, request.headers());
+
+    match limiter.check(client, is_expensive(&request)) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs().max(1)),
+            )],
+            "Too many requests, please slow down.",
+        )
+            .into_response(),
+    }
+}
+
+/// `/robots.txt`, asking crawlers not to visit the pages that are expensive to render, nor the
+/// pages repositories exclude with `rgit.crawlerExclude`. Only public repositories are named, so
+/// the file can't be used to find the ones that are hidden or taken down.
+pub async fn robots_txt(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+) -> Response {
+    let mut body = String::from("User-agent: *\n");
+
+    for page in EXPENSIVE_PAGES {
+        body.push_str(&format!("Disallow: /*/{page}\n"));
+    }
+
+    for parameter in EXPENSIVE_LOG_PARAMETERS {
+        body.push_str(&format!("Disallow: /*/log?*{parameter}=\n"));
+    }
+
+    let repositories = tokio::task::spawn_blocking(move || {
+        let mut repositories = Repository::fetch_all(&db)?;
+        repositories.retain(|path, _| is_public(&db, &takedowns, path));
+        Ok::<_, anyhow::Error>(repositories)
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|v| v);
+
+    match repositories {
+        Ok(repositories) => {
+            for (path, repository) in repositories {
+                for view in repository.get().crawler_excluded_views.as_slice() {
+                    body.push_str(&format!("Disallow: /{}/{view}\n", repository_url(&path)));
+                }
+            }
+        }
+        Err(error) => error!(%error, "Failed to list repositories for robots.txt"),
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain")], body).into_response()
+}
@@ -0,0 +1,149 @@
+//! The timezone absolute times are shown in, picked by the viewer with `?tz=` and remembered in a
+//! cookie. Without a preference, times are shown in the timezone they were recorded in, which for
+//! commits and tags is the author's own and for everything else is UTC.
+
+use axum::{
+    extract::{Query, Request},
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use time::{OffsetDateTime, UtcOffset};
+
+const COOKIE_NAME: &str = "tz";
+
+/// How long the preference is remembered for.
+const COOKIE_MAX_AGE: u32 = 60 * 60 * 24 * 365;
+
+tokio::task_local! {
+    static TIMEZONE: Option<UtcOffset>;
+}
+
+#[derive(Deserialize)]
+struct TimezoneQuery {
+    tz: Option<String>,
+}
+
+/// Parses a timezone given as `UTC`, an offset such as `+02:00`, `-0530` or `+9`, or either of
+/// those prefixed with `UTC`. `original` (or nothing at all) asks for times to be left in the
+/// timezone they were recorded in, and is returned as `Some(None)`.
+fn parse(value: &str) -> Option<Option<UtcOffset>> {
+    let value = value.trim_end();
+
+    if value.is_empty() || value.eq_ignore_ascii_case("original") {
+        return Some(None);
+    }
+
+    let offset = value
+        .get(..3)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("utc") || prefix.eq_ignore_ascii_case("gmt"))
+        .map_or(value, |_| &value[3..]);
+
+    if offset.is_empty() {
+        return Some(Some(UtcOffset::UTC));
+    }
+
+    // a `+` that wasn't percent-encoded in the query string comes through as a space
+    let (sign, offset) = match offset.as_bytes()[0] {
+        b'+' | b' ' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if offset.len() == 4 && offset.is_ascii() => offset.split_at(2),
+        None => (offset, "0"),
+    };
+
+    let hours: i8 = hours.parse().ok()?;
+    let minutes: i8 = minutes.parse().ok()?;
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0)
+        .ok()
+        .map(Some)
+}
+
+fn from_cookie(request: &Request) -> Option<UtcOffset> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == COOKIE_NAME).then_some(value)
+        })
+        .and_then(parse)
+        .flatten()
+}
+
+/// Serves the request with the viewer's timezone preference, remembering it if it was just given
+/// in the query string.
+pub async fn scope(request: Request, next: Next) -> Response {
+    let requested = Query::<TimezoneQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.tz)
+        .and_then(|tz| parse(&tz));
+
+    let timezone = match requested {
+        Some(timezone) => timezone,
+        None => from_cookie(&request),
+    };
+
+    let mut response = TIMEZONE.scope(timezone, next.run(request)).await;
+
+    if let Some(timezone) = requested {
+        let cookie = match timezone {
+            Some(offset) => format!(
+                "{COOKIE_NAME}={}; Path=/; Max-Age={COOKIE_MAX_AGE}; SameSite=Lax",
+                format_offset(offset)
+            ),
+            None => format!("{COOKIE_NAME}=; Path=/; Max-Age=0; SameSite=Lax"),
+        };
+
+        if let Ok(cookie) = HeaderValue::try_from(cookie) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+fn format_offset(offset: UtcOffset) -> String {
+    let (hours, minutes, _) = offset.as_hms();
+    let sign = if offset.is_negative() { '-' } else { '+' };
+
+    format!(
+        "{sign}{:02}:{:02}",
+        hours.unsigned_abs(),
+        minutes.unsigned_abs()
+    )
+}
+
+/// The offset the viewer asked for times to be shown in, if they asked for one.
+pub fn current() -> Option<UtcOffset> {
+    TIMEZONE.try_with(|v| *v).ok().flatten()
+}
+
+/// Moves `time` into the viewer's timezone, if they've picked one.
+pub fn apply(time: OffsetDateTime) -> OffsetDateTime {
+    current().map_or(time, |offset| time.to_offset(offset))
+}
+
+/// The timezone times are being shown in, for the footer, or `None` if they're being left in the
+/// timezone they were recorded in.
+pub fn label() -> Option<String> {
+    current().map(|offset| {
+        if offset.is_utc() {
+            "UTC".to_string()
+        } else {
+            format!("UTC{}", format_offset(offset))
+        }
+    })
+}
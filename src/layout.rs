@@ -0,0 +1,78 @@
+//! Context shared by every page through `base.html`: the stylesheets to link to and the
+//! site-wide branding. This is built once at startup, so templates can reach it without every
+//! view having to carry it around.
+
+use std::sync::OnceLock;
+
+use crate::{
+    branding::{Branding, ColourScheme},
+    build_asset_hash,
+};
+
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+/// A static asset served under a URL containing a hash of its contents, so it can be cached
+/// forever.
+pub struct Asset {
+    pub hash: Box<str>,
+    pub content: &'static [u8],
+}
+
+impl Asset {
+    fn new(content: String) -> Self {
+        let content: &'static [u8] = Box::leak(content.into_boxed_str().into_boxed_bytes());
+
+        Self {
+            hash: build_asset_hash(content),
+            content,
+        }
+    }
+}
+
+pub struct Layout {
+    pub version: &'static str,
+    pub colour_scheme: ColourScheme,
+    pub stylesheet: Asset,
+    pub highlight_stylesheet: Asset,
+    pub dark_highlight_stylesheet: Asset,
+    /// The admin-supplied stylesheet, linked after all the others so it can override them.
+    pub custom_stylesheet: Option<Asset>,
+    pub logo_html: Option<String>,
+    pub footer_html: Option<String>,
+    /// Shown alongside links to the site when they're unfurled by chat tools.
+    pub social_image: Option<String>,
+}
+
+impl Layout {
+    /// Builds the layout, rewriting each stylesheet to match the configured colour scheme.
+    pub fn new(
+        branding: Branding,
+        stylesheet: &str,
+        highlight_stylesheet: &str,
+        dark_highlight_stylesheet: &str,
+    ) -> Self {
+        let colour_scheme = branding.colour_scheme;
+        let asset = |css: &str| Asset::new(colour_scheme.apply(css));
+
+        Self {
+            version: crate::CRATE_VERSION,
+            colour_scheme,
+            stylesheet: asset(stylesheet),
+            highlight_stylesheet: asset(highlight_stylesheet),
+            dark_highlight_stylesheet: asset(dark_highlight_stylesheet),
+            custom_stylesheet: branding.custom_css.as_deref().map(asset),
+            logo_html: branding.logo_html,
+            footer_html: branding.footer_html,
+            social_image: branding.social_image,
+        }
+    }
+
+    /// Makes the layout available to templates, must be called before serving any requests.
+    pub fn install(self) -> &'static Self {
+        LAYOUT.get_or_init(|| self)
+    }
+}
+
+pub fn layout() -> &'static Layout {
+    LAYOUT.get().expect("layout not installed")
+}
@@ -0,0 +1,129 @@
+//! Support for blobs stored with Git LFS, where the blob in the repository is a small pointer
+//! file referencing the real object on an LFS server.
+
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use serde_json::json;
+
+const USER_AGENT: &str = concat!("rgit/", clap::crate_version!());
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pointer files are required by the spec to be smaller than this.
+const MAX_POINTER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LfsPointer {
+    /// Hex-encoded SHA-256 of the object's contents.
+    pub oid: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Parses a blob as an LFS pointer file, returning `None` if it isn't one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() >= MAX_POINTER_SIZE {
+            return None;
+        }
+
+        let data = std::str::from_utf8(data).ok()?;
+        let mut lines = data.lines();
+
+        if !lines
+            .next()?
+            .strip_prefix("version ")?
+            .starts_with("https://git-lfs.github.com/spec/")
+        {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+
+        for line in lines {
+            if let Some(v) = line.strip_prefix("oid sha256:") {
+                oid = Some(v)
+                    .filter(|v| v.len() == 64 && v.bytes().all(|c| c.is_ascii_hexdigit()))
+                    .map(str::to_ascii_lowercase);
+            } else if let Some(v) = line.strip_prefix("size ") {
+                size = v.parse().ok();
+            }
+        }
+
+        Some(Self {
+            oid: oid?,
+            size: size?,
+        })
+    }
+
+    /// Asks the LFS server at `server` for a download URL for this object using the batch API.
+    pub fn resolve_download(&self, server: &str) -> anyhow::Result<String> {
+        #[derive(Deserialize)]
+        struct Response {
+            objects: Vec<Object>,
+        }
+
+        #[derive(Deserialize)]
+        struct Object {
+            actions: Option<Actions>,
+            error: Option<ObjectError>,
+        }
+
+        #[derive(Deserialize)]
+        struct Actions {
+            download: Option<Action>,
+        }
+
+        #[derive(Deserialize)]
+        struct Action {
+            href: String,
+        }
+
+        #[derive(Deserialize)]
+        struct ObjectError {
+            message: String,
+        }
+
+        let url = format!("{}/objects/batch", server.trim_end_matches('/'));
+
+        let response: Response = ureq::post(&url)
+            .set("User-Agent", USER_AGENT)
+            .set("Accept", "application/vnd.git-lfs+json")
+            .set("Content-Type", "application/vnd.git-lfs+json")
+            .timeout(TIMEOUT)
+            .send_json(json!({
+                "operation": "download",
+                "transfers": ["basic"],
+                "objects": [{ "oid": self.oid, "size": self.size }],
+            }))
+            .map_err(|e| anyhow!("request to {url} failed: {e}"))?
+            .into_json()
+            .with_context(|| format!("invalid response from {url}"))?;
+
+        let object = response
+            .objects
+            .into_iter()
+            .next()
+            .context("LFS server didn't return the requested object")?;
+
+        if let Some(error) = object.error {
+            anyhow::bail!("LFS server returned an error: {}", error.message);
+        }
+
+        object
+            .actions
+            .and_then(|v| v.download)
+            .map(|v| v.href)
+            .context("LFS server didn't return a download action")
+    }
+}
+
+impl Display for LfsPointer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256:{} ({} bytes)", self.oid, self.size)
+    }
+}
@@ -0,0 +1,167 @@
+//! Turns references in commit messages (issue numbers, ticket IDs, commit hashes) into links
+//! using rules configured for the site and for each repository.
+
+use std::{fmt::Write, str::FromStr, sync::LazyLock};
+
+use anyhow::Context;
+use regex::Regex;
+use tracing::warn;
+
+static COMMIT_HASH: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b[0-9a-f]{40}\b").unwrap());
+
+/// The schemes rules are allowed to link to.
+const ALLOWED_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// A pattern to search commit messages for and the URL to link matches to. `$1`-style references
+/// in the URL are replaced with the pattern's capture groups.
+#[derive(Debug, Clone)]
+pub struct LinkRule {
+    pattern: Regex,
+    url: String,
+}
+
+impl FromStr for LinkRule {
+    type Err = anyhow::Error;
+
+    /// Parses a rule in the form `<pattern> <url>`. URLs can't contain whitespace, so the rule is
+    /// split on the last whitespace to allow patterns to contain spaces. URLs that start with a
+    /// capture group are checked once they've been expanded instead, see [`is_allowed_href`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, url) = s
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .context("expected a link rule in the form `<pattern> <url>`")?;
+
+        if !url.starts_with('$') && !is_allowed_href(url) {
+            anyhow::bail!("link rule URLs must be http, https or mailto links");
+        }
+
+        Ok(Self {
+            pattern: Regex::new(pattern.trim_end()).context("invalid link rule pattern")?,
+            url: url.to_string(),
+        })
+    }
+}
+
+pub struct Linkifier {
+    rules: Vec<LinkRule>,
+    /// Prefix that full commit hashes are appended to, to link them to their commit page
+    commit_href: String,
+}
+
+impl Linkifier {
+    /// Builds a linkifier for the repository served at `repository_url`, rules configured on the
+    /// repository take precedence over those configured for the site.
+    pub fn new<'a>(
+        repository_url: &str,
+        site_rules: &[LinkRule],
+        repository_rules: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let rules = repository_rules
+            .into_iter()
+            .filter_map(|rule| match LinkRule::from_str(rule) {
+                Ok(v) => Some(v),
+                Err(error) => {
+                    warn!(%error, rule, "Ignoring invalid link rule");
+                    None
+                }
+            })
+            .chain(site_rules.iter().cloned())
+            .collect();
+
+        Self {
+            rules,
+            commit_href: format!("/{repository_url}/commit?id="),
+        }
+    }
+
+    /// Renders `text` as HTML, linking any references found.
+    pub fn render(&self, text: &str) -> String {
+        self.render_with(text, |out, plain| {
+            v_htmlescape::b_escape(plain.as_bytes(), out);
+        })
+    }
+
+    /// Renders a commit summary as HTML for places where the summary itself links to the commit,
+    /// since links can't be nested the text between references is linked to the commit instead.
+    pub fn render_summary(&self, summary: &str, hash: &[u8]) -> String {
+        let href = format!("{}{}", self.commit_href, const_hex::encode(hash));
+
+        self.render_with(summary, |out, plain| {
+            if !plain.is_empty() {
+                write!(out, r#"<a href="{href}">"#).unwrap();
+                v_htmlescape::b_escape(plain.as_bytes(), out);
+                out.push_str("</a>");
+            }
+        })
+    }
+
+    fn render_with(&self, text: &str, mut plain: impl FnMut(&mut String, &str)) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+
+        while let Some((start, end, url)) = self.next_match(text, pos) {
+            plain(&mut out, &text[pos..start]);
+
+            write!(out, r#"<a href="{}">"#, v_htmlescape::escape(&url)).unwrap();
+            v_htmlescape::b_escape(text[start..end].as_bytes(), &mut out);
+            out.push_str("</a>");
+
+            pos = end;
+        }
+
+        plain(&mut out, &text[pos..]);
+
+        out
+    }
+
+    /// Finds the earliest reference in `text` starting at `pos`, returning its span and the URL
+    /// it should link to. Where multiple rules match at the same position, the first one wins.
+    fn next_match(&self, text: &str, pos: usize) -> Option<(usize, usize, String)> {
+        let mut best: Option<(usize, usize, String)> = None;
+
+        for rule in &self.rules {
+            let Some(captures) = rule.pattern.captures_at(text, pos) else {
+                continue;
+            };
+
+            let m = captures.get(0).unwrap();
+
+            if m.is_empty() || best.as_ref().is_some_and(|(start, ..)| *start <= m.start()) {
+                continue;
+            }
+
+            let mut url = String::new();
+            captures.expand(&rule.url, &mut url);
+
+            // the URL can come from the matched text, which mustn't get to pick a `javascript:`
+            // URL
+            if !is_allowed_href(&url) {
+                continue;
+            }
+
+            best = Some((m.start(), m.end(), url));
+        }
+
+        if let Some(m) = COMMIT_HASH.find_at(text, pos) {
+            if best.as_ref().map_or(true, |(start, ..)| m.start() < *start) {
+                best = Some((
+                    m.start(),
+                    m.end(),
+                    format!("{}{}", self.commit_href, m.as_str()),
+                ));
+            }
+        }
+
+        best
+    }
+}
+
+/// Whether `url` is a link to one of the [`ALLOWED_SCHEMES`].
+fn is_allowed_href(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        ALLOWED_SCHEMES
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+    })
+}
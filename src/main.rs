@@ -5,9 +5,9 @@ use std::{
     fmt::{Display, Formatter},
     future::IntoFuture,
     net::SocketAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::{Arc, OnceLock},
+    sync::{mpsc, Arc},
     time::Duration,
 };
 
@@ -18,19 +18,16 @@ use axum::{
     http,
     http::{HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
     Extension, Router,
 };
 use clap::Parser;
-use const_format::formatcp;
 use database::schema::SCHEMA_VERSION;
-use rocksdb::{Options, SliceTransform};
+use rocksdb::{ColumnFamilyDescriptor, Options, SliceTransform};
 use tokio::{
     net::TcpListener,
     signal::unix::{signal, SignalKind},
-    sync::mpsc,
 };
-use tower_http::{cors::CorsLayer, timeout::TimeoutLayer};
 use tower_layer::layer_fn;
 use tracing::{error, info, instrument, warn};
 use tracing_subscriber::{
@@ -39,51 +36,264 @@ use tracing_subscriber::{
 use xxhash_rust::const_xxh3;
 
 use crate::{
-    database::schema::prefixes::{
-        COMMIT_COUNT_FAMILY, COMMIT_FAMILY, REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY,
+    activity::ActivityHints,
+    archive::SnapshotConfig,
+    blob_stream::{BlobStreamConfig, BlobStreamer},
+    branding::{Branding, BrandingConfig},
+    bundles::{BundleConfig, Bundles},
+    database::{
+        indexer::{IndexOptions, IndexState, RunErrorCounter},
+        progress::IndexProgress,
+        schema::prefixes::{
+            ACTIVITY_FAMILY, ANNOTATIONS_FAMILY, AUTHOR_FAMILY, BACKPORT_FAMILY,
+            COMMIT_COUNT_FAMILY, COMMIT_FAMILY, COMMIT_FILTER_FAMILY,
+            COMMIT_LOCATION_BY_REPOSITORY_FAMILY, COMMIT_LOCATION_FAMILY, COMMIT_RELATION_FAMILY,
+            INDEX_RUN_FAMILY, REFERENCE_FAMILY, REF_UPDATE_FAMILY, REPOSITORY_CASEFOLD_FAMILY,
+            REPOSITORY_FAMILY, TAG_BY_DATE_FAMILY, TAG_FAMILY, TOMBSTONE_FAMILY,
+        },
     },
+    email::EmailDisplay,
     git::Git,
-    layers::logger::LoggingMiddleware,
-    syntax_highlight::prime_highlighters,
+    layers::{
+        cors::CorsConfig,
+        logger::LoggingMiddleware,
+        pinned::PinnedPages,
+        rate_limit::{RateLimitConfig, RateLimiter},
+    },
+    layout::Layout,
+    linkify::LinkRule,
+    methods::{
+        feed::FeedConfig,
+        index::{IndexGrouping, ShowRecentCommits},
+        repo::BranchOrder,
+    },
+    reindex::SharedDb,
+    repository_pool::RepositoryPool,
+    scan_path::{DiscoveryConfig, ScanPaths, ScanRoot},
+    schedule::{RunPlan, Schedule, Scheduler},
+    snapshot_jobs::{SnapshotJobs, SnapshotStore},
+    syntax_highlight::{load_custom_grammars, prime_highlighters},
+    takedown::Takedowns,
     theme::Theme,
+    url_scheme::UrlConfig,
 };
 
+mod activity;
+mod admin;
+mod ansi;
+mod archive;
+mod bench;
+mod bisect;
+mod blob_stream;
+mod branding;
+mod bundles;
+mod cache_dir;
+mod commit_graph;
 mod database;
+mod email;
+mod error;
+mod forge;
 mod git;
+mod grep;
+mod i18n;
 mod layers;
+mod layout;
+mod lfs;
+mod linkify;
+mod maintenance;
+mod markdown;
+mod markup;
 mod methods;
+mod patch;
+mod projects_list;
+mod reindex;
+mod repository_config;
+mod repository_pool;
+mod scan_path;
+mod schedule;
+mod script;
+mod shutdown;
+mod snapshot_jobs;
 mod syntax_highlight;
+mod takedown;
 mod theme;
+mod trailers;
 mod unified_diff_builder;
+mod url_scheme;
 
 const CRATE_VERSION: &str = clap::crate_version!();
 
 const GLOBAL_CSS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/statics/css/style.css"));
-const GLOBAL_CSS_HASH: &str = const_hex::Buffer::<16, false>::new()
-    .const_format(&const_xxh3::xxh3_128(GLOBAL_CSS).to_be_bytes())
-    .as_str();
-
-static HIGHLIGHT_CSS_HASH: OnceLock<Box<str>> = OnceLock::new();
-static DARK_HIGHLIGHT_CSS_HASH: OnceLock<Box<str>> = OnceLock::new();
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    serve: Option<Args>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Benchmarks indexing and rendering against a repository
+    Bench(bench::Args),
+    /// Inspects and maintains the index
+    Db(maintenance::Args),
+    /// Tends to the repositories in the scan path
+    Admin(admin::Args),
+}
+
+/// Serves the repositories in the scan path, when no subcommand is given.
+#[derive(clap::Args, Debug)]
 pub struct Args {
     /// Path to a directory in which the `RocksDB` database should be stored, will be created if it doesn't already exist
     ///
     /// The `RocksDB` database is very quick to generate, so this can be pointed to temporary storage
     #[clap(short, long, value_parser)]
     db_store: PathBuf,
+    /// Path to the database of another rgit instance, which will be served read-only instead of
+    /// indexing the scan path. The database is caught up with on each refresh interval, and
+    /// `--db-store` is used for the secondary's own logs
+    #[clap(long, value_parser)]
+    primary_db_store: Option<PathBuf>,
     /// The socket address to bind to (eg. 0.0.0.0:3333)
     bind_address: SocketAddr,
-    /// The path in which your bare Git repositories reside (will be scanned recursively)
-    scan_path: PathBuf,
-    /// Configures the metadata refresh interval (eg. "never" or "60s")
-    #[clap(long, default_value_t = RefreshInterval::Duration(Duration::from_secs(300)))]
-    refresh_interval: RefreshInterval,
+    /// The paths in which your Git repositories reside (will be scanned recursively for bare
+    /// repositories, checkouts and linked worktrees), each
+    /// optionally followed by `,prefix=<prefix>` to serve its repositories under a URL prefix
+    /// and `,projects-list=<file>` to only serve the repositories listed in a gitweb-style
+    /// `projects.list`, which may also list globs and `!`-prefixed exclusions
+    #[clap(value_name = "SCAN_PATH", required = true)]
+    scan_paths: Vec<ScanRoot>,
+    /// Configures how often the scan path is indexed, as an interval, a cron expression evaluated
+    /// in UTC or "never" (eg. "60s" or "*/15 * * * *"). Repositories can override this with
+    /// `rgit.refreshInterval`
+    #[clap(long, default_value_t = Schedule::Every(Duration::from_secs(300)))]
+    refresh_interval: Schedule,
+    /// Delays each scheduled index run by a random amount of time up to this long, so instances
+    /// sharing a schedule don't all index at once
+    #[clap(long, default_value_t = Duration::ZERO.into())]
+    refresh_jitter: humantime::Duration,
+    /// Tunes how often each repository is indexed, and how long browsers and proxies may cache
+    /// its pages for, by how often it's committed to. Busy repositories are indexed every minute
+    /// and dormant ones every few hours, unless they set `rgit.refreshInterval`
+    #[clap(long)]
+    activity_hints: bool,
     /// Configures the request timeout.
     #[clap(long, default_value_t = Duration::from_secs(10).into())]
     request_timeout: humantime::Duration,
+    /// Configures how much time may be spent syntax highlighting a single diff, once exceeded
+    /// the remainder of the diff is rendered without highlighting.
+    #[clap(long, default_value_t = Duration::from_secs(1).into())]
+    diff_highlight_budget: humantime::Duration,
+    /// Configures how often metadata (description, owner, topics) of mirrored repositories is
+    /// refreshed from their upstream forge's API (eg. "never" or "1d")
+    #[clap(long, default_value_t = RefreshInterval::Never)]
+    forge_sync_interval: RefreshInterval,
+    /// Configures how repositories are grouped on the index page by default.
+    #[clap(long, value_enum, default_value_t = IndexGrouping::Path)]
+    index_grouping: IndexGrouping,
+    /// How branches are ordered on the refs page and in the branch selector, after the default
+    /// branch. Repositories can pick their own with `rgit.branchOrder`
+    #[clap(long, value_enum, default_value_t = BranchOrder::Name)]
+    branch_order: BranchOrder,
+    /// Lets visitors expand each repository on the index page to see the newest few commits on
+    /// its default branch.
+    #[clap(long)]
+    index_recent_commits: bool,
+    /// Adds a rule for linking references in commit messages, in the form `<pattern> <url>`
+    /// (eg. '#(\d+) https://github.com/w4/rgit/issues/$1'), may be given multiple times
+    #[clap(long = "link-rule")]
+    link_rules: Vec<LinkRule>,
+    /// Configures how much of the email addresses of authors, committers and taggers is shown in
+    /// pages, feeds and the API
+    #[clap(long, value_enum, default_value_t = EmailDisplay::Full)]
+    email_display: EmailDisplay,
+    /// Configures the locale pages are served in when the browser doesn't ask for one there's a
+    /// translation for (eg. 'de')
+    #[clap(long, default_value = "en")]
+    default_locale: String,
+    /// Keeps the summary, about and log pages of a repository rendered in memory, rendering them
+    /// again after each index run (eg. 'linux.git'), may be given multiple times
+    #[clap(long = "pin-repository")]
+    pinned_repositories: Vec<String>,
+    #[clap(flatten)]
+    indexing: IndexingConfig,
+    /// Configures the format logs are written to stdout in.
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Path to a file containing the token to accept for the admin API, which is disabled unless
+    /// this is given
+    #[clap(long, value_parser)]
+    admin_token_file: Option<PathBuf>,
+    /// Path to a list of repositories and objects taken down for legal reasons, which are answered
+    /// with a 451 and left out of listings. Read once at startup
+    #[clap(long, value_parser)]
+    takedown_list: Option<PathBuf>,
+    #[clap(flatten)]
+    urls: UrlConfig,
+    #[clap(flatten)]
+    rate_limits: RateLimitConfig,
+    #[clap(flatten)]
+    cors: CorsConfig,
+    #[clap(flatten)]
+    feeds: FeedConfig,
+    #[clap(flatten)]
+    snapshots: SnapshotConfig,
+    #[clap(flatten)]
+    branding: BrandingConfig,
+    #[clap(flatten)]
+    bundles: BundleConfig,
+    #[clap(flatten)]
+    discovery: DiscoveryConfig,
+    #[clap(flatten)]
+    blob_streaming: BlobStreamConfig,
+    /// Path to a directory of extra tree-sitter grammars (in `grammars/`) and highlighting themes
+    /// (`theme.toml` and `theme-dark.toml`) to load at startup
+    #[clap(long, value_parser)]
+    highlight_dir: Option<PathBuf>,
+}
+
+/// Tunes how the scan path is indexed.
+#[derive(clap::Args, Debug)]
+struct IndexingConfig {
+    /// Compresses the commits stored in the index with zstd, using a dictionary trained on the
+    /// commits already indexed. Roughly halves the size of the index for large repositories.
+    #[clap(long)]
+    compress_commits: bool,
+    /// Configures how long the index of a repository that has gone from the scan path is kept
+    /// for, so it can be restored without reindexing if it comes back (eg. "0s" or "7d")
+    #[clap(long, default_value_t = Duration::from_secs(7 * 24 * 60 * 60).into())]
+    tombstone_retention: humantime::Duration,
+    /// Treats failures to open repositories as transient, retrying them with a backoff and only
+    /// removing a repository once it's been missing for `--missing-runs` index runs in a row.
+    /// Useful when the scan path is on NFS or another network filesystem
+    #[clap(long)]
+    network_filesystem: bool,
+    /// How many index runs in a row a repository must be missing from disk for before it's
+    /// removed, with `--network-filesystem`
+    #[clap(long, default_value_t = 3)]
+    missing_runs: u32,
+    /// Rebuilds the index from scratch on startup, serving the existing index until the new one
+    /// is complete and then swapping it in. The old index is kept alongside `--db-store` with a
+    /// `.backup` suffix
+    #[clap(long, conflicts_with = "primary_db_store")]
+    reindex_all: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines, with the fields of the spans each message was logged in prefixed.
+    Text,
+    /// A JSON object per line, with the fields of every span each message was logged in.
+    Json,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -101,6 +311,15 @@ impl Display for RefreshInterval {
     }
 }
 
+impl From<RefreshInterval> for Option<Duration> {
+    fn from(value: RefreshInterval) -> Self {
+        match value {
+            RefreshInterval::Never => None,
+            RefreshInterval::Duration(v) => Some(v),
+        }
+    }
+}
+
 impl FromStr for RefreshInterval {
     type Err = &'static str;
 
@@ -118,50 +337,149 @@ impl FromStr for RefreshInterval {
 #[tokio::main]
 #[allow(clippy::too_many_lines)]
 async fn main() -> Result<(), anyhow::Error> {
-    let args: Args = Args::parse();
+    let Cli { command, serve } = Cli::parse();
+
+    match command {
+        Some(Command::Bench(args)) => return bench::run(&args).await,
+        Some(Command::Db(args)) => return maintenance::run(&args),
+        Some(Command::Admin(args)) => return admin::run(&args),
+        None => {}
+    }
+
+    // the server's required arguments are only waived when a subcommand is given
+    let args = serve.context("Missing arguments")?;
 
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    let logger_layer = tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE);
+    let text_layer = (args.log_format == LogFormat::Text)
+        .then(|| tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE));
+    let json_layer = (args.log_format == LogFormat::Json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_span_events(FmtSpan::CLOSE)
+    });
     let env_filter = EnvFilter::from_default_env();
 
     tracing_subscriber::registry()
         .with(env_filter)
-        .with(logger_layer)
+        .with(text_layer)
+        .with(json_layer)
+        .with(RunErrorCounter)
         .init();
 
-    let db = open_db(&args)?;
+    let branding = Branding::load(&args.branding)?;
+
+    args.urls.install();
+    url_scheme::install_public_url(args.feeds.public_url.as_deref());
+    args.email_display.install();
+    i18n::install(&args.default_locale)?;
+
+    shutdown::listen_for_signals();
+
+    let repositories = Arc::new(RepositoryPool::default());
+    let progress = Arc::new(IndexProgress::default());
+    let pinned = Arc::new(PinnedPages::new(&args.pinned_repositories));
+    let scheduler = Arc::new(Scheduler::new(
+        args.refresh_interval.clone(),
+        args.refresh_jitter.into(),
+        args.activity_hints,
+    ));
 
-    let indexer_wakeup_task =
-        run_indexer(db.clone(), args.scan_path.clone(), args.refresh_interval);
+    let (db, mut background_task) = if let Some(primary) = &args.primary_db_store {
+        info!("Serving from a secondary of {}", primary.display());
 
-    let css = {
-        let theme = toml::from_str::<Theme>(include_str!("../themes/github_light.toml"))
-            .unwrap()
-            .build_css();
-        let css = Box::leak(
-            format!(r#"@media (prefers-color-scheme: light){{{theme}}}"#)
-                .into_boxed_str()
-                .into_boxed_bytes(),
+        let db = open_secondary_db(primary, &args.db_store)?;
+        let task = follow_primary(db.clone(), pinned.clone(), scheduler.clone());
+        (Arc::new(SharedDb::new(db)), task)
+    } else {
+        let db = Arc::new(SharedDb::new(open_db(&args.db_store)?));
+
+        // the server starts serving straight away, repositories show up as the indexer gets to
+        // them
+        let task = run_indexer(
+            db.clone(),
+            repositories.clone(),
+            progress.clone(),
+            pinned.clone(),
+            scheduler.clone(),
+            &args,
         );
-        HIGHLIGHT_CSS_HASH.set(build_asset_hash(css)).unwrap();
-        css
+        (db, task)
     };
 
-    let dark_css = {
-        let theme = toml::from_str::<Theme>(include_str!("../themes/onedark.toml"))
-            .unwrap()
-            .build_css();
-        let css = Box::leak(
-            format!(r#"@media (prefers-color-scheme: dark){{{theme}}}"#)
-                .into_boxed_str()
-                .into_boxed_bytes(),
+    let admin_token = args
+        .admin_token_file
+        .as_deref()
+        .map(load_admin_token)
+        .transpose()?;
+
+    let takedowns = Arc::new(
+        args.takedown_list
+            .as_deref()
+            .map(Takedowns::load)
+            .transpose()?
+            .unwrap_or_default(),
+    );
+
+    let snapshot_jobs = args
+        .snapshots
+        .background_snapshots
+        .then(|| {
+            let dir = args
+                .snapshots
+                .snapshot_cache_dir
+                .clone()
+                .unwrap_or_else(|| {
+                    std::env::temp_dir().join(format!("rgit-snapshots-{}", std::process::id()))
+                });
+
+            let store = match &args.snapshots.snapshot_cache_url {
+                Some(url) => SnapshotStore::bucket(url, dir)?,
+                None => SnapshotStore::Directory(dir),
+            };
+
+            SnapshotJobs::new(store).map(Arc::new)
+        })
+        .transpose()?;
+
+    let bundles = args
+        .bundles
+        .bundle_dir
+        .clone()
+        .map(Bundles::new)
+        .transpose()?
+        .map(Arc::new);
+
+    if let Some(bundles) = &bundles {
+        bundles.clone().spawn(
+            db.clone(),
+            ScanPaths::new(args.scan_paths.clone(), args.discovery.clone()),
+            args.bundles.bundle_interval.into(),
         );
-        DARK_HIGHLIGHT_CSS_HASH.set(build_asset_hash(css)).unwrap();
-        css
-    };
+    }
+
+    let blob_streamer = args
+        .blob_streaming
+        .stream_blob_threshold
+        .map(|threshold| {
+            let dir = args
+                .blob_streaming
+                .blob_cache_dir
+                .clone()
+                .unwrap_or_else(|| {
+                    std::env::temp_dir().join(format!("rgit-blobs-{}", std::process::id()))
+                });
+
+            BlobStreamer::new(threshold, dir, args.blob_streaming.blob_cache_size)
+        })
+        .transpose()?;
+
+    let layout = install_layout(branding, args.highlight_dir.as_deref())?;
+    let request_timeout = Duration::from(args.request_timeout);
 
     let static_favicon = |content: &'static [u8]| {
         move || async move {
@@ -185,82 +503,260 @@ async fn main() -> Result<(), anyhow::Error> {
         }
     };
 
+    if let Some(dir) = &args.highlight_dir {
+        let count = load_custom_grammars(dir)?;
+        info!(count, "Loaded custom grammars");
+    }
+
     info!("Priming highlighters...");
     prime_highlighters();
     info!("Server starting up...");
 
-    let app = Router::new()
+    let cors = args.cors.build()?;
+
+    let api = Router::new()
+        .route("/api/v1/health", get(methods::api::health))
+        .route("/api/v1/repositories", get(methods::api::repositories))
+        .route("/api/v1/activity", get(methods::api::activity))
+        .route("/api/v1/index/events", get(methods::api::index_events))
+        .route("/events", get(methods::api::events))
+        .route("/api/v1/index-runs", get(methods::api::index_runs))
+        .route("/api/v1/commit/*repository", get(methods::api::commit))
+        .route("/api/v1/bisect/*repository", get(methods::api::bisect))
+        .route("/api/v1/log/*repository", get(methods::api::log))
+        .route("/api/v1/rewrites/*repository", get(methods::api::rewrites))
+        .route(
+            "/api/v1/complete/refs/*repository",
+            get(methods::api::complete_refs),
+        )
+        .route(
+            "/api/v1/complete/paths/*repository",
+            get(methods::api::complete_paths),
+        )
+        .layer(cors.0.clone());
+
+    let mut app = Router::new()
         .route("/", get(methods::index::handle))
+        .route("/atom", get(methods::index::handle_atom))
         .route(
-            formatcp!("/style-{}.css", GLOBAL_CSS_HASH),
-            get(static_css(GLOBAL_CSS)),
+            &format!("/style-{}.css", layout.stylesheet.hash),
+            get(static_css(layout.stylesheet.content)),
         )
         .route(
-            &format!("/highlight-{}.css", HIGHLIGHT_CSS_HASH.get().unwrap()),
-            get(static_css(css)),
+            &format!("/highlight-{}.css", layout.highlight_stylesheet.hash),
+            get(static_css(layout.highlight_stylesheet.content)),
         )
         .route(
             &format!(
                 "/highlight-dark-{}.css",
-                DARK_HIGHLIGHT_CSS_HASH.get().unwrap()
+                layout.dark_highlight_stylesheet.hash
             ),
-            get(static_css(dark_css)),
+            get(static_css(layout.dark_highlight_stylesheet.content)),
         )
         .route(
             "/favicon.ico",
             get(static_favicon(include_bytes!("../statics/favicon.ico"))),
         )
+        .merge(api)
+        .route("/author/:identity", get(methods::author::handle))
+        .route("/author/:identity/atom", get(methods::author::handle_atom))
+        .route("/c/:hash", get(methods::short_link::handle));
+
+    if let Some(token) = admin_token {
+        app = app
+            .route(
+                "/api/v1/admin/repositories/*repository",
+                get(methods::admin::get_annotations)
+                    .put(methods::admin::put_annotations)
+                    .delete(methods::admin::delete_annotations)
+                    .route_layer(axum::middleware::from_fn_with_state(
+                        token.clone(),
+                        methods::admin::authorize,
+                    )),
+            )
+            .route(
+                "/api/v1/admin/tombstones",
+                get(methods::admin::list_tombstones).route_layer(
+                    axum::middleware::from_fn_with_state(token.clone(), methods::admin::authorize),
+                ),
+            )
+            .route(
+                "/api/v1/admin/tombstones/*repository",
+                post(methods::admin::restore_tombstone).route_layer(
+                    axum::middleware::from_fn_with_state(token.clone(), methods::admin::authorize),
+                ),
+            )
+            .route(
+                "/api/v1/admin/patch-preview/*repository",
+                post(methods::admin::preview_patch).route_layer(
+                    axum::middleware::from_fn_with_state(token.clone(), methods::admin::authorize),
+                ),
+            )
+            .route(
+                "/api/v1/admin/announcement",
+                get(methods::admin::get_announcement)
+                    .put(methods::admin::put_announcement)
+                    .delete(methods::admin::delete_announcement)
+                    .route_layer(axum::middleware::from_fn_with_state(
+                        token,
+                        methods::admin::authorize,
+                    )),
+            );
+    }
+
+    if args.rate_limits.robots_txt {
+        app = app.route("/robots.txt", get(layers::rate_limit::robots_txt));
+    }
+
+    if let Some(custom) = &layout.custom_stylesheet {
+        app = app.route(
+            &format!("/custom-{}.css", custom.hash),
+            get(static_css(custom.content)),
+        );
+    }
+
+    let app = app
         .fallback(methods::repo::service)
-        .layer(TimeoutLayer::new(args.request_timeout.into()))
+        .layer(axum::middleware::from_fn_with_state(
+            pinned.clone(),
+            layers::pinned::serve,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(RateLimiter::new(args.rate_limits)),
+            layers::rate_limit::limit,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            request_timeout,
+            error::timeout,
+        ))
+        .layer(layers::compression::layer())
         .layer(layer_fn(LoggingMiddleware))
-        .layer(Extension(Arc::new(Git::new())))
+        .layer(Extension(Arc::new(Git::new(
+            args.diff_highlight_budget.into(),
+            repositories,
+            blob_streamer,
+        ))))
+        .layer(axum::middleware::from_fn(layers::announcement::scope))
+        .layer(axum::middleware::from_fn(i18n::scope))
+        .layer(axum::middleware::from_fn(layers::timezone::scope))
+        .layer(axum::middleware::from_fn_with_state(
+            db.clone(),
+            reindex::inject,
+        ))
         .layer(Extension(db))
-        .layer(Extension(Arc::new(args.scan_path)))
-        .layer(CorsLayer::new());
+        .layer(Extension(progress))
+        .layer(Extension(pinned.clone()))
+        .layer(Extension(scheduler))
+        .layer(Extension(Arc::new(ScanPaths::new(
+            args.scan_paths,
+            args.discovery,
+        ))))
+        .layer(Extension(args.index_grouping))
+        .layer(Extension(args.branch_order))
+        .layer(Extension(ShowRecentCommits(args.index_recent_commits)))
+        .layer(Extension(ActivityHints(args.activity_hints)))
+        .layer(Extension(Arc::<[LinkRule]>::from(args.link_rules)))
+        .layer(Extension(args.feeds))
+        .layer(Extension(args.snapshots))
+        .layer(Extension(snapshot_jobs))
+        .layer(Extension(bundles))
+        .layer(Extension(takedowns))
+        .layer(Extension(cors));
+
+    pinned.spawn_refresher(app.clone());
 
     let listener = TcpListener::bind(&args.bind_address).await?;
     let app = app.into_make_service_with_connect_info::<SocketAddr>();
-    let server = axum::serve(listener, app).into_future();
+    let server = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::wait())
+        .into_future();
+
+    // streamed responses such as snapshots aren't bound by the request timeout, so give up on
+    // them if they're still going once in-flight requests should have finished
+    let grace_period = async {
+        shutdown::wait().await;
+        tokio::time::sleep(request_timeout).await;
+    };
 
     tokio::select! {
-        res = server => res.context("failed to run server"),
-        res = indexer_wakeup_task => res.context("failed to run indexer"),
-        _ = tokio::signal::ctrl_c() => {
-            info!("Received ctrl-c, shutting down");
-            Ok(())
-        }
+        res = server => res.context("failed to run server")?,
+        res = &mut background_task.wakeups => return res.context("failed to run indexer"),
+        () = grace_period => warn!("Timed out waiting for in-flight requests to finish"),
     }
+
+    info!("Waiting for the indexer to reach a safe point");
+    background_task.stop().await?;
+
+    info!("Shut down cleanly");
+    Ok(())
+}
+
+/// Reads the admin API's token, kept out of the command line so it isn't visible to other users.
+fn load_admin_token(path: &Path) -> Result<Arc<str>, anyhow::Error> {
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read admin token from {}", path.display()))?;
+    let token = token.trim();
+
+    anyhow::ensure!(!token.is_empty(), "Admin token file is empty");
+
+    Ok(Arc::from(token))
 }
 
-fn open_db(args: &Args) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
+/// Builds the stylesheets and installs the layout every page is rendered with, taking the
+/// highlighting themes from `--highlight-dir` in place of the built-in ones if it has them.
+fn install_layout(
+    branding: Branding,
+    highlight_dir: Option<&Path>,
+) -> Result<&'static Layout, anyhow::Error> {
+    let load_theme = |file: &str, default: &str| -> Result<String, anyhow::Error> {
+        let custom = highlight_dir
+            .map(|dir| dir.join(file))
+            .filter(|path| path.exists());
+
+        let theme = match custom {
+            Some(path) => toml::from_str::<Theme>(
+                &std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            )
+            .with_context(|| format!("Failed to parse theme {}", path.display()))?,
+            None => toml::from_str::<Theme>(default).unwrap(),
+        };
+
+        Ok(theme.build_css())
+    };
+
+    let light_theme = load_theme("theme.toml", include_str!("../themes/github_light.toml"))?;
+    let dark_theme = load_theme("theme-dark.toml", include_str!("../themes/onedark.toml"))?;
+
+    Ok(Layout::new(
+        branding,
+        std::str::from_utf8(GLOBAL_CSS)?,
+        &format!(r#"@media (prefers-color-scheme: light){{{light_theme}}}"#),
+        &format!(r#"@media (prefers-color-scheme: dark){{{dark_theme}}}"#),
+    )
+    .install())
+}
+
+fn open_db(db_store: &Path) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
+    // rocksdb refuses to open a database without being given all of its column families, so one
+    // holding a family that has since been dropped from the schema can't be opened to check its
+    // version
+    if let Ok(families) = rocksdb::DB::list_cf(&Options::default(), db_store) {
+        if let Some(family) = families.iter().find(|v| {
+            *v != rocksdb::DEFAULT_COLUMN_FAMILY_NAME && !ALL_FAMILIES.contains(&v.as_str())
+        }) {
+            warn!("Clearing outdated database (unknown column family {family})");
+            rocksdb::DB::destroy(&Options::default(), db_store)?;
+        }
+    }
+
     loop {
         let mut db_options = Options::default();
         db_options.create_missing_column_families(true);
         db_options.create_if_missing(true);
 
-        let mut commit_family_options = Options::default();
-        commit_family_options.set_prefix_extractor(SliceTransform::create(
-            "commit_prefix",
-            |input| input.split(|&c| c == b'\0').next().unwrap_or(input),
-            None,
-        ));
-
-        let mut tag_family_options = Options::default();
-        tag_family_options.set_prefix_extractor(SliceTransform::create_fixed_prefix(
-            std::mem::size_of::<u64>(),
-        )); // repository id prefix
-
-        let db = rocksdb::DB::open_cf_with_opts(
-            &db_options,
-            &args.db_store,
-            vec![
-                (COMMIT_FAMILY, commit_family_options),
-                (REPOSITORY_FAMILY, Options::default()),
-                (TAG_FAMILY, tag_family_options),
-                (REFERENCE_FAMILY, Options::default()),
-                (COMMIT_COUNT_FAMILY, Options::default()),
-            ],
-        )?;
+        let db =
+            rocksdb::DB::open_cf_descriptors(&db_options, db_store, column_family_descriptors())?;
 
         let needs_schema_regen = match db.get("schema_version")? {
             Some(v) if v.as_slice() != SCHEMA_VERSION.as_bytes() => Some(Some(v)),
@@ -279,53 +775,253 @@ fn open_db(args: &Args) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
             warn!("Clearing outdated database ({old_version} != {SCHEMA_VERSION})");
 
             drop(db);
-            rocksdb::DB::destroy(&Options::default(), &args.db_store)?;
+            rocksdb::DB::destroy(&Options::default(), db_store)?;
         } else {
-            break Ok(Arc::new(db));
+            let db = Arc::new(db);
+            database::schema::commit::repair(db.clone())?;
+            break Ok(db);
+        }
+    }
+}
+
+/// Opens the database written by another rgit process as a read-only secondary instance, storing
+/// the secondary's own logs in `db_store`. The primary's writes aren't visible until the
+/// secondary catches up with it.
+fn open_secondary_db(primary: &Path, db_store: &Path) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
+    let mut db_options = Options::default();
+    // secondaries have to be able to keep every file the primary has open
+    db_options.set_max_open_files(-1);
+
+    let db = rocksdb::DB::open_cf_descriptors_as_secondary(
+        &db_options,
+        primary,
+        db_store,
+        column_family_descriptors(),
+    )
+    .with_context(|| format!("Failed to open {} as a secondary", primary.display()))?;
+
+    // there's no clearing the primary's database if it's outdated, that's for it to do
+    match db.get("schema_version")? {
+        Some(v) if v.as_slice() == SCHEMA_VERSION.as_bytes() => Ok(Arc::new(db)),
+        Some(v) => anyhow::bail!(
+            "Primary database is on schema {}, expected {SCHEMA_VERSION}",
+            String::from_utf8_lossy(&v)
+        ),
+        None => anyhow::bail!("Primary database hasn't been initialised"),
+    }
+}
+
+fn column_family_descriptors() -> Vec<ColumnFamilyDescriptor> {
+    let mut commit_family_options = Options::default();
+    commit_family_options.set_prefix_extractor(SliceTransform::create(
+        "commit_prefix",
+        |input| input.split(|&c| c == b'\0').next().unwrap_or(input),
+        None,
+    ));
+
+    let mut tag_family_options = Options::default();
+    tag_family_options.set_prefix_extractor(SliceTransform::create_fixed_prefix(
+        std::mem::size_of::<u64>(),
+    )); // repository id prefix
+
+    let mut author_family_options = Options::default();
+    author_family_options.set_prefix_extractor(SliceTransform::create(
+        "author_prefix",
+        |input| input.split(|&c| c == b'\0').next().unwrap_or(input),
+        None,
+    ));
+
+    vec![
+        ColumnFamilyDescriptor::new(COMMIT_FAMILY, commit_family_options),
+        ColumnFamilyDescriptor::new(REPOSITORY_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(REPOSITORY_CASEFOLD_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(TAG_BY_DATE_FAMILY, tag_family_options.clone()),
+        ColumnFamilyDescriptor::new(TAG_FAMILY, tag_family_options),
+        ColumnFamilyDescriptor::new(REFERENCE_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(COMMIT_COUNT_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(COMMIT_FILTER_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(AUTHOR_FAMILY, author_family_options),
+        ColumnFamilyDescriptor::new(ACTIVITY_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(ANNOTATIONS_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(TOMBSTONE_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(REF_UPDATE_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(COMMIT_RELATION_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(BACKPORT_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(INDEX_RUN_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(COMMIT_LOCATION_FAMILY, Options::default()),
+        ColumnFamilyDescriptor::new(COMMIT_LOCATION_BY_REPOSITORY_FAMILY, Options::default()),
+    ]
+}
+
+fn run_indexer(
+    shared_db: Arc<SharedDb>,
+    repositories: Arc<RepositoryPool>,
+    progress: Arc<IndexProgress>,
+    pinned: Arc<PinnedPages>,
+    scheduler: Arc<Scheduler>,
+    args: &Args,
+) -> BackgroundTask {
+    let scan_paths = ScanPaths::new(args.scan_paths.clone(), args.discovery.clone());
+    let db_store = args.db_store.clone();
+    let reindex_all = args.indexing.reindex_all;
+    let options = IndexOptions {
+        forge_sync_interval: args.forge_sync_interval.into(),
+        compress_commits: args.indexing.compress_commits,
+        tombstone_retention: args.indexing.tombstone_retention.into(),
+        network_filesystem: args.indexing.network_filesystem,
+        missing_runs: args.indexing.missing_runs,
+    };
+    let feeds = args.feeds.clone();
+
+    let (indexer_wakeup_send, indexer_wakeup_recv) = mpsc::channel();
+
+    let worker = std::thread::spawn(move || {
+        let mut db = shared_db.get();
+
+        if reindex_all {
+            let res = reindex::rebuild(
+                &db_store,
+                &shared_db,
+                db,
+                &scan_paths,
+                &repositories,
+                &options,
+            );
+
+            db = match res {
+                Ok(v) => v,
+                Err(error) => {
+                    error!(%error, "Failed to rebuild index");
+                    shared_db.get()
+                }
+            };
+
+            if shutdown::requested() {
+                return;
+            }
+
+            pinned.index_updated();
+        }
+
+        let mut forced = false;
+        let mut state = IndexState::default();
+
+        loop {
+            let plan = scheduler.begin_run(forced);
+
+            info!("Running periodic index");
+            let updated_references = crate::database::indexer::run(
+                &scan_paths,
+                &db,
+                &repositories,
+                &progress,
+                &options,
+                &plan,
+                &mut state,
+            );
+            info!("Finished periodic index");
+
+            scheduler.finish_run(&plan, &db);
+            feeds.publish(&updated_references);
+            pinned.index_updated();
+
+            if shutdown::requested() {
+                break;
+            }
+
+            match wait_for_wakeup(&scheduler, &indexer_wakeup_recv) {
+                Some(v) => forced = v,
+                None => break,
+            }
         }
+    });
+
+    BackgroundTask {
+        wakeups: spawn_wakeups(indexer_wakeup_send),
+        worker,
     }
 }
 
-async fn run_indexer(
+/// Keeps a secondary database up to date with its primary, catching up on the same schedule the
+/// primary would be indexing on.
+fn follow_primary(
     db: Arc<rocksdb::DB>,
-    scan_path: PathBuf,
-    refresh_interval: RefreshInterval,
-) -> Result<(), tokio::task::JoinError> {
-    let (indexer_wakeup_send, mut indexer_wakeup_recv) = mpsc::channel(10);
-
-    std::thread::spawn(move || loop {
-        info!("Running periodic index");
-        crate::database::indexer::run(&scan_path, &db);
-        info!("Finished periodic index");
-
-        if indexer_wakeup_recv.blocking_recv().is_none() {
-            break;
+    pinned: Arc<PinnedPages>,
+    scheduler: Arc<Scheduler>,
+) -> BackgroundTask {
+    let (catch_up_send, catch_up_recv) = mpsc::channel();
+
+    let worker = std::thread::spawn(move || {
+        scheduler.finish_run(&RunPlan::All, &db);
+
+        while let Some(forced) = wait_for_wakeup(&scheduler, &catch_up_recv) {
+            let plan = scheduler.begin_run(forced);
+
+            if let Err(error) = db.try_catch_up_with_primary() {
+                error!(%error, "Failed to catch up with primary database");
+            } else {
+                pinned.index_updated();
+            }
+
+            scheduler.finish_run(&plan, &db);
         }
     });
 
+    BackgroundTask {
+        wakeups: spawn_wakeups(catch_up_send),
+        worker,
+    }
+}
+
+/// Blocks until the scheduler says the next run is due, returning whether the run was instead
+/// brought forward by a SIGHUP. Returns `None` once the background task has been stopped.
+fn wait_for_wakeup(scheduler: &Scheduler, wakeup_recv: &mpsc::Receiver<()>) -> Option<bool> {
+    let res = match scheduler.time_until_next_run() {
+        Some(timeout) => wakeup_recv.recv_timeout(timeout),
+        None => wakeup_recv
+            .recv()
+            .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+    };
+
+    match res {
+        Ok(()) => Some(true),
+        Err(mpsc::RecvTimeoutError::Timeout) => Some(false),
+        Err(mpsc::RecvTimeoutError::Disconnected) => None,
+    }
+}
+
+/// A thread doing work in the background, woken up by its schedule or a task.
+struct BackgroundTask {
+    wakeups: tokio::task::JoinHandle<()>,
+    worker: std::thread::JoinHandle<()>,
+}
+
+impl BackgroundTask {
+    /// Stops waking the thread, and waits for it to finish whatever it's in the middle of.
+    async fn stop(self) -> Result<(), anyhow::Error> {
+        // dropping the sender held by the task lets the thread exit once it's next idle
+        self.wakeups.abort();
+
+        tokio::task::spawn_blocking(move || self.worker.join())
+            .await?
+            .map_err(|_| anyhow::anyhow!("background thread panicked"))
+    }
+}
+
+/// Wakes the background thread listening on `wakeup_send` whenever a SIGHUP is received, the
+/// thread otherwise wakes itself up when its schedule is next due.
+fn spawn_wakeups(wakeup_send: mpsc::Sender<()>) -> tokio::task::JoinHandle<()> {
     tokio::spawn({
         let mut sighup = signal(SignalKind::hangup()).expect("could not subscribe to sighup");
-        let build_sleeper = move || async move {
-            match refresh_interval {
-                RefreshInterval::Never => futures_util::future::pending().await,
-                RefreshInterval::Duration(v) => tokio::time::sleep(v).await,
-            };
-        };
 
         async move {
-            loop {
-                tokio::select! {
-                    _ = sighup.recv() => {},
-                    () = build_sleeper() => {},
-                }
-
-                if indexer_wakeup_send.send(()).await.is_err() {
-                    error!("Indexing thread has died and is no longer accepting wakeup messages");
+            while sighup.recv().await.is_some() {
+                if wakeup_send.send(()).is_err() {
+                    error!("Background thread has died and is no longer accepting wakeup messages");
                 }
             }
         }
     })
-    .await
 }
 
 #[must_use]
@@ -0,0 +1,429 @@
+//! `rgit db <command>`, inspects and maintains the index without having to throw the whole thing
+//! away.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context};
+use gix::ObjectId;
+use rocksdb::{IteratorMode, Options, WriteBatch, DEFAULT_COLUMN_FAMILY_NAME};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    column_family_descriptors,
+    database::schema::{prefixes::ALL_FAMILIES, repository::Repository, SCHEMA_VERSION},
+    methods::filters::format_size,
+    reindex,
+    scan_path::{DiscoveryConfig, ScanPaths, ScanRoot},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Path to the `RocksDB` database, as given to `--db-store` when serving
+    #[clap(short, long, value_parser)]
+    db_store: PathBuf,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Prints the number of keys in, and size of, each column family
+    Stats,
+    /// Checks that the heads of every indexed repository still exist in git
+    Verify {
+        /// The paths in which your bare Git repositories reside, as given to the server
+        #[clap(value_name = "SCAN_PATH", required = true)]
+        scan_paths: Vec<ScanRoot>,
+    },
+    /// Compacts every column family, reclaiming the space used by deleted and overwritten keys
+    Compact,
+    /// Removes a single repository from the index, it'll be indexed again from scratch on the
+    /// next index run if it still exists
+    DropRepo {
+        /// Path of the repository relative to the scan path (eg. 'linux.git')
+        path: String,
+    },
+    /// Writes out the whole index, so it can be moved to another host without reindexing
+    Export {
+        #[clap(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        /// File to write the export to, or `-` for stdout
+        #[clap(short, long, default_value = "-")]
+        output: PathBuf,
+    },
+    /// Reads an export into a new database, which mustn't already exist
+    Import {
+        #[clap(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        /// File to read the export from, or `-` for stdin
+        #[clap(short, long, default_value = "-")]
+        input: PathBuf,
+    },
+}
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone)]
+enum ExportFormat {
+    /// A header line giving the schema version, followed by a line for every key in the index
+    Jsonl,
+}
+
+/// Number of keys written to the new database in each batch while importing.
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// The first line of an export.
+#[derive(Serialize, Deserialize)]
+struct ExportHeader<'a> {
+    schema_version: Cow<'a, str>,
+}
+
+/// A key in one of the index's column families, the key and value being hex encoded.
+#[derive(Serialize, Deserialize)]
+struct ExportRecord<'a> {
+    family: Cow<'a, str>,
+    key: String,
+    value: String,
+}
+
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    match &args.command {
+        Command::Stats => stats(&open(&args.db_store, true)?),
+        Command::Verify { scan_paths } => verify(
+            &open(&args.db_store, true)?,
+            &ScanPaths::new(scan_paths.clone(), DiscoveryConfig::default()),
+        ),
+        Command::Compact => compact(&open(&args.db_store, false)?),
+        Command::DropRepo { path } => drop_repo(&open(&args.db_store, false)?, path),
+        Command::Export {
+            format: ExportFormat::Jsonl,
+            output,
+        } => export(&open(&args.db_store, true)?, output),
+        Command::Import {
+            format: ExportFormat::Jsonl,
+            input,
+        } => import(&args.db_store, input),
+    }
+}
+
+/// Opens an existing database, read-only if possible so it can be inspected while it's being
+/// served from. Unlike when serving, a database with an outdated schema is left alone.
+pub fn open(db_store: &Path, read_only: bool) -> anyhow::Result<Arc<rocksdb::DB>> {
+    let db = if read_only {
+        rocksdb::DB::open_cf_descriptors_read_only(
+            &Options::default(),
+            db_store,
+            column_family_descriptors(),
+            false,
+        )
+    } else {
+        rocksdb::DB::open_cf_descriptors(&Options::default(), db_store, column_family_descriptors())
+    }
+    .with_context(|| format!("Failed to open database at {}", db_store.display()))?;
+
+    match db.get("schema_version")? {
+        Some(v) if v.as_slice() == SCHEMA_VERSION.as_bytes() => Ok(Arc::new(db)),
+        Some(v) => bail!(
+            "Database was written by a different version of rgit ({} != {SCHEMA_VERSION})",
+            String::from_utf8_lossy(&v)
+        ),
+        None => bail!("Database has no schema version, it may not have been written by rgit"),
+    }
+}
+
+fn stats(db: &rocksdb::DB) -> anyhow::Result<()> {
+    println!(
+        "{:<16} {:>14} {:>12} {:>12}",
+        "column family", "keys", "on disk", "in memory"
+    );
+
+    let (mut total_keys, mut total_disk, mut total_memory) = (0, 0, 0);
+
+    for name in ALL_FAMILIES {
+        let cf = db.cf_handle(name).context("missing column family")?;
+        let property = |property: &str| {
+            db.property_int_value_cf(cf, property)
+                .map(Option::unwrap_or_default)
+        };
+
+        let keys = property("rocksdb.estimate-num-keys")?;
+        let disk = property("rocksdb.total-sst-files-size")?;
+        let memory = property("rocksdb.size-all-mem-tables")?;
+
+        println!(
+            "{name:<16} {keys:>14} {:>12} {:>12}",
+            format_size(disk),
+            format_size(memory)
+        );
+
+        total_keys += keys;
+        total_disk += disk;
+        total_memory += memory;
+    }
+
+    println!(
+        "{:<16} {total_keys:>14} {:>12} {:>12}",
+        "total",
+        format_size(total_disk),
+        format_size(total_memory)
+    );
+
+    println!();
+    println!("{} repositories", Repository::fetch_all(db)?.len());
+
+    Ok(())
+}
+
+fn verify(db: &Arc<rocksdb::DB>, scan_paths: &ScanPaths) -> anyhow::Result<()> {
+    let repositories = Repository::fetch_all(db)?;
+    let mut problems = 0_usize;
+
+    for (path, repository) in &repositories {
+        let Some(git_dir) = scan_paths.resolve(path) else {
+            println!("{path}: not listed by any scan path");
+            problems += 1;
+            continue;
+        };
+
+        let git = match gix::open::Options::isolated()
+            .open_path_as_is(true)
+            .open(git_dir)
+        {
+            Ok(v) => v.to_thread_local(),
+            Err(e) => {
+                println!("{path}: failed to open repository: {e}");
+                problems += 1;
+                continue;
+            }
+        };
+
+        // not indexed yet, so there's nothing to check
+        let Some(heads) = repository.get().heads(db)? else {
+            continue;
+        };
+
+        for head in heads.get().0.iter() {
+            let name = head.name.as_str();
+
+            if git.try_find_reference(name)?.is_none() {
+                println!("{path}: {name} no longer exists");
+                problems += 1;
+                continue;
+            }
+
+            let commit_tree = repository.get().commit_tree(db.clone(), name);
+
+            if let Some(latest) = commit_tree.fetch_latest_one()? {
+                let id = ObjectId::Sha1(latest.get().hash);
+
+                if !git.has_object(id) {
+                    println!("{path}: {name} was indexed up to {id}, which no longer exists");
+                    problems += 1;
+                }
+            }
+        }
+    }
+
+    println!(
+        "Checked {} repositories, found {problems} problems",
+        repositories.len()
+    );
+
+    ensure!(problems == 0, "Index is out of date with the scan path");
+
+    Ok(())
+}
+
+fn compact(db: &rocksdb::DB) -> anyhow::Result<()> {
+    for name in ALL_FAMILIES {
+        let cf = db.cf_handle(name).context("missing column family")?;
+
+        println!("Compacting {name}");
+        db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+    }
+
+    db.compact_range(None::<&[u8]>, None::<&[u8]>);
+
+    println!("Compacted {} column families", ALL_FAMILIES.len() + 1);
+
+    Ok(())
+}
+
+fn drop_repo(db: &rocksdb::DB, path: &str) -> anyhow::Result<()> {
+    let path = path.trim_matches('/');
+
+    let repository =
+        Repository::open(db, path)?.with_context(|| format!("{path} isn't in the index"))?;
+    repository.get().delete(db, path)?;
+
+    println!("Dropped {path} from the index");
+
+    Ok(())
+}
+
+/// Every column family in the database, including the default one holding the schema version
+/// and compression dictionary.
+fn every_family() -> impl Iterator<Item = &'static str> {
+    std::iter::once(DEFAULT_COLUMN_FAMILY_NAME).chain(ALL_FAMILIES)
+}
+
+fn export(db: &rocksdb::DB, output: &Path) -> anyhow::Result<()> {
+    let mut out: BufWriter<Box<dyn Write>> = BufWriter::new(if output == Path::new("-") {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(File::create(output).context("Failed to create export")?)
+    });
+
+    serde_json::to_writer(
+        &mut out,
+        &ExportHeader {
+            schema_version: Cow::Borrowed(SCHEMA_VERSION),
+        },
+    )?;
+    out.write_all(b"\n")?;
+
+    let mut exported = 0_usize;
+
+    for family in every_family() {
+        let cf = db.cf_handle(family).context("missing column family")?;
+
+        for entry in db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, value) = entry.with_context(|| format!("Failed to read {family}"))?;
+
+            serde_json::to_writer(
+                &mut out,
+                &ExportRecord {
+                    family: Cow::Borrowed(family),
+                    key: const_hex::encode(key),
+                    value: const_hex::encode(value),
+                },
+            )?;
+            out.write_all(b"\n")?;
+
+            exported += 1;
+        }
+    }
+
+    out.flush()?;
+
+    // stdout may be the export itself, so report on stderr
+    eprintln!("Exported {exported} keys");
+
+    Ok(())
+}
+
+/// Imports an export into a new database at `db_store`. The database is built alongside it and
+/// only moved into place once it's complete, so an import that fails part way through never
+/// leaves behind a database that looks usable.
+fn import(db_store: &Path, input: &Path) -> anyhow::Result<()> {
+    let input: Box<dyn BufRead> = if input == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(
+            File::open(input).context("Failed to open export")?,
+        ))
+    };
+    let mut lines = input.lines();
+
+    let header = lines.next().context("Export is empty")??;
+    let header: ExportHeader<'_> =
+        serde_json::from_str(&header).context("Export has an invalid header")?;
+
+    ensure!(
+        header.schema_version == SCHEMA_VERSION,
+        "Export was written by a different version of rgit ({} != {SCHEMA_VERSION})",
+        header.schema_version
+    );
+
+    // an empty directory may have been created for the database ahead of time
+    let is_empty = match std::fs::read_dir(db_store) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => true,
+        Err(error) => return Err(error).context("Failed to read database directory"),
+    };
+
+    ensure!(
+        is_empty,
+        "Refusing to import over the existing database at {}",
+        db_store.display()
+    );
+
+    let staging = reindex::sibling(db_store, ".import");
+
+    // left behind by an import that was interrupted
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).context("Failed to remove previous import")?;
+    }
+
+    let imported = match import_into(&staging, lines) {
+        Ok(v) => v,
+        Err(error) => {
+            let _res = std::fs::remove_dir_all(&staging);
+            return Err(error);
+        }
+    };
+
+    if db_store.exists() {
+        std::fs::remove_dir(db_store).context("Failed to remove empty database directory")?;
+    }
+
+    std::fs::rename(&staging, db_store).context("Failed to move imported database into place")?;
+
+    println!("Imported {imported} keys into {}", db_store.display());
+
+    Ok(())
+}
+
+/// Writes the records of an export to a new database at `path`, returning the number of keys
+/// written.
+fn import_into(
+    path: &Path,
+    lines: impl Iterator<Item = std::io::Result<String>>,
+) -> anyhow::Result<usize> {
+    let mut db_options = Options::default();
+    db_options.create_missing_column_families(true);
+    db_options.create_if_missing(true);
+
+    let db = rocksdb::DB::open_cf_descriptors(&db_options, path, column_family_descriptors())
+        .with_context(|| format!("Failed to open database at {}", path.display()))?;
+
+    let mut batch = WriteBatch::default();
+    let mut imported = 0_usize;
+
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        let record: ExportRecord<'_> = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid record on line {}", number + 2))?;
+
+        let cf = db
+            .cf_handle(&record.family)
+            .with_context(|| format!("Unknown column family {}", record.family))?;
+        let key = const_hex::decode(&record.key)?;
+
+        // written once everything else is
+        if record.family == DEFAULT_COLUMN_FAMILY_NAME && key == b"schema_version" {
+            continue;
+        }
+
+        batch.put_cf(cf, key, const_hex::decode(&record.value)?);
+        imported += 1;
+
+        if batch.len() == IMPORT_BATCH_SIZE {
+            db.write_without_wal(std::mem::take(&mut batch))?;
+        }
+    }
+
+    db.write_without_wal(batch)?;
+
+    for family in every_family() {
+        db.flush_cf(db.cf_handle(family).context("missing column family")?)?;
+    }
+
+    db.put("schema_version", SCHEMA_VERSION)?;
+
+    Ok(imported)
+}
@@ -0,0 +1,43 @@
+//! Renders the Markdown found in repositories, their READMEs and descriptions.
+
+use comrak::{ComrakPlugins, Options};
+
+/// Renders a document (READMEs and the like) as HTML with GitHub's extensions to Markdown
+/// enabled. Raw HTML in the document is escaped.
+pub fn render(s: &str, plugins: &ComrakPlugins<'_>) -> String {
+    // enable gfm extensions
+    // https://github.github.com/gfm/
+    let mut options = Options::default();
+    options.extension.autolink = true;
+    options.extension.footnotes = true;
+    options.extension.strikethrough = true;
+    options.extension.table = true;
+    options.extension.tagfilter = true;
+    options.extension.tasklist = true;
+
+    comrak::markdown_to_html_with_plugins(s, &options, plugins)
+}
+
+/// Renders a repository description as Markdown, allowing a small set of inline HTML tags
+/// through. Anything else (scripts, styles, block elements, etc.) is stripped by the sanitiser.
+pub fn render_trusted_description(description: &str) -> String {
+    let mut options = comrak::Options::default();
+    options.extension.autolink = true;
+    options.extension.strikethrough = true;
+    options.render.unsafe_ = true;
+
+    let html = comrak::markdown_to_html(description.trim(), &options);
+
+    ammonia::Builder::empty()
+        .add_tags([
+            "a", "abbr", "b", "br", "code", "del", "em", "i", "s", "small", "strong", "sub", "sup",
+        ])
+        .add_tag_attributes("a", ["href", "title"])
+        .add_tag_attributes("abbr", ["title"])
+        .url_schemes(["http", "https", "mailto"].into())
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(&html)
+        .to_string()
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,324 @@
+//! Renders "document" style files (Markdown, AsciiDoc and Jupyter notebooks) to HTML for the
+//! rendered tab of the blob view.
+
+use std::{ffi::OsStr, fmt::Write, path::Path};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    git::parse_and_transform_markdown,
+    syntax_highlight::{format_file, FileIdentifier},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MarkupFormat {
+    Markdown,
+    AsciiDoc,
+    Notebook,
+}
+
+impl MarkupFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension().and_then(OsStr::to_str)?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "md" | "markdown" => Some(Self::Markdown),
+            "adoc" | "asciidoc" => Some(Self::AsciiDoc),
+            "ipynb" => Some(Self::Notebook),
+            _ => None,
+        }
+    }
+
+    pub fn render(self, content: &str) -> anyhow::Result<String> {
+        match self {
+            Self::Markdown => Ok(parse_and_transform_markdown(content)),
+            Self::AsciiDoc => Ok(parse_and_transform_markdown(&asciidoc_to_markdown(content))),
+            Self::Notebook => render_notebook(content).or_else(|error| {
+                // notebooks we can't make sense of are still JSON, which is better shown than
+                // an error page
+                tracing::debug!("Falling back to notebook source: {error:#}");
+                render_notebook_source(content)
+            }),
+        }
+    }
+}
+
+/// A very small subset of AsciiDoc, translated line-by-line to Markdown. This covers headings,
+/// listing blocks, lists and the common inline formatting, which is enough to make the majority
+/// of READMEs and docs legible. Anything we don't understand is passed through as text.
+fn asciidoc_to_markdown(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut in_listing = false;
+    let mut pending_language: Option<&str> = None;
+
+    for line in content.lines() {
+        if line.trim_end() == "----" || line.trim_end() == "...." {
+            if in_listing {
+                out.push_str("```\n");
+            } else {
+                writeln!(out, "```{}", pending_language.take().unwrap_or_default()).unwrap();
+            }
+
+            in_listing = !in_listing;
+            continue;
+        }
+
+        if in_listing {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+
+        // block attributes, such as `[source,rust]`
+        if let Some(attributes) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let mut attributes = attributes.split(',');
+            if attributes.next() == Some("source") {
+                pending_language = attributes.next().map(str::trim);
+            }
+            continue;
+        }
+
+        // document attributes and comments
+        if (line.starts_with(':') && line[1..].contains(':')) || line.starts_with("//") {
+            continue;
+        }
+
+        let heading_level = line.bytes().take_while(|&c| c == b'=').count();
+        if heading_level > 0 && line.as_bytes().get(heading_level) == Some(&b' ') {
+            writeln!(
+                out,
+                "{} {}",
+                "#".repeat(heading_level),
+                &line[heading_level + 1..]
+            )
+            .unwrap();
+            continue;
+        }
+
+        let bullet_level = line.bytes().take_while(|&c| c == b'*').count();
+        if bullet_level > 0 && line.as_bytes().get(bullet_level) == Some(&b' ') {
+            writeln!(
+                out,
+                "{}- {}",
+                "  ".repeat(bullet_level - 1),
+                &line[bullet_level + 1..]
+            )
+            .unwrap();
+            continue;
+        }
+
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    if in_listing {
+        out.push_str("```\n");
+    }
+
+    out
+}
+
+#[derive(Deserialize)]
+struct Notebook {
+    #[serde(default)]
+    cells: Vec<NotebookCell>,
+    #[serde(default)]
+    metadata: NotebookMetadata,
+}
+
+#[derive(Deserialize, Default)]
+struct NotebookMetadata {
+    language_info: Option<NotebookLanguageInfo>,
+}
+
+#[derive(Deserialize)]
+struct NotebookLanguageInfo {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "cell_type", rename_all = "snake_case")]
+enum NotebookCell {
+    Markdown {
+        source: MultilineString,
+    },
+    Code {
+        source: MultilineString,
+        #[serde(default)]
+        outputs: Vec<NotebookOutput>,
+        execution_count: Option<u64>,
+    },
+    Raw {
+        source: MultilineString,
+    },
+    /// Cell types added in newer versions of the format, which we skip over.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum NotebookOutput {
+    Stream {
+        text: MultilineString,
+    },
+    DisplayData {
+        data: NotebookOutputData,
+    },
+    ExecuteResult {
+        data: NotebookOutputData,
+    },
+    Error {
+        ename: String,
+        evalue: String,
+    },
+    /// Output types added in newer versions of the format, which we skip over.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+struct NotebookOutputData {
+    #[serde(rename = "text/html")]
+    html: Option<MultilineString>,
+    #[serde(rename = "image/png")]
+    png: Option<MultilineString>,
+    #[serde(rename = "image/jpeg")]
+    jpeg: Option<MultilineString>,
+    #[serde(rename = "text/plain")]
+    plain: Option<MultilineString>,
+}
+
+/// Notebooks store strings either as a single string or as a list of lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MultilineString {
+    Single(String),
+    Lines(Vec<String>),
+}
+
+impl MultilineString {
+    fn join(&self) -> String {
+        match self {
+            Self::Single(v) => v.clone(),
+            Self::Lines(v) => v.concat(),
+        }
+    }
+}
+
+fn render_notebook(content: &str) -> anyhow::Result<String> {
+    let notebook: Notebook =
+        serde_json::from_str(content).context("Failed to parse Jupyter notebook")?;
+
+    let language = notebook
+        .metadata
+        .language_info
+        .map_or_else(|| "python".to_string(), |v| v.name);
+
+    let mut out = String::new();
+    out.push_str(r#"<div class="notebook">"#);
+
+    for cell in notebook.cells {
+        match cell {
+            NotebookCell::Markdown { source } => {
+                out.push_str(r#"<div class="notebook-cell notebook-markdown">"#);
+                out.push_str(&parse_and_transform_markdown(&source.join()));
+                out.push_str("</div>");
+            }
+            NotebookCell::Raw { source } => {
+                out.push_str(r#"<div class="notebook-cell notebook-raw"><pre>"#);
+                v_htmlescape::b_escape(source.join().as_bytes(), &mut out);
+                out.push_str("</pre></div>");
+            }
+            NotebookCell::Code {
+                source,
+                outputs,
+                execution_count,
+            } => {
+                out.push_str(r#"<div class="notebook-cell notebook-code">"#);
+                write!(
+                    out,
+                    r#"<div class="notebook-prompt">In [{}]:</div><pre>"#,
+                    execution_count.map(|v| v.to_string()).unwrap_or_default()
+                )?;
+                out.push_str(&format_file(
+                    &source.join(),
+                    FileIdentifier::Token(&language),
+                )?);
+                out.push_str("</pre>");
+
+                for output in outputs {
+                    render_notebook_output(&mut out, output);
+                }
+
+                out.push_str("</div>");
+            }
+            NotebookCell::Unknown => {}
+        }
+    }
+
+    out.push_str("</div>");
+
+    Ok(out)
+}
+
+/// The notebook as the highlighted JSON it's stored as, for notebooks that couldn't be rendered.
+fn render_notebook_source(content: &str) -> anyhow::Result<String> {
+    Ok(format!(
+        r#"<div class="notebook"><pre>{}</pre></div>"#,
+        format_file(content, FileIdentifier::Token("json"))?
+    ))
+}
+
+fn render_notebook_output(out: &mut String, output: NotebookOutput) {
+    if matches!(output, NotebookOutput::Unknown) {
+        return;
+    }
+
+    out.push_str(r#"<div class="notebook-output">"#);
+
+    match output {
+        NotebookOutput::Stream { text } => {
+            out.push_str("<pre>");
+            v_htmlescape::b_escape(text.join().as_bytes(), out);
+            out.push_str("</pre>");
+        }
+        NotebookOutput::DisplayData { data } | NotebookOutput::ExecuteResult { data } => {
+            if let Some(image) = data.png.as_ref().and_then(base64_payload) {
+                write!(out, r#"<img src="data:image/png;base64,{image}">"#).unwrap();
+            } else if let Some(image) = data.jpeg.as_ref().and_then(base64_payload) {
+                write!(out, r#"<img src="data:image/jpeg;base64,{image}">"#).unwrap();
+            } else if let Some(html) = data.html {
+                out.push_str(&ammonia::clean(&html.join()));
+            } else if let Some(plain) = data.plain {
+                out.push_str("<pre>");
+                v_htmlescape::b_escape(plain.join().as_bytes(), out);
+                out.push_str("</pre>");
+            }
+        }
+        NotebookOutput::Error { ename, evalue } => {
+            out.push_str(r#"<pre class="notebook-error">"#);
+            v_htmlescape::b_escape(format!("{ename}: {evalue}").as_bytes(), out);
+            out.push_str("</pre>");
+        }
+        NotebookOutput::Unknown => {}
+    }
+
+    out.push_str("</div>");
+}
+
+/// Strips whitespace from a base64 payload, returning `None` if anything outside of the base64
+/// alphabet is present so it can't escape the attribute it's written to.
+fn base64_payload(data: &MultilineString) -> Option<String> {
+    let payload: String = data
+        .join()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+
+    payload
+        .bytes()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'+' | b'/' | b'='))
+        .then_some(payload)
+}
@@ -0,0 +1,324 @@
+//! Authenticated JSON API for annotating repositories at runtime, restoring removed ones,
+//! setting the site-wide announcement and previewing patches, mounted under `/api/v1/admin` when
+//! an admin token is configured.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    database::schema::{
+        annotations::Annotations,
+        announcement::{Announcement, Severity},
+        repository::Repository,
+        tombstone::Tombstone,
+    },
+    git::Git,
+    layers::pinned::PinnedPages,
+    methods::{
+        api::json,
+        repo::{Error, Result},
+    },
+    patch::{self, FilePreview},
+    scan_path::ScanPaths,
+};
+
+/// Rejects requests that don't carry the admin token as a bearer token.
+pub async fn authorize(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response()
+    }
+}
+
+/// Compares the token without bailing at the first difference, so the time taken doesn't give
+/// away how much of it was guessed correctly.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Normalises a repository path given in a URL, failing if it isn't a repository we've indexed.
+fn indexed_path(db: &rocksdb::DB, repository: &str) -> Result<String> {
+    let relative = PathBuf::from(repository.trim_matches('/')).clean();
+
+    if relative.as_os_str().is_empty() || !Repository::exists(db, &relative)? {
+        return Err(Error::repository_not_found());
+    }
+
+    relative
+        .into_os_string()
+        .into_string()
+        .map_err(|_| Error::BadRequest("Invalid repository path".into()))
+}
+
+/// `GET /api/v1/admin/repositories/<repo>`, returns the annotations set on a repository.
+pub async fn get_annotations(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let path = indexed_path(&db, &repository)?;
+        let annotations = Annotations::open(&db, &path)?.unwrap_or_default();
+
+        Ok(json(&annotations))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `PUT /api/v1/admin/repositories/<repo>`, replaces the annotations set on a repository with
+/// those in the JSON body. Any fields left out are reset.
+pub async fn put_annotations(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(pinned): Extension<Arc<PinnedPages>>,
+    body: Bytes,
+) -> Result<Response> {
+    let annotations: Annotations =
+        serde_json::from_slice(&body).map_err(|e| Error::BadRequest(e.to_string().into()))?;
+
+    tokio::task::spawn_blocking(move || {
+        let path = indexed_path(&db, &repository)?;
+        annotations.insert(&db, &path)?;
+
+        pinned.index_updated();
+
+        Ok(json(&annotations))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `DELETE /api/v1/admin/repositories/<repo>`, clears every annotation set on a repository.
+pub async fn delete_annotations(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(pinned): Extension<Arc<PinnedPages>>,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let path = indexed_path(&db, &repository)?;
+        Annotations::delete(&db, &path)?;
+
+        pinned.index_updated();
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Serialize)]
+struct TombstoneResponse {
+    repository: String,
+    removed_at: String,
+}
+
+/// `GET /api/v1/admin/tombstones`, lists the repositories that have gone from the scan path and
+/// are waiting to be purged from the index.
+pub async fn list_tombstones(Extension(db): Extension<Arc<rocksdb::DB>>) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let tombstones: Vec<_> = Tombstone::fetch_all(&db)?
+            .into_iter()
+            .map(|(repository, tombstone)| TombstoneResponse {
+                repository,
+                removed_at: OffsetDateTime::from_unix_timestamp(tombstone.removed_at)
+                    .ok()
+                    .and_then(|v| v.format(&Rfc3339).ok())
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(json(&tombstones))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `POST /api/v1/admin/tombstones/<repo>`, puts a removed repository back into the index. It'll
+/// be removed again on the next index run if it's still gone from disk.
+pub async fn restore_tombstone(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(pinned): Extension<Arc<PinnedPages>>,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let path = PathBuf::from(repository.trim_matches('/'))
+            .clean()
+            .into_os_string()
+            .into_string()
+            .map_err(|_| Error::BadRequest("Invalid repository path".into()))?;
+
+        if !Tombstone::restore(&db, &path)? {
+            return Err(Error::repository_not_found());
+        }
+
+        pinned.index_updated();
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Serialize, Deserialize)]
+struct AnnouncementBody {
+    message: String,
+    #[serde(default)]
+    severity: Severity,
+    /// RFC 3339 timestamp after which the announcement is no longer shown
+    #[serde(default)]
+    expires_at: Option<String>,
+}
+
+impl From<Announcement> for AnnouncementBody {
+    fn from(announcement: Announcement) -> Self {
+        Self {
+            message: announcement.message,
+            severity: announcement.severity,
+            expires_at: announcement
+                .expires_at
+                .and_then(|v| OffsetDateTime::from_unix_timestamp(v).ok())
+                .and_then(|v| v.format(&Rfc3339).ok()),
+        }
+    }
+}
+
+/// `GET /api/v1/admin/announcement`, returns the announcement shown on every page.
+pub async fn get_announcement(Extension(db): Extension<Arc<rocksdb::DB>>) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let announcement =
+            Announcement::open(&db)?.ok_or(Error::NotFound("No announcement is set".into()))?;
+
+        Ok(json(&AnnouncementBody::from(announcement)))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `PUT /api/v1/admin/announcement`, sets the announcement shown on every page.
+pub async fn put_announcement(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(pinned): Extension<Arc<PinnedPages>>,
+    body: Bytes,
+) -> Result<Response> {
+    let body: AnnouncementBody =
+        serde_json::from_slice(&body).map_err(|e| Error::BadRequest(e.to_string().into()))?;
+
+    if body.message.trim().is_empty() {
+        return Err(Error::BadRequest("Announcement message is empty".into()));
+    }
+
+    let expires_at = body
+        .expires_at
+        .as_deref()
+        .map(|v| OffsetDateTime::parse(v, &Rfc3339))
+        .transpose()
+        .map_err(|e| Error::BadRequest(format!("Invalid expires_at: {e}").into()))?
+        .map(OffsetDateTime::unix_timestamp);
+
+    let announcement = Announcement {
+        message: body.message,
+        severity: body.severity,
+        expires_at,
+    };
+
+    tokio::task::spawn_blocking(move || {
+        announcement.insert(&db)?;
+
+        pinned.index_updated();
+
+        Ok(json(&AnnouncementBody::from(announcement)))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `DELETE /api/v1/admin/announcement`, takes the announcement down.
+pub async fn delete_announcement(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(pinned): Extension<Arc<PinnedPages>>,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        Announcement::delete(&db)?;
+
+        pinned.index_updated();
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Deserialize)]
+pub struct PatchPreviewQuery {
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+#[derive(Serialize)]
+struct PatchPreviewResponse {
+    /// The commit the patch was applied to
+    commit: String,
+    /// Whether every file could be patched
+    applies: bool,
+    files: Vec<FilePreview>,
+}
+
+/// `POST /api/v1/admin/patch-preview/<repo>?h=<ref>`, applies the unified diff in the body to the
+/// given (or default) branch in memory, returning each file as it would be once patched along with
+/// the hunks that don't apply. Nothing is written to the repository.
+pub async fn preview_patch(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(scan_paths): Extension<Arc<ScanPaths>>,
+    Extension(git): Extension<Arc<Git>>,
+    Query(query): Query<PatchPreviewQuery>,
+    body: Bytes,
+) -> Result<Response> {
+    let diff = std::str::from_utf8(&body)
+        .map_err(|_| Error::BadRequest("Patch isn't valid UTF-8".into()))?;
+    let patch = patch::parse(diff).map_err(|e| Error::BadRequest(e.to_string().into()))?;
+
+    if patch.is_empty() {
+        return Err(Error::BadRequest("No changes found in patch".into()));
+    }
+
+    let path = tokio::task::spawn_blocking(move || indexed_path(&db, &repository))
+        .await
+        .context("Failed to join Tokio task")??;
+
+    let path = scan_paths
+        .resolve(path)
+        .ok_or_else(Error::repository_not_found)?;
+    let open_repo = git.repo(path, query.branch).await?;
+    let (commit, files) = open_repo.preview_patch(patch).await?;
+
+    Ok(json(&PatchPreviewResponse {
+        commit,
+        applies: files.iter().all(|file| file.conflicts.is_empty()),
+        files,
+    }))
+}
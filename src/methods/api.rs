@@ -0,0 +1,988 @@
+//! Read-only JSON API, mounted under `/api/v1`, along with the `/events` stream.
+
+use std::{borrow::Cow, collections::HashSet, convert::Infallible, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    bisect,
+    database::{
+        progress::{IndexEvent, IndexProgress},
+        schema::{
+            activity::ActivityIndex,
+            annotations::Annotations,
+            commit::{ArchivedAuthor, ArchivedCommit},
+            index_run::{IndexRunLog, INDEX_RUN_LOG_SIZE},
+            ref_update::{RefUpdateLog, REF_UPDATE_LOG_SIZE},
+            repository::{Repository, RepositoryId},
+        },
+    },
+    email::EmailDisplay,
+    git::{Commit, CommitUser},
+    methods::{
+        filters,
+        repo::{
+            log::{get_branch_commits, FilterQuery},
+            Error, Result,
+        },
+    },
+    reindex::SharedDb,
+    scan_path::ScanPaths,
+    schedule::{ScheduleStatus, Scheduler},
+    shutdown,
+    takedown::Takedowns,
+    trailers::Trailer,
+    url_scheme::author_url,
+    Git,
+};
+
+/// Serialises `value` as the body of a JSON response.
+pub fn json<T: Serialize>(value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            body,
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Cleans up a repository path given in a URL, refusing repositories that were taken down.
+fn requested_repository(takedowns: &Takedowns, repository: &str) -> Result<PathBuf> {
+    let relative = PathBuf::from(repository.trim_matches('/')).clean();
+
+    match takedowns.repository(&relative) {
+        Some(reason) => Err(Error::LegalReasons(reason.to_string().into())),
+        None => Ok(relative),
+    }
+}
+
+/// Resolves a repository path given in a URL to its location on disk, returning `None` if the
+/// repository isn't one we've indexed.
+fn resolve_repository(
+    db: &rocksdb::DB,
+    scan_paths: &ScanPaths,
+    takedowns: &Takedowns,
+    repository: &str,
+) -> Result<Option<PathBuf>> {
+    let relative = requested_repository(takedowns, repository)?;
+
+    if relative.as_os_str().is_empty()
+        || !crate::database::schema::repository::Repository::exists(db, &relative)?
+    {
+        return Ok(None);
+    }
+
+    Ok(scan_paths.resolve(relative))
+}
+
+#[derive(Serialize)]
+struct UserResponse<'a> {
+    name: &'a str,
+    /// Obscured or left out according to `--email-display`
+    email: Option<Cow<'a, str>>,
+    time: String,
+}
+
+impl<'a> From<&'a CommitUser> for UserResponse<'a> {
+    fn from(user: &'a CommitUser) -> Self {
+        Self {
+            name: user.name(),
+            email: EmailDisplay::current().apply(user.email()),
+            time: user.time().format(&Rfc3339).unwrap_or_default(),
+        }
+    }
+}
+
+impl<'a> From<&'a ArchivedAuthor> for UserResponse<'a> {
+    fn from(user: &'a ArchivedAuthor) -> Self {
+        Self {
+            name: &user.name,
+            email: EmailDisplay::current().apply(&user.email),
+            time: user.time().format(&Rfc3339).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CommitResponse<'a> {
+    oid: &'a str,
+    tree: &'a str,
+    parents: Vec<&'a str>,
+    author: UserResponse<'a>,
+    committer: UserResponse<'a>,
+    summary: &'a str,
+    body: &'a str,
+    trailers: Vec<TrailerResponse<'a>>,
+}
+
+#[derive(Serialize)]
+struct TrailerResponse<'a> {
+    key: &'a str,
+    value: Cow<'a, str>,
+    /// The full ID of the commit referenced by a `Fixes` trailer, if it could be resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit: Option<&'a str>,
+    /// The page listing the commits of the person named by the trailer, if addresses are shown
+    /// in full
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_url: Option<String>,
+}
+
+impl<'a> From<&'a Trailer> for TrailerResponse<'a> {
+    /// Shows the address of the person named by the trailer, if any, as `--email-display`
+    /// allows.
+    fn from(trailer: &'a Trailer) -> Self {
+        let value = match trailer.person() {
+            Some((name, email)) => match EmailDisplay::current().apply(email) {
+                Some(email) => Cow::Owned(format!("{name} <{email}>")),
+                None => Cow::Borrowed(name),
+            },
+            None => Cow::Borrowed(trailer.value.as_str()),
+        };
+
+        Self {
+            key: &trailer.key,
+            value,
+            commit: trailer.commit.as_deref(),
+            author_url: trailer
+                .person()
+                .filter(|_| crate::email::in_full())
+                .map(|(_, email)| author_url(email)),
+        }
+    }
+}
+
+impl<'a> From<&'a Commit> for CommitResponse<'a> {
+    fn from(commit: &'a Commit) -> Self {
+        Self {
+            oid: commit.oid(),
+            tree: commit.tree(),
+            parents: commit.parents().collect(),
+            author: commit.author().into(),
+            committer: commit.committer().into(),
+            summary: commit.summary(),
+            body: commit.body(),
+            trailers: commit
+                .trailers()
+                .iter()
+                .map(TrailerResponse::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CommitQuery {
+    id: Option<String>,
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+/// `/api/v1/commit/<repo>?id=<oid>`, returns the metadata of a commit, or the latest commit on
+/// the given (or default) branch if no ID is given.
+pub async fn commit(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(scan_paths): Extension<Arc<ScanPaths>>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<CommitQuery>,
+) -> Result<Response> {
+    let Some(repository_path) = resolve_repository(&db, &scan_paths, &takedowns, &repository)?
+    else {
+        return Err(Error::repository_not_found());
+    };
+
+    if let Some(reason) = query.id.as_deref().and_then(|id| takedowns.object(id)) {
+        return Err(Error::LegalReasons(reason.to_string().into()));
+    }
+
+    let open_repo = git.repo(repository_path, query.branch).await?;
+
+    let commit = if let Some(id) = query.id.as_deref() {
+        open_repo.commit(id, 0, false).await?
+    } else {
+        Arc::new(open_repo.latest_commit(0, false).await?)
+    };
+
+    Ok(json(&CommitResponse::from(&*commit)))
+}
+
+/// The most commits a bisection can be started between, marking a newer good commit narrows it
+/// down.
+const BISECT_MAX_COMMITS: usize = 100_000;
+
+#[derive(Deserialize)]
+pub struct BisectQuery {
+    /// The commit known to be bad
+    bad: String,
+    /// The commits known to be good, separated by commas
+    #[serde(default)]
+    good: String,
+    /// The commits that can't be tested, separated by commas
+    #[serde(default)]
+    skip: String,
+}
+
+/// `/api/v1/bisect/<repo>?bad=<rev>&good=<rev>,<rev>&skip=<oid>,<oid>`, works out the commits to
+/// test next to find the first bad commit between the good and bad ones, as `git bisect` would.
+pub async fn bisect(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(scan_paths): Extension<Arc<ScanPaths>>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<BisectQuery>,
+) -> Result<Response> {
+    let Some(repository_path) = resolve_repository(&db, &scan_paths, &takedowns, &repository)?
+    else {
+        return Err(Error::repository_not_found());
+    };
+
+    let split = |value: &str| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    let good = split(&query.good);
+    let skip = split(&query.skip)
+        .iter()
+        .map(|id| gix::ObjectId::from_hex(id.as_bytes()))
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|_| Error::BadRequest("Skipped commits must be given as full IDs".into()))?;
+
+    let open_repo = git.repo(repository_path, None).await?;
+    let Some(candidates) = open_repo
+        .bisect_candidates(query.bad, good, BISECT_MAX_COMMITS)
+        .await?
+    else {
+        return Err(Error::BadRequest(
+            format!("There are more than {BISECT_MAX_COMMITS} commits to bisect").into(),
+        ));
+    };
+
+    if candidates.is_empty() {
+        return Err(Error::BadRequest(
+            "The bad commit is an ancestor of a good commit".into(),
+        ));
+    }
+
+    let Some(bisection) = bisect::bisect(&candidates, &skip) else {
+        return Err(Error::BadRequest(
+            "There are too many merges between the good and bad commits to bisect".into(),
+        ));
+    };
+
+    Ok(json(&bisection))
+}
+
+const ACTIVITY_DEFAULT_LIMIT: usize = 50;
+const ACTIVITY_MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ActivityQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+/// A commit as stored in the index, which only holds a subset of a commit's metadata.
+#[derive(Serialize)]
+struct IndexedCommitResponse<'a> {
+    oid: String,
+    parents: Vec<String>,
+    summary: &'a str,
+    author: UserResponse<'a>,
+    committer: UserResponse<'a>,
+}
+
+impl<'a> From<&'a ArchivedCommit> for IndexedCommitResponse<'a> {
+    fn from(commit: &'a ArchivedCommit) -> Self {
+        Self {
+            oid: const_hex::encode(commit.hash),
+            parents: commit.parents.iter().map(const_hex::encode).collect(),
+            summary: &commit.summary,
+            author: (&commit.author).into(),
+            committer: (&commit.committer).into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ActivityCommitResponse<'a> {
+    repository: &'a str,
+    #[serde(flatten)]
+    commit: IndexedCommitResponse<'a>,
+}
+
+#[derive(Serialize)]
+struct ActivityResponse<'a> {
+    commits: Vec<ActivityCommitResponse<'a>>,
+    next_cursor: Option<String>,
+}
+
+/// `/api/v1/activity?cursor=<cursor>&limit=<n>`, returns the newest commits across every
+/// repository on the instance. Further pages are fetched by passing back `next_cursor`, which is
+/// `null` once the end of the index has been reached.
+pub async fn activity(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<Response> {
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(const_hex::decode)
+        .transpose()
+        .map_err(|_| Error::BadRequest("Invalid cursor".into()))?;
+
+    let limit = query
+        .limit
+        .unwrap_or(ACTIVITY_DEFAULT_LIMIT)
+        .clamp(1, ACTIVITY_MAX_LIMIT);
+
+    tokio::task::spawn_blocking(move || {
+        let index = ActivityIndex::new(db.clone());
+        let mut commits = Vec::with_capacity(limit + 1);
+        let mut after = cursor;
+
+        // the index isn't pruned when a repository is removed, nor does it know which are hidden,
+        // so skip over their commits here, fetching more until the page is full
+        'fetch: loop {
+            let chunk = index.fetch_latest(after.as_deref(), limit + 1)?;
+            let exhausted = chunk.len() < limit + 1;
+
+            if let Some((key, _)) = chunk.last() {
+                after = Some(key.to_vec());
+            }
+
+            for (key, commit) in chunk {
+                let v = commit.get();
+                if is_public(&db, &takedowns, &v.repository)
+                    && takedowns.allows(&v.repository, &v.commit.hash)
+                {
+                    commits.push((key, commit));
+
+                    if commits.len() > limit {
+                        break 'fetch;
+                    }
+                }
+            }
+
+            if exhausted {
+                break;
+            }
+        }
+
+        let next_cursor = if commits.len() > limit {
+            commits.truncate(limit);
+            commits.last().map(|(key, _)| const_hex::encode(key))
+        } else {
+            None
+        };
+
+        Ok(json(&ActivityResponse {
+            commits: commits
+                .iter()
+                .map(|(_, v)| {
+                    let v = v.get();
+
+                    ActivityCommitResponse {
+                        repository: &v.repository,
+                        commit: (&v.commit).into(),
+                    }
+                })
+                .collect(),
+            next_cursor,
+        }))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+const LOG_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    #[serde(rename = "h")]
+    branch: Option<String>,
+    #[serde(rename = "ofs")]
+    offset: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct LogResponse<'a> {
+    commits: Vec<IndexedCommitResponse<'a>>,
+    next_offset: Option<u64>,
+}
+
+/// `/api/v1/log/<repo>?h=<branch>&ofs=<offset>`, lists the commits on the given (or default)
+/// branch newest first, accepting the same `author`, `committer`, `since` and `until` filters as
+/// the log page.
+pub async fn log(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<LogQuery>,
+    Query(filter_query): Query<FilterQuery>,
+) -> Result<Response> {
+    let filter = filter_query
+        .parse()
+        .map_err(|e| Error::BadRequest(e.into()))?;
+
+    let relative = requested_repository(&takedowns, &repository)?;
+
+    tokio::task::spawn_blocking(move || {
+        let Some(repository) = Repository::open(&db, relative)? else {
+            return Err(Error::repository_not_found());
+        };
+
+        let offset = query.offset.unwrap_or(0);
+        let mut commits = get_branch_commits(
+            &repository,
+            &db,
+            query.branch.as_deref(),
+            &filter,
+            LOG_PAGE_SIZE + 1,
+            offset,
+        )?;
+
+        let next_offset = if commits.len() as u64 > LOG_PAGE_SIZE {
+            commits.pop();
+            Some(offset + LOG_PAGE_SIZE)
+        } else {
+            None
+        };
+
+        Ok(json(&LogResponse {
+            commits: commits.iter().map(|v| v.get().into()).collect(),
+            next_offset,
+        }))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Serialize)]
+struct RewriteResponse<'a> {
+    reference: &'a str,
+    old: String,
+    new: String,
+    detected_at: String,
+}
+
+/// `/api/v1/rewrites/<repo>`, lists the branches the indexer found to have been force-pushed or
+/// otherwise rewritten, newest first, with the commits they moved between.
+pub async fn rewrites(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+) -> Result<Response> {
+    let relative = requested_repository(&takedowns, &repository)?;
+
+    tokio::task::spawn_blocking(move || {
+        let Some(repository) = Repository::open(&db, relative)? else {
+            return Err(Error::repository_not_found());
+        };
+
+        let rewrites = RefUpdateLog::new(db, RepositoryId(repository.get().id.0.to_native()))
+            .fetch_forced(REF_UPDATE_LOG_SIZE)?;
+
+        let response: Vec<_> = rewrites
+            .iter()
+            .filter_map(|rewrite| {
+                let rewrite = rewrite.get();
+                let (old, new) = rewrite.forced_range()?;
+
+                Some(RewriteResponse {
+                    reference: &rewrite.reference,
+                    old: const_hex::encode(old),
+                    new: const_hex::encode(new),
+                    detected_at: rewrite.observed_at().format(&Rfc3339).unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        Ok(json(&response))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// The number of completions returned at most.
+const COMPLETION_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+pub struct CompletionQuery {
+    /// The prefix to complete
+    #[serde(default)]
+    q: String,
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+#[derive(Serialize)]
+struct RefCompletion<'a> {
+    name: &'a str,
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct RefCompletionResponse<'a> {
+    refs: Vec<RefCompletion<'a>>,
+}
+
+/// `/api/v1/complete/refs/<repo>?q=<prefix>`, lists the branches and tags whose names start with
+/// the given prefix, as of the last time the repository was indexed.
+pub async fn complete_refs(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<CompletionQuery>,
+) -> Result<Response> {
+    let relative = requested_repository(&takedowns, &repository)?;
+
+    tokio::task::spawn_blocking(move || {
+        let Some(repository) = Repository::open(&db, relative)? else {
+            return Err(Error::repository_not_found());
+        };
+
+        let default_branch = repository
+            .get()
+            .default_branch
+            .as_deref()
+            .and_then(|v| v.strip_prefix("refs/heads/"));
+
+        let heads = repository.get().heads(&db)?;
+        let mut refs: Vec<_> = heads
+            .iter()
+            .flat_map(|heads| heads.get().0.as_slice())
+            .filter_map(|head| {
+                if let Some(name) = head.name.strip_prefix("refs/heads/") {
+                    Some(RefCompletion {
+                        name,
+                        kind: "branch",
+                    })
+                } else {
+                    head.name
+                        .strip_prefix("refs/tags/")
+                        .map(|name| RefCompletion { name, kind: "tag" })
+                }
+            })
+            .filter(|v| v.name.starts_with(&query.q))
+            .collect();
+
+        // the default branch is the one most likely wanted, so it's offered first
+        refs.sort_by_key(|v| v.kind != "branch" || Some(v.name) != default_branch);
+        refs.truncate(COMPLETION_LIMIT);
+
+        Ok(json(&RefCompletionResponse { refs }))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Serialize)]
+struct PathCompletionResponse<'a> {
+    tree: String,
+    paths: &'a [String],
+}
+
+/// `/api/v1/complete/paths/<repo>?h=<branch>&q=<prefix>`, lists the files on the given (or
+/// default) branch whose paths start with the given prefix, from the same cached listing as the
+/// tree view's file finder.
+pub async fn complete_paths(
+    Path(repository): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(scan_paths): Extension<Arc<ScanPaths>>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<CompletionQuery>,
+) -> Result<Response> {
+    let Some(repository_path) = resolve_repository(&db, &scan_paths, &takedowns, &repository)?
+    else {
+        return Err(Error::repository_not_found());
+    };
+
+    let open_repo = git.repo(repository_path, query.branch).await?;
+    let (tree, files) = open_repo.tree_index().await?;
+
+    // the listing is sorted, so everything sharing the prefix sits together
+    let prefix = query.q.trim_start_matches('/');
+    let start = files.partition_point(|v| v.as_str() < prefix);
+    let len = files[start..]
+        .iter()
+        .take(COMPLETION_LIMIT)
+        .take_while(|v| v.starts_with(prefix))
+        .count();
+
+    Ok(json(&PathCompletionResponse {
+        tree: tree.to_string(),
+        paths: &files[start..start + len],
+    }))
+}
+
+#[derive(Serialize)]
+struct RepositoryResponse<'a> {
+    path: &'a str,
+    name: &'a str,
+    description: Option<&'a str>,
+    owner: Option<&'a str>,
+    homepage: Option<&'a str>,
+    links: Vec<LinkResponse<'a>>,
+    last_modified: String,
+    commit_count: u64,
+    /// Bytes taken up by the repository's git directory
+    size: Option<u64>,
+    object_count: Option<u64>,
+    latest_commit: Option<LatestCommitResponse<'a>>,
+}
+
+#[derive(Serialize)]
+struct LinkResponse<'a> {
+    label: &'a str,
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct LatestCommitResponse<'a> {
+    oid: String,
+    summary: &'a str,
+    author: &'a str,
+    time: String,
+}
+
+/// `/api/v1/repositories`, lists the repositories shown on the index page along with the length
+/// and newest commit of their default branch, and how much they take up on disk.
+pub async fn repositories(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let annotations = Annotations::fetch_all(&db)?;
+        let repositories = Repository::fetch_all(&db)?;
+
+        let response: Vec<_> = repositories
+            .iter()
+            .filter(|(path, repository)| {
+                !repository.get().hidden
+                    && !annotations.get(*path).is_some_and(|v| v.hidden)
+                    && takedowns.repository(path).is_none()
+            })
+            .map(|(path, repository)| {
+                let repository = repository.get();
+
+                RepositoryResponse {
+                    path,
+                    name: &repository.name,
+                    description: annotations
+                        .get(path)
+                        .and_then(|v| v.description.as_deref())
+                        .or(repository.description.as_deref()),
+                    owner: repository.owner.as_deref(),
+                    homepage: repository.homepage.as_deref(),
+                    links: repository
+                        .links
+                        .iter()
+                        .map(|v| LinkResponse {
+                            label: &v.label,
+                            url: &v.url,
+                        })
+                        .collect(),
+                    last_modified: filters::format_time(&repository.last_modified)
+                        .unwrap_or_default(),
+                    commit_count: repository.commit_count.to_native(),
+                    size: repository.size.as_ref().map(|v| v.bytes.to_native()),
+                    object_count: repository.size.as_ref().map(|v| v.objects.to_native()),
+                    latest_commit: repository.latest_commit().map(|v| LatestCommitResponse {
+                        oid: const_hex::encode(v.hash),
+                        summary: &v.summary,
+                        author: &v.author,
+                        time: v.time().format(&Rfc3339).unwrap_or_default(),
+                    }),
+                }
+            })
+            .collect();
+
+        Ok(json(&response))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    schedule: ScheduleStatus,
+}
+
+/// `/api/v1/health`, reports when the indexer last ran and when it, and each repository with its
+/// own refresh interval, is next due.
+pub async fn health(Extension(scheduler): Extension<Arc<Scheduler>>) -> Response {
+    json(&HealthResponse {
+        status: "ok",
+        schedule: scheduler.status(),
+    })
+}
+
+/// The number of index run reports returned when no limit is given.
+const INDEX_RUNS_DEFAULT_LIMIT: usize = 20;
+
+#[derive(Deserialize)]
+pub struct IndexRunsQuery {
+    limit: Option<usize>,
+    #[serde(rename = "ofs")]
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct IndexRunPhaseResponse<'a> {
+    name: &'a str,
+    duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct IndexRunResponse<'a> {
+    id: &'a str,
+    started_at: String,
+    duration_ms: u64,
+    repositories_scanned: u64,
+    repositories_added: u64,
+    repositories_removed: u64,
+    commits_ingested: u64,
+    errors: u64,
+    interrupted: bool,
+    phases: Vec<IndexRunPhaseResponse<'a>>,
+}
+
+/// `/api/v1/index-runs?limit=<n>&ofs=<offset>`, reports on the most recent index runs, newest
+/// first, with what each of them found and how long each of its phases took.
+pub async fn index_runs(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Query(query): Query<IndexRunsQuery>,
+) -> Result<Response> {
+    let limit = query
+        .limit
+        .unwrap_or(INDEX_RUNS_DEFAULT_LIMIT)
+        .clamp(1, INDEX_RUN_LOG_SIZE);
+
+    tokio::task::spawn_blocking(move || {
+        let runs = IndexRunLog::new(db).fetch_latest(limit, query.offset.unwrap_or(0))?;
+
+        let response: Vec<_> = runs
+            .iter()
+            .map(|run| {
+                let run = run.get();
+
+                IndexRunResponse {
+                    id: &run.id,
+                    started_at: run.started_at().format(&Rfc3339).unwrap_or_default(),
+                    duration_ms: run.duration_ms.to_native(),
+                    repositories_scanned: run.repositories_scanned.to_native(),
+                    repositories_added: run.repositories_added.to_native(),
+                    repositories_removed: run.repositories_removed.to_native(),
+                    commits_ingested: run.commits_ingested.to_native(),
+                    errors: run.errors.to_native(),
+                    interrupted: run.interrupted,
+                    phases: run
+                        .phases
+                        .iter()
+                        .map(|phase| IndexRunPhaseResponse {
+                            name: &phase.name,
+                            duration_ms: phase.duration_ms.to_native(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Ok(json(&response))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// `/api/v1/index/events`, a stream of server-sent events announcing repositories as the indexer
+/// ingests them for the first time. Each `ready` event carries the path of a repository, and a
+/// `lagged` event is sent if the subscriber fell behind and missed some. Hidden and taken down
+/// repositories aren't announced.
+pub async fn index_events(
+    Extension(shared_db): Extension<Arc<SharedDb>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Extension(progress): Extension<Arc<IndexProgress>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = futures_util::stream::unfold(progress.subscribe(), move |mut ready| {
+        let shared_db = shared_db.clone();
+        let takedowns = takedowns.clone();
+
+        async move {
+            loop {
+                let path = match ready.recv().await {
+                    Ok(path) => path,
+                    Err(RecvError::Lagged(_)) => {
+                        return Some((Ok(Event::default().event("lagged").data("")), ready));
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+
+                let shared_db = shared_db.clone();
+                let takedowns = takedowns.clone();
+                let repository = path.clone();
+                let public = tokio::task::spawn_blocking(move || {
+                    is_public(&shared_db.get(), &takedowns, &repository)
+                })
+                .await
+                .unwrap_or_default();
+
+                if public {
+                    return Some((Ok(Event::default().event("ready").data(&*path)), ready));
+                }
+            }
+        }
+    });
+
+    // the stream would otherwise hold the connection open through a graceful shutdown
+    Sse::new(events.take_until(shutdown::wait())).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Only sends events about the repository at this path
+    repository: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RefChangedEvent<'a> {
+    repository: &'a str,
+    reference: &'a str,
+    kind: &'static str,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RepositoryUpdatedEvent<'a> {
+    repository: &'a str,
+}
+
+/// `/events`, a stream of server-sent events announcing changes as the indexer picks them up,
+/// for tools that want to react to pushes without polling. A `ref-changed` event is sent for each
+/// reference created, moved or deleted, followed by a `repository-updated` event once every change
+/// to the repository in that index run has been sent. Both carry JSON naming the repository, and
+/// a `lagged` event is sent if the subscriber fell behind and missed some.
+///
+/// Repositories are only announced once they've been indexed for the first time, and hidden or
+/// taken down repositories aren't announced at all.
+pub async fn events(
+    Extension(shared_db): Extension<Arc<SharedDb>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Extension(progress): Extension<Arc<IndexProgress>>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let only = query
+        .repository
+        .map(|v| PathBuf::from(v.trim_matches('/')).clean());
+
+    // the stream can stay open for days, so it mustn't hold onto the database it was opened
+    // against and keep `--reindex-all` from releasing it
+    let events = futures_util::stream::unfold(progress.subscribe_events(), move |mut events| {
+        let shared_db = shared_db.clone();
+        let takedowns = takedowns.clone();
+        let only = only.clone();
+
+        async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        return Some((Ok(Event::default().event("lagged").data("")), events));
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+
+                let repository = event.repository().clone();
+
+                if only
+                    .as_deref()
+                    .is_some_and(|only| only != std::path::Path::new(&*repository))
+                {
+                    continue;
+                }
+
+                let shared_db = shared_db.clone();
+                let takedowns = takedowns.clone();
+                let public = tokio::task::spawn_blocking(move || {
+                    is_public(&shared_db.get(), &takedowns, &repository)
+                })
+                .await
+                .unwrap_or_default();
+
+                if !public {
+                    continue;
+                }
+
+                if let Some(event) = to_sse_event(&event) {
+                    return Some((Ok(event), events));
+                }
+            }
+        }
+    });
+
+    // the stream would otherwise hold the connection open through a graceful shutdown
+    Sse::new(events.take_until(shutdown::wait())).keep_alive(KeepAlive::default())
+}
+
+/// Whether a repository is listed on the index page, and so can be announced to anyone.
+pub fn is_public(db: &rocksdb::DB, takedowns: &Takedowns, path: &str) -> bool {
+    takedowns.repository(path).is_none()
+        && Repository::open(db, path)
+            .ok()
+            .flatten()
+            .is_some_and(|v| !v.get().hidden)
+        && !Annotations::open(db, path)
+            .ok()
+            .flatten()
+            .is_some_and(|v| v.hidden)
+}
+
+fn to_sse_event(event: &IndexEvent) -> Option<Event> {
+    let event =
+        match event {
+            IndexEvent::ReferenceChanged(change) => Event::default()
+                .event("ref-changed")
+                .json_data(RefChangedEvent {
+                    repository: &change.repository,
+                    reference: &change.reference,
+                    kind: change.kind.name(),
+                    old: change.old.map(const_hex::encode),
+                    new: change.new.map(const_hex::encode),
+                }),
+            IndexEvent::RepositoryUpdated { repository } => Event::default()
+                .event("repository-updated")
+                .json_data(RepositoryUpdatedEvent { repository }),
+        };
+
+    event.ok()
+}
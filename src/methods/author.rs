@@ -0,0 +1,172 @@
+//! Lists an author's commits across every repository on the instance.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{Path, Query},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Deserialize;
+
+use super::filters;
+use crate::{
+    database::schema::author::{AuthorIndex, YokedAuthoredCommit},
+    error::Error,
+    into_response,
+    methods::{
+        api::is_public,
+        feed::{FeedConfig, FeedValidators},
+    },
+    takedown::Takedowns,
+    url_scheme::absolute_url,
+};
+
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "ofs")]
+    offset: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "author.html")]
+pub struct View {
+    identity: String,
+    name: Option<String>,
+    commits: Vec<YokedAuthoredCommit>,
+    next_offset: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "author.xml")]
+pub struct FeedView {
+    identity: String,
+    name: Option<String>,
+    commits: Vec<YokedAuthoredCommit>,
+    updated: (i64, i32),
+    self_url: String,
+}
+
+/// Fetches an author's most recent commits, skipping over any from repositories that have since
+/// been removed from the index, hidden or taken down. Along with the commits, returns the offset of the
+/// last commit fetched so a page can start from it.
+fn fetch_commits(
+    db: &Arc<rocksdb::DB>,
+    takedowns: &Takedowns,
+    identity: &str,
+    amount: usize,
+    offset: usize,
+) -> anyhow::Result<(Vec<YokedAuthoredCommit>, usize)> {
+    const CHUNK_SIZE: usize = 250;
+
+    let index = AuthorIndex::new(db.clone());
+    let mut commits = Vec::with_capacity(amount);
+    let mut position = offset;
+
+    loop {
+        let chunk = index.fetch_latest(identity, CHUNK_SIZE, position)?;
+        let exhausted = chunk.len() < CHUNK_SIZE;
+
+        for commit in chunk {
+            let current = position;
+            position += 1;
+
+            let v = commit.get();
+            if is_public(db, takedowns, &v.repository)
+                && takedowns.allows(&v.repository, &v.commit.hash)
+            {
+                commits.push(commit);
+
+                if commits.len() == amount {
+                    return Ok((commits, current));
+                }
+            }
+        }
+
+        if exhausted {
+            return Ok((commits, position));
+        }
+    }
+}
+
+fn author_name(commits: &[YokedAuthoredCommit]) -> Option<String> {
+    commits
+        .first()
+        .map(|v| v.get().commit.author.name.to_string())
+}
+
+pub async fn handle(
+    Path(identity): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse, Error> {
+    tokio::task::spawn_blocking(move || {
+        let offset = query.offset.unwrap_or(0);
+
+        let (mut commits, next_offset) =
+            fetch_commits(&db, &takedowns, &identity, PAGE_SIZE + 1, offset)?;
+
+        let next_offset = if commits.len() > PAGE_SIZE {
+            commits.pop();
+            Some(next_offset)
+        } else {
+            None
+        };
+
+        Ok(into_response(View {
+            name: author_name(&commits),
+            identity: AuthorIndex::identity(&identity),
+            commits,
+            next_offset,
+        }))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+pub async fn handle_atom(
+    Path(identity): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(feed_config): Extension<FeedConfig>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    tokio::task::spawn_blocking(move || {
+        let identity = AuthorIndex::identity(&identity);
+        let amount = usize::try_from(feed_config.commits).unwrap_or(usize::MAX);
+        let (commits, _) = fetch_commits(&db, &takedowns, &identity, amount, 0)?;
+
+        let updated = commits.first().map_or_else(
+            || time::OffsetDateTime::UNIX_EPOCH,
+            |v| v.get().commit.committer.time(),
+        );
+
+        let mut key = Vec::new();
+        key.extend_from_slice(identity.as_bytes());
+        key.extend_from_slice(&feed_config.commits.to_be_bytes());
+        if let Some(commit) = commits.first() {
+            key.extend_from_slice(&commit.get().commit.hash);
+        }
+
+        let validators = FeedValidators::new(updated, &key);
+
+        Ok(validators.respond(
+            &headers,
+            FeedView {
+                name: author_name(&commits),
+                self_url: absolute_url(&format!("/author/{identity}/atom")),
+                identity,
+                commits,
+                updated: (updated.unix_timestamp(), updated.offset().whole_seconds()),
+            },
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
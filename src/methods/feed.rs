@@ -0,0 +1,151 @@
+//! Shared handling for the Atom feeds, including conditional requests so feed readers can poll
+//! cheaply.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use askama::Template;
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime, UtcOffset};
+use tracing::warn;
+use xxhash_rust::const_xxh3;
+
+use crate::url_scheme::{absolute_url, push_query_value, repository_page_url, repository_url};
+
+const USER_AGENT: &str = concat!("rgit/", clap::crate_version!());
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of entries to include in each feed, and how subscribers are notified of updates.
+#[derive(clap::Args, Debug, Clone)]
+pub struct FeedConfig {
+    /// The number of commits to include in a repository's commit feed
+    #[clap(long = "commit-feed-length", default_value_t = 50)]
+    pub commits: u64,
+    /// The number of tags to include in a repository's tag feed
+    #[clap(long = "tag-feed-length", default_value_t = 50)]
+    pub tags: usize,
+    /// The number of repositories to include in the feed of newly added repositories
+    #[clap(long = "repository-feed-length", default_value_t = 50)]
+    pub repositories: usize,
+    /// The public URL rgit is served from (eg. <https://git.example.com>), used to give feeds
+    /// absolute URLs
+    #[clap(long)]
+    pub public_url: Option<String>,
+    /// A `WebSub` hub to advertise in feeds and notify whenever they're updated
+    #[clap(long, requires = "public_url")]
+    pub websub_hub: Option<String>,
+}
+
+impl FeedConfig {
+    /// Pings the `WebSub` hub, if one is configured, for every commit feed that would have changed
+    /// as a result of the given references being updated.
+    pub fn publish(&self, updated_references: &BTreeMap<String, Vec<String>>) {
+        let Some(hub) = self.websub_hub.as_deref() else {
+            return;
+        };
+
+        for (repository, references) in updated_references {
+            let branches = references
+                .iter()
+                .filter_map(|v| v.strip_prefix("refs/heads/"))
+                .map(|branch| {
+                    let mut query = String::from("?h=");
+                    push_query_value(&mut query, branch);
+                    query
+                });
+
+            for query in std::iter::once(String::new()).chain(branches) {
+                let topic = absolute_url(&format!(
+                    "{}{query}",
+                    repository_page_url(repository_url(repository), "atom")
+                ));
+
+                let res = ureq::post(hub)
+                    .set("User-Agent", USER_AGENT)
+                    .timeout(PUBLISH_TIMEOUT)
+                    .send_form(&[("hub.mode", "publish"), ("hub.url", &topic)]);
+
+                if let Err(error) = res {
+                    warn!(%error, topic, "Failed to notify WebSub hub of update");
+                }
+            }
+        }
+    }
+}
+
+/// The `ETag` and `Last-Modified` validators for a feed.
+pub struct FeedValidators {
+    etag: String,
+    last_modified: String,
+}
+
+impl FeedValidators {
+    /// Builds the validators for a feed last updated at `updated`, `key` should contain
+    /// everything that determines the content of the feed (ie. the newest entry and the feed
+    /// length).
+    pub fn new(updated: OffsetDateTime, key: &[u8]) -> Self {
+        let last_modified = updated
+            .to_offset(UtcOffset::UTC)
+            .format(&Rfc2822)
+            .unwrap_or_default();
+
+        Self {
+            etag: format!("\"{:016x}\"", const_xxh3::xxh3_64(key)),
+            // HTTP dates are always given in GMT rather than as an offset
+            last_modified: last_modified.replace("+0000", "GMT"),
+        }
+    }
+
+    /// Whether the client already has the current version of the feed.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+        {
+            return if_none_match
+                .split(',')
+                .map(|v| v.trim().trim_start_matches("W/"))
+                .any(|v| v == "*" || v == self.etag);
+        }
+
+        // we don't parse dates, but clients send back the value we gave them
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .is_some_and(|v| v.as_bytes() == self.last_modified.as_bytes())
+    }
+
+    fn headers(&self) -> [(header::HeaderName, HeaderValue); 2] {
+        [
+            (
+                header::ETAG,
+                HeaderValue::try_from(&self.etag).expect("etag is a valid header value"),
+            ),
+            (
+                header::LAST_MODIFIED,
+                HeaderValue::try_from(&self.last_modified).expect("date is a valid header value"),
+            ),
+        ]
+    }
+
+    /// Renders the feed, or responds with `304 Not Modified` if the client's copy is current.
+    pub fn respond<T: Template>(&self, request_headers: &HeaderMap, feed: T) -> Response {
+        if self.matches(request_headers) {
+            return (StatusCode::NOT_MODIFIED, self.headers()).into_response();
+        }
+
+        match feed.render() {
+            Ok(body) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/atom+xml; charset=utf-8"),
+                )],
+                self.headers(),
+                body,
+            )
+                .into_response(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
@@ -4,12 +4,15 @@
 use std::{
     borrow::Borrow,
     collections::HashMap,
+    fmt::Display,
     sync::{Arc, LazyLock},
+    time::Duration,
 };
 
 use arc_swap::ArcSwap;
+use fluent_bundle::FluentValue;
 use rkyv::{
-    rend::{i32_le, i64_le},
+    rend::{i32_le, i64_le, u64_le},
     tuple::ArchivedTuple2,
 };
 use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
@@ -24,17 +27,126 @@ pub fn format_time(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
         .map_err(askama::Error::Custom)
 }
 
+pub fn repository_url(relative_path: &str) -> Result<&str, askama::Error> {
+    Ok(crate::url_scheme::repository_url(relative_path))
+}
+
+/// The path of one of a repository's pages, given the repository's URL, see
+/// [`crate::url_scheme::repository_page_url`].
+pub fn repository_page(repository_url: &str, page: &str) -> Result<String, askama::Error> {
+    Ok(crate::url_scheme::repository_page_url(repository_url, page))
+}
+
+/// The path of the page of a commit, given the repository's URL and the commit's raw hash.
+pub fn commit_url(repository_url: &str, id: &[u8]) -> Result<String, askama::Error> {
+    Ok(crate::url_scheme::commit_url(
+        repository_url,
+        &const_hex::encode(id),
+    ))
+}
+
+/// A path made absolute against the public URL rgit is served from, for links that leave the
+/// site such as those in feeds. Left as is if the public URL isn't configured.
+pub fn absolute_url(path: &str) -> Result<String, askama::Error> {
+    Ok(crate::url_scheme::absolute_url(path))
+}
+
+/// `value` percent-encoded for use in a query string.
+pub fn query_value(value: impl Display) -> Result<String, askama::Error> {
+    let mut out = String::new();
+    crate::url_scheme::push_query_value(&mut out, &value.to_string());
+    Ok(out)
+}
+
 pub fn branch_query(branch: Option<&str>) -> String {
+    let mut out = String::new();
+
     if let Some(b) = branch {
-        format!("?h={b}")
-    } else {
-        String::new()
+        out.push_str("?h=");
+        crate::url_scheme::push_query_value(&mut out, b);
     }
+
+    out
 }
 
 pub fn timeago(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
-    Ok(timeago::Formatter::new()
-        .convert((OffsetDateTime::now_utc() - s.into().0).try_into().unwrap()))
+    Ok(crate::i18n::time_ago(
+        (OffsetDateTime::now_utc() - s.into().0)
+            .try_into()
+            .unwrap_or_default(),
+    ))
+}
+
+/// How long something took (eg. `3 minutes`), as opposed to [`timeago`] which is for points in
+/// the past.
+pub fn duration(duration: &Duration) -> Result<String, askama::Error> {
+    Ok(crate::i18n::duration(*duration))
+}
+
+/// An absolute time, formatted for the locale the page is being rendered in and moved into the
+/// viewer's timezone if they've picked one.
+pub fn format_datetime(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
+    Ok(crate::i18n::format_datetime(
+        crate::layers::timezone::apply(s.into().0),
+    ))
+}
+
+/// Looks `key` up in the catalog of the locale the page is being rendered in, see
+/// [`crate::i18n`].
+pub fn t(key: &str) -> Result<String, askama::Error> {
+    Ok(crate::i18n::t(key))
+}
+
+/// Looks `key` up with `$count` set, for strings that have to agree with a number. Plural forms
+/// are picked by the catalog, following the rules of the locale.
+pub fn t_count(key: &str, count: impl Count) -> Result<String, askama::Error> {
+    Ok(crate::i18n::t_with(key, "count", count.value()))
+}
+
+pub fn t_arg(key: &str, name: &str, value: impl Display) -> Result<String, askama::Error> {
+    Ok(crate::i18n::t_with(key, name, value.to_string()))
+}
+
+pub fn t_arg2(
+    key: &str,
+    name: &str,
+    value: impl Display,
+    other_name: &str,
+    other_value: impl Display,
+) -> Result<String, askama::Error> {
+    Ok(crate::i18n::t_args(
+        key,
+        [
+            (name, value.to_string()),
+            (other_name, other_value.to_string()),
+        ],
+    ))
+}
+
+/// A size in bytes in the largest binary unit it's at least one of (eg. `1.5 MiB`).
+pub fn file_size(bytes: impl Size) -> Result<String, askama::Error> {
+    Ok(format_size(bytes.bytes()))
+}
+
+/// Formats a number of bytes in the largest binary unit it's at least one of, for use outside of
+/// templates.
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
 }
 
 pub fn file_perms(s: &u16) -> Result<String, askama::Error> {
@@ -45,6 +157,50 @@ pub fn hex(s: &[u8]) -> Result<String, askama::Error> {
     Ok(const_hex::encode(s))
 }
 
+/// The number of hex digits object IDs are abbreviated to.
+const ABBREVIATED_OID_LENGTH: usize = 12;
+
+/// The abbreviated form of a commit hash, for places where the full one would be too wide.
+pub fn short_hex(s: &[u8]) -> Result<String, askama::Error> {
+    Ok(const_hex::encode(
+        &s[..s.len().min(ABBREVIATED_OID_LENGTH / 2)],
+    ))
+}
+
+/// The abbreviated form of a hex encoded object ID, as [`short_hex`] gives for raw ones.
+pub fn short_oid(s: &str) -> Result<&str, askama::Error> {
+    Ok(abbreviate_oid(s))
+}
+
+/// The abbreviated form of a hex encoded object ID, for use outside of templates.
+pub fn abbreviate_oid(s: &str) -> &str {
+    s.get(..ABBREVIATED_OID_LENGTH).unwrap_or(s)
+}
+
+/// The name of a branch, tag or remote-tracking branch without the prefix of its namespace, as
+/// `git` shows them (eg. `main` for `refs/heads/main`).
+pub fn short_ref(name: &str) -> Result<&str, askama::Error> {
+    Ok(["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .into_iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name))
+}
+
+/// The `dir` and `lang` attributes for an element containing `text`, see
+/// [`crate::script::attributes`].
+pub fn script_attrs(text: &str) -> Result<&'static str, askama::Error> {
+    Ok(crate::script::attributes(text))
+}
+
+/// The email address of a person shown after their name, as ` <user@example.com>`, or nothing if
+/// addresses are hidden. See [`crate::email`].
+pub fn email_suffix(email: &str) -> Result<String, askama::Error> {
+    Ok(crate::email::EmailDisplay::current()
+        .apply(email)
+        .map(|v| format!(" <{v}>"))
+        .unwrap_or_default())
+}
+
 pub fn gravatar(email: &str) -> Result<&'static str, askama::Error> {
     static CACHE: LazyLock<ArcSwap<HashMap<&'static str, &'static str>>> =
         LazyLock::new(|| ArcSwap::new(Arc::new(HashMap::new())));
@@ -69,7 +225,60 @@ pub fn gravatar(email: &str) -> Result<&'static str, askama::Error> {
     Ok(url)
 }
 
-pub struct Timestamp(OffsetDateTime);
+/// A number a translated string can be made to agree with, however many references askama has
+/// wrapped it in.
+pub trait Count {
+    fn value(&self) -> FluentValue<'static>;
+}
+
+impl Count for u64 {
+    fn value(&self) -> FluentValue<'static> {
+        FluentValue::from(*self)
+    }
+}
+
+impl Count for usize {
+    fn value(&self) -> FluentValue<'static> {
+        FluentValue::from(*self)
+    }
+}
+
+impl Count for u64_le {
+    fn value(&self) -> FluentValue<'static> {
+        FluentValue::from(self.to_native())
+    }
+}
+
+impl<T: Count + ?Sized> Count for &T {
+    fn value(&self) -> FluentValue<'static> {
+        (**self).value()
+    }
+}
+
+/// A number of bytes to be shown as a size, however many references askama has wrapped it in.
+pub trait Size {
+    fn bytes(&self) -> u64;
+}
+
+impl Size for u64 {
+    fn bytes(&self) -> u64 {
+        *self
+    }
+}
+
+impl Size for u64_le {
+    fn bytes(&self) -> u64 {
+        self.to_native()
+    }
+}
+
+impl<T: Size + ?Sized> Size for &T {
+    fn bytes(&self) -> u64 {
+        (**self).bytes()
+    }
+}
+
+pub struct Timestamp(pub OffsetDateTime);
 
 impl From<&ArchivedTuple2<i64_le, i32_le>> for Timestamp {
     fn from(value: &ArchivedTuple2<i64_le, i32_le>) -> Self {
@@ -101,8 +310,205 @@ impl From<&(i64, i32)> for Timestamp {
     }
 }
 
+impl From<&i64_le> for Timestamp {
+    fn from(value: &i64_le) -> Self {
+        Self(OffsetDateTime::from_unix_timestamp(value.to_native()).unwrap())
+    }
+}
+
+impl From<&OffsetDateTime> for Timestamp {
+    fn from(value: &OffsetDateTime) -> Self {
+        Self(*value)
+    }
+}
+
 impl From<OffsetDateTime> for Timestamp {
     fn from(value: OffsetDateTime) -> Self {
         Self(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::OffsetDateTime;
+
+    // run outside of a request, so in the default locale with the default URL scheme and email
+    // display
+
+    const TIME: (i64, i32) = (1_700_000_000, 0);
+
+    #[test]
+    fn format_time() {
+        assert_eq!(super::format_time(TIME).unwrap(), "2023-11-14T22:13:20Z");
+        assert_eq!(
+            super::format_time((1_700_000_000, 3600)).unwrap(),
+            "2023-11-14T23:13:20+01:00"
+        );
+    }
+
+    #[test]
+    fn format_datetime() {
+        assert_eq!(
+            super::format_datetime(TIME).unwrap(),
+            "2023-11-14 22:13:20 +00:00"
+        );
+    }
+
+    #[test]
+    fn timeago() {
+        let now = OffsetDateTime::now_utc();
+
+        assert_eq!(super::timeago(now).unwrap(), "just now");
+        assert_eq!(
+            super::timeago(now - time::Duration::seconds(90)).unwrap(),
+            "1 minute ago"
+        );
+        assert_eq!(
+            super::timeago(now - time::Duration::days(3)).unwrap(),
+            "3 days ago"
+        );
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(
+            super::duration(&Duration::from_micros(1500)).unwrap(),
+            "1.5 milliseconds"
+        );
+        assert_eq!(
+            super::duration(&Duration::from_secs(1)).unwrap(),
+            "1 second"
+        );
+        assert_eq!(
+            super::duration(&Duration::from_secs(150)).unwrap(),
+            "2 minutes"
+        );
+        assert_eq!(
+            super::duration(&Duration::from_secs(2 * 86_400)).unwrap(),
+            "2 days"
+        );
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(super::repository_url("foo/bar.git").unwrap(), "foo/bar.git");
+        assert_eq!(super::repository_page("foo.git", "").unwrap(), "/foo.git");
+        assert_eq!(
+            super::repository_page("foo.git", "tags").unwrap(),
+            "/foo.git/tags"
+        );
+        assert_eq!(
+            super::commit_url("foo.git", &[0xab; 2]).unwrap(),
+            "/foo.git/commit/?id=abab"
+        );
+        assert_eq!(super::branch_query(Some("main")), "?h=main");
+        assert_eq!(super::branch_query(Some("a&b")), "?h=a%26b");
+        assert_eq!(super::branch_query(None), "");
+        assert_eq!(
+            super::query_value("src/a b&c.rs").unwrap(),
+            "src%2Fa%20b%26c.rs"
+        );
+    }
+
+    #[test]
+    fn absolute_url() {
+        crate::url_scheme::install_public_url(Some("https://git.example.com/"));
+
+        assert_eq!(
+            super::absolute_url("/foo.git/tags").unwrap(),
+            "https://git.example.com/foo.git/tags"
+        );
+    }
+
+    #[test]
+    fn translations() {
+        assert_eq!(super::t("nav-log").unwrap(), "log");
+        assert_eq!(super::t("no-such-key").unwrap(), "no-such-key");
+        assert_eq!(
+            super::t_arg("feed-commits", "repository", "foo").unwrap(),
+            "foo commits"
+        );
+        assert_eq!(
+            super::t_arg2("feed-commits-changing", "repository", "foo", "path", "src").unwrap(),
+            "foo commits changing src"
+        );
+    }
+
+    #[test]
+    fn t_count_pluralizes() {
+        assert_eq!(super::t_count("time-days-ago", 1_u64).unwrap(), "1 day ago");
+        assert_eq!(
+            super::t_count("time-days-ago", &2_usize).unwrap(),
+            "2 days ago"
+        );
+        assert_eq!(
+            super::t_count("duration-seconds", 0_u64).unwrap(),
+            "0 seconds"
+        );
+    }
+
+    #[test]
+    fn file_size() {
+        assert_eq!(super::file_size(512_u64).unwrap(), "512 B");
+        assert_eq!(super::file_size(&1536_u64).unwrap(), "1.5 KiB");
+        assert_eq!(super::file_size(3 * 1024 * 1024_u64).unwrap(), "3.0 MiB");
+    }
+
+    #[test]
+    fn file_perms() {
+        assert_eq!(super::file_perms(&0o100_644).unwrap(), "-rw-r--r--");
+        assert_eq!(super::file_perms(&0o040_755).unwrap(), "drwxr-xr-x");
+    }
+
+    #[test]
+    fn hashes() {
+        let hash = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+
+        assert_eq!(super::hex(&hash).unwrap(), "123456789abcdef0");
+        assert_eq!(super::short_hex(&hash).unwrap(), "123456789abc");
+        assert_eq!(super::short_hex(&hash[..2]).unwrap(), "1234");
+        assert_eq!(
+            super::short_oid("123456789abcdef0").unwrap(),
+            "123456789abc"
+        );
+        assert_eq!(super::short_oid("1234").unwrap(), "1234");
+    }
+
+    #[test]
+    fn short_ref() {
+        assert_eq!(super::short_ref("refs/heads/main").unwrap(), "main");
+        assert_eq!(super::short_ref("refs/tags/v1.0").unwrap(), "v1.0");
+        assert_eq!(
+            super::short_ref("refs/remotes/origin/main").unwrap(),
+            "origin/main"
+        );
+        assert_eq!(super::short_ref("HEAD").unwrap(), "HEAD");
+    }
+
+    #[test]
+    fn script_attrs() {
+        assert_eq!(super::script_attrs("hello").unwrap(), "");
+        assert_eq!(super::script_attrs("مرحبا").unwrap(), r#" dir="rtl""#);
+    }
+
+    #[test]
+    fn email_suffix() {
+        assert_eq!(
+            super::email_suffix("user@example.com").unwrap(),
+            " <user@example.com>"
+        );
+    }
+
+    #[test]
+    fn gravatar() {
+        let url = super::gravatar("").unwrap();
+
+        assert_eq!(
+            url,
+            "https://www.gravatar.com/avatar/d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert!(std::ptr::eq(url, super::gravatar("").unwrap()));
+    }
+}
@@ -1,29 +1,261 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
 use anyhow::Context;
 use askama::Template;
-use axum::{response::IntoResponse, Extension};
+use axum::{
+    extract::Query,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
 
 use super::filters;
 use crate::{
-    database::schema::repository::{Repository, YokedRepository},
+    database::schema::{
+        annotations::Annotations,
+        repository::{ArchivedRepository, Repository, YokedRepository},
+    },
     into_response,
+    methods::feed::{FeedConfig, FeedValidators},
+    takedown::Takedowns,
+    url_scheme::absolute_url,
 };
 
+/// The number of repositories listed in each of the index page's recent panels.
+const RECENT_REPOSITORIES: usize = 5;
+
+/// How repositories are grouped on the index page.
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexGrouping {
+    /// Group repositories by the directory they reside in.
+    #[default]
+    Path,
+    /// Group repositories by how recently they were last updated.
+    Activity,
+}
+
+/// Whether repositories on the index page can be expanded to show their newest few commits.
+#[derive(Debug, Copy, Clone)]
+pub struct ShowRecentCommits(pub bool);
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    group: Option<IndexGrouping>,
+}
+
+pub struct IndexRow {
+    /// The path of the repository relative to the scan path, used for linking.
+    pub path: String,
+    /// The name to display for the repository.
+    pub display_name: String,
+    pub repository: YokedRepository,
+    /// Whether the indexer hasn't got to the repository's commits yet.
+    pub indexing: bool,
+    pub annotations: Annotations,
+}
+
+/// A repository listed in one of the index page's recent panels.
+pub struct RecentRepository {
+    pub path: String,
+    pub description: Option<String>,
+    pub time: (i64, i32),
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct View {
-    pub repositories: BTreeMap<Option<String>, Vec<YokedRepository>>,
+    pub repositories: Vec<(Option<String>, Vec<IndexRow>)>,
+    pub grouping: IndexGrouping,
+    pub show_recent_commits: bool,
+    pub recently_active: Vec<RecentRepository>,
+    pub recently_added: Vec<RecentRepository>,
+}
+
+#[derive(Template)]
+#[template(path = "index.xml")]
+pub struct FeedView {
+    pub repositories: Vec<RecentRepository>,
+    pub updated: (i64, i32),
+    pub self_url: String,
 }
 
 pub async fn handle(
     Extension(db): Extension<Arc<rocksdb::DB>>,
-) -> Result<impl IntoResponse, super::repo::Error> {
-    let mut repositories: BTreeMap<Option<String>, Vec<YokedRepository>> = BTreeMap::new();
+    Extension(default_grouping): Extension<IndexGrouping>,
+    Extension(ShowRecentCommits(show_recent_commits)): Extension<ShowRecentCommits>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse, crate::error::Error> {
+    let grouping = query.group.unwrap_or(default_grouping);
+
+    let (fetched, indexing, mut annotations) = tokio::task::spawn_blocking(move || {
+        let (fetched, annotations) = fetch_listed(&db, &takedowns)?;
+
+        let mut indexing = HashSet::new();
+        for (path, repository) in &fetched {
+            if !repository.get().is_indexed(&db)? {
+                indexing.insert(path.clone());
+            }
+        }
+
+        Ok::<_, anyhow::Error>((fetched, indexing, annotations))
+    })
+    .await
+    .context("Failed to join Tokio task")??;
+
+    let recently_active = most_recent(
+        &fetched,
+        |repository| {
+            Some((
+                repository.last_modified.0.to_native(),
+                repository.last_modified.1.to_native(),
+            ))
+        },
+        RECENT_REPOSITORIES,
+    );
+    let recently_added = most_recent(&fetched, first_indexed, RECENT_REPOSITORIES);
+
+    let (pinned, fetched) = fetched
+        .into_iter()
+        .partition::<BTreeMap<_, _>, _>(|(path, _)| {
+            annotations.get(path).is_some_and(|v| v.pinned)
+        });
+
+    let mut repositories = match grouping {
+        IndexGrouping::Path => group_by_path(fetched),
+        IndexGrouping::Activity => group_by_activity(fetched, OffsetDateTime::now_utc()),
+    };
+
+    if !pinned.is_empty() {
+        repositories.insert(0, (Some("pinned".to_string()), pinned_rows(pinned)));
+    }
+
+    for row in repositories.iter_mut().flat_map(|(_, rows)| rows) {
+        row.indexing = indexing.contains(&row.path);
+        row.annotations = annotations.remove(&row.path).unwrap_or_default();
+    }
+
+    Ok(into_response(View {
+        repositories,
+        grouping,
+        show_recent_commits,
+        recently_active,
+        recently_added,
+    }))
+}
+
+/// `/atom`, a feed of the repositories most recently added to the instance.
+pub async fn handle_atom(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(feed_config): Extension<FeedConfig>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+    headers: HeaderMap,
+) -> Result<Response, crate::error::Error> {
+    tokio::task::spawn_blocking(move || {
+        let (fetched, _) = fetch_listed(&db, &takedowns)?;
+
+        let repositories = most_recent(&fetched, first_indexed, feed_config.repositories);
+
+        let updated = repositories
+            .first()
+            .map_or(OffsetDateTime::UNIX_EPOCH, |v| {
+                filters::Timestamp::from(v.time).0
+            });
 
-    let fetched = tokio::task::spawn_blocking(move || Repository::fetch_all(&db))
-        .await
-        .context("Failed to join Tokio task")??;
+        let mut key = Vec::new();
+        key.extend_from_slice(&feed_config.repositories.to_be_bytes());
+        for repository in &repositories {
+            key.extend_from_slice(repository.path.as_bytes());
+        }
+
+        let validators = FeedValidators::new(updated, &key);
+
+        Ok(validators.respond(
+            &headers,
+            FeedView {
+                repositories,
+                updated: (updated.unix_timestamp(), updated.offset().whole_seconds()),
+                self_url: absolute_url("/atom"),
+            },
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// Fetches every repository listed on the index page, leaving out hidden ones, along with their
+/// annotations.
+fn fetch_listed(
+    db: &rocksdb::DB,
+) -> anyhow::Result<(
+    BTreeMap<String, YokedRepository>,
+    HashMap<String, Annotations>,
+)> {
+    let mut annotations = Annotations::fetch_all(db)?;
+    let mut fetched = Repository::fetch_all(db)?;
+    fetched.retain(|path, repository| {
+        !repository.get().hidden && !annotations.get(path).is_some_and(|v| v.hidden)
+    });
+    annotations.retain(|path, _| fetched.contains_key(path));
+
+    Ok((fetched, annotations))
+}
+
+fn first_indexed(repository: &ArchivedRepository) -> Option<(i64, i32)> {
+    let first_indexed = repository.first_indexed.as_ref()?;
+    Some((first_indexed.0.to_native(), first_indexed.1.to_native()))
+}
+
+fn recent(
+    path: &str,
+    repository: &ArchivedRepository,
+    time: impl Fn(&ArchivedRepository) -> Option<(i64, i32)>,
+) -> Option<RecentRepository> {
+    Some(RecentRepository {
+        path: path.to_string(),
+        description: repository.description.as_ref().map(ToString::to_string),
+        time: time(repository)?,
+    })
+}
+
+/// The `limit` repositories with the latest `time`, newest first.
+fn most_recent(
+    fetched: &BTreeMap<String, YokedRepository>,
+    time: impl Fn(&ArchivedRepository) -> Option<(i64, i32)>,
+    limit: usize,
+) -> Vec<RecentRepository> {
+    let mut recent = fetched
+        .iter()
+        .filter_map(|(path, repository)| recent(path, repository.get(), &time))
+        .collect::<Vec<_>>();
+    recent.sort_by(|a, b| b.time.0.cmp(&a.time.0));
+    recent.truncate(limit);
+    recent
+}
+
+fn pinned_rows(fetched: BTreeMap<String, YokedRepository>) -> Vec<IndexRow> {
+    fetched
+        .into_iter()
+        .map(|(k, v)| IndexRow {
+            display_name: k.clone(),
+            path: k,
+            repository: v,
+            indexing: false,
+            annotations: Annotations::default(),
+        })
+        .collect()
+}
+
+fn group_by_path(
+    fetched: BTreeMap<String, YokedRepository>,
+) -> Vec<(Option<String>, Vec<IndexRow>)> {
+    let mut repositories: BTreeMap<Option<String>, Vec<IndexRow>> = BTreeMap::new();
 
     for (k, v) in fetched {
         // TODO: fixme
@@ -31,9 +263,85 @@ pub async fn handle(
         split.pop();
         let key = Some(split.join("/")).filter(|v| !v.is_empty());
 
-        let k = repositories.entry(key).or_default();
-        k.push(v);
+        repositories.entry(key).or_default().push(IndexRow {
+            display_name: v.get().name.to_string(),
+            path: k,
+            repository: v,
+            indexing: false,
+            annotations: Annotations::default(),
+        });
     }
 
-    Ok(into_response(View { repositories }))
+    repositories.into_iter().collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ActivityBucket {
+    Today,
+    ThisWeek,
+    ThisMonth,
+    Older,
+}
+
+impl ActivityBucket {
+    fn new(now: OffsetDateTime, last_modified: OffsetDateTime) -> Self {
+        let age = now - last_modified;
+
+        if last_modified.to_offset(now.offset()).date() == now.date() {
+            Self::Today
+        } else if age < Duration::weeks(1) {
+            Self::ThisWeek
+        } else if age < Duration::days(30) {
+            Self::ThisMonth
+        } else {
+            Self::Older
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Today => "today",
+            Self::ThisWeek => "this week",
+            Self::ThisMonth => "this month",
+            Self::Older => "older",
+        }
+    }
+}
+
+fn group_by_activity(
+    fetched: BTreeMap<String, YokedRepository>,
+    now: OffsetDateTime,
+) -> Vec<(Option<String>, Vec<IndexRow>)> {
+    let mut repositories: BTreeMap<ActivityBucket, Vec<(OffsetDateTime, IndexRow)>> =
+        BTreeMap::new();
+
+    for (k, v) in fetched {
+        let last_modified = filters::Timestamp::from(&v.get().last_modified).0;
+
+        repositories
+            .entry(ActivityBucket::new(now, last_modified))
+            .or_default()
+            .push((
+                last_modified,
+                IndexRow {
+                    display_name: k.clone(),
+                    path: k,
+                    repository: v,
+                    indexing: false,
+                    annotations: Annotations::default(),
+                },
+            ));
+    }
+
+    repositories
+        .into_iter()
+        .map(|(bucket, mut rows)| {
+            rows.sort_by(|a, b| b.0.cmp(&a.0));
+
+            (
+                Some(bucket.label().to_string()),
+                rows.into_iter().map(|(_, row)| row).collect(),
+            )
+        })
+        .collect()
 }
@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod api;
+pub mod author;
+pub mod feed;
 pub mod filters;
 pub mod index;
 pub mod repo;
+pub mod short_link;
@@ -0,0 +1,167 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use askama::Template;
+use axum::{extract::Query, http::HeaderMap, response::Response, Extension};
+use rkyv::string::ArchivedString;
+use serde::Deserialize;
+
+use crate::{
+    database::schema::commit::YokedCommit,
+    error::NotFound,
+    git::CommitOrder,
+    linkify::{LinkRule, Linkifier},
+    methods::{
+        feed::{FeedConfig, FeedValidators},
+        filters,
+        repo::{
+            log::{get_branch_commits, normalise_path, FilterQuery},
+            Error, Repository, RepositoryPath, Result,
+        },
+    },
+    url_scheme::{absolute_url, push_query_value, repository_page_url},
+    Git,
+};
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "h")]
+    branch: Option<String>,
+    /// Only includes commits that changed the file or directory at this path
+    path: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/atom.xml")]
+pub struct View {
+    repo: Repository,
+    commits: Vec<YokedCommit>,
+    branch: Option<String>,
+    path: Option<String>,
+    filter_query: String,
+    updated: (i64, i32),
+    linkifier: Linkifier,
+    self_url: String,
+    hub: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
+    Extension(feed_config): Extension<FeedConfig>,
+    Query(query): Query<UriQuery>,
+    Query(filter_query): Query<FilterQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let path = normalise_path(query.path.as_deref());
+
+    let filter = filter_query
+        .parse()
+        .map_err(|e| Error::BadRequest(e.into()))?;
+    let filter_query = filter_query.to_query_string();
+
+    let walked = if let Some(path) = &path {
+        let open_repo = git
+            .repo(repository_path, query.branch.as_deref().map(Arc::from))
+            .await?;
+        let commits = open_repo
+            .walk_commits(
+                CommitOrder::Date,
+                Some(PathBuf::from(path)),
+                false,
+                filter.clone(),
+                usize::try_from(feed_config.commits).context("feed length out of range")?,
+                0,
+            )
+            .await?;
+
+        Some(commits)
+    } else {
+        None
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let commits = if let Some(walked) = walked {
+            walked
+                .into_iter()
+                .map(crate::database::schema::commit::Commit::into_yoked)
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            get_branch_commits(
+                &repository,
+                &db,
+                query.branch.as_deref(),
+                &filter,
+                feed_config.commits,
+                0,
+            )?
+        };
+
+        let updated = commits.first().map_or_else(
+            || filters::Timestamp::from(&repository.get().last_modified).0,
+            |commit| commit.get().committer.time(),
+        );
+
+        let mut key = Vec::new();
+        key.extend_from_slice(repo.as_os_str().as_encoded_bytes());
+        key.extend_from_slice(query.branch.as_deref().unwrap_or_default().as_bytes());
+        key.push(b'\0');
+        key.extend_from_slice(path.as_deref().unwrap_or_default().as_bytes());
+        key.push(b'\0');
+        key.extend_from_slice(filter_query.as_bytes());
+        key.extend_from_slice(&feed_config.commits.to_be_bytes());
+        if let Some(commit) = commits.first() {
+            key.extend_from_slice(&commit.get().hash);
+        }
+
+        let validators = FeedValidators::new(updated, &key);
+
+        let linkifier = Linkifier::new(
+            &repo.url(),
+            &link_rules,
+            repository
+                .get()
+                .link_rules
+                .iter()
+                .map(ArchivedString::as_str),
+        );
+
+        let mut self_url = absolute_url(&repository_page_url(&repo.url(), "atom"));
+        if let Some(branch) = &query.branch {
+            self_url.push_str("?h=");
+            push_query_value(&mut self_url, branch);
+        }
+        if let Some(path) = &path {
+            self_url.push(if query.branch.is_some() { '&' } else { '?' });
+            self_url.push_str("path=");
+            push_query_value(&mut self_url, path);
+        }
+        if !filter_query.is_empty() {
+            self_url.push(if self_url.contains('?') { '&' } else { '?' });
+            self_url.push_str(&filter_query);
+        }
+
+        Ok(validators.respond(
+            &headers,
+            View {
+                repo,
+                commits,
+                branch: query.branch,
+                path,
+                filter_query,
+                updated: (updated.unix_timestamp(), updated.offset().whole_seconds()),
+                linkifier,
+                self_url,
+                hub: feed_config.websub_hub.clone(),
+            },
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
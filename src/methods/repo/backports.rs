@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{extract::Query, response::IntoResponse, Extension};
+use serde::Deserialize;
+
+use crate::{
+    database::schema::commit::YokedCommit,
+    error::NotFound,
+    into_response,
+    methods::{
+        filters,
+        repo::{Error, Repository, Result, DEFAULT_BRANCHES},
+    },
+};
+
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "ofs")]
+    offset: Option<u64>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/backports.html")]
+pub struct View {
+    repo: Repository,
+    mainline: String,
+    /// The stable branches, in the order they're configured
+    stable_branches: Vec<String>,
+    rows: Vec<Row>,
+    next_offset: Option<u64>,
+    branch: Option<Arc<str>>,
+}
+
+/// A mainline commit, and its backport to each of the stable branches.
+pub struct Row {
+    commit: YokedCommit,
+    backports: Vec<Option<[u8; 20]>>,
+}
+
+/// Lists the commits on the mainline branch, newest first, alongside the commit backporting each
+/// of them to the stable branches the repository configures with `rgit.stableBranch`.
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let offset = query.offset.unwrap_or(0);
+
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let repository = repository.get();
+
+        if repository.stable_branches.is_empty() {
+            return Err(Error::NotFound(
+                "Repository doesn't track any stable branches".into(),
+            ));
+        }
+
+        let mainline = repository
+            .mainline_branch
+            .as_deref()
+            .or(repository.default_branch.as_deref())
+            .unwrap_or(DEFAULT_BRANCHES[0]);
+
+        let mut commits = repository
+            .commit_tree(db.clone(), mainline)
+            .fetch_latest(PAGE_SIZE as u64 + 1, offset)?;
+
+        let next_offset = if commits.len() > PAGE_SIZE {
+            commits.pop();
+            Some(offset + PAGE_SIZE as u64)
+        } else {
+            None
+        };
+
+        // merges never need backporting
+        commits.retain(|commit| !commit.get().is_merge());
+
+        let hashes: Vec<_> = commits.iter().map(|commit| commit.get().hash).collect();
+        let backports = repository
+            .stable_branches
+            .iter()
+            .map(|branch| {
+                repository
+                    .commit_tree(db.clone(), branch)
+                    .fetch_backports(&hashes)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let rows = commits
+            .into_iter()
+            .enumerate()
+            .map(|(i, commit)| Row {
+                commit,
+                backports: backports.iter().map(|branch| branch[i]).collect(),
+            })
+            .collect();
+
+        Ok(into_response(View {
+            repo,
+            mainline: mainline.to_string(),
+            stable_branches: repository
+                .stable_branches
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            rows,
+            next_offset,
+            branch: None,
+        }))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use super::{BlobId, Error, RepositoryPath, Result};
+use crate::git::{sniff_mime, Git};
+
+/// Blobs are content addressed, so once fetched they never need to be revalidated.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+pub async fn handle(
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(BlobId(oid)): Extension<BlobId>,
+    Extension(git): Extension<Arc<Git>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let etag = HeaderValue::try_from(format!("\"{oid}\"")).expect("oid is a valid header value");
+
+    if headers.get(header::IF_NONE_MATCH) == Some(&etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag),
+                (
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                ),
+            ],
+        )
+            .into_response());
+    }
+
+    let open_repo = git.repo(repository_path, None).await?;
+
+    if let Some(blob) = open_repo.clone().large_blob(oid).await? {
+        let content_type = blob.mime.unwrap_or("application/octet-stream");
+
+        return Ok((
+            [
+                (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+                (header::ETAG, etag),
+                (
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                ),
+            ],
+            blob,
+        )
+            .into_response());
+    }
+
+    let Some(data) = open_repo.blob(oid).await? else {
+        return Err(Error::NotFound("Blob not found".into()));
+    };
+
+    let content_type = sniff_mime(&data).unwrap_or("application/octet-stream");
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag),
+            (
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(CACHE_CONTROL),
+            ),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+        ],
+        data,
+    )
+        .into_response())
+}
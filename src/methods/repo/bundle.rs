@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    http::{header, Response},
+    Extension,
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    bundles::Bundles,
+    error::NotFound,
+    methods::repo::{Repository, Result},
+};
+
+/// Serves the bundle built for the repository by `--bundle-dir`.
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(bundles): Extension<Option<Arc<Bundles>>>,
+) -> Result<Response<Body>> {
+    let bundles = bundles.ok_or(NotFound("Bundles are not enabled"))?;
+
+    let file = tokio::fs::File::open(bundles.path(&repo))
+        .await
+        .map_err(|_| NotFound("Repository has no bundle"))?;
+    let size = file
+        .metadata()
+        .await
+        .context("Failed to read bundle metadata")?
+        .len();
+
+    let name = repo
+        .file_name()
+        .map(|v| v.to_string_lossy())
+        .unwrap_or_default();
+    let name = name.strip_suffix(".git").unwrap_or(&name);
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}.bundle\""),
+        )
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .context("failed to build response")?)
+}
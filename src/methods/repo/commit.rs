@@ -1,16 +1,32 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
+use anyhow::Context;
 use askama::Template;
-use axum::{extract::Query, response::IntoResponse, Extension};
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use gix::ObjectId;
+use rkyv::string::ArchivedString;
 use serde::Deserialize;
 
 use crate::{
-    git::{Commit, OpenRepository},
+    archive::{SnapshotConfig, SnapshotFormat, Snapshots},
+    database::schema::{
+        relation::{CommitRelations, RelationKind},
+        repository::RepositoryId,
+    },
+    error::NotFound,
+    git::{AmbiguousObject, Commit, FileDiff, OpenRepository, ResolvedCommit},
     into_response,
+    linkify::{LinkRule, Linkifier},
     methods::{
         filters,
-        repo::{Repository, RepositoryPath, Result},
+        repo::{CommitSpec, Error, Repository, RepositoryPath, Result},
     },
+    url_scheme::{commit_url, push_query_value},
     Git,
 };
 
@@ -22,6 +38,34 @@ pub struct View {
     pub branch: Option<Arc<str>>,
     pub dl_branch: Arc<str>,
     pub id: Option<String>,
+    pub linkifier: Linkifier,
+    pub snapshots: Snapshots,
+    pub changed_files: Vec<(Option<String>, Vec<ChangedFile>)>,
+    /// The commit's page on the forge the repository is mirrored from
+    pub upstream_url: Option<String>,
+    /// The commits that revert or fix this one
+    pub referenced_by: Vec<(RelationKind, String)>,
+    /// The parent the diff is against, counting from 1 like `<commit>^<n>`
+    pub parent: usize,
+}
+
+#[derive(Template)]
+#[template(path = "repo/ambiguous.html")]
+pub struct AmbiguousView {
+    pub repo: Repository,
+    pub branch: Option<Arc<str>>,
+    /// The abbreviated hash that was asked for
+    pub spec: Arc<str>,
+    pub candidates: Vec<AmbiguousObject>,
+}
+
+/// A file in the sidebar listing the files changed by the commit.
+pub struct ChangedFile {
+    /// The position of the file in the diff, for linking to it
+    pub index: usize,
+    pub name: String,
+    pub insertions: usize,
+    pub deletions: usize,
 }
 
 #[derive(Deserialize)]
@@ -29,38 +73,215 @@ pub struct UriQuery {
     pub id: Option<String>,
     #[serde(rename = "h")]
     pub branch: Option<Arc<str>>,
+    /// The parent to diff against, counting from 1. Merges are diffed against their first parent
+    /// unless another is picked
+    pub parent: Option<usize>,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(git): Extension<Arc<Git>>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
     Query(query): Query<UriQuery>,
-) -> Result<impl IntoResponse> {
+) -> Result<Response> {
+    let parent = query.parent.unwrap_or(1);
+
+    if parent == 0 {
+        return Err(Error::BadRequest("Parents are counted from 1".into()));
+    }
+
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
 
-    let (dl_branch, commit) = tokio::try_join!(
+    // anything but a full hash is resolved to one and redirected to, so each commit has the one
+    // URL
+    if let Some(id) = query
+        .id
+        .as_deref()
+        .filter(|v| ObjectId::from_hex(v.as_bytes()).is_err())
+    {
+        return resolve(repo, open_repo, Arc::from(id), &query).await;
+    }
+
+    let (dl_branch, commit, (linkifier, snapshot_formats, upstream_commit_url)) = tokio::try_join!(
         fetch_dl_branch(query.branch.clone(), open_repo.clone()),
-        fetch_commit(query.id.as_deref(), open_repo),
+        fetch_commit(query.id.as_deref(), parent - 1, open_repo),
+        load_repository_config(repo.clone(), db.clone(), link_rules),
     )?;
 
+    let referenced_by = fetch_referenced_by(repo.clone(), db, commit.oid()).await?;
+
+    let changed_files = group_by_directory(&commit.files);
+    let upstream_url =
+        upstream_commit_url.map(|template| template.replace("{commit}", commit.oid()));
+
     Ok(into_response(View {
         repo,
         commit,
         branch: query.branch,
         id: query.id,
         dl_branch,
-    }))
+        linkifier,
+        snapshots: Snapshots {
+            formats: snapshot_formats,
+            checksums: snapshot_config.checksums(),
+        },
+        changed_files,
+        upstream_url,
+        referenced_by,
+        parent,
+    })
+    .into_response())
+}
+
+/// `/<repo>/commit/<spec>`, redirects to the page of the commit named by an abbreviated hash, a
+/// branch or tag name, or a revspec such as `HEAD~3`.
+pub async fn handle_revspec(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(CommitSpec(spec)): Extension<CommitSpec>,
+    Query(query): Query<UriQuery>,
+) -> Result<Response> {
+    let open_repo = git.repo(repository_path, query.branch.clone()).await?;
+
+    resolve(repo, open_repo, spec, &query).await
+}
+
+/// Redirects to the canonical URL of the commit `spec` names, or lists the objects it could
+/// mean if it's an ambiguous abbreviation.
+async fn resolve(
+    repo: Repository,
+    open_repo: Arc<OpenRepository>,
+    spec: Arc<str>,
+    query: &UriQuery,
+) -> Result<Response> {
+    match open_repo.resolve_commit(spec.clone()).await? {
+        ResolvedCommit::Commit(id) => {
+            let location = resolved_location(&repo.url(), &id.to_string(), query);
+            Ok(Redirect::to(&location).into_response())
+        }
+        ResolvedCommit::Ambiguous(candidates) => Ok((
+            StatusCode::MULTIPLE_CHOICES,
+            into_response(AmbiguousView {
+                repo,
+                branch: query.branch.clone(),
+                spec,
+                candidates,
+            }),
+        )
+            .into_response()),
+    }
+}
+
+/// The page of the commit `id`, keeping the branch and parent it was asked for with.
+fn resolved_location(repository_url: &str, id: &str, query: &UriQuery) -> String {
+    let mut location = commit_url(repository_url, id);
+
+    if let Some(branch) = &query.branch {
+        location.push_str("&h=");
+        push_query_value(&mut location, branch);
+    }
+
+    if let Some(parent) = query.parent {
+        location.push_str("&parent=");
+        push_query_value(&mut location, &parent.to_string());
+    }
+
+    location
+}
+
+/// Groups the files changed by a commit by the directory they're in, with files in the root of
+/// the repository first.
+fn group_by_directory(files: &[FileDiff]) -> Vec<(Option<String>, Vec<ChangedFile>)> {
+    let mut directories: BTreeMap<Option<String>, Vec<ChangedFile>> = BTreeMap::new();
+
+    for (index, file) in files.iter().enumerate() {
+        let (directory, name) = match file.path.rsplit_once('/') {
+            Some((directory, name)) => (Some(directory.to_string()), name),
+            None => (None, file.path.as_str()),
+        };
+
+        directories.entry(directory).or_default().push(ChangedFile {
+            index,
+            name: name.to_string(),
+            insertions: file.insertions,
+            deletions: file.deletions,
+        });
+    }
+
+    directories.into_iter().collect()
+}
+
+async fn load_repository_config(
+    repo: Repository,
+    db: Arc<rocksdb::DB>,
+    link_rules: Arc<[LinkRule]>,
+) -> Result<(Linkifier, Vec<SnapshotFormat>, Option<String>)> {
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let repository = repository.get();
+
+        let linkifier = Linkifier::new(
+            &repo.url(),
+            &link_rules,
+            repository.link_rules.iter().map(ArchivedString::as_str),
+        );
+
+        let upstream_commit_url = repository
+            .mirror_status
+            .as_ref()
+            .and_then(|v| v.commit_url.as_ref())
+            .map(ToString::to_string);
+
+        Ok((
+            linkifier,
+            repository.snapshot_formats(),
+            upstream_commit_url,
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
+/// Fetches the commits the indexer has seen revert or fix the commit with the given ID.
+async fn fetch_referenced_by(
+    repo: Repository,
+    db: Arc<rocksdb::DB>,
+    oid: &str,
+) -> Result<Vec<(RelationKind, String)>> {
+    let Ok(target) = const_hex::decode_to_array::<_, 20>(oid) else {
+        return Ok(Vec::new());
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+
+        let relations = CommitRelations::new(db, RepositoryId(repository.get().id.0.to_native()))
+            .referring_to(target)?;
+
+        Ok(relations
+            .into_iter()
+            .map(|(kind, source)| (kind, const_hex::encode(source)))
+            .collect())
+    })
+    .await
+    .context("Failed to join Tokio task")?
 }
 
 async fn fetch_commit(
     commit_id: Option<&str>,
+    parent: usize,
     open_repo: Arc<OpenRepository>,
 ) -> Result<Arc<Commit>> {
     Ok(if let Some(commit) = commit_id {
-        open_repo.commit(commit, true).await?
+        open_repo.commit(commit, parent, true).await?
     } else {
-        Arc::new(open_repo.latest_commit(true).await?)
+        Arc::new(open_repo.latest_commit(parent, true).await?)
     })
 }
 
@@ -80,3 +301,35 @@ async fn fetch_dl_branch(
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{resolved_location, UriQuery};
+
+    #[test]
+    fn resolved_location_encodes_branch() {
+        let query = UriQuery {
+            id: None,
+            branch: Some("feature/a&b #1".into()),
+            parent: Some(2),
+        };
+
+        assert_eq!(
+            resolved_location("repo", "abc123", &query),
+            "/repo/commit/?id=abc123&h=feature%2Fa%26b%20%231&parent=2"
+        );
+    }
+
+    #[test]
+    fn resolved_location_is_a_valid_header() {
+        let query = UriQuery {
+            id: None,
+            branch: Some("\n".into()),
+            parent: None,
+        };
+
+        let location = resolved_location("repo", "abc123", &query);
+        assert_eq!(location, "/repo/commit/?id=abc123&h=%0A");
+        assert!(axum::http::HeaderValue::from_str(&location).is_ok());
+    }
+}
@@ -7,7 +7,6 @@ use axum::{
     response::{IntoResponse, Response},
     Extension,
 };
-use bytes::{BufMut, BytesMut};
 use clap::crate_version;
 use time::format_description::well_known::Rfc2822;
 
@@ -37,9 +36,9 @@ pub async fn handle(
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
     let commit = if let Some(commit) = query.id {
-        open_repo.commit(&commit, true).await?
+        open_repo.commit(&commit, 0, true).await?
     } else {
-        Arc::new(open_repo.latest_commit(true).await?)
+        Arc::new(open_repo.latest_commit(0, true).await?)
     };
 
     Ok(into_response(View {
@@ -56,9 +55,9 @@ pub async fn handle_plain(
 ) -> Result<Response> {
     let open_repo = git.repo(repository_path, query.branch).await?;
     let commit = if let Some(commit) = query.id {
-        open_repo.commit(&commit, false).await?
+        open_repo.commit(&commit, 0, false).await?
     } else {
-        Arc::new(open_repo.latest_commit(false).await?)
+        Arc::new(open_repo.latest_commit(0, false).await?)
     };
 
     let headers = [(
@@ -66,8 +65,16 @@ pub async fn handle_plain(
         HeaderValue::from_static("text/plain"),
     )];
 
-    let mut data = BytesMut::new();
+    let mut data = String::new();
+    write_patch(&mut data, &commit, "PATCH", "");
 
+    Ok((headers, data).into_response())
+}
+
+/// Writes `commit` as a `git format-patch` style email. `subject_prefix` is written within the
+/// brackets at the start of the subject and `extra_headers`, each terminated by a newline, directly
+/// after it.
+pub fn write_patch(data: &mut String, commit: &Commit, subject_prefix: &str, extra_headers: &str) {
     writeln!(data, "From {} Mon Sep 17 00:00:00 2001", commit.oid()).unwrap();
     writeln!(
         data,
@@ -76,28 +83,33 @@ pub async fn handle_plain(
         commit.author().email()
     )
     .unwrap();
+    writeln!(
+        data,
+        "Date: {}",
+        commit.author().time().format(&Rfc2822).unwrap()
+    )
+    .unwrap();
+    writeln!(data, "Subject: [{subject_prefix}] {}", commit.summary()).unwrap();
+    writeln!(data, "{extra_headers}").unwrap();
 
-    write!(data, "Date: ").unwrap();
-    let mut writer = data.writer();
-    commit
-        .author()
-        .time()
-        .format_into(&mut writer, &Rfc2822)
-        .unwrap();
-    let mut data = writer.into_inner();
-    writeln!(data).unwrap();
+    let body = commit.body().trim_end();
+    if !body.is_empty() {
+        writeln!(data, "{body}").unwrap();
 
-    writeln!(data, "Subject: [PATCH] {}\n", commit.summary()).unwrap();
+        if !commit.trailers().is_empty() {
+            writeln!(data).unwrap();
+        }
+    }
 
-    write!(data, "{}", commit.body()).unwrap();
+    for trailer in commit.trailers() {
+        writeln!(data, "{}: {}", trailer.key, trailer.value).unwrap();
+    }
 
     writeln!(data, "---").unwrap();
 
-    data.extend_from_slice(commit.diff_stats.as_bytes());
-    data.extend_from_slice(b"\n");
-    data.extend_from_slice(commit.diff.as_bytes());
+    data.push_str(&commit.diff_stats);
+    data.push('\n');
+    data.push_str(&commit.diff);
 
     writeln!(data, "--\nrgit {}", crate_version!()).unwrap();
-
-    Ok((headers, data.freeze()).into_response())
 }
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{extract::Query, response::IntoResponse, Extension};
+use serde::Deserialize;
+
+use crate::{
+    git::GrepResults,
+    into_response,
+    methods::{
+        filters,
+        repo::{Error, Repository, RepositoryPath, Result},
+    },
+    Git,
+};
+
+/// The longest search accepted, in bytes.
+const MAX_QUERY_LENGTH: usize = 256;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    q: Option<String>,
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/grep.html")]
+pub struct View {
+    repo: Repository,
+    query: String,
+    results: Option<GrepResults>,
+    branch: Option<Arc<str>>,
+}
+
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(git): Extension<Arc<Git>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    let needle = query.q.unwrap_or_default();
+
+    if needle.len() > MAX_QUERY_LENGTH {
+        return Err(Error::BadRequest("Search is too long".into()));
+    }
+
+    let results = if needle.is_empty() {
+        None
+    } else {
+        let open_repo = git.repo(repository_path, query.branch.clone()).await?;
+        Some(open_repo.grep(needle.clone()).await?)
+    };
+
+    Ok(into_response(View {
+        repo,
+        query: needle,
+        results,
+        branch: query.branch,
+    }))
+}
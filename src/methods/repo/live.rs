@@ -0,0 +1,211 @@
+use std::{convert::Infallible, path::Path, sync::Arc};
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::Query,
+    http::{header::CACHE_CONTROL, HeaderValue},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use rkyv::string::ArchivedString;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    database::{
+        progress::{IndexEvent, IndexProgress},
+        schema::{
+            commit::{CommitFilter, YokedCommit},
+            repository::YokedRepository,
+        },
+    },
+    error::NotFound,
+    into_response,
+    linkify::{LinkRule, Linkifier},
+    methods::{
+        filters,
+        repo::{
+            log::{get_branch_commits, resolve_branch},
+            Repository, Result,
+        },
+    },
+    shutdown,
+};
+
+/// Number of commits kept on the page, older ones fall off the bottom as new ones come in.
+const LIVE_LENGTH: u64 = 50;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "h")]
+    branch: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/live.html")]
+pub struct View {
+    repo: Repository,
+    branch: Option<String>,
+    commits: Vec<YokedCommit>,
+    linkifier: Linkifier,
+}
+
+#[derive(Template)]
+#[template(path = "repo/live_rows.html")]
+pub struct RowsView {
+    commits: Vec<YokedCommit>,
+    linkifier: Linkifier,
+}
+
+fn open(db: &rocksdb::DB, repo: &Repository) -> Result<YokedRepository> {
+    let repository = crate::database::schema::repository::Repository::open(db, &**repo)?
+        .ok_or(NotFound("Repository does not exist"))?;
+
+    Ok(repository)
+}
+
+fn latest_commits(
+    repo: &Repository,
+    repository: &YokedRepository,
+    db: &Arc<rocksdb::DB>,
+    link_rules: &[LinkRule],
+    branch: Option<&str>,
+) -> Result<(Vec<YokedCommit>, Linkifier)> {
+    let commits = get_branch_commits(
+        repository,
+        db,
+        branch,
+        &CommitFilter::default(),
+        LIVE_LENGTH,
+        0,
+    )?;
+
+    let linkifier = Linkifier::new(
+        &repo.url(),
+        link_rules,
+        repository
+            .get()
+            .link_rules
+            .iter()
+            .map(ArchivedString::as_str),
+    );
+
+    Ok((commits, linkifier))
+}
+
+/// The newest commits on a branch, refreshed in place as the indexer picks up new ones, for
+/// keeping a branch's history up on a wall display.
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let repository = open(&db, &repo)?;
+        let (commits, linkifier) = latest_commits(
+            &repo,
+            &repository,
+            &db,
+            &link_rules,
+            query.branch.as_deref(),
+        )?;
+
+        Ok(into_response(View {
+            repo,
+            branch: query.branch,
+            commits,
+            linkifier,
+        }))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
+
+/// `/live/rows`, the table rows of the live page on their own, fetched by the page whenever the
+/// branch moves so they're rendered in the viewer's language and timezone.
+pub async fn handle_rows(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let repository = open(&db, &repo)?;
+        let (commits, linkifier) = latest_commits(
+            &repo,
+            &repository,
+            &db,
+            &link_rules,
+            query.branch.as_deref(),
+        )?;
+
+        Ok((
+            [(CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+            into_response(RowsView { commits, linkifier }),
+        ))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
+
+/// `/live/events`, a stream of server-sent events sending an `updated` event whenever new commits
+/// are indexed on the branch. The event carries no data, the page fetches the rows again from
+/// [`handle_rows`].
+pub async fn handle_events(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(progress): Extension<Arc<IndexProgress>>,
+    Query(query): Query<UriQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    // subscribed before looking up the branch, so an update landing in between isn't missed
+    let updates = progress.subscribe_events();
+
+    let reference = {
+        let repo = repo.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let repository = open(&db, &repo)?;
+            resolve_branch(&repository, &db, query.branch.as_deref())
+        })
+        .await
+        .context("Failed to attach to tokio task")??
+        .ok_or(NotFound("Branch does not exist"))?
+    };
+
+    let repository = Arc::new(repo.0);
+    let reference: Arc<str> = Arc::from(reference);
+
+    let events = futures_util::stream::unfold(updates, move |mut updates| {
+        let repository = repository.clone();
+        let reference = reference.clone();
+
+        async move {
+            loop {
+                match updates.recv().await {
+                    Ok(IndexEvent::ReferenceChanged(change))
+                        if change.new.is_some()
+                            && Path::new(&*change.repository) == repository.as_path()
+                            && change.reference == reference =>
+                    {
+                        break;
+                    }
+                    Ok(_) => {}
+                    // any of the announcements we missed may have been for this branch
+                    Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+
+            Some((Ok(Event::default().event("updated").data("")), updates))
+        }
+    });
+
+    // the stream would otherwise hold the connection open through a graceful shutdown
+    Ok(Sse::new(events.take_until(shutdown::wait())).keep_alive(KeepAlive::default()))
+}
@@ -1,17 +1,31 @@
-use std::sync::Arc;
+use std::{borrow::Cow, path::PathBuf, sync::Arc};
 
 use anyhow::Context;
 use askama::Template;
-use axum::{extract::Query, response::IntoResponse, Extension};
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use rkyv::string::ArchivedString;
 use serde::Deserialize;
 
 use crate::{
-    database::schema::{commit::YokedCommit, repository::YokedRepository},
+    commit_graph::CommitGraph,
+    database::schema::{
+        author::AuthorIndex,
+        commit::{CommitFilter, YokedCommit},
+        repository::YokedRepository,
+    },
+    error::NotFound,
+    git::CommitOrder,
     into_response,
+    linkify::{LinkRule, Linkifier},
     methods::{
         filters,
-        repo::{Repository, Result, DEFAULT_BRANCHES},
+        repo::{Error, Repository, RepositoryPath, Result, DEFAULT_BRANCHES},
     },
+    Git,
 };
 
 #[derive(Deserialize)]
@@ -20,6 +34,104 @@ pub struct UriQuery {
     offset: Option<u64>,
     #[serde(rename = "h")]
     branch: Option<String>,
+    /// Walks the repository live to list commits in a particular order, rather than in the order
+    /// they were indexed in
+    order: Option<CommitOrder>,
+    /// Only lists commits that changed the file or directory at this path, walking the
+    /// repository live in date order if no other order is given
+    path: Option<String>,
+    /// Hides merge commits from the log when set to anything but `0`
+    #[serde(rename = "no-merges")]
+    no_merges: Option<String>,
+}
+
+/// Filters on who made a commit and when, accepted by the log, its feed and the JSON API.
+#[derive(Deserialize, Default)]
+pub struct FilterQuery {
+    /// Email address of the commit author
+    author: Option<String>,
+    /// Email address of the committer
+    committer: Option<String>,
+    /// Earliest commit date to include, either as `YYYY-MM-DD` or a unix timestamp
+    since: Option<String>,
+    /// Latest commit date to include, either as `YYYY-MM-DD` or a unix timestamp
+    until: Option<String>,
+}
+
+impl FilterQuery {
+    fn fields(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("author", self.author.as_deref()),
+            ("committer", self.committer.as_deref()),
+            ("since", self.since.as_deref()),
+            ("until", self.until.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, non_empty(value)?)))
+    }
+
+    /// Parses the query into a filter, returning an error message suitable for showing to the
+    /// user if any of the dates are malformed.
+    pub fn parse(&self) -> Result<CommitFilter, String> {
+        let date = |value: Option<&str>, end_of_day| {
+            non_empty(value)
+                .map(|value| {
+                    parse_date(value, end_of_day).ok_or_else(|| format!("Invalid date: {value}"))
+                })
+                .transpose()
+        };
+
+        Ok(CommitFilter {
+            author: non_empty(self.author.as_deref()).map(AuthorIndex::identity),
+            committer: non_empty(self.committer.as_deref()).map(AuthorIndex::identity),
+            since: date(self.since.as_deref(), false)?,
+            until: date(self.until.as_deref(), true)?,
+        })
+    }
+
+    /// Serialises the filter back into a query string (without a leading `?` or `&`) to carry
+    /// it across links.
+    pub fn to_query_string(&self) -> String {
+        let mut out = String::new();
+
+        for (key, value) in self.fields() {
+            if !out.is_empty() {
+                out.push('&');
+            }
+
+            out.push_str(key);
+            out.push('=');
+            crate::url_scheme::push_query_value(&mut out, value);
+        }
+
+        out
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Parses a date given to filter commits by, dates are taken to be in UTC and cover the whole day
+/// when `end_of_day` is set.
+fn parse_date(value: &str, end_of_day: bool) -> Option<i64> {
+    if let Ok(timestamp) = value.parse() {
+        return Some(timestamp);
+    }
+
+    let mut parts = value.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = time::Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let date = time::Date::from_calendar_date(year, month, day).ok()?;
+
+    let datetime = if end_of_day {
+        date.with_hms(23, 59, 59).ok()?
+    } else {
+        date.midnight()
+    };
+
+    Some(datetime.assume_utc().unix_timestamp())
 }
 
 #[derive(Template)]
@@ -29,78 +141,230 @@ pub struct View {
     commits: Vec<YokedCommit>,
     next_offset: Option<u64>,
     branch: Option<String>,
+    order: Option<CommitOrder>,
+    path: Option<String>,
+    no_merges: bool,
+    filter: FilterQuery,
+    /// The filter serialised for carrying across links, empty if no filter is applied
+    filter_query: String,
+    /// The graph column for each commit, empty if the graph isn't meaningful for the listing
+    graph: Vec<String>,
+    linkifier: Linkifier,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(git): Extension<Arc<Git>>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
     Query(query): Query<UriQuery>,
-) -> Result<impl IntoResponse> {
-    tokio::task::spawn_blocking(move || {
-        let offset = query.offset.unwrap_or(0);
+    Query(filter_query): Query<FilterQuery>,
+) -> Result<Response> {
+    let offset = query.offset.unwrap_or(0);
+    let path = normalise_path(query.path.as_deref());
+    let no_merges = query.no_merges.as_deref().is_some_and(|v| v != "0");
+
+    let filter = filter_query
+        .parse()
+        .map_err(|e| Error::BadRequest(e.into()))?;
+
+    let walked = if query.order.is_some() || path.is_some() {
+        let open_repo = git
+            .repo(repository_path, query.branch.as_deref().map(Arc::from))
+            .await?;
+        let commits = open_repo
+            .walk_commits(
+                query.order.unwrap_or(CommitOrder::Date),
+                path.as_ref().map(PathBuf::from),
+                no_merges,
+                filter.clone(),
+                101,
+                usize::try_from(offset).context("offset out of range")?,
+            )
+            .await?;
+
+        Some(commits)
+    } else {
+        None
+    };
 
+    tokio::task::spawn_blocking(move || {
         let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
-        let mut commits =
-            get_branch_commits(&repository, &db, query.branch.as_deref(), 101, offset)?;
+            .ok_or(NotFound("Repository does not exist"))?;
+        let (mut commits, next_offset) = if let Some(walked) = walked {
+            let commits = walked
+                .into_iter()
+                .map(crate::database::schema::commit::Commit::into_yoked)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            (commits, offset + 100)
+        } else if no_merges {
+            get_branch_commits_without_merges(
+                &repository,
+                &db,
+                query.branch.as_deref(),
+                &filter,
+                101,
+                offset,
+            )?
+        } else {
+            let commits = get_branch_commits(
+                &repository,
+                &db,
+                query.branch.as_deref(),
+                &filter,
+                101,
+                offset,
+            )?;
+            (commits, offset + 100)
+        };
 
         let next_offset = if commits.len() == 101 {
             commits.pop();
-            Some(offset + 100)
+            Some(next_offset)
         } else {
             None
         };
 
+        // the graph is drawn from parent links, which don't line up once commits are filtered out
+        let graph = if path.is_none() && !no_merges && filter.is_empty() {
+            let mut graph = CommitGraph::default();
+            commits
+                .iter()
+                .map(|commit| {
+                    let commit = commit.get();
+                    graph.push(&commit.hash, &commit.parents)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let linkifier = Linkifier::new(
+            &repo.url(),
+            &link_rules,
+            repository
+                .get()
+                .link_rules
+                .iter()
+                .map(ArchivedString::as_str),
+        );
+
         Ok(into_response(View {
             repo,
             commits,
             next_offset,
             branch: query.branch,
-        }))
+            order: query.order,
+            path,
+            no_merges,
+            filter_query: filter_query.to_query_string(),
+            filter: filter_query,
+            graph,
+            linkifier,
+        })
+        .into_response())
     })
     .await
     .context("Failed to attach to tokio task")?
 }
 
-pub fn get_branch_commits(
+/// Normalises a path given to filter commits by, returning `None` for the root of the repository.
+pub fn normalise_path(path: Option<&str>) -> Option<String> {
+    let path = path?.trim_matches('/');
+    (!path.is_empty()).then(|| path.to_string())
+}
+
+/// Fetches commits from the index, skipping over merge commits. Along with the commits, returns
+/// the offset of the last commit fetched so a page can start from it.
+fn get_branch_commits_without_merges(
     repository: &YokedRepository,
     database: &Arc<rocksdb::DB>,
     branch: Option<&str>,
-    amount: u64,
+    filter: &CommitFilter,
+    amount: usize,
     offset: u64,
-) -> Result<Vec<YokedCommit>> {
-    if let Some(reference) = branch {
-        let commit_tree = repository
-            .get()
-            .commit_tree(database.clone(), &format!("refs/heads/{reference}"));
-        let commit_tree = commit_tree.fetch_latest(amount, offset)?;
+) -> Result<(Vec<YokedCommit>, u64)> {
+    const CHUNK_SIZE: u64 = 250;
 
-        if !commit_tree.is_empty() {
-            return Ok(commit_tree);
-        }
+    let mut commits = Vec::with_capacity(amount);
+    let mut position = offset;
 
-        let tag_tree = repository
-            .get()
-            .commit_tree(database.clone(), &format!("refs/tags/{reference}"));
-        let tag_tree = tag_tree.fetch_latest(amount, offset)?;
+    loop {
+        let chunk = get_branch_commits(repository, database, branch, filter, CHUNK_SIZE, position)?;
+        let exhausted = (chunk.len() as u64) < CHUNK_SIZE;
+
+        for commit in chunk {
+            let current = position;
+            position += 1;
+
+            if !commit.get().is_merge() {
+                commits.push(commit);
 
-        return Ok(tag_tree);
+                if commits.len() == amount {
+                    return Ok((commits, current));
+                }
+            }
+        }
+
+        if exhausted {
+            return Ok((commits, position));
+        }
     }
+}
 
-    for branch in repository
+pub fn get_branch_commits(
+    repository: &YokedRepository,
+    database: &Arc<rocksdb::DB>,
+    branch: Option<&str>,
+    filter: &CommitFilter,
+    amount: u64,
+    offset: u64,
+) -> Result<Vec<YokedCommit>> {
+    let Some(reference) = resolve_branch(repository, database, branch)? else {
+        return Ok(vec![]);
+    };
+
+    Ok(repository
         .get()
-        .default_branch
-        .as_deref()
-        .into_iter()
-        .chain(DEFAULT_BRANCHES.into_iter())
-    {
-        let commit_tree = repository.get().commit_tree(database.clone(), branch);
-        let commits = commit_tree.fetch_latest(amount, offset)?;
+        .commit_tree(database.clone(), &reference)
+        .fetch_filtered(filter, amount, offset)?)
+}
+
+/// Finds the full name of the reference a branch or tag given by its short name points to, or
+/// the repository's default branch if none is given. Returns `None` if no commits have been
+/// indexed for it.
+pub fn resolve_branch(
+    repository: &YokedRepository,
+    database: &Arc<rocksdb::DB>,
+    branch: Option<&str>,
+) -> Result<Option<String>> {
+    let candidates: Vec<Cow<'_, str>> = if let Some(reference) = branch {
+        vec![
+            Cow::Owned(format!("refs/heads/{reference}")),
+            Cow::Owned(format!("refs/tags/{reference}")),
+        ]
+    } else {
+        repository
+            .get()
+            .default_branch
+            .as_deref()
+            .into_iter()
+            .chain(DEFAULT_BRANCHES)
+            .map(Cow::Borrowed)
+            .collect()
+    };
 
-        if !commits.is_empty() {
-            return Ok(commits);
+    for reference in candidates {
+        if repository
+            .get()
+            .commit_tree(database.clone(), &reference)
+            .len()?
+            > 0
+        {
+            return Ok(Some(reference.into_owned()));
         }
     }
 
-    Ok(vec![])
+    Ok(None)
 }
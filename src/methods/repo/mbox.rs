@@ -0,0 +1,171 @@
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    extract::Query,
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use clap::crate_version;
+use serde::Deserialize;
+use time::format_description::well_known::Rfc2822;
+
+use crate::{
+    git::PatchSeries,
+    http,
+    methods::{
+        filters::abbreviate_oid,
+        repo::{diff::write_patch, Repository, RepositoryPath, Result},
+    },
+    Git,
+};
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    /// The commit the series is based on, which isn't included in the export
+    from: String,
+    /// The tip of the series, if this is an annotated tag its message is used as the cover letter
+    to: Option<String>,
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+/// Exports a range of commits as an mbox of threaded patch emails that can be applied using
+/// `git am --patch-format=mboxrd`.
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(git): Extension<Arc<Git>>,
+    Query(query): Query<UriQuery>,
+) -> Result<Response> {
+    let open_repo = git.repo(repository_path, query.branch).await?;
+    let series = open_repo.patch_series(query.from, query.to).await?;
+
+    let file_name = repo
+        .file_name()
+        .map_or_else(|| "series".into(), |v| v.to_string_lossy());
+
+    let headers = [
+        (
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/mbox"),
+        ),
+        (
+            http::header::CONTENT_DISPOSITION,
+            HeaderValue::try_from(format!("attachment; filename=\"{file_name}.mbox\""))
+                .unwrap_or_else(|_| HeaderValue::from_static("attachment")),
+        ),
+    ];
+
+    Ok((headers, build_mbox(&series)).into_response())
+}
+
+fn build_mbox(series: &PatchSeries) -> String {
+    let total = series.patches.len();
+    let message_id = |n: usize| format!("<{}.{n}-{total}@rgit>", series.id);
+
+    let mut out = String::new();
+    let mut message = String::new();
+
+    // the cover letter comes from an annotated tag if we were given one, otherwise it's generated
+    // from the first commit in the series, but there's no need for one with a single patch
+    let has_cover_letter = series.cover_letter.is_some() || total > 1;
+
+    if has_cover_letter {
+        write_cover_letter(&mut message, series, &message_id(0));
+        push_mboxrd(&mut out, &message);
+    }
+
+    for (i, commit) in series.patches.iter().enumerate() {
+        let n = i + 1;
+
+        let subject_prefix = if total == 1 {
+            "PATCH".to_string()
+        } else {
+            format!("PATCH {n}/{total}")
+        };
+
+        let mut headers = format!("Message-ID: {}\n", message_id(n));
+        if has_cover_letter || n > 1 {
+            let parent = message_id(if has_cover_letter { 0 } else { 1 });
+            writeln!(headers, "In-Reply-To: {parent}\nReferences: {parent}").unwrap();
+        }
+
+        message.clear();
+        write_patch(&mut message, commit, &subject_prefix, &headers);
+        push_mboxrd(&mut out, &message);
+    }
+
+    out
+}
+
+fn write_cover_letter(out: &mut String, series: &PatchSeries, message_id: &str) {
+    let total = series.patches.len();
+
+    let (author, time, subject, body) = if let Some(cover) = &series.cover_letter {
+        let time = cover.author.time();
+        (
+            &cover.author,
+            time,
+            cover.subject.as_str(),
+            cover.body.as_str(),
+        )
+    } else {
+        let first = &series.patches[0];
+        (
+            first.author(),
+            first.committer().time(),
+            first.summary(),
+            "",
+        )
+    };
+
+    writeln!(out, "From {} Mon Sep 17 00:00:00 2001", series.id).unwrap();
+    writeln!(out, "From: {} <{}>", author.name(), author.email()).unwrap();
+    writeln!(out, "Date: {}", time.format(&Rfc2822).unwrap()).unwrap();
+    writeln!(out, "Subject: [PATCH 0/{total}] {subject}").unwrap();
+    writeln!(out, "Message-ID: {message_id}\n").unwrap();
+
+    if !body.is_empty() {
+        writeln!(out, "{body}\n").unwrap();
+    }
+
+    for commit in &series.patches {
+        writeln!(
+            out,
+            "{} ({}):",
+            commit.author().name(),
+            abbreviate_oid(commit.oid())
+        )
+        .unwrap();
+        writeln!(out, "  {}\n", commit.summary()).unwrap();
+    }
+
+    writeln!(out, "--\nrgit {}", crate_version!()).unwrap();
+}
+
+/// Appends a message to an mbox, quoting any lines in the message that look like the start of a
+/// new message as per the mboxrd format. Line endings are kept as they are, so patches to files
+/// with CRLF line endings still apply.
+fn push_mboxrd(out: &mut String, message: &str) {
+    let mut lines = message.split_inclusive('\n');
+
+    // the first line is our own `From ` separator
+    if let Some(separator) = lines.next() {
+        out.push_str(separator);
+    }
+
+    for line in lines {
+        if line.trim_start_matches('>').starts_with("From ") {
+            out.push('>');
+        }
+
+        out.push_str(line);
+    }
+
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+
+    out.push('\n');
+}
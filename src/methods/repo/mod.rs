@@ -1,16 +1,29 @@
 mod about;
+mod atom;
+mod backports;
+mod blob;
+mod bundle;
 mod commit;
 mod diff;
-mod log;
+mod grep;
+mod live;
+pub mod log;
+mod mbox;
+mod reflog;
 mod refs;
+mod rewrites;
 mod smart_git;
 mod snapshot;
 mod summary;
 mod tag;
+mod tags;
 mod tree;
+mod tree_index;
 
 use std::{
-    collections::BTreeMap,
+    borrow::Cow,
+    cmp::Ordering,
+    fmt,
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
@@ -19,39 +32,143 @@ use std::{
 use axum::{
     body::Body,
     handler::HandlerWithoutStateExt,
-    http::{Request, StatusCode},
-    response::{IntoResponse, Response},
+    http::{
+        header::{CACHE_CONTROL, VARY},
+        HeaderName, HeaderValue, Request, Uri,
+    },
+    response::{IntoResponse, Redirect, Response},
 };
 use path_clean::PathClean;
-use tower::{util::BoxCloneService, Service};
+use time::OffsetDateTime;
+use tower::{util::BoxCloneService, Layer, Service};
 
 use self::{
     about::handle as handle_about,
-    commit::handle as handle_commit,
+    atom::handle as handle_atom,
+    backports::handle as handle_backports,
+    blob::handle as handle_blob,
+    bundle::handle as handle_bundle,
+    commit::{handle as handle_commit, handle_revspec as handle_commit_revspec},
     diff::{handle as handle_diff, handle_plain as handle_patch},
+    grep::handle as handle_grep,
+    live::{
+        handle as handle_live, handle_events as handle_live_events, handle_rows as handle_live_rows,
+    },
     log::handle as handle_log,
+    mbox::handle as handle_mbox,
+    reflog::handle as handle_reflog,
     refs::handle as handle_refs,
+    rewrites::handle_atom as handle_rewrites_atom,
     smart_git::handle as handle_smart_git,
     snapshot::handle as handle_snapshot,
     summary::handle as handle_summary,
     tag::handle as handle_tag,
+    tags::{handle as handle_tags, handle_atom as handle_tags_atom},
     tree::handle as handle_tree,
+    tree_index::handle as handle_tree_index,
 };
 use crate::database::schema::tag::YokedString;
 use crate::{
-    database::schema::{commit::YokedCommit, tag::YokedTag},
-    layers::UnwrapInfallible,
+    activity::{Activity, ActivityHints},
+    archive::SnapshotFormat,
+    database::schema::{
+        repository::{ArchivedHead, ArchivedRepository, HeadCommit},
+        tag::YokedTag,
+    },
+    layers::{cors::Cors, UnwrapInfallible},
+    scan_path::ScanPaths,
+    takedown::Takedowns,
+    url_scheme::{repository_url, UrlConfig, UrlScheme},
 };
 
+pub use crate::error::{Error, Result};
+
 pub const DEFAULT_BRANCHES: [&str; 2] = ["refs/heads/master", "refs/heads/main"];
 
+/// The pages of a repository that can be kept from crawlers with `rgit.crawlerExclude`, by the
+/// first segment of their path after the repository.
+pub const REPOSITORY_VIEWS: [&str; 19] = [
+    "about",
+    "atom",
+    "backports",
+    "blob",
+    "bundle",
+    "commit",
+    "diff",
+    "grep",
+    "live",
+    "log",
+    "mbox",
+    "patch",
+    "reflog",
+    "refs",
+    "rewrites",
+    "snapshot",
+    "tag",
+    "tags",
+    "tree",
+];
+
+/// Repositories with more branches than this have their branch selector completed as the user
+/// types, rather than listing every branch up front.
+pub const BRANCH_SELECT_LIMIT: usize = 50;
+
+/// How branches are ordered on the refs page and in the branch selector. Whichever is picked, the
+/// default branch always comes first.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum BranchOrder {
+    /// Order branches alphabetically.
+    #[default]
+    Name,
+    /// Order branches by their newest commit, most recently committed to first.
+    LastCommit,
+}
+
+impl BranchOrder {
+    /// Parses the value of `rgit.branchOrder`.
+    pub fn parse(value: &str) -> Option<Self> {
+        <Self as clap::ValueEnum>::from_str(value.trim(), true).ok()
+    }
+
+    /// The order the repository asks for, falling back to `default` if it doesn't ask for one.
+    pub fn for_repository(repository: &ArchivedRepository, default: Self) -> Self {
+        repository
+            .branch_order
+            .as_deref()
+            .and_then(Self::parse)
+            .unwrap_or(default)
+    }
+
+    /// Sorts `branches` (pairs of a short branch name and the time of its newest commit) into
+    /// this order, moving `default_branch` to the front.
+    pub fn sort<T>(
+        self,
+        branches: &mut [(String, T)],
+        default_branch: Option<&str>,
+        last_commit: impl Fn(&T) -> i64,
+    ) {
+        branches.sort_by(|(a_name, a), (b_name, b)| {
+            let a_default = Some(a_name.as_str()) == default_branch;
+            let b_default = Some(b_name.as_str()) == default_branch;
+
+            b_default
+                .cmp(&a_default)
+                .then_with(|| match self {
+                    Self::Name => Ordering::Equal,
+                    Self::LastCommit => last_commit(b).cmp(&last_commit(a)),
+                })
+                .then_with(|| a_name.cmp(b_name))
+        });
+    }
+}
+
 // this is some wicked, wicked abuse of axum right here...
 #[allow(clippy::trait_duplication_in_bounds)] // clippy seems a bit.. lost
 pub async fn service(mut request: Request<Body>) -> Response {
-    let scan_path = request
+    let scan_paths = request
         .extensions()
-        .get::<Arc<PathBuf>>()
-        .expect("scan_path missing");
+        .get::<Arc<ScanPaths>>()
+        .expect("scan_paths missing");
 
     let mut uri_parts: Vec<&str> = request
         .uri()
@@ -62,6 +179,21 @@ pub async fn service(mut request: Request<Body>) -> Response {
         .collect();
 
     let mut child_path = None;
+    let mut blob_id = None;
+    let mut tag_name = None;
+    let mut commit_spec = None;
+    let mut snapshot_target = None;
+    let mut tree_index_ref = None;
+    let mut is_smart_git = false;
+    // raw files and snapshots can be fetched from other origins, as long as they're allowed to
+    let mut is_raw = false;
+
+    let wants_raw = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .any(|pair| pair == "raw=true");
 
     macro_rules! h {
         ($handler:ident) => {
@@ -69,26 +201,126 @@ pub async fn service(mut request: Request<Body>) -> Response {
         };
     }
 
+    let db = request
+        .extensions()
+        .get::<Arc<rocksdb::DB>>()
+        .expect("db extension missing")
+        .clone();
+    let url_config = UrlConfig::current();
+    let is_repository = |parts: &[&str]| {
+        resolve_repository(&db, &parts.iter().collect::<PathBuf>().clean(), url_config).is_some()
+    };
+
     let mut service = match uri_parts.pop() {
         Some("about") => h!(handle_about),
         Some("refs") if uri_parts.last() == Some(&"info") => {
             uri_parts.pop();
+            is_smart_git = true;
+            h!(handle_smart_git)
+        }
+        Some("git-upload-pack") => {
+            is_smart_git = true;
             h!(handle_smart_git)
         }
-        Some("git-upload-pack") => h!(handle_smart_git),
         Some("refs") => h!(handle_refs),
+        Some("reflog") => h!(handle_reflog),
+        Some("backports") => h!(handle_backports),
+        Some("bundle") => {
+            is_raw = true;
+            h!(handle_bundle)
+        }
         Some("log") => h!(handle_log),
-        Some("tree") => h!(handle_tree),
+        Some("live") => h!(handle_live),
+        Some("events") if uri_parts.last() == Some(&"live") => {
+            uri_parts.pop();
+            h!(handle_live_events)
+        }
+        Some("rows") if uri_parts.last() == Some(&"live") => {
+            uri_parts.pop();
+            h!(handle_live_rows)
+        }
+        // only where the segment follows the repository, so repositories named `tags` or
+        // `rewrites` still get their commit feeds
+        Some("atom") if ends_with_marker(&uri_parts, "tags", &is_repository) => {
+            uri_parts.pop();
+            h!(handle_tags_atom)
+        }
+        Some("atom") if ends_with_marker(&uri_parts, "rewrites", &is_repository) => {
+            uri_parts.pop();
+            h!(handle_rewrites_atom)
+        }
+        Some("atom") => h!(handle_atom),
+        Some("tree") => {
+            is_raw = wants_raw;
+            h!(handle_tree)
+        }
+        Some("tree-index") => {
+            is_raw = true;
+            tree_index_ref = Some(Arc::from("HEAD"));
+            h!(handle_tree_index)
+        }
         Some("commit") => h!(handle_commit),
         Some("diff") => h!(handle_diff),
+        Some("grep") => h!(handle_grep),
         Some("patch") => h!(handle_patch),
+        Some("mbox") => h!(handle_mbox),
         Some("tag") => h!(handle_tag),
-        Some("snapshot") => h!(handle_snapshot),
+        Some("tags") => h!(handle_tags),
+        Some("snapshot") => {
+            is_raw = true;
+            h!(handle_snapshot)
+        }
+        Some(v)
+            if uri_parts.last() == Some(&"blob")
+                && gix::ObjectId::from_hex(v.as_bytes()).is_ok() =>
+        {
+            uri_parts.pop();
+            is_raw = true;
+            blob_id = gix::ObjectId::from_hex(v.as_bytes()).ok();
+            h!(handle_blob)
+        }
         Some(v) => {
             uri_parts.push(v);
 
-            // match tree children
-            if uri_parts.iter().any(|v| *v == "tree") {
+            if let (Some(position), Some((stem, format))) = (
+                rposition_after_repository(
+                    &uri_parts[..uri_parts.len() - 1],
+                    "snapshot",
+                    &is_repository,
+                ),
+                SnapshotFormat::split_file_name(v),
+            ) {
+                // match snapshots of a subtree, in the form `snapshot/<ref>/<path>.<format>`
+                uri_parts.pop();
+                let mut path = uri_parts.split_off(position + 1);
+                uri_parts.pop();
+
+                path.push(stem);
+                let reference = path.remove(0);
+
+                child_path =
+                    (!path.is_empty()).then(|| path.into_iter().collect::<PathBuf>().clean());
+                is_raw = true;
+                snapshot_target = Some(SnapshotTarget {
+                    // references containing slashes have them escaped to keep them to one segment
+                    reference: Arc::from(reference.replace("%2F", "/").replace("%2f", "/")),
+                    format: Arc::from(format),
+                });
+
+                h!(handle_snapshot)
+            } else if let Some(position) = uri_parts
+                .iter()
+                .rposition(|v| *v == "tree-index")
+                .filter(|&position| !uri_parts[..position].contains(&"tree"))
+            {
+                // match the file listing of a ref, which may contain slashes
+                is_raw = true;
+                tree_index_ref = Some(Arc::from(uri_parts.split_off(position + 1).join("/")));
+                uri_parts.pop();
+
+                h!(handle_tree_index)
+            } else if uri_parts.iter().any(|v| *v == "tree") {
+                // match tree children
                 // TODO: this needs fixing up so it doesn't accidentally match repos that have
                 //  `tree` in their path
                 let mut reconstructed_path = Vec::new();
@@ -103,42 +335,394 @@ pub async fn service(mut request: Request<Body>) -> Response {
                 }
 
                 child_path = Some(reconstructed_path.into_iter().collect::<PathBuf>().clean());
+                is_raw = wants_raw;
 
                 h!(handle_tree)
+            } else if let Some(position) =
+                rposition_after_repository(&uri_parts, "tag", &is_repository)
+            {
+                // match tag names, which may contain slashes, as may the repository's path
+                tag_name = Some(Arc::from(uri_parts.split_off(position + 1).join("/")));
+                uri_parts.pop();
+
+                h!(handle_tag)
+            } else if let Some(position) = uri_parts.iter().rposition(|v| *v == "commit") {
+                // match abbreviated hashes and revspecs, which may contain slashes
+                commit_spec = Some(Arc::from(uri_parts.split_off(position + 1).join("/")));
+                uri_parts.pop();
+
+                h!(handle_commit_revspec)
             } else {
                 h!(handle_summary)
             }
         }
-        None => panic!("not found"),
+        None => return Error::repository_not_found().into_response(),
     };
 
-    let uri = uri_parts.into_iter().collect::<PathBuf>().clean();
-    let path = scan_path.join(&uri);
+    let requested = uri_parts.into_iter().collect::<PathBuf>().clean();
 
-    let db = request
+    let exists = |uri: &Path| {
+        !uri.as_os_str().is_empty()
+            && crate::database::schema::repository::Repository::exists(&db, uri).unwrap_or_default()
+    };
+
+    let Some(uri) = resolve_repository(&db, &requested, url_config) else {
+        return Error::repository_not_found().into_response();
+    };
+
+    // git clients don't follow redirects on every request, so they're served from whatever URL
+    // they were given
+    if !is_smart_git {
+        if let Some(location) = canonical_location(request.uri(), &requested, &uri) {
+            // when only the suffix differs, another repository may already be served at the
+            // canonical URL, leaving nowhere to redirect to
+            if location.path == uri.to_str().unwrap_or_default()
+                || !exists(Path::new(&location.path))
+            {
+                return Redirect::permanent(&location.to_string()).into_response();
+            }
+        }
+    }
+
+    let takedowns = request
         .extensions()
-        .get::<Arc<rocksdb::DB>>()
-        .expect("db extension missing");
-    if path.as_os_str().is_empty()
-        || !crate::database::schema::repository::Repository::exists(db, &uri).unwrap_or_default()
-    {
-        return RepositoryNotFound.into_response();
+        .get::<Arc<Takedowns>>()
+        .expect("takedowns missing");
+
+    // objects can be named in the query, or in the path of blobs, snapshots and file listings
+    let id = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("id="));
+    let blob_hex = blob_id.map(|v| v.to_string());
+    let named_objects = [
+        id,
+        blob_hex.as_deref(),
+        snapshot_target.as_ref().map(|v| &*v.reference),
+        tree_index_ref.as_deref(),
+    ];
+
+    if let Some(reason) = takedowns.repository(&uri).or_else(|| {
+        named_objects
+            .into_iter()
+            .flatten()
+            .find_map(|id| takedowns.object(id))
+    }) {
+        return Error::LegalReasons(Cow::Owned(reason.to_string())).into_response();
     }
 
+    let Some(path) = scan_paths.resolve(&uri) else {
+        return Error::repository_not_found().into_response();
+    };
+
     request.extensions_mut().insert(ChildPath(child_path));
+    if let Some(blob_id) = blob_id {
+        request.extensions_mut().insert(BlobId(blob_id));
+    }
+    if let Some(tag_name) = tag_name {
+        request.extensions_mut().insert(TagName(tag_name));
+    }
+    if let Some(commit_spec) = commit_spec {
+        request.extensions_mut().insert(CommitSpec(commit_spec));
+    }
+    if let Some(snapshot_target) = snapshot_target {
+        request.extensions_mut().insert(snapshot_target);
+    }
+    if let Some(tree_index_ref) = tree_index_ref {
+        request
+            .extensions_mut()
+            .insert(TreeIndexRef(tree_index_ref));
+    }
+    let branch_order = request
+        .extensions()
+        .get::<BranchOrder>()
+        .copied()
+        .unwrap_or_default();
+    let context = Arc::new(RepositoryContext::load(&db, &uri, branch_order));
+
+    // the page is named by what follows the repository in the path, as it was requested
+    let view = request
+        .uri()
+        .path()
+        .trim_start_matches('/')
+        .strip_prefix(requested.to_str().unwrap_or_default())
+        .and_then(|rest| rest.trim_start_matches('/').split('/').next())
+        .unwrap_or_default();
+    let excluded_from_crawlers = context
+        .crawler_excluded_views
+        .iter()
+        .any(|excluded| excluded == view);
+
+    if is_raw {
+        let cors = request.extensions().get::<Cors>().expect("cors missing");
+        service = BoxCloneService::new(cors.0.layer(service));
+    }
+
+    // pages of busy repositories go stale sooner than those of dormant ones
+    let max_age = request
+        .extensions()
+        .get::<ActivityHints>()
+        .is_some_and(|v| v.0 && !is_smart_git)
+        .then_some(context.activity)
+        .flatten()
+        .map(Activity::max_age);
+
     request.extensions_mut().insert(Repository(uri));
     request.extensions_mut().insert(RepositoryPath(path));
+    request.extensions_mut().insert(RequestedPath(requested));
 
-    service
-        .call(request)
+    let mut response = REPOSITORY_CONTEXT
+        .scope(context, service.call(request))
         .await
         .unwrap_infallible()
-        .into_response()
+        .into_response();
+
+    // still served to crawlers that ignore `robots.txt`, but kept out of their indexes
+    if excluded_from_crawlers {
+        response.headers_mut().insert(
+            HeaderName::from_static("x-robots-tag"),
+            HeaderValue::from_static("noindex, nofollow"),
+        );
+    }
+
+    if let Some(max_age) = max_age.filter(|_| response.status().is_success()) {
+        let headers = response.headers_mut();
+
+        if !headers.contains_key(CACHE_CONTROL) {
+            if let Ok(value) =
+                HeaderValue::try_from(format!("public, max-age={}", max_age.as_secs()))
+            {
+                headers.insert(CACHE_CONTROL, value);
+                // times are rendered in the timezone picked with the `tz` cookie
+                headers.append(VARY, HeaderValue::from_static("cookie"));
+            }
+        }
+    }
+
+    response
+}
+
+/// Finds the repository on disk that `requested` names, which may be linked to without its
+/// suffix or, with `--case-insensitive-urls`, requested in a different case.
+fn resolve_repository(
+    db: &rocksdb::DB,
+    requested: &Path,
+    url_config: UrlConfig,
+) -> Option<PathBuf> {
+    let exists = |uri: &Path| {
+        !uri.as_os_str().is_empty()
+            && crate::database::schema::repository::Repository::exists(db, uri).unwrap_or_default()
+    };
+
+    let candidates = std::iter::once(requested.to_path_buf()).chain(
+        (url_config.url_scheme == UrlScheme::StripGitSuffix).then(|| {
+            let mut suffixed = requested.as_os_str().to_os_string();
+            suffixed.push(".git");
+            PathBuf::from(suffixed)
+        }),
+    );
+
+    candidates.clone().find(|uri| exists(uri)).or_else(|| {
+        candidates
+            .filter(|_| url_config.case_insensitive_urls)
+            .find_map(|uri| {
+                crate::database::schema::repository::Repository::find_case_insensitive(db, uri)
+                    .ok()
+                    .flatten()
+            })
+            .map(PathBuf::from)
+            .filter(|uri| exists(uri))
+    })
+}
+
+/// Whether the last segment is `marker` and directly follows the path of a repository, for pages
+/// nested under another page's segment.
+fn ends_with_marker(
+    uri_parts: &[&str],
+    marker: &str,
+    is_repository: impl Fn(&[&str]) -> bool,
+) -> bool {
+    uri_parts
+        .split_last()
+        .is_some_and(|(last, rest)| *last == marker && is_repository(rest))
+}
+
+/// Finds the last `marker` segment that directly follows the path of a repository, for pages
+/// whose own path can contain the segment that marks them, as can the path of the repository.
+fn rposition_after_repository(
+    uri_parts: &[&str],
+    marker: &str,
+    is_repository: impl Fn(&[&str]) -> bool,
+) -> Option<usize> {
+    (0..uri_parts.len())
+        .rev()
+        .find(|&position| uri_parts[position] == marker && is_repository(&uri_parts[..position]))
+}
+
+tokio::task_local! {
+    static REPOSITORY_CONTEXT: Arc<RepositoryContext>;
+}
+
+/// What's known about the repository being served, for the parts of `repo/base.html` shared by
+/// every repository page (the social meta tags and branch selector) so they can be reached
+/// without each view having to carry them around.
+#[derive(Default)]
+pub struct RepositoryContext {
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub homepage: Option<String>,
+    /// The short names of the repository's branches, in the order they should be listed in
+    pub branches: Vec<String>,
+    /// The short name of the branch shown when none is given
+    pub default_branch: Option<String>,
+    /// How busy the repository is, for `--activity-hints`
+    pub activity: Option<Activity>,
+    /// The pages crawlers are asked to stay away from, see [`REPOSITORY_VIEWS`]
+    pub crawler_excluded_views: Vec<String>,
+}
+
+impl RepositoryContext {
+    fn load(db: &Arc<rocksdb::DB>, uri: &Path, branch_order: BranchOrder) -> Self {
+        let annotations = uri.to_str().and_then(|path| {
+            crate::database::schema::annotations::Annotations::open(db, path)
+                .ok()
+                .flatten()
+        });
+
+        let Some(repository) = crate::database::schema::repository::Repository::open(db, uri)
+            .ok()
+            .flatten()
+        else {
+            return Self::default();
+        };
+        let repository = repository.get();
+
+        let mut branches: Vec<(String, i64)> = repository
+            .heads(db)
+            .ok()
+            .flatten()
+            .map(|heads| {
+                heads
+                    .get()
+                    .0
+                    .as_slice()
+                    .iter()
+                    .filter_map(|head| head.name.strip_prefix("refs/heads/"))
+                    .map(|name| (name.to_string(), 0))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let default_branch = repository
+            .default_branch
+            .as_deref()
+            .and_then(|v| v.strip_prefix("refs/heads/"))
+            .map(ToString::to_string);
+
+        let branch_order = BranchOrder::for_repository(repository, branch_order);
+
+        // past the limit, branches are completed as they're typed rather than listed, so there's
+        // no need to look up when each was last committed to
+        if branch_order == BranchOrder::LastCommit && branches.len() <= BRANCH_SELECT_LIMIT {
+            for (name, last_commit) in &mut branches {
+                *last_commit = repository
+                    .commit_tree(db.clone(), &format!("refs/heads/{name}"))
+                    .fetch_latest_one()
+                    .ok()
+                    .flatten()
+                    .map_or(0, |commit| commit.get().committer.time.0.to_native());
+            }
+        }
+
+        branch_order.sort(&mut branches, default_branch.as_deref(), |v| *v);
+
+        Self {
+            description: annotations
+                .and_then(|v| v.description)
+                .or_else(|| repository.description.as_ref().map(ToString::to_string)),
+            owner: repository.owner.as_ref().map(ToString::to_string),
+            homepage: repository.homepage.as_ref().map(ToString::to_string),
+            branches: branches.into_iter().map(|(name, _)| name).collect(),
+            default_branch,
+            activity: Some(Activity::of(repository, OffsetDateTime::now_utc())),
+            crawler_excluded_views: repository
+                .crawler_excluded_views
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    /// The branch being viewed, falling back to the default branch when none was given.
+    pub fn selected_branch<'a>(&'a self, branch: Option<&'a str>) -> Option<&'a str> {
+        branch.or(self.default_branch.as_deref())
+    }
+}
+
+/// The context of the repository the current request is for, empty outside of a repository.
+pub fn repository_context() -> Arc<RepositoryContext> {
+    REPOSITORY_CONTEXT.try_with(Arc::clone).unwrap_or_default()
+}
+
+/// Where a request addressing a repository by anything other than its canonical URL, such as
+/// its suffixed path or with different casing, should be redirected to.
+struct CanonicalLocation<'a> {
+    path: String,
+    rest: &'a str,
+    query: Option<&'a str>,
+}
+
+impl fmt::Display for CanonicalLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}{}", self.path, self.rest)?;
+
+        if let Some(query) = self.query {
+            write!(f, "?{query}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn canonical_location<'a>(
+    request_uri: &'a Uri,
+    requested: &Path,
+    repository: &Path,
+) -> Option<CanonicalLocation<'a>> {
+    let requested = requested.to_str()?;
+    let canonical = repository_url(repository.to_str()?);
+
+    if canonical == requested {
+        return None;
+    }
+
+    let rest = request_uri
+        .path()
+        .trim_start_matches('/')
+        .strip_prefix(requested)?;
+
+    Some(CanonicalLocation {
+        path: canonical.to_string(),
+        rest,
+        query: request_uri.query(),
+    })
 }
 
 #[derive(Clone)]
 pub struct Repository(pub PathBuf);
 
+impl Repository {
+    /// The path this repository is linked to at, without a leading slash.
+    pub fn url(&self) -> Cow<'_, str> {
+        match self.0.to_string_lossy() {
+            Cow::Borrowed(v) => Cow::Borrowed(repository_url(v)),
+            Cow::Owned(v) => Cow::Owned(repository_url(&v).to_string()),
+        }
+    }
+}
+
 impl Deref for Repository {
     type Target = Path;
 
@@ -150,9 +734,35 @@ impl Deref for Repository {
 #[derive(Clone)]
 pub struct RepositoryPath(pub PathBuf);
 
+/// The repository's path as it was given in the request, which may not be its own (eg. without
+/// its `.git` suffix).
+#[derive(Clone)]
+pub struct RequestedPath(pub PathBuf);
+
 #[derive(Clone)]
 pub struct ChildPath(pub Option<PathBuf>);
 
+#[derive(Clone)]
+pub struct BlobId(pub gix::ObjectId);
+
+#[derive(Clone)]
+pub struct TagName(pub Arc<str>);
+
+/// An abbreviated hash, reference name or revspec naming a commit, given in the path.
+#[derive(Clone)]
+pub struct CommitSpec(pub Arc<str>);
+
+/// The reference whose files are listed by the tree index.
+#[derive(Clone)]
+pub struct TreeIndexRef(pub Arc<str>);
+
+/// The reference and format of a snapshot requested by path rather than by query string.
+#[derive(Clone)]
+pub struct SnapshotTarget {
+    pub reference: Arc<str>,
+    pub format: Arc<str>,
+}
+
 impl Deref for RepositoryPath {
     type Target = Path;
 
@@ -161,37 +771,48 @@ impl Deref for RepositoryPath {
     }
 }
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
-
-pub struct RepositoryNotFound;
-
-impl IntoResponse for RepositoryNotFound {
-    fn into_response(self) -> Response {
-        (StatusCode::NOT_FOUND, "Repository not found").into_response()
-    }
+pub struct Refs {
+    heads: Vec<(String, Branch)>,
+    tags: Vec<(YokedString, YokedTag)>,
 }
 
-pub struct Error(anyhow::Error);
-
-impl From<Arc<anyhow::Error>> for Error {
-    fn from(e: Arc<anyhow::Error>) -> Self {
-        Self(anyhow::Error::msg(format!("{e:?}")))
-    }
+pub struct Branch {
+    /// The newest commit on the branch, and the number of commits on it, as of the last index run
+    commit: HeadCommit,
+    /// Number of commits ahead and behind the default branch, and the default branch's tip at
+    /// the time, as computed by the indexer
+    divergence: Option<(u64, u64, [u8; 20])>,
 }
 
-impl From<anyhow::Error> for Error {
-    fn from(e: anyhow::Error) -> Self {
-        Self(e)
+impl Branch {
+    /// Builds the row listing a branch from what the indexer recorded about it, returning its
+    /// short name alongside it. Tags, and branches that haven't been indexed yet, aren't listed.
+    pub fn from_head(head: &ArchivedHead) -> Option<(String, Self)> {
+        let name = head.name.strip_prefix("refs/heads/")?;
+        let commit =
+            rkyv::deserialize::<HeadCommit, rkyv::rancor::Error>(head.latest.as_ref()?).ok()?;
+
+        Some((
+            name.to_string(),
+            Self {
+                commit,
+                divergence: head
+                    .divergence
+                    .as_ref()
+                    .map(|v| (v.ahead.to_native(), v.behind.to_native(), v.base)),
+            },
+        ))
     }
-}
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", self.0)).into_response()
+    pub fn commit(&self) -> &HeadCommit {
+        &self.commit
     }
-}
 
-pub struct Refs {
-    heads: BTreeMap<String, YokedCommit>,
-    tags: Vec<(YokedString, YokedTag)>,
+    /// The commit to export a patch series from to get the commits on this branch that aren't on
+    /// the default branch, if there are any.
+    pub fn series_base(&self) -> Option<String> {
+        self.divergence
+            .filter(|(ahead, ..)| *ahead > 0)
+            .map(|(.., base)| const_hex::encode(base))
+    }
 }
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{extract::Query, response::IntoResponse, Extension};
+use serde::Deserialize;
+
+use crate::{
+    database::schema::{
+        ref_update::{RefUpdateLog, YokedRefUpdate},
+        repository::RepositoryId,
+    },
+    error::NotFound,
+    into_response,
+    methods::{
+        filters,
+        repo::{Repository, Result},
+    },
+};
+
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "ofs")]
+    offset: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/reflog.html")]
+pub struct View {
+    repo: Repository,
+    updates: Vec<YokedRefUpdate>,
+    next_offset: Option<usize>,
+    branch: Option<Arc<str>>,
+}
+
+/// Every change to the repository's branches and tags the indexer has seen, newest first.
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let offset = query.offset.unwrap_or(0);
+
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let mut updates = RefUpdateLog::new(db, RepositoryId(repository.get().id.0.to_native()))
+            .fetch_latest(PAGE_SIZE + 1, offset)?;
+
+        let next_offset = if updates.len() > PAGE_SIZE {
+            updates.pop();
+            Some(offset + PAGE_SIZE)
+        } else {
+            None
+        };
+
+        Ok(into_response(View {
+            repo,
+            updates,
+            next_offset,
+            branch: None,
+        }))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
@@ -1,17 +1,20 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::sync::Arc;
 
 use crate::{
+    archive::{SnapshotConfig, Snapshots},
+    error::NotFound,
     into_response,
     methods::{
         filters,
-        repo::{Refs, Repository, Result},
+        repo::{Branch, BranchOrder, Refs, Repository, Result},
     },
 };
 use anyhow::Context;
 use askama::Template;
 use axum::{response::IntoResponse, Extension};
-use rkyv::string::ArchivedString;
-use yoke::Yoke;
+
+/// The number of tags to show before linking to the full, paginated, list of tags.
+const TAG_LIMIT: usize = 25;
 
 #[derive(Template)]
 #[template(path = "repo/refs.html")]
@@ -19,43 +22,48 @@ pub struct View {
     repo: Repository,
     refs: Refs,
     branch: Option<Arc<str>>,
+    snapshots: Snapshots,
+    /// Whether the repository tracks backports to stable branches.
+    has_stable_branches: bool,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(branch_order): Extension<BranchOrder>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
 ) -> Result<impl IntoResponse> {
     tokio::task::spawn_blocking(move || {
         let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
+            .ok_or(NotFound("Repository does not exist"))?;
         let repository = repository.get();
 
-        let heads_db = repository.heads(&db)?;
-        let heads_db = heads_db.as_ref().map(Yoke::get);
-
-        let mut heads = BTreeMap::new();
-        if let Some(archived_heads) = heads_db {
-            for head in archived_heads
-                .0
-                .as_slice()
-                .iter()
-                .map(ArchivedString::as_str)
-            {
-                let commit_tree = repository.commit_tree(db.clone(), head);
-                let name = head.strip_prefix("refs/heads/");
+        let mut heads: Vec<_> = repository
+            .heads(&db)?
+            .iter()
+            .flat_map(|heads| heads.get().0.iter().filter_map(Branch::from_head))
+            .collect();
 
-                if let (Some(name), Some(commit)) = (name, commit_tree.fetch_latest_one()?) {
-                    heads.insert(name.to_string(), commit);
-                }
-            }
-        }
+        BranchOrder::for_repository(repository, branch_order).sort(
+            &mut heads,
+            repository
+                .default_branch
+                .as_deref()
+                .and_then(|v| v.strip_prefix("refs/heads/")),
+            |branch| branch.commit().committed_at,
+        );
 
-        let tags = repository.tag_tree(db).fetch_all()?;
+        let tags = repository.tag_tree(db).fetch_latest(TAG_LIMIT + 1, 0)?;
 
         Ok(into_response(View {
             repo,
             refs: Refs { heads, tags },
             branch: None,
+            snapshots: Snapshots {
+                formats: repository.snapshot_formats(),
+                checksums: snapshot_config.checksums(),
+            },
+            has_stable_branches: !repository.stable_branches.is_empty(),
         }))
     })
     .await
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{http::HeaderMap, response::Response, Extension};
+
+use crate::{
+    database::schema::{
+        ref_update::{RefUpdateLog, YokedRefUpdate},
+        repository::RepositoryId,
+    },
+    error::NotFound,
+    methods::{
+        feed::FeedValidators,
+        filters,
+        repo::{Repository, Result},
+    },
+    url_scheme::{absolute_url, repository_page_url},
+};
+
+/// The number of rewrites included in a repository's rewrite feed.
+const FEED_LENGTH: usize = 50;
+
+#[derive(Template)]
+#[template(path = "repo/rewrites.xml")]
+pub struct FeedView {
+    repo: Repository,
+    rewrites: Vec<YokedRefUpdate>,
+    updated: (i64, i32),
+    self_url: String,
+}
+
+/// The branches of a repository that have been force-pushed or otherwise had their history
+/// rewritten, for consumers of mirrors that need to know when to throw away what they've seen.
+pub async fn handle_atom(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let rewrites = RefUpdateLog::new(db, RepositoryId(repository.get().id.0.to_native()))
+            .fetch_forced(FEED_LENGTH)?;
+
+        let updated = rewrites.first().map_or_else(
+            || filters::Timestamp::from(&repository.get().last_modified).0,
+            |rewrite| rewrite.get().observed_at(),
+        );
+
+        let mut key = Vec::new();
+        key.extend_from_slice(repo.as_os_str().as_encoded_bytes());
+        if let Some((_, new)) = rewrites.first().and_then(|v| v.get().forced_range()) {
+            key.extend_from_slice(new);
+        }
+        key.extend_from_slice(&updated.unix_timestamp().to_be_bytes());
+
+        let validators = FeedValidators::new(updated, &key);
+
+        Ok(validators.respond(
+            &headers,
+            FeedView {
+                self_url: absolute_url(&repository_page_url(&repo.url(), "rewrites/atom")),
+                repo,
+                rewrites,
+                updated: (updated.unix_timestamp(), updated.offset().whole_seconds()),
+            },
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
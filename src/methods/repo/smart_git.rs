@@ -1,10 +1,17 @@
-use std::{io, io::ErrorKind, path::Path, process::Stdio, str::FromStr};
+use std::{
+    io,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Context};
 use axum::{
     body::Body,
     http::{
-        header::{HeaderMap, HeaderName, HeaderValue},
+        header::{HeaderMap, HeaderName, HeaderValue, VARY},
         Method, Uri,
     },
     response::{IntoResponse, Response},
@@ -23,20 +30,22 @@ use tokio_util::io::StreamReader;
 use tracing::{debug, error, info_span, warn, Instrument};
 
 use crate::{
-    methods::repo::{Repository, RepositoryPath, Result},
+    error::NotFound,
+    methods::repo::{Repository, RepositoryPath, RequestedPath, Result},
     StatusCode,
 };
 
-#[allow(clippy::unused_async)]
 pub async fn handle(
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(Repository(repository)): Extension<Repository>,
+    Extension(RequestedPath(requested)): Extension<RequestedPath>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
     method: Method,
     uri: Uri,
     headers: HeaderMap,
     body: Body,
 ) -> Result<impl IntoResponse> {
-    let path = extract_path(&uri, &repository)?;
+    let path = extract_path(&uri, &requested)?;
 
     let mut command = Command::new("git");
 
@@ -49,6 +58,33 @@ pub async fn handle(
         extract_header(&headers, &mut command, header, env)?;
     }
 
+    // bundles are only asked for by clients speaking protocol v2, which `upload-pack` only
+    // advertises them to if told to
+    let bundle_uris = load_bundle_uris(db, repository.clone()).await?;
+    if !bundle_uris.is_empty() {
+        let mut config = vec![
+            (
+                "uploadpack.advertiseBundleURIs".to_string(),
+                "true".to_string(),
+            ),
+            ("bundle.version".to_string(), "1".to_string()),
+            ("bundle.mode".to_string(), "all".to_string()),
+        ];
+        config.extend(
+            bundle_uris
+                .into_iter()
+                .enumerate()
+                .map(|(i, uri)| (format!("bundle.rgit-{i}.uri"), uri)),
+        );
+
+        command.env("GIT_CONFIG_COUNT", config.len().to_string());
+        for (i, (key, value)) in config.into_iter().enumerate() {
+            command
+                .env(format!("GIT_CONFIG_KEY_{i}"), key)
+                .env(format!("GIT_CONFIG_VALUE_{i}"), value);
+        }
+    }
+
     let mut child = command
         .arg("http-backend")
         .env("REQUEST_METHOD", method.as_str())
@@ -95,11 +131,19 @@ pub async fn handle(
     // if the `headers` loop broke with `None`, the `git http-backend` didn't return any parseable
     // headers so there's no reason for us to continue. there may be something in stderr for us
     // though.
-    let Some(headers) = headers else {
+    let Some(mut headers) = headers else {
         print_status(&mut child, &mut stderr).await;
         return Err(anyhow!("Received incomplete response from git http-backend").into());
     };
 
+    // the refs are advertised differently depending on the protocol version the client asked
+    // for, so caches mustn't hand one client's advertisement to another
+    if path.ends_with("/info/refs") {
+        headers
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("Git-Protocol"));
+    }
+
     // stream the response back to the client
     let (body_send, body_recv) = mpsc::channel(8);
     tokio::spawn(
@@ -110,6 +154,23 @@ pub async fn handle(
     Ok((headers, Body::from_stream(ReceiverStream::new(body_recv))))
 }
 
+/// The bundles `rgit.bundleUri` points clients at, empty for most repositories.
+async fn load_bundle_uris(db: Arc<rocksdb::DB>, repository: PathBuf) -> Result<Vec<String>> {
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &repository)?
+            .ok_or(NotFound("Repository does not exist"))?;
+
+        Ok(repository
+            .get()
+            .bundle_uris
+            .iter()
+            .map(ToString::to_string)
+            .collect())
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
+
 /// Forwards the entirety of `stdout` to `body_send`, printing subprocess stderr and status on
 /// completion.
 async fn forward_response_to_client(
@@ -167,16 +228,19 @@ fn extract_header(input: &HeaderMap, output: &mut Command, header: &str, env: &s
     Ok(())
 }
 
-/// Extract the path from the URL to determine the repository path.
-fn extract_path<'a>(uri: &'a Uri, repository: &Path) -> Result<&'a str> {
+/// Extract the path within the repository from the URL, following the repository's path as it
+/// was requested, which may not be the repository's own (eg. without its `.git` suffix).
+fn extract_path<'a>(uri: &'a Uri, requested: &Path) -> Result<&'a str> {
     let path = uri.path();
     let path = path.strip_prefix('/').unwrap_or(path);
 
-    if let Some(prefix) = repository.as_os_str().to_str() {
-        Ok(path.strip_prefix(prefix).unwrap_or(path))
-    } else {
-        Err(anyhow::Error::msg("Repository name contains invalid bytes").into())
-    }
+    let Some(prefix) = requested.as_os_str().to_str() else {
+        return Err(anyhow::Error::msg("Repository name contains invalid bytes").into());
+    };
+
+    path.strip_prefix(prefix)
+        .filter(|rest| rest.starts_with('/'))
+        .ok_or_else(|| anyhow!("Request path isn't within the requested repository").into())
 }
 
 // Intercept headers from the spawned `git http-backend` CGI and rewrite them to
@@ -213,3 +277,75 @@ pub fn parse_cgi_headers(buffer: &[u8]) -> Result<Option<(usize, Response<()>)>,
 
     Ok(Some((body_offset, response)))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use axum::http::Uri;
+
+    use super::extract_path;
+
+    fn git(args: &[&str], dir: &Path) {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "rgit")
+            .env("GIT_AUTHOR_EMAIL", "rgit@example.com")
+            .env("GIT_COMMITTER_NAME", "rgit")
+            .env("GIT_COMMITTER_EMAIL", "rgit@example.com")
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    #[test]
+    fn extract_path_follows_requested_repository() {
+        let uri: Uri = "/foo/info/refs?service=git-upload-pack".parse().unwrap();
+        assert_eq!(extract_path(&uri, Path::new("foo")).unwrap(), "/info/refs");
+
+        let uri: Uri = "/foo.git/info/refs".parse().unwrap();
+        assert_eq!(
+            extract_path(&uri, Path::new("foo.git")).unwrap(),
+            "/info/refs"
+        );
+
+        let uri: Uri = "/foobar/info/refs".parse().unwrap();
+        assert!(extract_path(&uri, Path::new("foo")).is_err());
+        assert!(extract_path(&uri, Path::new("bar")).is_err());
+    }
+
+    #[test]
+    fn clones_over_url_without_git_suffix() {
+        let root = std::env::temp_dir().join(format!("rgit-smart-git-{}", std::process::id()));
+        let _res = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("work")).unwrap();
+
+        git(&["init", "-q", "-b", "main"], &root.join("work"));
+        git(
+            &["commit", "-q", "--allow-empty", "-m", "initial"],
+            &root.join("work"),
+        );
+        git(&["clone", "-q", "--bare", "work", "foo.git"], &root);
+
+        // the clone URL given for `foo.git` when the `.git` suffix is stripped
+        let uri: Uri = "/foo/info/refs?service=git-upload-pack".parse().unwrap();
+        let path = extract_path(&uri, Path::new("foo")).unwrap();
+
+        let output = Command::new("git")
+            .arg("http-backend")
+            .env("REQUEST_METHOD", "GET")
+            .env("PATH_INFO", path)
+            .env("GIT_PROJECT_ROOT", root.join("foo.git"))
+            .env("QUERY_STRING", uri.query().unwrap())
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(!stdout.starts_with("Status:"), "{stdout}");
+        assert!(stdout.contains("refs/heads/main"), "{stdout}");
+    }
+}
@@ -1,28 +1,183 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Context};
-use axum::{body::Body, extract::Query, http::Response, Extension};
+use askama::Template;
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header, Response, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
 use serde::Deserialize;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info_span, Instrument};
 
-use super::{RepositoryPath, Result};
-use crate::git::Git;
+use super::{ChildPath, Error, Repository, RepositoryPath, Result, SnapshotTarget};
+use crate::{
+    archive::{SnapshotChecksum, SnapshotConfig, SnapshotFormat},
+    error::NotFound,
+    git::Git,
+    into_response,
+    snapshot_jobs::{SnapshotJobs, SnapshotState},
+};
+
+/// How long a request waits for a snapshot being built in the background before being shown
+/// the page asking them to wait.
+const PREPARE_WAIT: Duration = Duration::from_secs(3);
+
+/// How often, in seconds, the page asking the requester to wait refreshes to check whether the
+/// snapshot is ready.
+const PREPARING_REFRESH_INTERVAL: u64 = 2;
+
+#[derive(Template)]
+#[template(path = "repo/snapshot.html")]
+pub struct PreparingView {
+    repo: Repository,
+    branch: Option<Arc<str>>,
+    file_name: String,
+    written: u64,
+    refresh_interval: u64,
+}
 
 #[derive(Deserialize)]
 pub struct UriQuery {
     #[serde(rename = "h")]
     branch: Option<Arc<str>>,
     id: Option<Arc<str>>,
+    /// The extension of the format to download the snapshot in, defaulting to the first format
+    /// the repository offers. Suffixed with a [`SnapshotChecksum`] extension to download one of
+    /// the snapshot's companion files instead (eg. `tar.gz.sha256`)
+    format: Option<String>,
 }
 
+#[allow(clippy::too_many_lines, clippy::too_many_arguments)]
 pub async fn handle(
+    Extension(repo): Extension<Repository>,
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
     Extension(git): Extension<Arc<Git>>,
-    Query(query): Query<UriQuery>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
+    Extension(snapshot_jobs): Extension<Option<Arc<SnapshotJobs>>>,
+    Extension(ChildPath(subtree)): Extension<ChildPath>,
+    snapshot_target: Option<Extension<SnapshotTarget>>,
+    Query(mut query): Query<UriQuery>,
 ) -> Result<Response<Body>> {
+    // snapshots requested by path, `/<repo>/snapshot/<ref>/<path>.<format>`, take precedence
+    // over the query string
+    if let Some(Extension(SnapshotTarget { reference, format })) = snapshot_target {
+        if gix::ObjectId::from_hex(reference.as_bytes()).is_ok() {
+            query.id = Some(reference);
+        } else {
+            query.branch = Some(reference);
+        }
+
+        query.format = Some(format.to_string());
+    }
+
+    let (formats, compression_level) = tokio::task::spawn_blocking({
+        let repo = repo.clone();
+        move || {
+            let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+                .ok_or(NotFound("Repository does not exist"))?;
+            let repository = repository.get();
+
+            Ok::<_, anyhow::Error>((
+                repository.snapshot_formats(),
+                repository
+                    .snapshot_compression_level
+                    .as_ref()
+                    .map(|v| v.to_native()),
+            ))
+        }
+    })
+    .await
+    .context("Failed to join Tokio task")??;
+
+    let (format, checksum) = query.format.as_deref().map_or((None, None), |v| {
+        let (format, checksum) = SnapshotChecksum::split_format(v);
+        (Some(format), checksum)
+    });
+
+    let format = match format.map(SnapshotFormat::from_str) {
+        None => formats[0],
+        Some(Ok(format)) if formats.contains(&format) => format,
+        Some(_) => {
+            return Err(Error::NotFound("Snapshot format not available".into()));
+        }
+    };
+
+    if checksum.is_some_and(|v| !snapshot_config.checksums().contains(&v)) {
+        return Err(Error::NotFound("Snapshot checksum not available".into()));
+    }
+
+    let mut file_name = query
+        .id
+        .as_deref()
+        .or(query.branch.as_deref())
+        .unwrap_or("main")
+        .to_string();
+    if let Some(subtree) = &subtree {
+        file_name.push('-');
+        file_name.push_str(&subtree.to_string_lossy().replace('/', "-"));
+    }
+
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
 
+    if let Some(checksum) = checksum {
+        let content = open_repo
+            .snapshot_checksum(
+                query.id.as_deref(),
+                subtree,
+                format,
+                compression_level,
+                checksum,
+                snapshot_config.snapshot_signing_command,
+            )
+            .await?;
+
+        return checksum_response(&content, &file_name, format, checksum);
+    }
+
+    if let Some(snapshot_jobs) = snapshot_jobs {
+        let (key, snapshot) = open_repo
+            .prepare_snapshot(
+                snapshot_jobs.clone(),
+                query.id.as_deref(),
+                subtree,
+                format,
+                compression_level,
+            )
+            .await?;
+
+        return match snapshot.wait(PREPARE_WAIT).await {
+            SnapshotState::Ready { location, size } => match snapshot_jobs.open(&location).await {
+                Ok(body) => prepared_response(body, size, &file_name, format),
+                Err(error) => {
+                    // the bucket's lifecycle rules may have expired it from under us
+                    snapshot_jobs.forget(&key);
+                    Err(error.into())
+                }
+            },
+            SnapshotState::Preparing => Ok((
+                StatusCode::ACCEPTED,
+                [(header::RETRY_AFTER, PREPARING_REFRESH_INTERVAL.to_string())],
+                into_response(PreparingView {
+                    repo,
+                    branch: query.branch,
+                    file_name: format!("{file_name}.{format}"),
+                    written: snapshot.written(),
+                    refresh_interval: PREPARING_REFRESH_INTERVAL,
+                }),
+            )
+                .into_response()),
+            SnapshotState::Failed => {
+                snapshot_jobs.forget(&key);
+                Err(anyhow!("Failed to build archive").into())
+            }
+        };
+    }
+
     // byte stream back to the client
     let (send, recv) = tokio::sync::mpsc::channel(1);
 
@@ -35,7 +190,14 @@ pub async fn handle(
     let res = tokio::spawn(
         async move {
             if let Err(error) = open_repo
-                .archive(send.clone(), send_cont, id.as_deref())
+                .archive(
+                    send.clone(),
+                    send_cont,
+                    id.as_deref(),
+                    subtree,
+                    format,
+                    compression_level,
+                )
                 .await
             {
                 error!(%error, "Failed to build archive for client");
@@ -64,18 +226,51 @@ pub async fn handle(
         return Err(anyhow!("Ran into inconsistent error state whilst building archive, please file an issue at https://github.com/w4/rgit/issues").into());
     }
 
-    let file_name = query
-        .id
-        .as_deref()
-        .or(query.branch.as_deref())
-        .unwrap_or("main");
-
     Ok(Response::builder()
-        .header("Content-Type", "application/gzip")
+        .header("Content-Type", format.content_type())
         .header(
             "Content-Disposition",
-            format!("attachment; filename=\"{file_name}.tar.gz\""),
+            format!("attachment; filename=\"{file_name}.{format}\""),
         )
         .body(Body::from_stream(ReceiverStream::new(recv)))
         .context("failed to build response")?)
 }
+
+/// Serves a snapshot that was built in the background.
+fn prepared_response(
+    body: Body,
+    size: u64,
+    file_name: &str,
+    format: SnapshotFormat,
+) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header("Content-Type", format.content_type())
+        .header("Content-Length", size)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{file_name}.{format}\""),
+        )
+        .body(body)
+        .context("failed to build response")?)
+}
+
+fn checksum_response(
+    content: &str,
+    file_name: &str,
+    format: SnapshotFormat,
+    checksum: SnapshotChecksum,
+) -> Result<Response<Body>> {
+    let body = match checksum {
+        SnapshotChecksum::Sha256 => format!("{content}  {file_name}.{format}\n"),
+        SnapshotChecksum::Signature => content.to_string(),
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", checksum.content_type())
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{file_name}.{format}.{checksum}\""),
+        )
+        .body(Body::from(body))
+        .context("failed to build response")?)
+}
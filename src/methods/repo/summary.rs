@@ -1,72 +1,134 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::sync::Arc;
 
 use anyhow::Context;
 use askama::Template;
-use axum::{response::IntoResponse, Extension};
+use axum::{extract::Query, response::IntoResponse, Extension};
 use rkyv::string::ArchivedString;
+use serde::Deserialize;
 
 use crate::{
-    database::schema::{commit::YokedCommit, repository::YokedRepository},
+    archive::{SnapshotConfig, Snapshots},
+    bundles::Bundles,
+    database::schema::{
+        annotations::Annotations, commit::YokedCommit, repository::YokedRepository,
+    },
+    error::NotFound,
     into_response,
+    linkify::{LinkRule, Linkifier},
     methods::{
         filters,
-        repo::{Refs, Repository, Result, DEFAULT_BRANCHES},
+        repo::{Branch, BranchOrder, Refs, Repository, Result, DEFAULT_BRANCHES},
     },
+    url_scheme::{absolute_url, repository_page_url},
 };
 
 #[derive(Template)]
 #[template(path = "repo/summary.html")]
 pub struct View {
     repo: Repository,
+    db_repository: YokedRepository,
     refs: Refs,
     commit_list: Vec<YokedCommit>,
     branch: Option<Arc<str>>,
+    linkifier: Linkifier,
+    snapshots: Snapshots,
+    /// Whether the indexer hasn't got to the repository's commits yet.
+    indexing: bool,
+    /// The URL to clone the repository from, relative if the public URL isn't configured.
+    clone_url: String,
+    annotations: Annotations,
+    /// The size of the repository's pre-generated bundle, if it has one.
+    bundle_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(branch_order): Extension<BranchOrder>,
+    Extension(link_rules): Extension<Arc<[LinkRule]>>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
+    Extension(bundles): Extension<Option<Arc<Bundles>>>,
+    Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     tokio::task::spawn_blocking(move || {
         let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
-        let commits = get_default_branch_commits(&repository, &db)?;
-
-        let mut heads = BTreeMap::new();
-        if let Some(heads_db) = repository.get().heads(&db)? {
-            for head in heads_db
+            .ok_or(NotFound("Repository does not exist"))?;
+        let commits = get_branch_commits(&repository, &db, query.branch.as_deref())?;
+        let linkifier = Linkifier::new(
+            &repo.url(),
+            &link_rules,
+            repository
                 .get()
-                .0
-                .as_slice()
+                .link_rules
                 .iter()
-                .map(ArchivedString::as_str)
-            {
-                let commit_tree = repository.get().commit_tree(db.clone(), head);
-                let name = head.strip_prefix("refs/heads/");
+                .map(ArchivedString::as_str),
+        );
 
-                if let (Some(name), Some(commit)) = (name, commit_tree.fetch_latest_one()?) {
-                    heads.insert(name.to_string(), commit);
-                }
-            }
-        }
+        let mut heads: Vec<_> = repository
+            .get()
+            .heads(&db)?
+            .iter()
+            .flat_map(|heads| heads.get().0.iter().filter_map(Branch::from_head))
+            .collect();
+
+        BranchOrder::for_repository(repository.get(), branch_order).sort(
+            &mut heads,
+            repository
+                .get()
+                .default_branch
+                .as_deref()
+                .and_then(|v| v.strip_prefix("refs/heads/")),
+            |branch| branch.commit().committed_at,
+        );
 
-        let tags = repository.get().tag_tree(db).fetch_all()?;
+        let indexing = !repository.get().is_indexed(&db)?;
+        let tags = repository.get().tag_tree(db).fetch_latest(11, 0)?;
+        let clone_url = absolute_url(&repository_page_url(&repo.url(), ""));
+        let annotations = Annotations::open(&db, &repo.to_string_lossy())?.unwrap_or_default();
+        let bundle_size = bundles
+            .filter(|_| repository.get().bundle)
+            .and_then(|bundles| bundles.size(&repo));
 
         Ok(into_response(View {
             repo,
+            snapshots: Snapshots {
+                formats: repository.get().snapshot_formats(),
+                checksums: snapshot_config.checksums(),
+            },
+            db_repository: repository,
             refs: Refs { heads, tags },
             commit_list: commits,
-            branch: None,
+            branch: query.branch,
+            linkifier,
+            indexing,
+            clone_url,
+            annotations,
+            bundle_size,
         }))
     })
     .await
     .context("Failed to attach to tokio task")?
 }
 
-pub fn get_default_branch_commits(
+/// The latest commits on the given branch, or the default branch if none was given.
+pub fn get_branch_commits(
     repository: &YokedRepository,
     database: &Arc<rocksdb::DB>,
+    branch: Option<&str>,
 ) -> Result<Vec<YokedCommit>> {
+    if let Some(branch) = branch {
+        let commit_tree = repository
+            .get()
+            .commit_tree(database.clone(), &format!("refs/heads/{branch}"));
+        return Ok(commit_tree.fetch_latest(11, 0)?);
+    }
+
     for branch in repository
         .get()
         .default_branch
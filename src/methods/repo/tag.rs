@@ -1,15 +1,18 @@
 use std::sync::Arc;
 
+use anyhow::Context;
 use askama::Template;
 use axum::{extract::Query, response::IntoResponse, Extension};
 use serde::Deserialize;
 
 use crate::{
+    archive::{SnapshotConfig, Snapshots},
+    error::NotFound,
     git::DetailedTag,
     into_response,
     methods::{
         filters,
-        repo::{Repository, RepositoryPath, Result},
+        repo::{Repository, RepositoryPath, Result, TagName},
     },
     Git,
 };
@@ -17,7 +20,7 @@ use crate::{
 #[derive(Deserialize)]
 pub struct UriQuery {
     #[serde(rename = "h")]
-    name: Arc<str>,
+    name: Option<Arc<str>>,
 }
 
 #[derive(Template)]
@@ -26,20 +29,46 @@ pub struct View {
     repo: Repository,
     tag: DetailedTag,
     branch: Option<Arc<str>>,
+    snapshots: Snapshots,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(git): Extension<Arc<Git>>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
+    tag_name: Option<Extension<TagName>>,
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
-    let open_repo = git.repo(repository_path, Some(query.name.clone())).await?;
+    // tags were previously linked to using `/tag?h=<name>`, so keep those links working
+    let name = tag_name
+        .map(|Extension(TagName(v))| v)
+        .or(query.name)
+        .context("no tag given")?;
+
+    let open_repo = git.repo(repository_path, Some(name.clone())).await?;
     let tag = open_repo.tag_info().await?;
 
+    let snapshot_formats = {
+        let repo = repo.clone();
+        tokio::task::spawn_blocking(move || {
+            let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+                .ok_or(NotFound("Repository does not exist"))?;
+
+            Ok::<_, anyhow::Error>(repository.get().snapshot_formats())
+        })
+        .await
+        .context("Failed to join Tokio task")??
+    };
+
     Ok(into_response(View {
         repo,
         tag,
-        branch: Some(query.name),
+        branch: Some(name),
+        snapshots: Snapshots {
+            formats: snapshot_formats,
+            checksums: snapshot_config.checksums(),
+        },
     }))
 }
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::Query,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Deserialize;
+
+use crate::{
+    archive::{SnapshotConfig, SnapshotFormat, Snapshots},
+    database::schema::{
+        commit::ArchivedAuthor,
+        tag::{YokedString, YokedTag},
+    },
+    error::NotFound,
+    into_response,
+    methods::{
+        feed::{FeedConfig, FeedValidators},
+        filters,
+        repo::{Repository, Result},
+    },
+    url_scheme::{absolute_url, repository_page_url},
+};
+
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "ofs")]
+    offset: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/tags.html")]
+pub struct View {
+    repo: Repository,
+    tags: Vec<(YokedString, YokedTag)>,
+    next_offset: Option<usize>,
+    branch: Option<Arc<str>>,
+    snapshots: Snapshots,
+}
+
+#[derive(Template)]
+#[template(path = "repo/tags.xml")]
+pub struct FeedView {
+    repo: Repository,
+    tags: Vec<(YokedString, YokedTag)>,
+    updated: (i64, i32),
+    self_url: String,
+    snapshot_formats: Vec<SnapshotFormat>,
+}
+
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(snapshot_config): Extension<SnapshotConfig>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let offset = query.offset.unwrap_or(0);
+
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let snapshot_formats = repository.get().snapshot_formats();
+        let mut tags = repository
+            .get()
+            .tag_tree(db)
+            .fetch_latest(PAGE_SIZE + 1, offset)?;
+
+        let next_offset = if tags.len() > PAGE_SIZE {
+            tags.pop();
+            Some(offset + PAGE_SIZE)
+        } else {
+            None
+        };
+
+        Ok(into_response(View {
+            repo,
+            tags,
+            next_offset,
+            branch: None,
+            snapshots: Snapshots {
+                formats: snapshot_formats,
+                checksums: snapshot_config.checksums(),
+            },
+        }))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
+
+pub async fn handle_atom(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(feed_config): Extension<FeedConfig>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    tokio::task::spawn_blocking(move || {
+        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
+            .ok_or(NotFound("Repository does not exist"))?;
+        let tags = repository
+            .get()
+            .tag_tree(db)
+            .fetch_latest(feed_config.tags, 0)?;
+
+        let updated = tags
+            .first()
+            .and_then(|(_, tag)| tag.get().tagger.as_ref().map(ArchivedAuthor::time))
+            .unwrap_or_else(|| filters::Timestamp::from(&repository.get().last_modified).0);
+
+        let mut key = Vec::new();
+        key.extend_from_slice(repo.as_os_str().as_encoded_bytes());
+        key.extend_from_slice(&feed_config.tags.to_be_bytes());
+        if let Some((name, _)) = tags.first() {
+            key.extend_from_slice(name.get().as_bytes());
+        }
+        key.extend_from_slice(&updated.unix_timestamp().to_be_bytes());
+
+        let validators = FeedValidators::new(updated, &key);
+
+        Ok(validators.respond(
+            &headers,
+            FeedView {
+                self_url: absolute_url(&repository_page_url(&repo.url(), "tags/atom")),
+                repo,
+                tags,
+                updated: (updated.unix_timestamp(), updated.offset().whole_seconds()),
+                snapshot_formats: repository.get().snapshot_formats(),
+            },
+        ))
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
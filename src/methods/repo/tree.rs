@@ -1,5 +1,10 @@
+use anyhow::Context;
 use askama::Template;
-use axum::{extract::Query, response::IntoResponse, Extension};
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Redirect},
+    Extension,
+};
 use itertools::Itertools;
 use serde::Deserialize;
 use std::path::PathBuf;
@@ -9,12 +14,15 @@ use std::{
 };
 
 use crate::{
+    archive::SnapshotFormat,
+    error::NotFound,
     git::{FileWithContent, PathDestination, TreeItem},
     into_response,
     methods::{
         filters,
         repo::{ChildPath, Repository, RepositoryPath, Result},
     },
+    url_scheme::push_query_value,
     Git, ResponseEither,
 };
 
@@ -23,24 +31,28 @@ pub struct UriQuery {
     id: Option<String>,
     #[serde(default)]
     raw: bool,
+    /// Show the source of files that would otherwise be rendered (ie. Markdown)
+    #[serde(default)]
+    source: bool,
     #[serde(rename = "h")]
     branch: Option<Arc<str>>,
 }
 
 impl Display for UriQuery {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut prefix = "?";
+        let mut query = String::new();
 
         if let Some(id) = self.id.as_deref() {
-            write!(f, "{prefix}id={id}")?;
-            prefix = "&";
+            query.push_str("?id=");
+            push_query_value(&mut query, id);
         }
 
         if let Some(branch) = self.branch.as_deref() {
-            write!(f, "{prefix}h={branch}")?;
+            query.push_str(if query.is_empty() { "?h=" } else { "&h=" });
+            push_query_value(&mut query, branch);
         }
 
-        Ok(())
+        f.write_str(&query)
     }
 }
 
@@ -53,6 +65,7 @@ pub struct TreeView {
     pub query: UriQuery,
     pub repo_path: PathBuf,
     pub branch: Option<Arc<str>>,
+    pub snapshot_formats: Vec<SnapshotFormat>,
 }
 
 #[derive(Template)]
@@ -62,6 +75,7 @@ pub struct FileView {
     pub repo_path: PathBuf,
     pub file: FileWithContent,
     pub branch: Option<Arc<str>>,
+    pub source: bool,
 }
 
 pub async fn handle(
@@ -69,30 +83,67 @@ pub async fn handle(
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(ChildPath(child_path)): Extension<ChildPath>,
     Extension(git): Extension<Arc<Git>>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
 
     Ok(
         match open_repo
-            .path(child_path.clone(), query.id.as_deref(), !query.raw)
+            .path(
+                child_path.clone(),
+                query.id.as_deref(),
+                !query.raw,
+                !query.source,
+            )
             .await?
         {
             PathDestination::Tree(items) => {
+                let snapshot_formats = {
+                    let repo = repo.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let repository =
+                            crate::database::schema::repository::Repository::open(&db, &*repo)?
+                                .ok_or(NotFound("Repository does not exist"))?;
+
+                        Ok::<_, anyhow::Error>(repository.get().snapshot_formats())
+                    })
+                    .await
+                    .context("Failed to join Tokio task")??
+                };
+
                 ResponseEither::Left(ResponseEither::Left(into_response(TreeView {
                     repo,
                     items,
                     branch: query.branch.clone(),
                     query,
                     repo_path: child_path.unwrap_or_default(),
+                    snapshot_formats,
                 })))
             }
-            PathDestination::File(file) if query.raw => ResponseEither::Right(file.content),
+            PathDestination::File(FileWithContent {
+                lfs: Some(pointer),
+                lfs_server: Some(server),
+                ..
+            }) if query.raw => {
+                let href = tokio::task::spawn_blocking(move || pointer.resolve_download(&server))
+                    .await
+                    .context("Failed to join Tokio task")??;
+
+                ResponseEither::Right(ResponseEither::Left(Redirect::temporary(&href)))
+            }
+            PathDestination::File(file) if query.raw => {
+                ResponseEither::Right(ResponseEither::Right(file.into_response()))
+            }
+            PathDestination::LargeFile(blob) => {
+                ResponseEither::Right(ResponseEither::Right(blob.into_response()))
+            }
             PathDestination::File(file) => {
                 ResponseEither::Left(ResponseEither::Right(into_response(FileView {
                     repo,
                     file,
                     branch: query.branch,
+                    source: query.source,
                     repo_path: child_path.unwrap_or_default(),
                 })))
             }
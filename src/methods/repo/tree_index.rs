@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Serialize;
+
+use super::{RepositoryPath, Result, TreeIndexRef};
+use crate::{git::Git, methods::api::json};
+
+#[derive(Serialize)]
+struct TreeIndex<'a> {
+    tree: String,
+    files: &'a [String],
+}
+
+/// Lists every file in the tree a reference points to, for the tree view's file finder. The
+/// tree's ID doubles as an `ETag`, so the listing is only sent again once the reference has
+/// moved on to a different tree.
+pub async fn handle(
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(TreeIndexRef(reference)): Extension<TreeIndexRef>,
+    Extension(git): Extension<Arc<Git>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let open_repo = git.repo(repository_path, Some(reference)).await?;
+    let (tree, files) = open_repo.tree_index().await?;
+
+    let etag = HeaderValue::try_from(format!("\"{tree}\"")).expect("oid is a valid header value");
+    let cache_control = HeaderValue::from_static("no-cache");
+
+    if headers.get(header::IF_NONE_MATCH) == Some(&etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+
+    let mut response = json(&TreeIndex {
+        tree: tree.to_string(),
+        files: &files,
+    });
+    response.headers_mut().insert(header::ETAG, etag);
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, cache_control);
+
+    Ok(response)
+}
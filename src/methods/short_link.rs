@@ -0,0 +1,102 @@
+//! Instance-wide short links to commits, resolving an abbreviated hash to the repository the
+//! commit was found in so links can be shared without naming the repository.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use itertools::Itertools;
+
+use super::{api::is_public, filters};
+use crate::{
+    database::schema::{
+        commit_location::{CommitLocation, CommitLocations},
+        repository::Repository,
+    },
+    error::Error,
+    into_response,
+    takedown::Takedowns,
+    url_scheme::repository_url,
+};
+
+/// The shortest abbreviation resolved, as with git's `core.abbrev`.
+const MIN_LENGTH: usize = 4;
+
+/// The most candidates listed when an abbreviation matches more than one commit.
+const MAX_CANDIDATES: usize = 20;
+
+/// The most index entries looked at for an abbreviation, short abbreviations on large instances
+/// would otherwise match a large part of the index.
+const MAX_SCANNED: usize = 1_000;
+
+#[derive(Template)]
+#[template(path = "short_link.html")]
+pub struct AmbiguousView {
+    spec: String,
+    candidates: Vec<CommitLocation>,
+}
+
+/// Redirects `/c/<hash>` to the page of the commit the hash abbreviates, in whichever repository
+/// it was found in first, or lists the commits it could mean if it's ambiguous.
+pub async fn handle(
+    Path(spec): Path<String>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Extension(takedowns): Extension<Arc<Takedowns>>,
+) -> Result<Response, Error> {
+    let spec = spec.to_ascii_lowercase();
+
+    if spec.len() < MIN_LENGTH || spec.len() > 40 || !spec.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::NotFound("Commit not found".into()));
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let mut locations = CommitLocations::new(db.clone()).find_by_prefix(&spec, MAX_SCANNED)?;
+
+        // buried repositories keep their entries until they're purged, and their paths may have
+        // been taken by a new repository since
+        locations.retain(|location| {
+            Repository::open(&db, &location.repository)
+                .ok()
+                .flatten()
+                .is_some_and(|repository| {
+                    repository.get().id.0.to_native() == location.repository_id
+                })
+                && is_public(&db, &takedowns, &location.repository)
+                && takedowns.allows(&location.repository, &location.hash)
+        });
+
+        // commits shared between forks are only listed once
+        let mut candidates: Vec<_> = locations
+            .into_iter()
+            .unique_by(|location| location.hash)
+            .take(MAX_CANDIDATES)
+            .collect();
+
+        match candidates.len() {
+            0 => Err(Error::NotFound("Commit not found".into())),
+            1 => {
+                let location = candidates.remove(0);
+
+                Ok(Redirect::to(&format!(
+                    "/{}/commit/?id={}",
+                    repository_url(&location.repository),
+                    const_hex::encode(location.hash),
+                ))
+                .into_response())
+            }
+            _ => Ok((
+                StatusCode::MULTIPLE_CHOICES,
+                into_response(AmbiguousView { spec, candidates }),
+            )
+                .into_response()),
+        }
+    })
+    .await
+    .context("Failed to join Tokio task")?
+}
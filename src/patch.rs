@@ -0,0 +1,355 @@
+//! Parses unified diffs, as produced by `git diff` and `diff -u`, and applies them to file contents
+//! in memory so a patch can be previewed against a reference without a worktree.
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+/// The changes a patch makes to a single file.
+#[derive(Debug)]
+pub struct FilePatch {
+    /// The path being patched, `None` if the file is created by the patch
+    pub old_path: Option<String>,
+    /// The path after patching, `None` if the file is deleted by the patch
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+    /// Set for `GIT binary patch` sections, which can't be previewed
+    pub binary: bool,
+}
+
+#[derive(Debug)]
+pub struct Hunk {
+    /// The line the hunk starts at in the original file, counting from 1
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+    /// Whether the patched file doesn't end in a newline after this hunk
+    pub new_missing_newline: bool,
+}
+
+#[derive(Debug)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    /// The lines the hunk expects to find in the original file.
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(v) | HunkLine::Remove(v) => Some(v.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    /// The lines the hunk leaves in their place.
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            HunkLine::Context(v) | HunkLine::Add(v) => Some(v.as_str()),
+            HunkLine::Remove(_) => None,
+        })
+    }
+}
+
+/// Splits a unified diff into the changes it makes to each file. Anything outside of the file
+/// sections, such as the commit message of a `git format-patch` email, is ignored.
+pub fn parse(diff: &str) -> anyhow::Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = diff.lines().peekable();
+
+    // whether we're between a `diff --git` line and the first hunk of the file it starts
+    let mut in_git_header = false;
+
+    while let Some(line) = lines.next() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            // the paths are taken from the `---`/`+++` lines where there are any, but renames,
+            // mode changes and binary patches go without them
+            let (old, new) = rest
+                .split_once(" b/")
+                .map(|(old, new)| (old.strip_prefix("a/").unwrap_or(old), new))
+                .context("Malformed diff --git header")?;
+
+            in_git_header = true;
+
+            files.push(FilePatch {
+                old_path: Some(old.to_string()),
+                new_path: Some(new.to_string()),
+                hunks: Vec::new(),
+                binary: false,
+            });
+        } else if line.starts_with("new file mode") {
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.old_path = None;
+            }
+        } else if line.starts_with("deleted file mode") {
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.new_path = None;
+            }
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.old_path = Some(path.to_string());
+            }
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.new_path = Some(path.to_string());
+            }
+        } else if line == "GIT binary patch" || line.starts_with("Binary files ") {
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.binary = true;
+            }
+        } else if let Some(old) = line.strip_prefix("--- ") {
+            let Some(new) = lines.next_if(|v| v.starts_with("+++ ")) else {
+                continue;
+            };
+
+            let old_path = parse_path(old, "a/");
+            let new_path = parse_path(&new[4..], "b/");
+
+            // plain unified diffs have no `diff --git` line to start the file section
+            if let Some(file) = files.last_mut().filter(|_| in_git_header) {
+                file.old_path = old_path;
+                file.new_path = new_path;
+            } else {
+                files.push(FilePatch {
+                    old_path,
+                    new_path,
+                    hunks: Vec::new(),
+                    binary: false,
+                });
+            }
+        } else if let Some(header) = line.strip_prefix("@@ -") {
+            in_git_header = false;
+
+            let file = files.last_mut().context("Hunk found outside of a file")?;
+            let (old_start, old_count, new_count) = parse_hunk_header(header)?;
+
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+                new_missing_newline: false,
+            };
+
+            let (mut old_seen, mut new_seen) = (0, 0);
+
+            while old_seen < old_count || new_seen < new_count {
+                let Some(line) = lines.next() else {
+                    bail!(
+                        "Hunk at line {old_start} of {} is truncated",
+                        file.display_path()
+                    );
+                };
+
+                match line.as_bytes().first() {
+                    Some(b'+') => {
+                        new_seen += 1;
+                        hunk.lines.push(HunkLine::Add(line[1..].to_string()));
+                    }
+                    Some(b'-') => {
+                        old_seen += 1;
+                        hunk.lines.push(HunkLine::Remove(line[1..].to_string()));
+                    }
+                    // some editors strip the trailing space from empty context lines
+                    Some(b' ') | None => {
+                        old_seen += 1;
+                        new_seen += 1;
+                        hunk.lines
+                            .push(HunkLine::Context(line.get(1..).unwrap_or("").to_string()));
+                    }
+                    Some(b'\\') => {}
+                    Some(_) => bail!(
+                        "Unexpected line in hunk at line {old_start} of {}",
+                        file.display_path()
+                    ),
+                }
+
+                if lines.next_if(|v| v.starts_with('\\')).is_some() {
+                    // only the lines left in the patched file decide how it ends
+                    if matches!(
+                        hunk.lines.last(),
+                        Some(HunkLine::Add(_) | HunkLine::Context(_))
+                    ) {
+                        hunk.new_missing_newline = true;
+                    }
+                }
+            }
+
+            file.hunks.push(hunk);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Parses the path from a `---` or `+++` line, stripping the `a/` or `b/` prefix git adds and any
+/// timestamp `diff -u` adds. `/dev/null` stands for a file that doesn't exist.
+fn parse_path(value: &str, prefix: &str) -> Option<String> {
+    let value = value.split('\t').next().unwrap_or(value).trim_end();
+
+    if value == "/dev/null" {
+        return None;
+    }
+
+    Some(value.strip_prefix(prefix).unwrap_or(value).to_string())
+}
+
+/// Parses the `-<start>,<count> +<start>,<count> @@` following the `@@ -` of a hunk header,
+/// returning the original start line and the number of lines on either side.
+fn parse_hunk_header(header: &str) -> anyhow::Result<(usize, usize, usize)> {
+    let (ranges, _) = header.split_once(" @@").context("Malformed hunk header")?;
+    let (old, new) = ranges.split_once(" +").context("Malformed hunk header")?;
+
+    let parse_range = |range: &str| -> anyhow::Result<(usize, usize)> {
+        Ok(match range.split_once(',') {
+            Some((start, count)) => (start.parse()?, count.parse()?),
+            None => (range.parse()?, 1),
+        })
+    };
+
+    let (old_start, old_count) = parse_range(old).context("Malformed hunk header")?;
+    let (_, new_count) = parse_range(new).context("Malformed hunk header")?;
+
+    Ok((old_start, old_count, new_count))
+}
+
+impl FilePatch {
+    /// The path to show for this file, preferring the one it ends up at.
+    pub fn display_path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// A hunk that couldn't be applied.
+#[derive(Serialize, Debug)]
+pub struct Conflict {
+    /// The position of the hunk within the file's changes, counting from 1, or 0 if the file as a
+    /// whole can't be patched
+    pub hunk: usize,
+    /// The line the hunk expected to start at in the original file, or 0 if the file as a whole
+    /// can't be patched
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// How a patch fared against a single file.
+#[derive(Serialize, Debug)]
+pub struct FilePreview {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    /// The content of the file once patched, `None` if the patch deletes it or it couldn't be
+    /// patched
+    pub content: Option<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Applies `patch` to `original`, the content of the file before patching (`None` if it doesn't
+/// exist). Hunks are applied where they say they apply, or where their context is found nearest
+/// to there if earlier changes have shifted the file. Every hunk is tried, so that all of the
+/// conflicts can be reported at once.
+pub fn apply(original: Option<&str>, patch: &FilePatch) -> Result<Option<String>, Vec<Conflict>> {
+    let conflict = |hunk, line, reason| Conflict { hunk, line, reason };
+
+    match (original, &patch.old_path) {
+        (Some(_), None) => return Err(vec![conflict(0, 0, "file already exists")]),
+        (None, Some(_)) => return Err(vec![conflict(0, 0, "file does not exist")]),
+        _ => {}
+    }
+
+    if patch.binary {
+        return Err(vec![conflict(0, 0, "binary patches can't be previewed")]);
+    }
+
+    let original = original.unwrap_or_default();
+    let mut lines: Vec<&str> = if original.is_empty() {
+        Vec::new()
+    } else {
+        original
+            .strip_suffix('\n')
+            .unwrap_or(original)
+            .split('\n')
+            .collect()
+    };
+    let mut missing_newline = !original.is_empty() && !original.ends_with('\n');
+    let mut conflicts = Vec::new();
+
+    // how far earlier hunks have moved the rest of the file
+    let mut offset: isize = 0;
+
+    for (index, hunk) in patch.hunks.iter().enumerate() {
+        let expected = hunk.old_lines();
+
+        // hunks that only add lines give the line they're added after, rather than the first
+        // line they touch
+        let wanted = if expected.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        }
+        .saturating_add_signed(offset);
+
+        let Some(position) = find_hunk(&lines, &expected, wanted) else {
+            conflicts.push(conflict(
+                index + 1,
+                hunk.old_start,
+                "context does not match",
+            ));
+            continue;
+        };
+
+        let replacement: Vec<&str> = hunk.new_lines().collect();
+        offset += isize::try_from(replacement.len()).unwrap_or(isize::MAX)
+            - isize::try_from(expected.len()).unwrap_or(isize::MAX);
+
+        let at_end = position + expected.len() == lines.len();
+        lines.splice(position..position + expected.len(), replacement);
+
+        if at_end {
+            missing_newline = hunk.new_missing_newline;
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+
+    if patch.new_path.is_none() {
+        return Ok(None);
+    }
+
+    let mut content = lines.join("\n");
+    if !lines.is_empty() && !missing_newline {
+        content.push('\n');
+    }
+
+    Ok(Some(content))
+}
+
+/// Finds where `expected` appears in `lines`, looking outwards from `wanted`. Line endings are
+/// ignored, since the lines of a patch pasted in or sent by email may have lost them.
+fn find_hunk(lines: &[&str], expected: &[&str], wanted: usize) -> Option<usize> {
+    let matches_at = |position: usize| {
+        lines
+            .get(position..position + expected.len())
+            .is_some_and(|found| {
+                found
+                    .iter()
+                    .zip(expected)
+                    .all(|(a, b)| a.trim_end_matches('\r') == b.trim_end_matches('\r'))
+            })
+    };
+
+    if expected.is_empty() {
+        return (wanted <= lines.len()).then_some(wanted);
+    }
+
+    (0..=lines.len()).find_map(|distance| {
+        [wanted.checked_sub(distance), wanted.checked_add(distance)]
+            .into_iter()
+            .flatten()
+            .find(|&position| matches_at(position))
+    })
+}
@@ -0,0 +1,87 @@
+//! Parses gitweb-style `projects.list` files, which limit the repositories served from a scan
+//! root to the ones they list.
+
+use std::path::PathBuf;
+
+use path_clean::PathClean;
+
+/// The repositories a `projects.list` asks to be served.
+#[derive(Default, Debug)]
+pub struct ProjectsList {
+    /// Repositories listed by their path
+    pub paths: Vec<PathBuf>,
+    /// Globs matched against the path of every repository under the root
+    pub patterns: Vec<String>,
+    /// Globs, from lines starting with `!`, excluding repositories that'd otherwise be served
+    pub exclusions: Vec<String>,
+}
+
+impl ProjectsList {
+    /// Parses a gitweb `projects.list`, where each line is the URL-encoded path of a repository
+    /// optionally followed by a space and its owner. Paths may also be globs, where `*` doesn't
+    /// match across directories but `**` does, and lines starting with `!` exclude the
+    /// repositories they match whichever line they'd otherwise be served by. Blank lines, lines
+    /// starting with `#` and paths leading out of the root are ignored.
+    pub fn parse(content: &str) -> Self {
+        let mut projects = Self::default();
+
+        for project in content
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+        {
+            if project.starts_with('#') {
+                continue;
+            }
+
+            let (excluded, project) = match project.strip_prefix('!') {
+                Some(project) => (true, project),
+                None => (false, project),
+            };
+
+            let project = PathBuf::from(unescape(project)).clean();
+
+            if project.is_absolute() || project.starts_with("..") {
+                continue;
+            }
+
+            let Some(project) = project.to_str() else {
+                continue;
+            };
+
+            if excluded {
+                projects.exclusions.push(project.to_string());
+            } else if project.contains(['*', '?', '[']) {
+                projects.patterns.push(project.to_string());
+            } else {
+                projects.paths.push(PathBuf::from(project));
+            }
+        }
+
+        projects
+    }
+}
+
+/// Decodes the `+` and `%XX` escapes used by `projects.list`.
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .and_then(|v| u8::from_str_radix(v, 16).ok());
+
+        if let Some(v) = escaped {
+            out.push(v);
+            i += 3;
+        } else {
+            out.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
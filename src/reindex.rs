@@ -0,0 +1,195 @@
+//! `--reindex-all`, rebuilds the index from scratch alongside the one being served from and swaps
+//! it in once it's complete, so a corrupt or bloated index can be replaced without downtime.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use rocksdb::Options;
+use tracing::{info, warn};
+
+use crate::{
+    column_family_descriptors,
+    database::{
+        indexer::{self, IndexOptions, IndexState},
+        progress::IndexProgress,
+        schema::{
+            annotations::Annotations,
+            announcement::Announcement,
+            ref_update::RefUpdateLog,
+            repository::{Repository, RepositoryId},
+            tombstone::Tombstone,
+        },
+    },
+    open_db,
+    repository_pool::RepositoryPool,
+    scan_path::ScanPaths,
+    schedule::RunPlan,
+    shutdown,
+};
+
+/// How often to check whether requests still hold on to a database that's been swapped out.
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The database requests are served from, which may be swapped out while the server is running.
+pub struct SharedDb(RwLock<Arc<rocksdb::DB>>);
+
+impl SharedDb {
+    pub fn new(db: Arc<rocksdb::DB>) -> Self {
+        Self(RwLock::new(db))
+    }
+
+    pub fn get(&self) -> Arc<rocksdb::DB> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Serves requests from `db` from now on, returning the database that was being served.
+    fn replace(&self, db: Arc<rocksdb::DB>) -> Arc<rocksdb::DB> {
+        std::mem::replace(&mut *self.0.write().unwrap(), db)
+    }
+}
+
+/// Hands each request the database currently being served from as an `Extension`.
+pub async fn inject(State(db): State<Arc<SharedDb>>, mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(db.get());
+    next.run(request).await
+}
+
+/// Appends `suffix` to the database's path, giving the path of a sibling directory.
+pub fn sibling(db_store: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(db_store.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Builds a new index into `<db_store>.reindex` while `shared` carries on serving the old one,
+/// then moves the old index to `<db_store>.backup` and the new one into its place. Returns the
+/// new database, which `old` should no longer be used in place of.
+pub fn rebuild(
+    db_store: &Path,
+    shared: &SharedDb,
+    old: Arc<rocksdb::DB>,
+    scan_paths: &ScanPaths,
+    repositories: &RepositoryPool,
+    options: &IndexOptions,
+) -> anyhow::Result<Arc<rocksdb::DB>> {
+    let rebuild_path = sibling(db_store, ".reindex");
+    let backup_path = sibling(db_store, ".backup");
+
+    // left behind by a rebuild that was interrupted
+    if rebuild_path.exists() {
+        std::fs::remove_dir_all(&rebuild_path)
+            .context("Failed to remove previous incomplete rebuild")?;
+    }
+
+    info!("Rebuilding index into {}", rebuild_path.display());
+
+    let new = open_db(&rebuild_path)?;
+
+    // repositories announced as ready are already served from the old index, so the rebuild
+    // reports its progress separately
+    indexer::run(
+        scan_paths,
+        &new,
+        repositories,
+        &IndexProgress::default(),
+        options,
+        &RunPlan::All,
+        &mut IndexState::default(),
+    );
+
+    ensure!(
+        !shutdown::requested(),
+        "Shut down before the rebuild finished, it'll be started again from scratch next time"
+    );
+
+    // the announcement isn't derived from the repositories, so it's carried over
+    if let Some(announcement) = Announcement::open(&old)? {
+        announcement.insert(&new)?;
+    }
+
+    // as are what admins have set on repositories, and the repositories that have gone missing,
+    // which wouldn't otherwise be listed or restorable
+    for (path, annotations) in Annotations::fetch_all(&old)? {
+        annotations.insert(&new, &path)?;
+    }
+    Tombstone::copy_all(&old, &new)?;
+
+    // nor are the rewrites and ref updates, which can't be seen again once the old tips are gone
+    let rebuilt = Repository::fetch_all(&new)?;
+    for (path, repository) in Repository::fetch_all(&old)? {
+        let Some(rebuilt) = rebuilt.get(&path) else {
+            continue;
+        };
+
+        let old_id = RepositoryId(repository.get().id.0.to_native());
+        let new_id = RepositoryId(rebuilt.get().id.0.to_native());
+
+        RefUpdateLog::new(old.clone(), old_id).copy_to(&RefUpdateLog::new(new.clone(), new_id))?;
+    }
+
+    new.flush()?;
+
+    // the indexer has already flushed everything it wrote to disk
+    release(new);
+
+    // the new index can't be moved while it's open for writing, so it's served read-only for
+    // the short time both directories are being moved around
+    let interim = Arc::new(rocksdb::DB::open_cf_descriptors_read_only(
+        &Options::default(),
+        &rebuild_path,
+        column_family_descriptors(),
+        false,
+    )?);
+
+    drop(old);
+    release(shared.replace(interim));
+
+    if backup_path.exists() {
+        std::fs::remove_dir_all(&backup_path).context("Failed to remove previous backup")?;
+    }
+
+    std::fs::rename(db_store, &backup_path).context("Failed to back up old index")?;
+    std::fs::rename(&rebuild_path, db_store).context("Failed to move rebuilt index into place")?;
+
+    let db = open_db(db_store)?;
+    drop(shared.replace(db.clone()));
+
+    info!(
+        "Rebuilt index swapped in, the old index was kept at {}",
+        backup_path.display()
+    );
+
+    Ok(db)
+}
+
+/// Closes a database once every request still using it has finished with it.
+fn release(mut db: Arc<rocksdb::DB>) {
+    let mut waited = Duration::ZERO;
+
+    loop {
+        match Arc::try_unwrap(db) {
+            Ok(db) => {
+                drop(db);
+                return;
+            }
+            Err(v) => db = v,
+        }
+
+        if waited.as_secs() > 0 && waited.as_millis() % 10_000 == 0 {
+            warn!("Still waiting for requests to finish with the old index");
+        }
+
+        std::thread::sleep(RELEASE_POLL_INTERVAL);
+        waited += RELEASE_POLL_INTERVAL;
+    }
+}
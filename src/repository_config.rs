@@ -0,0 +1,90 @@
+//! The per-repository settings read from a repository's own files, its Git `config` and a
+//! cgit-style `cgitrc`.
+
+use std::{collections::HashMap, path::Path};
+
+use ini::Ini;
+
+/// The Git config file of a bare repository, used to pull out per-repository settings.
+pub struct RepositoryConfig(Option<Ini>);
+
+impl RepositoryConfig {
+    pub fn load(repository_path: &Path) -> Self {
+        Self(Ini::load_from_file(repository_path.join("config")).ok())
+    }
+
+    /// Parses the contents of a config file, a config that can't be parsed has no settings.
+    pub fn parse(content: &str) -> Self {
+        Self(Ini::load_from_str(content).ok())
+    }
+
+    /// Fetches a value from the config, section and key names are matched case-insensitively
+    /// just like Git does.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.get_all(section, key).last()
+    }
+
+    /// Fetches every value of a multi-valued key, in the order they appear in the config.
+    pub fn get_all<'a: 'b, 'b>(
+        &'a self,
+        section: &'b str,
+        key: &'b str,
+    ) -> impl Iterator<Item = &'a str> + 'b {
+        self.0
+            .iter()
+            .flat_map(Ini::iter)
+            .filter(move |(name, _)| name.is_some_and(|name| name.eq_ignore_ascii_case(section)))
+            .flat_map(|(_, properties)| properties.iter())
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn get_bool(&self, section: &str, key: &str) -> bool {
+        self.get(section, key).is_some_and(|v| {
+            ["true", "yes", "on", "1"]
+                .iter()
+                .any(|truthy| v.eq_ignore_ascii_case(truthy))
+        })
+    }
+}
+
+/// A cgit-style `cgitrc` file in the root of a repository, holding `key=value` settings for it.
+/// Only the repository settings cgit and rgit have in common are read.
+pub struct CgitRc(HashMap<String, String>);
+
+impl CgitRc {
+    pub fn load(repository_path: &Path) -> Self {
+        let Ok(content) = std::fs::read_to_string(repository_path.join("cgitrc")) else {
+            return Self(HashMap::new());
+        };
+
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Self {
+        Self(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.starts_with('#'))
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| {
+                    let key = key.trim();
+                    (
+                        key.strip_prefix("repo.").unwrap_or(key).to_string(),
+                        value.trim().to_string(),
+                    )
+                })
+                .filter(|(_, value)| !value.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get(key).is_some_and(|v| v == "1")
+    }
+}
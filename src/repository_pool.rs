@@ -0,0 +1,77 @@
+//! Repositories opened once and shared between request handlers and the indexer, so neither has
+//! to re-read a repository's config and pack indexes every time it's touched.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use gix::ThreadSafeRepository;
+use moka::sync::Cache;
+
+/// Repositories that haven't been used for this long are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The number of repositories kept open at once, the least recently used are closed first.
+const MAX_OPEN_REPOSITORIES: u64 = 100;
+
+#[derive(Clone)]
+struct PooledRepository {
+    repository: ThreadSafeRepository,
+    /// Modification time of `packed-refs` when the repository was opened.
+    packed_refs_modified: Option<SystemTime>,
+}
+
+pub struct RepositoryPool {
+    repositories: Cache<PathBuf, PooledRepository>,
+}
+
+impl Default for RepositoryPool {
+    fn default() -> Self {
+        Self {
+            repositories: Cache::builder()
+                .time_to_idle(IDLE_TIMEOUT)
+                .max_capacity(MAX_OPEN_REPOSITORIES)
+                .build(),
+        }
+    }
+}
+
+impl RepositoryPool {
+    /// Returns the repository at `path`, reopening it if its `packed-refs` has been rewritten since
+    /// it was opened, which happens whenever the repository is repacked or garbage collected.
+    ///
+    /// This touches the filesystem, so must not be called from an async context.
+    pub fn get(&self, path: &Path) -> Result<ThreadSafeRepository, gix::open::Error> {
+        let packed_refs_modified = std::fs::metadata(path.join("packed-refs"))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        if let Some(pooled) = self.repositories.get(path) {
+            if pooled.packed_refs_modified == packed_refs_modified {
+                return Ok(pooled.repository);
+            }
+        }
+
+        let repository = match gix::open::Options::isolated()
+            .open_path_as_is(true)
+            .open(path)
+        {
+            Ok(v) => v,
+            Err(e) => {
+                self.repositories.invalidate(path);
+                return Err(e);
+            }
+        };
+
+        self.repositories.insert(
+            path.to_path_buf(),
+            PooledRepository {
+                repository: repository.clone(),
+                packed_refs_modified,
+            },
+        );
+
+        Ok(repository)
+    }
+}
@@ -0,0 +1,379 @@
+//! The directories repositories are served from. Several can be given, each optionally serving
+//! its repositories under a URL prefix and listing them in a `projects.list` rather than having
+//! them discovered, with all of them merged into the one index.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use gix::{bstr::ByteSlice, glob::wildmatch};
+use path_clean::PathClean;
+use tracing::{error, warn};
+
+use crate::projects_list::ProjectsList;
+
+/// A directory repositories are served from, given as
+/// `<path>[,prefix=<prefix>][,projects-list=<file>]`.
+#[derive(Clone, Debug)]
+pub struct ScanRoot {
+    pub path: PathBuf,
+    /// The path the root's repositories are served under, if they aren't served at the top level
+    pub prefix: Option<PathBuf>,
+    /// A gitweb-style list of the repositories to serve, relative to the root, one per line.
+    /// Lines may be globs matched against every repository under the root, or start with `!`
+    /// to exclude the repositories they match. Every repository under the root is served if not
+    /// given
+    pub projects_list: Option<PathBuf>,
+}
+
+impl FromStr for ScanRoot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut path = s;
+        let mut prefix = None;
+        let mut projects_list = None;
+
+        // options are peeled off the end, so commas elsewhere in the path are left alone
+        while let Some((rest, option)) = path.rsplit_once(',') {
+            if let Some(value) = option.strip_prefix("prefix=") {
+                let value = PathBuf::from(value.trim_matches('/')).clean();
+
+                if value.as_os_str().is_empty() || value.starts_with("..") {
+                    bail!("invalid prefix `{}`", value.display());
+                }
+
+                prefix = Some(value);
+            } else if let Some(value) = option.strip_prefix("projects-list=") {
+                projects_list = Some(PathBuf::from(value));
+            } else {
+                break;
+            }
+
+            path = rest;
+        }
+
+        if path.is_empty() {
+            bail!("scan path is empty");
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            prefix,
+            projects_list,
+        })
+    }
+}
+
+impl Display for ScanRoot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display())?;
+
+        if let Some(prefix) = &self.prefix {
+            write!(f, ",prefix={}", prefix.display())?;
+        }
+
+        if let Some(projects_list) = &self.projects_list {
+            write!(f, ",projects-list={}", projects_list.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ScanRoot {
+    /// Where the repository served at `relative` would be on disk if it belonged to this root,
+    /// unless the root's projects list leaves it out.
+    fn candidate(&self, relative: &Path) -> Option<PathBuf> {
+        let relative = match &self.prefix {
+            Some(prefix) => relative.strip_prefix(prefix).ok()?,
+            None => relative,
+        };
+
+        (!relative.as_os_str().is_empty() && self.lists(relative)).then(|| self.path.join(relative))
+    }
+
+    /// Whether the repository at `relative` in this root is one it serves. Roots without a
+    /// projects list serve everything in them, as do roots whose list can't be read so a
+    /// transient failure doesn't take their repositories down.
+    fn lists(&self, relative: &Path) -> bool {
+        let Some(projects_list) = &self.projects_list else {
+            return true;
+        };
+
+        match std::fs::read_to_string(projects_list) {
+            Ok(content) => {
+                let projects = ProjectsList::parse(&content);
+
+                (projects.paths.iter().any(|v| v == relative)
+                    || matches_any(&projects.patterns, relative))
+                    && !matches_any(&projects.exclusions, relative)
+            }
+            Err(error) => {
+                warn!(%error, "Failed to read {}", projects_list.display());
+                true
+            }
+        }
+    }
+
+    /// Finds the repositories in this root, as the paths they're served at and their git
+    /// directories.
+    fn discover(&self, discovery: &DiscoveryConfig) -> Vec<(PathBuf, PathBuf)> {
+        let mut discovered = Vec::new();
+
+        if let Some(projects_list) = &self.projects_list {
+            match std::fs::read_to_string(projects_list)
+                .map(|content| ProjectsList::parse(&content))
+            {
+                Ok(projects) => discovered = self.discover_listed(&projects, discovery),
+                Err(error) => error!(%error, "Failed to read {}", projects_list.display()),
+            }
+        } else {
+            discover_repositories(&self.path, discovery, &mut discovered);
+        }
+
+        discovered
+            .into_iter()
+            .filter_map(|full| {
+                let relative = full.strip_prefix(&self.path).ok()?;
+                let relative = match &self.prefix {
+                    Some(prefix) => prefix.join(relative),
+                    None => relative.to_path_buf(),
+                };
+
+                Some((relative, git_dir(&full).unwrap_or(full)))
+            })
+            .collect()
+    }
+
+    /// Finds the repositories named by the root's projects list, in the order they're listed.
+    fn discover_listed(
+        &self,
+        projects: &ProjectsList,
+        discovery: &DiscoveryConfig,
+    ) -> Vec<PathBuf> {
+        let mut discovered: Vec<_> = projects
+            .paths
+            .iter()
+            .map(|v| self.path.join(v))
+            .filter(|v| v.is_dir())
+            .collect();
+
+        // globs can only be expanded by scanning the root for every repository in it
+        if !projects.patterns.is_empty() {
+            let mut found = Vec::new();
+            discover_repositories(&self.path, discovery, &mut found);
+
+            discovered.extend(found.into_iter().filter(|full| {
+                full.strip_prefix(&self.path)
+                    .is_ok_and(|relative| matches_any(&projects.patterns, relative))
+            }));
+        }
+
+        let mut seen = HashSet::new();
+
+        discovered.retain(|full| {
+            full.strip_prefix(&self.path).is_ok_and(|relative| {
+                !matches_any(&projects.exclusions, relative) && seen.insert(relative.to_path_buf())
+            })
+        });
+
+        discovered
+    }
+}
+
+/// How scan paths without a projects list are searched for repositories.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct DiscoveryConfig {
+    /// Skips the directories matching a glob when scanning for repositories, matched against
+    /// their path relative to the scan path where `*` doesn't match across directories but `**`
+    /// does (eg. '*/attic/*'), may be given multiple times
+    #[clap(long = "exclude-path", value_name = "GLOB")]
+    pub exclude_paths: Vec<String>,
+    /// How many directories deep repositories are looked for below each scan path, where 1 only
+    /// finds the repositories directly inside of it. Unlimited if not given
+    #[clap(long)]
+    pub scan_depth: Option<usize>,
+    /// Doesn't follow symlinks to directories when scanning for repositories
+    #[clap(long)]
+    pub no_follow_symlinks: bool,
+}
+
+/// Every directory repositories are served from.
+#[derive(Clone, Debug)]
+pub struct ScanPaths {
+    roots: Vec<ScanRoot>,
+    discovery: DiscoveryConfig,
+}
+
+impl ScanPaths {
+    pub fn new(roots: Vec<ScanRoot>, discovery: DiscoveryConfig) -> Self {
+        Self { roots, discovery }
+    }
+
+    /// Finds every repository to serve, as the paths they're served at and their git
+    /// directories. Where several roots have a repository at the same path, the one given first
+    /// wins.
+    pub fn discover(&self) -> Vec<(PathBuf, PathBuf)> {
+        let mut seen = HashSet::new();
+        let mut discovered = Vec::new();
+
+        for root in &self.roots {
+            for (relative, full) in root.discover(&self.discovery) {
+                if seen.insert(relative.clone()) {
+                    discovered.push((relative, full));
+                } else {
+                    warn!(
+                        "Repository {} is shadowed by another scan path, ignoring it",
+                        full.display()
+                    );
+                }
+            }
+        }
+
+        discovered
+    }
+
+    /// The git directory of the repository served at `relative`, preferring the first root it
+    /// exists in. Returns the path it'd have in the first root it could belong to if it doesn't
+    /// exist in any of them, or `None` if no root serves it, such as when it's been taken off
+    /// every projects list it was on.
+    pub fn resolve<P: AsRef<Path>>(&self, relative: P) -> Option<PathBuf> {
+        let relative = relative.as_ref();
+        let mut candidates = self
+            .roots
+            .iter()
+            .filter_map(|root| root.candidate(relative));
+
+        let first = candidates.next()?;
+
+        let found = if first.exists() {
+            first
+        } else {
+            candidates.find(|v| v.exists()).unwrap_or(first)
+        };
+
+        Some(git_dir(&found).unwrap_or(found))
+    }
+
+    /// Returns the first root that can't be read, so callers can avoid treating the
+    /// repositories in it as gone.
+    pub fn unreadable(&self) -> Option<(&Path, std::io::Error)> {
+        self.roots
+            .iter()
+            .find_map(|root| Some((root.path.as_path(), std::fs::read_dir(&root.path).err()?)))
+    }
+}
+
+impl From<PathBuf> for ScanPaths {
+    fn from(path: PathBuf) -> Self {
+        Self::new(
+            vec![ScanRoot {
+                path,
+                prefix: None,
+                projects_list: None,
+            }],
+            DiscoveryConfig::default(),
+        )
+    }
+}
+
+/// Whether `path` matches any of the globs, where `*` doesn't match across directories but `**`
+/// does.
+fn matches_any(patterns: &[String], path: &Path) -> bool {
+    let path = path.as_os_str().as_encoded_bytes().as_bstr();
+
+    patterns.iter().any(|pattern| {
+        wildmatch(
+            pattern.as_bytes().as_bstr(),
+            path,
+            wildmatch::Mode::NO_MATCH_SLASH_LITERAL,
+        )
+    })
+}
+
+/// Finds the repositories below `root`, as the paths of bare repositories and the working
+/// directories of checkouts and linked worktrees.
+fn discover_repositories(root: &Path, discovery: &DiscoveryConfig, discovered: &mut Vec<PathBuf>) {
+    let mut visited = HashSet::new();
+    walk_repositories(root, root, 1, discovery, &mut visited, discovered);
+}
+
+fn walk_repositories(
+    root: &Path,
+    current: &Path,
+    depth: usize,
+    discovery: &DiscoveryConfig,
+    visited: &mut HashSet<PathBuf>,
+    discovered_repos: &mut Vec<PathBuf>,
+) {
+    // symlinks can lead back to a directory that's already been walked
+    if let Ok(canonical) = current.canonicalize() {
+        if !visited.insert(canonical) {
+            return;
+        }
+    }
+
+    let entries = match std::fs::read_dir(current) {
+        Ok(v) => v,
+        Err(error) => {
+            error!(%error, "Failed to enter repository directory {}", current.display());
+            return;
+        }
+    };
+
+    let dirs = entries
+        .filter_map(Result::ok)
+        .filter(|entry| match entry.file_type() {
+            Ok(v) if v.is_symlink() => !discovery.no_follow_symlinks && entry.path().is_dir(),
+            Ok(v) => v.is_dir(),
+            Err(_) => false,
+        })
+        .map(|v| v.path())
+        .filter(|path| {
+            path.strip_prefix(root).map_or(true, |relative| {
+                !matches_any(&discovery.exclude_paths, relative)
+            })
+        });
+
+    for dir in dirs {
+        if git_dir(&dir).is_some() {
+            discovered_repos.push(dir);
+        } else if discovery.scan_depth.map_or(true, |max| depth < max) {
+            // not a repository, lets look deeper
+            walk_repositories(root, &dir, depth + 1, discovery, visited, discovered_repos);
+        }
+    }
+}
+
+/// The git directory of the repository at `path`, if there is one. That's `path` itself for bare
+/// repositories, or where its `.git` leads for checkouts and linked worktrees.
+fn git_dir(path: &Path) -> Option<PathBuf> {
+    if is_git_dir(path) {
+        return Some(path.to_path_buf());
+    }
+
+    let dot_git = path.join(".git");
+
+    if dot_git.is_dir() {
+        return is_git_dir(&dot_git).then_some(dot_git);
+    }
+
+    // linked worktrees have a `.git` file pointing at their own git directory in the main
+    // repository's
+    let content = std::fs::read_to_string(&dot_git).ok()?;
+    let target = path.join(content.strip_prefix("gitdir:")?.trim());
+
+    is_git_dir(&target).then_some(target)
+}
+
+/// Whether `path` looks like a git directory, which fresh repositories are despite not having a
+/// `packed-refs` yet.
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file()
+        && (path.join("objects").is_dir() || path.join("commondir").is_file())
+}
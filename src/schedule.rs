@@ -0,0 +1,403 @@
+//! When the indexer runs. The scan path as a whole is indexed on a schedule given either as an
+//! interval or a cron expression, with optional jitter so instances sharing a host don't all
+//! wake up at once. Repositories can set their own interval through `rgit.refreshInterval`, and
+//! are then indexed on that instead. With `--activity-hints`, repositories without their own
+//! interval are given one by how often they're committed to.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, Time};
+
+use crate::{activity::Activity, database::schema::repository::Repository};
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Never,
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The first time this schedule fires after `after`.
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        match self {
+            Self::Never => None,
+            Self::Every(interval) => Some(after + *interval),
+            Self::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Never => write!(f, "never"),
+            Self::Every(v) => write!(f, "{}", humantime::format_duration(*v)),
+            Self::Cron(cron) => write!(f, "{}", cron.expression),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "never" {
+            Ok(Self::Never)
+        } else if let Ok(v) = humantime::parse_duration(s) {
+            Ok(Self::Every(v))
+        } else if s.split_whitespace().count() == 5 {
+            Ok(Self::Cron(Cron::from_str(s)?))
+        } else {
+            Err("must be a human readable duration (eg. '10m'), a cron expression or 'never'")
+        }
+    }
+}
+
+/// A standard five field cron expression (minute, hour, day of month, month and day of week),
+/// evaluated in UTC.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields were given, as cron matches a day if
+    /// either of them does when both are.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    /// Searching stops after this many days, which is long enough to reach the next leap day.
+    const SEARCH_DAYS: i64 = 366 * 8;
+
+    fn matches_day(&self, date: time::Date) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().number_days_from_sunday()) != 0;
+
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let after = after.to_offset(time::UtcOffset::UTC);
+        let mut next = after.replace_time(Time::from_hms(after.hour(), after.minute(), 0).ok()?)
+            + time::Duration::minutes(1);
+        let limit = next + time::Duration::days(Self::SEARCH_DAYS);
+
+        while next < limit {
+            if self.months & (1 << u8::from(next.month())) == 0 || !self.matches_day(next.date()) {
+                next = next
+                    .replace_date(next.date().next_day()?)
+                    .replace_time(Time::MIDNIGHT);
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.replace_time(Time::from_hms(next.hour(), 0, 0).ok()?)
+                    + time::Duration::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next += time::Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+}
+
+impl FromStr for Cron {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err("cron expressions must have five fields");
+        };
+
+        // sunday can be given as either 0 or 7
+        let mut weekday_bits = parse_cron_field(weekdays, 0, 7)?;
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+
+        Ok(Self {
+            expression: s.to_string(),
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)?,
+            days: parse_cron_field(days, 1, 31)?,
+            months: parse_cron_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+/// Parses a field made up of a comma separated list of `*`, single values and ranges, each
+/// optionally followed by a step (eg. `*/15` or `1-5,10`), into a bitset of the values it
+/// matches.
+fn parse_cron_field(field: &str, min: u64, max: u64) -> Result<u64, &'static str> {
+    const INVALID: &str = "invalid cron field";
+
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| INVALID)?),
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start.parse().map_err(|_| INVALID)?,
+                end.parse().map_err(|_| INVALID)?,
+            )
+        } else {
+            let start = range.parse().map_err(|_| INVALID)?;
+            // `5/15` is shorthand for `5-<max>/15`
+            (start, if part.contains('/') { max } else { start })
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(INVALID);
+        }
+
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+/// The repositories an index run should go through.
+pub enum RunPlan {
+    All,
+    /// Only the given repositories, those with their own interval that are due.
+    Only(HashSet<String>),
+    /// Every repository besides the given ones, those with their own interval that aren't due.
+    Except(HashSet<String>),
+}
+
+impl RunPlan {
+    pub fn includes(&self, repository: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(v) => v.contains(repository),
+            Self::Except(v) => !v.contains(repository),
+        }
+    }
+}
+
+/// Keeps track of when the indexer, and each repository with its own interval, is next due.
+pub struct Scheduler {
+    schedule: Schedule,
+    jitter: Duration,
+    activity_hints: bool,
+    state: Mutex<SchedulerState>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// When the scan path is next due to be indexed, `None` until the first run has finished or
+    /// if the schedule never fires
+    next_run: Option<OffsetDateTime>,
+    /// Repositories with their own interval, and when they're next due
+    repositories: HashMap<String, (Duration, OffsetDateTime)>,
+    last_run_started: Option<OffsetDateTime>,
+    last_run_finished: Option<OffsetDateTime>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule, jitter: Duration, activity_hints: bool) -> Self {
+        Self {
+            schedule,
+            jitter,
+            activity_hints,
+            state: Mutex::default(),
+        }
+    }
+
+    fn with_jitter(&self, time: OffsetDateTime) -> OffsetDateTime {
+        if self.jitter.is_zero() {
+            return time;
+        }
+
+        time + rand::thread_rng().gen_range(Duration::ZERO..self.jitter)
+    }
+
+    /// How long until the indexer is next due, `None` if it never will be unless woken.
+    pub fn time_until_next_run(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+
+        let next = state
+            .repositories
+            .values()
+            .map(|(_, next)| *next)
+            .chain(state.next_run)
+            .min()?;
+
+        Some(
+            (next - OffsetDateTime::now_utc())
+                .try_into()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Works out what's due as an index run starts, everything is if the run was forced.
+    pub fn begin_run(&self, forced: bool) -> RunPlan {
+        let now = OffsetDateTime::now_utc();
+        let mut state = self.state.lock().unwrap();
+
+        state.last_run_started = Some(now);
+
+        let scheduled = state
+            .next_run
+            .map_or(state.last_run_finished.is_none(), |v| v <= now);
+
+        if forced || (scheduled && state.repositories.is_empty()) {
+            return RunPlan::All;
+        }
+
+        if scheduled {
+            RunPlan::Except(
+                state
+                    .repositories
+                    .iter()
+                    .filter(|(_, (_, next))| *next > now)
+                    .map(|(path, _)| path.clone())
+                    .collect(),
+            )
+        } else {
+            RunPlan::Only(
+                state
+                    .repositories
+                    .iter()
+                    .filter(|(_, (_, next))| *next <= now)
+                    .map(|(path, _)| path.clone())
+                    .collect(),
+            )
+        }
+    }
+
+    /// Schedules the next runs once an index run has finished, picking up any changes to the
+    /// intervals set by repositories.
+    pub fn finish_run(&self, plan: &RunPlan, db: &rocksdb::DB) {
+        let now = OffsetDateTime::now_utc();
+
+        let intervals: HashMap<String, Duration> = Repository::fetch_all(db)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(path, repository)| {
+                let repository = repository.get();
+
+                let interval = match repository.refresh_interval.as_ref() {
+                    Some(interval) => Duration::from_secs(interval.to_native()),
+                    None if self.activity_hints => {
+                        self.activity_interval(Activity::of(repository, now))?
+                    }
+                    None => return None,
+                };
+
+                Some((path, interval))
+            })
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+
+        if !matches!(plan, RunPlan::Only(_)) || state.next_run.is_none() {
+            state.next_run = self
+                .schedule
+                .next_after(now)
+                .map(|next| self.with_jitter(next));
+        }
+
+        let previous = std::mem::take(&mut state.repositories);
+
+        state.repositories = intervals
+            .into_iter()
+            .map(|(path, interval)| {
+                let next = match previous.get(&path) {
+                    Some((previous_interval, next))
+                        if *previous_interval == interval && !plan.includes(&path) =>
+                    {
+                        *next
+                    }
+                    _ => self.with_jitter(now + interval),
+                };
+
+                (path, (interval, next))
+            })
+            .collect();
+
+        state.last_run_finished = Some(now);
+    }
+
+    /// The interval a repository with the given activity is indexed on, `None` where the global
+    /// schedule would already index it at least as often as its activity calls for, or where it
+    /// would index a dormant repository less often.
+    fn activity_interval(&self, activity: Activity) -> Option<Duration> {
+        let interval = activity.refresh_interval()?;
+
+        match (&self.schedule, activity) {
+            (Schedule::Every(global), Activity::Hot) if *global <= interval => None,
+            (Schedule::Every(global), Activity::Dormant) if *global >= interval => None,
+            _ => Some(interval),
+        }
+    }
+
+    pub fn status(&self) -> ScheduleStatus {
+        let state = self.state.lock().unwrap();
+        let format = |v: OffsetDateTime| v.format(&Rfc3339).unwrap_or_default();
+
+        ScheduleStatus {
+            schedule: self.schedule.to_string(),
+            jitter: humantime::format_duration(self.jitter).to_string(),
+            next_run: state.next_run.map(format),
+            last_run_started: state.last_run_started.map(format),
+            last_run_finished: state.last_run_finished.map(format),
+            repositories: state
+                .repositories
+                .iter()
+                .map(|(path, (interval, next))| RepositoryScheduleStatus {
+                    repository: path.clone(),
+                    interval: humantime::format_duration(*interval).to_string(),
+                    next_run: format(*next),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ScheduleStatus {
+    schedule: String,
+    jitter: String,
+    next_run: Option<String>,
+    last_run_started: Option<String>,
+    last_run_finished: Option<String>,
+    repositories: Vec<RepositoryScheduleStatus>,
+}
+
+#[derive(Serialize)]
+pub struct RepositoryScheduleStatus {
+    repository: String,
+    interval: String,
+    next_run: String,
+}
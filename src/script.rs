@@ -0,0 +1,59 @@
+//! Guesses the script a piece of text is written in, so commit messages from projects written in
+//! right-to-left or CJK languages are rendered in the right direction and with fonts that have
+//! the right glyphs, rather than being mangled by the bidi algorithm or falling back to whatever
+//! font happens to cover the characters.
+
+#[derive(Default)]
+struct Letters {
+    ltr: usize,
+    rtl: usize,
+    han: usize,
+    kana: usize,
+    hangul: usize,
+}
+
+impl Letters {
+    fn count(text: &str) -> Self {
+        let mut letters = Self::default();
+
+        for c in text.chars() {
+            match u32::from(c) {
+                // hebrew, arabic, syriac, thaana, n'ko and their presentation forms
+                0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF => letters.rtl += 1,
+                0x3040..=0x30FF | 0x31F0..=0x31FF => letters.kana += 1,
+                0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => letters.hangul += 1,
+                0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => letters.han += 1,
+                _ if c.is_alphabetic() => letters.ltr += 1,
+                _ => {}
+            }
+        }
+
+        letters
+    }
+}
+
+/// The `dir` and `lang` attributes to give the element `text` is rendered in, with a leading
+/// space, or nothing if the text doesn't need any.
+///
+/// Text mostly written in a right-to-left script is laid out right-to-left, and text that only
+/// contains a little is left to the browser to work out from its first strong character. Text
+/// containing CJK characters is tagged with the language it's most likely written in, since the
+/// same characters are drawn differently in each.
+pub fn attributes(text: &str) -> &'static str {
+    let letters = Letters::count(text);
+    let ltr = letters.ltr + letters.han + letters.kana + letters.hangul;
+
+    if letters.rtl > ltr {
+        r#" dir="rtl""#
+    } else if letters.rtl > 0 {
+        r#" dir="auto""#
+    } else if letters.kana > 0 {
+        r#" lang="ja""#
+    } else if letters.hangul > 0 {
+        r#" lang="ko""#
+    } else if letters.han > 0 {
+        r#" lang="zh""#
+    } else {
+        ""
+    }
+}
@@ -0,0 +1,38 @@
+//! Process-wide shutdown flag, set on SIGTERM or SIGINT so the server can stop accepting
+//! connections and the indexer can stop between batches, leaving the database consistent.
+
+use std::sync::LazyLock;
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::info;
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Whether the process is shutting down, long-running work should check this and stop at the
+/// next point it can do so cleanly.
+pub fn requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Resolves once the process has started shutting down.
+pub async fn wait() {
+    // the sender is static, so can't be dropped while we're waiting on it
+    let _res = SHUTDOWN.subscribe().wait_for(|v| *v).await;
+}
+
+/// Requests a shutdown when the process receives SIGTERM or SIGINT.
+pub fn listen_for_signals() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("could not subscribe to sigterm");
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Received ctrl-c, shutting down"),
+        }
+
+        SHUTDOWN.send_replace(true);
+    });
+}
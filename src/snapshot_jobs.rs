@@ -0,0 +1,402 @@
+//! `--background-snapshots`, builds snapshots into a cache off the request path so snapshots of
+//! very large trees don't hold a connection open for minutes. Requesters are shown a page that
+//! refreshes until the snapshot is ready, at which point it's served from the cache.
+//!
+//! The cache is either a local directory or an S3-compatible bucket, the latter letting several
+//! instances behind a load balancer serve snapshots built by any one of them.
+
+use std::{
+    fs::File,
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use axum::body::Body;
+use gix::ObjectId;
+use moka::sync::Cache;
+use object_store::{aws::AmazonS3Builder, prefix::PrefixStore, ObjectStore};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{watch, Semaphore},
+};
+use tokio_util::io::ReaderStream;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{archive::SnapshotFormat, cache_dir};
+
+/// How long built snapshots are kept for after they were last downloaded.
+const CACHE_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The number of built snapshots kept at once, the least recently downloaded are removed first.
+const CACHE_CAPACITY: u64 = 64;
+
+/// The number of snapshots built at once, any others requested wait for one of these to finish.
+const MAX_CONCURRENT_BUILDS: usize = 4;
+
+/// The repository, commit, subtree, format and compression level a snapshot was built from.
+pub type SnapshotKey = (PathBuf, ObjectId, Option<PathBuf>, SnapshotFormat, u32);
+
+/// Where built snapshots are kept.
+pub enum SnapshotStore {
+    /// A directory private to this instance, cleared on startup.
+    Directory(PathBuf),
+    /// An S3-compatible bucket, possibly shared with other instances. Snapshots are built into
+    /// `staging` before being uploaded, and are never removed from the bucket, expiring them is
+    /// left to its lifecycle rules.
+    Bucket {
+        store: Arc<dyn ObjectStore>,
+        staging: PathBuf,
+    },
+}
+
+impl SnapshotStore {
+    /// Opens the bucket at `url`, given as `s3://<bucket>[/<prefix>]`. Credentials, the region
+    /// and the endpoint of S3-compatible services such as MinIO are read from the standard
+    /// `AWS_*` environment variables.
+    pub fn bucket(url: &str, staging: PathBuf) -> anyhow::Result<Self> {
+        let Some(rest) = url.strip_prefix("s3://") else {
+            bail!("snapshot cache URL `{url}` must start with s3://");
+        };
+
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .context("Failed to open snapshot cache bucket")?;
+
+        let store: Arc<dyn ObjectStore> = match prefix.trim_matches('/') {
+            "" => Arc::new(store),
+            prefix => Arc::new(PrefixStore::new(store, prefix)),
+        };
+
+        Ok(Self::Bucket { store, staging })
+    }
+}
+
+/// Where a built snapshot was stored.
+#[derive(Clone, Debug)]
+pub enum SnapshotLocation {
+    File(Arc<Path>),
+    Object(object_store::path::Path),
+}
+
+#[derive(Clone, Debug)]
+pub enum SnapshotState {
+    Preparing,
+    Ready {
+        location: SnapshotLocation,
+        size: u64,
+    },
+    Failed,
+}
+
+/// A snapshot being built, or that has been built, in the background.
+pub struct PreparedSnapshot {
+    state: watch::Sender<SnapshotState>,
+    written: AtomicU64,
+    /// Set once the snapshot has been evicted from the cache, so a build that was still running
+    /// at the time knows to remove what it built.
+    evicted: AtomicBool,
+}
+
+impl PreparedSnapshot {
+    /// Removes the built snapshot's file if the snapshot has been evicted. Called both on
+    /// eviction and once the build finishes, as either can happen first.
+    fn remove_if_evicted(&self) {
+        if !self.evicted.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let SnapshotState::Ready {
+            location: SnapshotLocation::File(path),
+            ..
+        } = &*self.state.borrow()
+        {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => {
+                    error!(%error, "Failed to remove expired snapshot {}", path.display());
+                }
+            }
+        }
+    }
+
+    /// The number of bytes of the snapshot built so far.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Waits up to `timeout` for the snapshot to finish building, returning its state either way.
+    pub async fn wait(&self, timeout: Duration) -> SnapshotState {
+        let mut receiver = self.state.subscribe();
+
+        let _res = tokio::time::timeout(
+            timeout,
+            receiver.wait_for(|v| !matches!(v, SnapshotState::Preparing)),
+        )
+        .await;
+
+        self.state.borrow().clone()
+    }
+}
+
+pub struct SnapshotJobs {
+    store: SnapshotStore,
+    snapshots: Cache<SnapshotKey, Arc<PreparedSnapshot>>,
+    builds: Arc<Semaphore>,
+}
+
+impl SnapshotJobs {
+    /// Builds snapshots into `store`, clearing out anything left behind in local directories by
+    /// a previous run since the snapshots in them are no longer tracked, see [`cache_dir::reset`].
+    pub fn new(store: SnapshotStore) -> anyhow::Result<Self> {
+        let dir = match &store {
+            SnapshotStore::Directory(dir) => dir,
+            SnapshotStore::Bucket { staging, .. } => staging,
+        };
+
+        cache_dir::reset(dir)?;
+
+        let snapshots = Cache::builder()
+            .time_to_idle(CACHE_IDLE_TIMEOUT)
+            .max_capacity(CACHE_CAPACITY)
+            .eviction_listener(|_, snapshot: Arc<PreparedSnapshot>, _| {
+                snapshot.evicted.store(true, Ordering::SeqCst);
+                snapshot.remove_if_evicted();
+            })
+            .build();
+
+        Ok(Self {
+            store,
+            snapshots,
+            builds: Arc::new(Semaphore::new(MAX_CONCURRENT_BUILDS)),
+        })
+    }
+
+    /// Returns the snapshot for `key`, starting to build it using `build` if it isn't already
+    /// built or being built.
+    pub fn prepare(
+        &self,
+        key: SnapshotKey,
+        build: impl FnOnce(&mut dyn Write) -> anyhow::Result<()> + Send + 'static,
+    ) -> Arc<PreparedSnapshot> {
+        self.snapshots.get_with(key.clone(), || {
+            let snapshot = Arc::new(PreparedSnapshot {
+                state: watch::Sender::new(SnapshotState::Preparing),
+                written: AtomicU64::new(0),
+                evicted: AtomicBool::new(false),
+            });
+
+            let task = snapshot.clone();
+            let builds = self.builds.clone();
+
+            match &self.store {
+                SnapshotStore::Directory(dir) => {
+                    let path = dir.join(Uuid::new_v4().to_string());
+
+                    tokio::spawn(async move {
+                        let _permit = builds.acquire_owned().await;
+
+                        let builder = task.clone();
+                        let built = tokio::task::spawn_blocking(move || {
+                            build_file(&path, &builder, build).map(|()| path)
+                        })
+                        .await
+                        .context("Failed to join Tokio task")
+                        .and_then(|res| res);
+
+                        let state = match built {
+                            Ok(path) => SnapshotState::Ready {
+                                location: SnapshotLocation::File(Arc::from(path)),
+                                size: task.written(),
+                            },
+                            Err(error) => {
+                                error!(%error, "Failed to build snapshot in the background");
+                                SnapshotState::Failed
+                            }
+                        };
+
+                        task.state.send_replace(state);
+                        task.remove_if_evicted();
+                    });
+                }
+                SnapshotStore::Bucket { store, staging } => {
+                    let store = store.clone();
+                    let staging = staging.join(Uuid::new_v4().to_string());
+                    let location = object_name(&key);
+
+                    tokio::spawn(async move {
+                        let _permit = builds.acquire_owned().await;
+
+                        let state =
+                            match build_object(&store, &location, staging, &task, build).await {
+                                Ok(size) => SnapshotState::Ready {
+                                    location: SnapshotLocation::Object(location),
+                                    size,
+                                },
+                                Err(error) => {
+                                    error!(%error, "Failed to build snapshot in the background");
+                                    SnapshotState::Failed
+                                }
+                            };
+
+                        task.state.send_replace(state);
+                    });
+                }
+            }
+
+            snapshot
+        })
+    }
+
+    /// Opens a built snapshot to be streamed back to the requester.
+    pub async fn open(&self, location: &SnapshotLocation) -> anyhow::Result<Body> {
+        match (location, &self.store) {
+            (SnapshotLocation::File(path), _) => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .context("Failed to open built snapshot")?;
+
+                Ok(Body::from_stream(ReaderStream::new(file)))
+            }
+            (SnapshotLocation::Object(location), SnapshotStore::Bucket { store, .. }) => {
+                let object = store
+                    .get(location)
+                    .await
+                    .context("Failed to fetch built snapshot from bucket")?;
+
+                Ok(Body::from_stream(object.into_stream()))
+            }
+            (SnapshotLocation::Object(_), SnapshotStore::Directory(_)) => {
+                bail!("Snapshot was stored in a bucket that isn't configured")
+            }
+        }
+    }
+
+    /// Forgets a snapshot, so it's built again from scratch the next time it's requested.
+    pub fn forget(&self, key: &SnapshotKey) {
+        self.snapshots.invalidate(key);
+    }
+}
+
+/// Builds a snapshot into the file at `path`, removing it if the build fails.
+fn build_file(
+    path: &Path,
+    snapshot: &PreparedSnapshot,
+    build: impl FnOnce(&mut dyn Write) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    info!("Building snapshot in the background");
+
+    let res = File::create(path)
+        .context("Failed to create snapshot file")
+        .and_then(|file| {
+            let mut out = CountingWriter {
+                inner: BufWriter::new(file),
+                written: &snapshot.written,
+            };
+
+            build(&mut out)?;
+            out.inner.into_inner()?.sync_all()?;
+
+            Ok(())
+        });
+
+    if res.is_err() {
+        let _res = std::fs::remove_file(path);
+    }
+
+    res
+}
+
+/// Builds a snapshot into `staging` and uploads it to the bucket, returning its size. Skips
+/// straight to serving it if another instance sharing the bucket already built it.
+async fn build_object(
+    store: &Arc<dyn ObjectStore>,
+    location: &object_store::path::Path,
+    staging: PathBuf,
+    snapshot: &Arc<PreparedSnapshot>,
+    build: impl FnOnce(&mut dyn Write) -> anyhow::Result<()> + Send + 'static,
+) -> anyhow::Result<u64> {
+    if let Ok(meta) = store.head(location).await {
+        return Ok(meta.size as u64);
+    }
+
+    let res = async {
+        let task = snapshot.clone();
+        let path = staging.clone();
+        tokio::task::spawn_blocking(move || build_file(&path, &task, build))
+            .await
+            .context("Failed to join Tokio task")??;
+
+        let mut file = tokio::fs::File::open(&staging)
+            .await
+            .context("Failed to open built snapshot")?;
+        let mut upload = object_store::buffered::BufWriter::new(store.clone(), location.clone());
+
+        tokio::io::copy(&mut file, &mut upload)
+            .await
+            .context("Failed to upload snapshot")?;
+        upload
+            .shutdown()
+            .await
+            .context("Failed to upload snapshot")?;
+
+        Ok(snapshot.written())
+    }
+    .await;
+
+    let _res = tokio::fs::remove_file(&staging).await;
+
+    res
+}
+
+/// The name a snapshot is stored under in a bucket, derived from its key so every instance
+/// sharing the bucket agrees on it.
+fn object_name(key: &SnapshotKey) -> object_store::path::Path {
+    let (repository, commit, subtree, format, level) = key;
+
+    let mut hasher = Sha256::new();
+    hasher.update(repository.as_os_str().as_encoded_bytes());
+    hasher.update([0]);
+    hasher.update(commit.as_bytes());
+    hasher.update([0]);
+    if let Some(subtree) = subtree {
+        hasher.update(subtree.as_os_str().as_encoded_bytes());
+    }
+    hasher.update([0]);
+    hasher.update(level.to_be_bytes());
+
+    object_store::path::Path::from(format!(
+        "{}.{}",
+        const_hex::encode(hasher.finalize()),
+        format.extension()
+    ))
+}
+
+/// Keeps count of the number of bytes written through it.
+struct CountingWriter<'a, W> {
+    inner: W,
+    written: &'a AtomicU64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
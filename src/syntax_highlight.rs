@@ -4,13 +4,17 @@ use std::{
     fmt::Write as FmtWrite,
     io::{ErrorKind, Write as IoWrite},
     path::Path,
-    sync::LazyLock,
+    sync::{LazyLock, OnceLock},
 };
 
+use anyhow::Context;
 use comrak::adapters::SyntaxHighlighterAdapter;
-use tracing::{debug, error};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, error, info};
 use tree_sitter_grammar_repository::{Grammar, Language};
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
+use tree_sitter_language::LanguageFn;
 
 thread_local! {
     static HIGHLIGHTER: RefCell<Highlighter> = RefCell::new(Highlighter::new());
@@ -112,14 +116,183 @@ static HIGHLIGHTER_CONFIGS: LazyLock<Vec<HighlightConfiguration>> = LazyLock::ne
         .collect()
 });
 
+/// Grammars loaded from `--highlight-dir` at startup, which take precedence over the built-in
+/// ones so they can be used to override them as well as to add new languages.
+static CUSTOM_GRAMMARS: OnceLock<Vec<CustomGrammar>> = OnceLock::new();
+
+struct CustomGrammar {
+    extensions: Vec<String>,
+    file_names: Vec<String>,
+    injection_regex: Option<Regex>,
+    configuration: HighlightConfiguration,
+}
+
+/// `grammar.toml`, describing a grammar in `--highlight-dir`.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CustomGrammarConfig {
+    /// The shared library the parser is in, relative to the grammar's directory
+    library: Option<String>,
+    /// The function the library exposes the language through, `tree_sitter_<name>` by default
+    symbol: Option<String>,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    file_names: Vec<String>,
+    /// Matched against the language given on Markdown code blocks and by injection queries
+    injection_regex: Option<String>,
+}
+
+/// Loads the grammars in the `grammars` directory of `--highlight-dir`, each a directory holding
+/// a `grammar.toml`, the compiled parser and its queries in `queries/`. Must be called before
+/// any highlighting takes place, returning the number of grammars loaded.
+pub fn load_custom_grammars(dir: &Path) -> anyhow::Result<usize> {
+    let dir = dir.join("grammars");
+
+    let mut grammars = Vec::new();
+
+    if dir.exists() {
+        for entry in
+            std::fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            let grammar = load_custom_grammar(&path)
+                .with_context(|| format!("failed to load grammar from {}", path.display()))?;
+            info!(path = %path.display(), "Loaded custom grammar");
+            grammars.push(grammar);
+        }
+    }
+
+    let count = grammars.len();
+    let _res = CUSTOM_GRAMMARS.set(grammars);
+
+    Ok(count)
+}
+
+fn load_custom_grammar(path: &Path) -> anyhow::Result<CustomGrammar> {
+    let name = path
+        .file_name()
+        .and_then(|v| v.to_str())
+        .context("grammar directory name isn't valid UTF-8")?;
+
+    let config: CustomGrammarConfig = toml::from_str(
+        &std::fs::read_to_string(path.join("grammar.toml"))
+            .context("failed to read grammar.toml")?,
+    )
+    .context("failed to parse grammar.toml")?;
+
+    let library_path =
+        path.join(config.library.unwrap_or_else(|| {
+            format!("libtree-sitter-{name}.{}", std::env::consts::DLL_EXTENSION)
+        }));
+    let symbol = config
+        .symbol
+        .unwrap_or_else(|| format!("tree_sitter_{}", name.replace('-', "_")));
+
+    // SAFETY: the library is trusted configuration given to us by the operator, and is expected
+    // to be a tree-sitter parser exposing its language through `symbol`. It's never unloaded, so
+    // the language it returns stays valid for the life of the process.
+    let language = unsafe {
+        let library = libloading::Library::new(&library_path)
+            .with_context(|| format!("failed to load {}", library_path.display()))?;
+        let function = *library
+            .get::<unsafe extern "C" fn() -> *const ()>(symbol.as_bytes())
+            .with_context(|| format!("{} doesn't export {symbol}", library_path.display()))?;
+        std::mem::forget(library);
+        LanguageFn::from_raw(function)
+    };
+
+    let read_query = |file: &str| -> anyhow::Result<String> {
+        let path = path.join("queries").join(file);
+
+        match std::fs::read_to_string(&path) {
+            Ok(v) => Ok(v),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    };
+
+    let mut configuration = HighlightConfiguration::new(
+        language.into(),
+        name,
+        &read_query("highlights.scm")?,
+        &read_query("injections.scm")?,
+        &read_query("locals.scm")?,
+    )
+    .context("invalid query")?;
+    configuration.configure(&HIGHLIGHT_NAMES);
+
+    Ok(CustomGrammar {
+        extensions: config.extensions,
+        file_names: config.file_names,
+        injection_regex: config
+            .injection_regex
+            .map(|v| Regex::new(&format!("^(?:{v})$")))
+            .transpose()
+            .context("invalid injection-regex")?,
+        configuration,
+    })
+}
+
+fn custom_grammars() -> &'static [CustomGrammar] {
+    CUSTOM_GRAMMARS.get().map_or(&[], Vec::as_slice)
+}
+
+/// The grammar a file is highlighted with. Highlighted output depends only on the content and
+/// the grammar, so this identifies it in caches regardless of where the file was found.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Syntax {
+    /// Index into the grammars loaded from `--highlight-dir`
+    Custom(usize),
+    /// Index into the built-in grammars
+    Builtin(usize),
+}
+
+impl Syntax {
+    pub fn for_path(file: &Path) -> Option<Self> {
+        let file_name = file.file_name().and_then(|v| v.to_str());
+        let extension = file.extension().and_then(|v| v.to_str());
+
+        if let Some(idx) = custom_grammars().iter().position(|grammar| {
+            file_name.is_some_and(|name| grammar.file_names.iter().any(|v| v == name))
+                || extension.is_some_and(|ext| grammar.extensions.iter().any(|v| v == ext))
+        }) {
+            return Some(Self::Custom(idx));
+        }
+
+        Language::from_file_name(file)
+            .map(Language::grammar)
+            .map(Grammar::idx)
+            .map(Self::Builtin)
+    }
+
+    fn configuration(self) -> &'static HighlightConfiguration {
+        match self {
+            Self::Custom(idx) => &custom_grammars()[idx].configuration,
+            Self::Builtin(idx) => &HIGHLIGHTER_CONFIGS[idx],
+        }
+    }
+}
+
 pub fn fetch_highlighter_config(file: &Path) -> Option<&'static HighlightConfiguration> {
-    Language::from_file_name(file)
-        .map(Language::grammar)
-        .map(Grammar::idx)
-        .map(|idx| &HIGHLIGHTER_CONFIGS[idx])
+    Syntax::for_path(file).map(Syntax::configuration)
 }
 
 pub fn fetch_highlighter_config_by_token(token: &str) -> Option<&'static HighlightConfiguration> {
+    if let Some(grammar) = custom_grammars().iter().find(|grammar| {
+        grammar
+            .injection_regex
+            .as_ref()
+            .is_some_and(|v| v.is_match(token))
+    }) {
+        return Some(&grammar.configuration);
+    }
+
     Language::from_injection(token)
         .map(Language::grammar)
         .map(Grammar::idx)
@@ -161,35 +334,114 @@ impl SyntaxHighlighterAdapter for ComrakHighlightAdapter {
 pub enum FileIdentifier<'a> {
     Path(&'a Path),
     Token(&'a str),
+    /// A grammar that's already been looked up, `None` for plain text
+    Syntax(Option<Syntax>),
+}
+
+/// Controls how each line of highlighted output is wrapped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineStyle {
+    /// Lines are written out as-is, separated by newlines.
+    Bare,
+    /// Each line is wrapped in a `<code>` tag.
+    Code,
+    /// Each line is wrapped in a `<code id="L{n}">` tag prefixed with a link to itself, so
+    /// individual lines (and ranges of lines) can be linked to.
+    AnchoredCode,
+}
+
+/// Keeps track of the current line number whilst opening and closing lines.
+struct LineWriter {
+    style: LineStyle,
+    line: usize,
+}
+
+impl LineWriter {
+    fn new(style: LineStyle) -> Self {
+        Self { style, line: 0 }
+    }
+
+    fn start(&mut self, out: &mut String) {
+        self.line += 1;
+
+        match self.style {
+            LineStyle::Bare => {}
+            LineStyle::Code => out.push_str("<code>"),
+            LineStyle::AnchoredCode => {
+                write!(
+                    out,
+                    r##"<code id="L{0}"><a href="#L{0}" class="line-number"></a>"##,
+                    self.line
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    fn end(&self, out: &mut String) {
+        match self.style {
+            LineStyle::Bare => out.push('\n'),
+            LineStyle::Code | LineStyle::AnchoredCode => out.push_str("</code>\n"),
+        }
+    }
+
+    fn write_plain(&mut self, out: &mut String, content: &str) {
+        for line in content.lines() {
+            self.start(out);
+            v_htmlescape::b_escape(line.as_bytes(), out);
+            self.end(out);
+        }
+    }
 }
 
 pub fn format_file(content: &str, identifier: FileIdentifier<'_>) -> anyhow::Result<String> {
     let mut out = String::new();
-    format_file_inner(&mut out, content, identifier, true)?;
+    format_file_inner(&mut out, content, identifier, LineStyle::Code)?;
+    Ok(out)
+}
+
+/// Formats a file for the blob view, where every line is given an `L{n}` anchor.
+pub fn format_file_with_line_anchors(
+    content: &str,
+    identifier: FileIdentifier<'_>,
+) -> anyhow::Result<String> {
+    let mut out = String::new();
+    format_file_inner(&mut out, content, identifier, LineStyle::AnchoredCode)?;
     Ok(out)
 }
 
+/// Formats a file containing ANSI escape sequences for the blob view, rendering them as styled
+/// text rather than highlighting the file, see [`crate::ansi`].
+pub fn format_ansi_with_line_anchors(content: &str) -> String {
+    let mut out = String::new();
+    let mut lines = LineWriter::new(LineStyle::AnchoredCode);
+    let mut renderer = crate::ansi::Renderer::default();
+
+    for line in content.lines() {
+        lines.start(&mut out);
+        renderer.render_line(line, &mut out);
+        lines.end(&mut out);
+    }
+
+    out
+}
+
 pub fn format_file_inner(
     out: &mut String,
     content: &str,
     identifier: FileIdentifier<'_>,
-    code_tag: bool,
+    line_style: LineStyle,
 ) -> anyhow::Result<()> {
     let config = match identifier {
         FileIdentifier::Path(v) => fetch_highlighter_config(v),
         FileIdentifier::Token(v) => fetch_highlighter_config_by_token(v),
+        FileIdentifier::Syntax(v) => v.map(Syntax::configuration),
     };
 
-    let line_prefix = if code_tag { "<code>" } else { "" };
-    let line_suffix = if code_tag { "</code>\n" } else { "\n" };
+    let mut lines = LineWriter::new(line_style);
 
     let Some(config) = config else {
-        for line in content.lines() {
-            out.push_str(line_prefix);
-            v_htmlescape::b_escape(line.as_bytes(), out);
-            out.push_str(line_suffix);
-        }
-
+        lines.write_plain(out, content);
         return Ok(());
     };
 
@@ -209,22 +461,17 @@ pub fn format_file_inner(
                     "Failed to run highlighter, falling back to plaintext"
                 );
 
-                for line in content.lines() {
-                    out.push_str(line_prefix);
-                    v_htmlescape::b_escape(line.as_bytes(), out);
-                    out.push_str(line_suffix);
-                }
-
+                lines.write_plain(out, content);
                 return Ok(());
             }
         };
 
         let mut tag_open = true;
-        out.push_str(line_prefix);
+        lines.start(out);
 
         while let Some(span) = spans.next().transpose()? {
             if !tag_open {
-                out.push_str(line_prefix);
+                lines.start(out);
                 tag_open = true;
             }
 
@@ -234,15 +481,15 @@ pub fn format_file_inner(
 
                     for (i, line) in content.lines().enumerate() {
                         if i != 0 {
-                            out.push_str(line_suffix);
-                            out.push_str(line_prefix);
+                            lines.end(out);
+                            lines.start(out);
                         }
 
                         v_htmlescape::b_escape(line.as_bytes(), out);
                     }
 
                     if content.ends_with('\n') {
-                        out.push_str(line_suffix);
+                        lines.end(out);
                         tag_open = false;
                     }
                 }
@@ -260,7 +507,7 @@ pub fn format_file_inner(
         }
 
         if tag_open {
-            out.push_str(line_suffix);
+            lines.end(out);
         }
 
         Ok::<_, anyhow::Error>(())
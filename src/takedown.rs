@@ -0,0 +1,73 @@
+//! `--takedown-list`, repositories and objects that mustn't be served for legal reasons, such as
+//! a DMCA notice. Requests for them are answered with a 451 giving the reason, and the
+//! repositories are left out of every listing.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use gix::ObjectId;
+use path_clean::PathClean;
+
+/// Shown when an entry in the list doesn't give a reason of its own.
+const DEFAULT_REASON: &str = "This content is unavailable for legal reasons.";
+
+#[derive(Default)]
+pub struct Takedowns {
+    repositories: HashMap<PathBuf, Arc<str>>,
+    objects: HashMap<ObjectId, Arc<str>>,
+}
+
+impl Takedowns {
+    /// Reads the list, where each line is the path of a repository relative to the scan path or
+    /// the ID of an object, optionally followed by a space and the reason it was taken down.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        let mut takedowns = Self::default();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (target, reason) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let reason = Arc::from(match reason.trim() {
+                "" => DEFAULT_REASON,
+                reason => reason,
+            });
+
+            if let Ok(oid) = ObjectId::from_str(target) {
+                takedowns.objects.insert(oid, reason);
+            } else {
+                let path = PathBuf::from(target.trim_matches('/')).clean();
+                takedowns.repositories.insert(path, reason);
+            }
+        }
+
+        Ok(takedowns)
+    }
+
+    /// The reason the repository served at `path` was taken down, if it was.
+    pub fn repository<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        self.repositories.get(path.as_ref()).map(AsRef::as_ref)
+    }
+
+    /// The reason the object with the given hex ID was taken down, if it was.
+    pub fn object(&self, id: &str) -> Option<&str> {
+        let oid = ObjectId::from_str(id).ok()?;
+        self.objects.get(&oid).map(AsRef::as_ref)
+    }
+
+    /// Whether a commit from the given repository may be shown in a listing.
+    pub fn allows(&self, repository: &str, commit: &[u8; 20]) -> bool {
+        self.repository(repository).is_none()
+            && !self.objects.contains_key(&ObjectId::Sha1(*commit))
+    }
+}
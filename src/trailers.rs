@@ -0,0 +1,123 @@
+//! Parses the trailers (`Signed-off-by: ...` and friends) from the end of commit messages.
+
+#[derive(Debug, Clone)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+    /// The full ID of the commit referenced by a `Fixes` trailer, if it could be resolved
+    pub commit: Option<String>,
+}
+
+impl Trailer {
+    /// Splits a `Name <email>` value into its parts, as used by `Signed-off-by`, `Reviewed-by`,
+    /// `Co-authored-by` and the like.
+    pub fn person(&self) -> Option<(&str, &str)> {
+        split_person(&self.value)
+    }
+
+    /// The (possibly abbreviated) hash referenced by a `Fixes: <hash> ("summary")` trailer.
+    pub fn fixes_hash(&self) -> Option<&str> {
+        if !self.key.eq_ignore_ascii_case("fixes") {
+            return None;
+        }
+
+        let hash = self.value.split_whitespace().next()?;
+
+        (hash.len() >= 7 && hash.len() <= 40 && hash.bytes().all(|c| c.is_ascii_hexdigit()))
+            .then_some(hash)
+    }
+}
+
+fn split_person(value: &str) -> Option<(&str, &str)> {
+    let (name, email) = value.strip_suffix('>')?.rsplit_once('<')?;
+    let name = name.trim();
+
+    (!email.is_empty() && email.contains('@')).then_some((name, email))
+}
+
+/// Splits the trailer block off the end of a commit message body (everything after the
+/// summary), returning the remainder of the body along with the parsed trailers. If the final
+/// paragraph isn't made up entirely of trailers, the body is returned untouched.
+pub fn split_trailers(body: &str) -> (&str, Vec<Trailer>) {
+    let trimmed = body.trim_end();
+    let start = trimmed.rfind("\n\n").map_or(0, |i| i + 2);
+
+    let mut trailers: Vec<Trailer> = Vec::new();
+
+    for line in trimmed[start..].lines() {
+        // values can be continued over multiple lines by indenting them
+        if line.starts_with(char::is_whitespace) {
+            let Some(last) = trailers.last_mut() else {
+                return (body, Vec::new());
+            };
+
+            last.value.push(' ');
+            last.value.push_str(line.trim());
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            return (body, Vec::new());
+        };
+
+        // the separator has to be followed by whitespace so a paragraph that's just a URL
+        // (`https://...`) isn't mistaken for a trailer
+        if !value.starts_with(char::is_whitespace) {
+            return (body, Vec::new());
+        }
+
+        let value = value.trim();
+
+        if value.is_empty()
+            || !key.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !key.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        {
+            return (body, Vec::new());
+        }
+
+        trailers.push(Trailer {
+            key: key.to_string(),
+            value: value.to_string(),
+            commit: None,
+        });
+    }
+
+    if trailers.is_empty() {
+        return (body, trailers);
+    }
+
+    (trimmed[..start].trim_end(), trailers)
+}
+
+/// The hash of the commit a commit message says it reverts, from the `This reverts commit
+/// <hash>.` line `git revert` writes into the body.
+pub fn reverted_hash(body: &str) -> Option<&str> {
+    let (_, rest) = body.split_once("This reverts commit ")?;
+    leading_hash(rest)
+}
+
+/// The hash of the commit a commit message says it's a backport of, from the `commit <hash>
+/// upstream.` or `[ Upstream commit <hash> ]` lines of kernel-style stable branches, or the
+/// `(cherry picked from commit <hash>)` line written by `git cherry-pick -x`.
+pub fn upstream_hash(body: &str) -> Option<&str> {
+    body.lines().find_map(|line| {
+        let line = line.trim();
+
+        let rest = line
+            .strip_prefix("commit ")
+            .filter(|rest| rest.trim_end_matches('.').ends_with(" upstream"))
+            .or_else(|| line.strip_prefix("[ Upstream commit "))
+            .or_else(|| line.strip_prefix("(cherry picked from commit "))?;
+
+        leading_hash(rest)
+    })
+}
+
+/// The (possibly abbreviated) hash at the start of `value`.
+fn leading_hash(value: &str) -> Option<&str> {
+    let hash = &value[..value
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(value.len())];
+
+    (hash.len() >= 7 && hash.len() <= 40).then_some(hash)
+}
@@ -12,6 +12,10 @@ pub(crate) trait Callback {
     fn addition(&mut self, data: &str, dst: &mut String);
     fn remove(&mut self, data: &str, dst: &mut String);
     fn context(&mut self, data: &str, dst: &mut String);
+
+    /// Called once all the lines of a hunk have been passed to the callback, allowing callbacks
+    /// that buffer lines to write them out.
+    fn end_hunk(&mut self, _dst: &mut String) {}
 }
 
 impl<C: Callback> Callback for &mut C {
@@ -26,6 +30,10 @@ impl<C: Callback> Callback for &mut C {
     fn context(&mut self, data: &str, dst: &mut String) {
         (*self).context(data, dst);
     }
+
+    fn end_hunk(&mut self, dst: &mut String) {
+        (*self).end_hunk(dst);
+    }
 }
 
 /// A [`Sink`] that creates a textual diff
@@ -80,6 +88,7 @@ where
 
         let end = (self.pos + 3).min(u32::try_from(self.before.len()).unwrap_or(u32::MAX));
         self.update_pos(end, end);
+        self.callback.end_hunk(&mut self.buffer);
 
         writeln!(
             &mut self.dst,
@@ -0,0 +1,111 @@
+//! How repositories are laid out in URLs. By default a repository is served at its path relative
+//! to the scan path, but sites can choose to drop the `.git` suffix of bare repositories for
+//! cleaner project URLs, and to accept URLs regardless of their casing.
+
+use std::{fmt::Write, sync::OnceLock};
+
+use clap::ValueEnum;
+
+static URL_CONFIG: OnceLock<UrlConfig> = OnceLock::new();
+
+static PUBLIC_URL: OnceLock<String> = OnceLock::new();
+
+#[derive(clap::Args, Copy, Clone, Debug, Default)]
+pub struct UrlConfig {
+    /// Configures how repositories are addressed in URLs, `strip-git-suffix` serves `foo.git` at
+    /// `/foo` and redirects requests for `/foo.git` there.
+    #[clap(long, value_enum, default_value_t = UrlScheme::Path)]
+    pub url_scheme: UrlScheme,
+    /// Resolves repository URLs regardless of their casing, redirecting to the repository's
+    /// canonical URL
+    #[clap(long)]
+    pub case_insensitive_urls: bool,
+}
+
+impl UrlConfig {
+    /// Sets the config URLs are resolved and generated with, must be called before serving any
+    /// requests.
+    pub fn install(self) {
+        let _res = URL_CONFIG.set(self);
+    }
+
+    pub fn current() -> Self {
+        URL_CONFIG.get().copied().unwrap_or_default()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum UrlScheme {
+    /// Serve repositories at their path on disk, relative to the scan path.
+    #[default]
+    Path,
+    /// Serve repositories with the `.git` suffix of their directory removed, requests using the
+    /// suffix are redirected.
+    StripGitSuffix,
+}
+
+impl UrlScheme {
+    pub fn current() -> Self {
+        UrlConfig::current().url_scheme
+    }
+}
+
+/// The path, without a leading slash, that a repository is linked to at.
+pub fn repository_url(relative_path: &str) -> &str {
+    match UrlScheme::current() {
+        UrlScheme::Path => relative_path,
+        UrlScheme::StripGitSuffix => relative_path
+            .strip_suffix(".git")
+            .filter(|v| !v.is_empty() && !v.ends_with('/'))
+            .unwrap_or(relative_path),
+    }
+}
+
+/// Sets the public URL rgit is served from (`--public-url`), that links which have to be absolute,
+/// like those in feeds, are made against. Must be called before serving any requests.
+pub fn install_public_url(url: Option<&str>) {
+    if let Some(url) = url {
+        let _res = PUBLIC_URL.set(url.trim_end_matches('/').to_string());
+    }
+}
+
+/// `path`, starting with a slash, made absolute against the public URL if we know it.
+pub fn absolute_url(path: &str) -> String {
+    format!(
+        "{}{path}",
+        PUBLIC_URL.get().map(String::as_str).unwrap_or_default()
+    )
+}
+
+/// Appends `value` to a query string, percent-encoding anything that isn't unreserved.
+pub fn push_query_value(out: &mut String, value: &str) {
+    for c in value.bytes() {
+        if c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.' | b'~' | b'@') {
+            out.push(char::from(c));
+        } else {
+            write!(out, "%{c:02X}").unwrap();
+        }
+    }
+}
+
+/// The path of one of a repository's pages, given the repository's URL as returned by
+/// [`repository_url`]. The repository's summary if `page` is empty.
+pub fn repository_page_url(repository_url: &str, page: &str) -> String {
+    if page.is_empty() {
+        format!("/{repository_url}")
+    } else {
+        format!("/{repository_url}/{page}")
+    }
+}
+
+/// The path of the page listing the commits of the author with the address `email` across every
+/// repository.
+pub fn author_url(email: &str) -> String {
+    format!("/author/{email}")
+}
+
+/// The path of the page of the commit `id` in a repository, given the repository's URL as
+/// returned by [`repository_url`].
+pub fn commit_url(repository_url: &str, id: &str) -> String {
+    format!("/{repository_url}/commit/?id={id}")
+}